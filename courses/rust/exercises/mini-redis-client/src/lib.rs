@@ -0,0 +1,474 @@
+//! An async client for mini-redis.
+//!
+//! A `Client` sends its requests over a single connection, which is reused for
+//! every request. Subscribing turns the client into a `Subscriber`, as the server
+//! only accepts subscription commands on a subscribed connection, and `multi` starts
+//! a `Transaction` running its commands at once.
+//!
+//! ```no_run
+//! # async fn run() -> mini_redis_client::Result<()> {
+//! use mini_redis_client::Client;
+//!
+//! let mut client = Client::connect("127.0.0.1:6379").await?;
+//! client.set("key", "value".into()).await?;
+//! assert_eq!(client.get("key").await?, Some("value".into()));
+//! # Ok(())
+//! # }
+//! ```
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+pub use mini_redis_proto::Frame;
+use mini_redis_proto::{ProtocolError, Push, Request, RespCodec};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio_util::codec::Framed;
+
+/// Error of a request.
+#[derive(Debug)]
+pub enum ClientError {
+    /// IO error of the connection, or invalid data received from the server
+    Protocol(ProtocolError),
+    /// Error replied by the server
+    Server(String),
+    /// Reply which does not match the request
+    UnexpectedReply(Frame),
+    /// The server closed the connection
+    Closed,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::Protocol(e) => write!(f, "{}", e),
+            ClientError::Server(msg) => write!(f, "{}", msg),
+            ClientError::UnexpectedReply(frame) => write!(f, "Unexpected reply: {:?}", frame),
+            ClientError::Closed => write!(f, "Connection closed by the server"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<ProtocolError> for ClientError {
+    fn from(err: ProtocolError) -> Self {
+        ClientError::Protocol(err)
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(err: io::Error) -> Self {
+        ClientError::Protocol(ProtocolError::Io(err))
+    }
+}
+
+/// Result type of the client.
+pub type Result<T> = std::result::Result<T, ClientError>;
+
+/// A connection to a mini-redis server.
+pub struct Client {
+    framed: Framed<TcpStream, RespCodec>,
+    // a transaction was dropped without EXEC or DISCARD, so the server still queues
+    // the commands of this connection
+    dropped_transaction: bool,
+}
+
+impl Client {
+    /// Connects to the server at `addr`.
+    pub async fn connect<A: ToSocketAddrs>(addr: A) -> Result<Client> {
+        let stream = TcpStream::connect(addr).await?;
+        Ok(Client {
+            framed: Framed::new(stream, RespCodec::default()),
+            dropped_transaction: false,
+        })
+    }
+
+    /// Pings the server, returning `PONG` or `msg` if set.
+    pub async fn ping(&mut self, msg: Option<Bytes>) -> Result<Bytes> {
+        match self.request(Request::Ping(msg)).await? {
+            Frame::Simple(s) => Ok(Bytes::from(s)),
+            Frame::Bulk(msg) => Ok(msg),
+            frame => Err(ClientError::UnexpectedReply(frame)),
+        }
+    }
+
+    /// Returns the value of a key.
+    pub async fn get(&mut self, key: &str) -> Result<Option<Bytes>> {
+        let req = Request::Get {
+            key: key.to_owned(),
+        };
+        match self.request(req).await? {
+            Frame::Bulk(value) => Ok(Some(value)),
+            Frame::Null => Ok(None),
+            frame => Err(ClientError::UnexpectedReply(frame)),
+        }
+    }
+
+    /// Sets the value of a key.
+    pub async fn set(&mut self, key: &str, value: Bytes) -> Result<()> {
+        self.set_request(key, value, None).await
+    }
+
+    /// Sets the value of a key, removed by the server after `expire`.
+    pub async fn set_expires(&mut self, key: &str, value: Bytes, expire: Duration) -> Result<()> {
+        self.set_request(key, value, Some(expire)).await
+    }
+
+    async fn set_request(
+        &mut self,
+        key: &str,
+        value: Bytes,
+        expire: Option<Duration>,
+    ) -> Result<()> {
+        let req = Request::Set {
+            key: key.to_owned(),
+            value,
+            expire,
+            condition: None,
+        };
+        self.ok(req).await
+    }
+
+    /// Removes keys, returning the number of keys removed.
+    pub async fn del(&mut self, keys: &[&str]) -> Result<i64> {
+        let req = Request::Del {
+            keys: keys.iter().map(|&key| key.to_owned()).collect(),
+        };
+        self.integer(req).await
+    }
+
+    /// Increments the integer value of a key, returning the new value.
+    pub async fn incr(&mut self, key: &str) -> Result<i64> {
+        let req = Request::Incr {
+            key: key.to_owned(),
+        };
+        self.integer(req).await
+    }
+
+    /// Watches keys, so the next transaction is aborted if one of them changes before
+    /// it runs.
+    pub async fn watch(&mut self, keys: &[&str]) -> Result<()> {
+        let req = Request::Watch {
+            keys: keys.iter().map(|&key| key.to_owned()).collect(),
+        };
+        self.ok(req).await
+    }
+
+    /// Unwatches all the keys.
+    pub async fn unwatch(&mut self) -> Result<()> {
+        self.ok(Request::Unwatch).await
+    }
+
+    /// Starts a transaction.
+    ///
+    /// The transaction should end with `exec` or `discard`, the connection queues the
+    /// commands until then. If it is dropped instead, its commands are discarded
+    /// before the next request.
+    pub async fn multi(&mut self) -> Result<Transaction<'_>> {
+        self.ok(Request::Multi).await?;
+        Ok(Transaction {
+            client: self,
+            done: false,
+        })
+    }
+
+    /// Publishes a message to a channel, returning the number of subscribers it is
+    /// sent to.
+    pub async fn publish(&mut self, channel: &str, message: Bytes) -> Result<i64> {
+        let req = Request::Publish {
+            channel: channel.to_owned(),
+            message,
+        };
+        self.integer(req).await
+    }
+
+    /// Subscribes to channels.
+    pub async fn subscribe(self, channels: Vec<String>) -> Result<Subscriber> {
+        let mut subscriber = Subscriber::new(self);
+        subscriber.subscribe(channels).await?;
+        Ok(subscriber)
+    }
+
+    /// Subscribes to the channels matching glob patterns.
+    pub async fn psubscribe(self, patterns: Vec<String>) -> Result<Subscriber> {
+        let mut subscriber = Subscriber::new(self);
+        subscriber.psubscribe(patterns).await?;
+        Ok(subscriber)
+    }
+
+    async fn ok(&mut self, req: Request) -> Result<()> {
+        match self.request(req).await? {
+            Frame::Simple(ref s) if s == "OK" => Ok(()),
+            frame => Err(ClientError::UnexpectedReply(frame)),
+        }
+    }
+
+    async fn integer(&mut self, req: Request) -> Result<i64> {
+        match self.request(req).await? {
+            Frame::Integer(n) => Ok(n),
+            frame => Err(ClientError::UnexpectedReply(frame)),
+        }
+    }
+
+    /// Sends a request and reads its reply, returning error replies as errors
+    async fn request(&mut self, req: Request) -> Result<Frame> {
+        self.discard_dropped_transaction().await?;
+        self.framed.send(req.into_frame()).await?;
+        match self.read_frame().await? {
+            Frame::Error(msg) => Err(ClientError::Server(msg)),
+            frame => Ok(frame),
+        }
+    }
+
+    /// Ends the transaction left in the server by a dropped `Transaction`
+    async fn discard_dropped_transaction(&mut self) -> Result<()> {
+        if !self.dropped_transaction {
+            return Ok(());
+        }
+        self.dropped_transaction = false;
+        self.framed.send(Request::Discard.into_frame()).await?;
+        match self.read_frame().await? {
+            Frame::Simple(ref s) if s == "OK" => Ok(()),
+            frame => Err(ClientError::UnexpectedReply(frame)),
+        }
+    }
+
+    async fn read_frame(&mut self) -> Result<Frame> {
+        match self.framed.next().await {
+            Some(frame) => Ok(frame?),
+            None => Err(ClientError::Closed),
+        }
+    }
+}
+
+/// Commands queued by the server, to run at once on `exec`.
+///
+/// Dropping it without `exec` or `discard` discards the commands.
+pub struct Transaction<'a> {
+    client: &'a mut Client,
+    // whether EXEC or DISCARD is answered
+    done: bool,
+}
+
+impl Transaction<'_> {
+    /// Queues a `GET`.
+    pub async fn get(&mut self, key: &str) -> Result<()> {
+        let req = Request::Get {
+            key: key.to_owned(),
+        };
+        self.queue(req).await
+    }
+
+    /// Queues a `SET`.
+    pub async fn set(&mut self, key: &str, value: Bytes) -> Result<()> {
+        let req = Request::Set {
+            key: key.to_owned(),
+            value,
+            expire: None,
+            condition: None,
+        };
+        self.queue(req).await
+    }
+
+    /// Queues a `DEL`.
+    pub async fn del(&mut self, keys: &[&str]) -> Result<()> {
+        let req = Request::Del {
+            keys: keys.iter().map(|&key| key.to_owned()).collect(),
+        };
+        self.queue(req).await
+    }
+
+    /// Queues an `INCR`.
+    pub async fn incr(&mut self, key: &str) -> Result<()> {
+        let req = Request::Incr {
+            key: key.to_owned(),
+        };
+        self.queue(req).await
+    }
+
+    /// Runs the queued commands, returning their replies in order.
+    ///
+    /// Returns `None` if the transaction is aborted because a watched key changed.
+    pub async fn exec(mut self) -> Result<Option<Vec<Frame>>> {
+        let reply = self.client.request(Request::Exec).await;
+        self.done = true;
+        match reply? {
+            Frame::Array(replies) => Ok(Some(replies)),
+            Frame::Null => Ok(None),
+            frame => Err(ClientError::UnexpectedReply(frame)),
+        }
+    }
+
+    /// Drops the queued commands.
+    pub async fn discard(mut self) -> Result<()> {
+        let res = self.client.ok(Request::Discard).await;
+        self.done = true;
+        res
+    }
+
+    async fn queue(&mut self, req: Request) -> Result<()> {
+        match self.client.request(req).await? {
+            Frame::Simple(ref s) if s == "QUEUED" => Ok(()),
+            frame => Err(ClientError::UnexpectedReply(frame)),
+        }
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        // DISCARD can't be sent from here, so it is sent before the next request
+        if !self.done {
+            self.client.dropped_transaction = true;
+        }
+    }
+}
+
+/// A message published to a subscribed channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    /// Channel the message is published to
+    pub channel: String,
+    /// Pattern matching the channel, if the message is received through a pattern
+    pub pattern: Option<String>,
+    /// Published message
+    pub message: Bytes,
+}
+
+/// A client in push mode, receiving the messages published to its subscriptions.
+pub struct Subscriber {
+    client: Client,
+    channels: Vec<String>,
+    patterns: Vec<String>,
+    // messages received while waiting for a subscription confirmation
+    pending: VecDeque<Message>,
+}
+
+impl Subscriber {
+    fn new(client: Client) -> Subscriber {
+        Subscriber {
+            client,
+            channels: Vec::new(),
+            patterns: Vec::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Returns the subscribed channels.
+    pub fn channels(&self) -> &[String] {
+        &self.channels
+    }
+
+    /// Returns the subscribed patterns.
+    pub fn patterns(&self) -> &[String] {
+        &self.patterns
+    }
+
+    /// Returns the next message, or `None` if the server closed the connection.
+    pub async fn next_message(&mut self) -> Result<Option<Message>> {
+        if let Some(message) = self.pending.pop_front() {
+            return Ok(Some(message));
+        }
+        loop {
+            let frame = match self.client.framed.next().await {
+                Some(frame) => frame?,
+                None => return Ok(None),
+            };
+            if let Some(message) = self.push(frame)? {
+                return Ok(Some(message));
+            }
+        }
+    }
+
+    /// Subscribes to more channels.
+    pub async fn subscribe(&mut self, channels: Vec<String>) -> Result<()> {
+        // an empty list is answered with a single error
+        let count = channels.len().max(1);
+        self.send(Request::Subscribe { channels }, count).await
+    }
+
+    /// Subscribes to more glob patterns.
+    pub async fn psubscribe(&mut self, patterns: Vec<String>) -> Result<()> {
+        // an empty list is answered with a single error
+        let count = patterns.len().max(1);
+        self.send(Request::Psubscribe { patterns }, count).await
+    }
+
+    /// Unsubscribes from channels, or from all of them if `channels` is empty.
+    pub async fn unsubscribe(&mut self, channels: Vec<String>) -> Result<()> {
+        let count = match channels.len() {
+            0 => self.channels.len().max(1),
+            n => n,
+        };
+        self.send(Request::Unsubscribe { channels }, count).await
+    }
+
+    /// Unsubscribes from patterns, or from all of them if `patterns` is empty.
+    pub async fn punsubscribe(&mut self, patterns: Vec<String>) -> Result<()> {
+        let count = match patterns.len() {
+            0 => self.patterns.len().max(1),
+            n => n,
+        };
+        self.send(Request::Punsubscribe { patterns }, count).await
+    }
+
+    /// Sends a subscription command and waits for its `confirmations`
+    async fn send(&mut self, req: Request, mut confirmations: usize) -> Result<()> {
+        self.client.discard_dropped_transaction().await?;
+        self.client.framed.send(req.into_frame()).await?;
+        while confirmations > 0 {
+            let frame = self.client.read_frame().await?;
+            match self.push(frame)? {
+                Some(message) => self.pending.push_back(message),
+                None => confirmations -= 1,
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles a pushed frame, returning it if it is a message
+    fn push(&mut self, frame: Frame) -> Result<Option<Message>> {
+        if let Frame::Error(msg) = frame {
+            return Err(ClientError::Server(msg));
+        }
+        let message = match Push::from_frame(frame)? {
+            Push::Subscribe { channel, .. } => {
+                if !self.channels.contains(&channel) {
+                    self.channels.push(channel);
+                }
+                None
+            }
+            Push::Psubscribe { pattern, .. } => {
+                if !self.patterns.contains(&pattern) {
+                    self.patterns.push(pattern);
+                }
+                None
+            }
+            Push::Unsubscribe { channel, .. } => {
+                self.channels.retain(|c| Some(c) != channel.as_ref());
+                None
+            }
+            Push::Punsubscribe { pattern, .. } => {
+                self.patterns.retain(|p| Some(p) != pattern.as_ref());
+                None
+            }
+            Push::Message { channel, message } => Some(Message {
+                channel,
+                pattern: None,
+                message,
+            }),
+            Push::Pmessage {
+                pattern,
+                channel,
+                message,
+            } => Some(Message {
+                channel,
+                pattern: Some(pattern),
+                message,
+            }),
+        };
+        Ok(message)
+    }
+}
@@ -1,30 +1,183 @@
-use anyhow::Result;
-use mini_redis_proto::{Request, Response};
-use tokio::net::TcpStream;
-use tokio::prelude::*;
-use tokio::time::{delay_for, Duration};
+use bytes::Bytes;
+use mini_redis_client::{Client, ClientError, Message, Result};
+use std::process::exit;
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+use tokio::time::delay_for;
 
-async fn ping(addr: &str) -> Result<()> {
-    let mut stream = TcpStream::connect(&addr).await?;
-    let req: String = serde_json::to_string(&Request::Ping)?;
-    stream.write(&req.as_bytes()).await?;
-    let mut buf = [0; 64];
-    let bytes_read = stream.read(&mut buf).await?;
-    let resp: Response = serde_json::from_str(std::str::from_utf8(&buf[..bytes_read])?)?;
-    println!("wrote to stream; num bytes={:?}", resp);
+#[derive(StructOpt, Debug)]
+#[structopt(name = "mini-redis-client")]
+struct Opt {
+    /// Host of the server
+    #[structopt(long, default_value = "127.0.0.1")]
+    host: String,
+    /// Port of the server
+    #[structopt(long, default_value = "6379")]
+    port: u16,
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Ping the server
+    Ping {
+        /// Message to send instead of PING
+        message: Option<String>,
+        /// Number of pings to send, 0 to ping forever
+        #[structopt(long, default_value = "1")]
+        repeat: u64,
+        /// Seconds between pings
+        #[structopt(long, default_value = "1", parse(try_from_str = parse_interval))]
+        interval: Duration,
+    },
+    /// Get the value of a key
+    Get { key: String },
+    /// Set the value of a key
+    Set {
+        key: String,
+        value: String,
+        /// Seconds after which the key is removed
+        #[structopt(long)]
+        expire: Option<u64>,
+    },
+    /// Remove keys
+    Del {
+        #[structopt(required = true)]
+        keys: Vec<String>,
+    },
+    /// Publish a message to a channel
+    Publish { channel: String, message: String },
+    /// Print the messages published to channels
+    Subscribe {
+        #[structopt(required = true)]
+        channels: Vec<String>,
+    },
+    /// Print the messages published to the channels matching glob patterns
+    Psubscribe {
+        #[structopt(required = true)]
+        patterns: Vec<String>,
+    },
+}
+
+fn parse_interval(s: &str) -> std::result::Result<Duration, String> {
+    match s.parse::<f64>() {
+        Ok(secs) if secs.is_finite() && secs >= 0.0 => Ok(Duration::from_secs_f64(secs)),
+        _ => Err(format!("invalid number of seconds: {}", s)),
+    }
+}
+
+/// Pings the server `repeat` times over one connection, reconnecting after an error.
+///
+/// Returns the result of the last ping.
+async fn ping(
+    addr: (&str, u16),
+    message: Option<String>,
+    repeat: u64,
+    interval: Duration,
+) -> Result<()> {
+    let mut client = None;
+    let mut sent = 0;
+    loop {
+        let result = async {
+            if client.is_none() {
+                client = Some(Client::connect(addr).await?);
+            }
+            let start = Instant::now();
+            let msg = message.clone().map(Bytes::from);
+            let reply = client.as_mut().unwrap().ping(msg).await?;
+            Ok::<_, ClientError>((reply, start.elapsed()))
+        }
+        .await;
+        let result = match result {
+            Ok((reply, elapsed)) => {
+                println!(
+                    "{} ({:.3} ms)",
+                    String::from_utf8_lossy(&reply),
+                    elapsed.as_secs_f64() * 1000.0
+                );
+                Ok(())
+            }
+            Err(err) => {
+                client = None;
+                Err(err)
+            }
+        };
+        sent += 1;
+        if sent == repeat {
+            return result;
+        }
+        if let Err(err) = result {
+            eprintln!("ERROR: {}", err);
+        }
+        delay_for(interval).await;
+    }
+}
+
+fn print_message(message: Message) {
+    let text = String::from_utf8_lossy(&message.message);
+    match message.pattern {
+        Some(pattern) => println!("{} ({}): {}", message.channel, pattern, text),
+        None => println!("{}: {}", message.channel, text),
+    }
+}
+
+async fn run(opt: Opt) -> Result<()> {
+    let addr = (opt.host.as_str(), opt.port);
+    let command = match opt.command {
+        Command::Ping {
+            message,
+            repeat,
+            interval,
+        } => return ping(addr, message, repeat, interval).await,
+        command => command,
+    };
+    let mut client = Client::connect(addr).await?;
+    let mut subscriber = match command {
+        Command::Ping { .. } => unreachable!(),
+        Command::Get { key } => {
+            match client.get(&key).await? {
+                Some(value) => println!("{}", String::from_utf8_lossy(&value)),
+                None => println!("(nil)"),
+            }
+            return Ok(());
+        }
+        Command::Set { key, value, expire } => {
+            let value = Bytes::from(value);
+            match expire {
+                Some(secs) => {
+                    client
+                        .set_expires(&key, value, Duration::from_secs(secs))
+                        .await?
+                }
+                None => client.set(&key, value).await?,
+            }
+            println!("OK");
+            return Ok(());
+        }
+        Command::Del { keys } => {
+            let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+            println!("{}", client.del(&keys).await?);
+            return Ok(());
+        }
+        Command::Publish { channel, message } => {
+            let sent = client.publish(&channel, Bytes::from(message)).await?;
+            println!("{}", sent);
+            return Ok(());
+        }
+        Command::Subscribe { channels } => client.subscribe(channels).await?,
+        Command::Psubscribe { patterns } => client.psubscribe(patterns).await?,
+    };
+    while let Some(message) = subscriber.next_message().await? {
+        print_message(message);
+    }
     Ok(())
 }
 
 #[tokio::main]
 async fn main() {
-    loop {
-        ping("127.0.0.1:6379")
-            .await
-            .map_err(|err| eprintln!("ERROR: {}", err))
-            .and_then(|_| {
-                println!("PING Success!");
-                Ok(())
-            });
-        delay_for(Duration::from_secs(3)).await;
+    if let Err(err) = run(Opt::from_args()).await {
+        eprintln!("ERROR: {}", err);
+        exit(1);
     }
 }
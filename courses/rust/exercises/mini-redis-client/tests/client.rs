@@ -0,0 +1,325 @@
+use bytes::Bytes;
+use futures::future;
+use mini_redis_client::{Client, ClientError, Frame, Message};
+use mini_redis_server::{Db, PubSub};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::process::Command;
+use tokio::time::delay_for;
+
+/// Starts a server in the background, on a port picked by the OS
+async fn start_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(mini_redis_server::run(
+        listener,
+        Db::new(),
+        PubSub::new(16),
+        future::pending::<()>(),
+    ));
+    addr
+}
+
+#[tokio::test]
+async fn ping() {
+    let mut client = Client::connect(start_server().await).await.unwrap();
+    assert_eq!(client.ping(None).await.unwrap(), Bytes::from("PONG"));
+    let msg = Bytes::from("hello");
+    assert_eq!(client.ping(Some(msg.clone())).await.unwrap(), msg);
+}
+
+// Requests are sent one after the other over the same connection
+#[tokio::test]
+async fn get_set_del() {
+    let mut client = Client::connect(start_server().await).await.unwrap();
+    assert_eq!(client.get("key").await.unwrap(), None);
+    client.set("key", Bytes::from("value")).await.unwrap();
+    client.set("other", Bytes::from("value")).await.unwrap();
+    assert_eq!(client.get("key").await.unwrap(), Some(Bytes::from("value")));
+    client.set("key", Bytes::from("new")).await.unwrap();
+    assert_eq!(client.get("key").await.unwrap(), Some(Bytes::from("new")));
+    assert_eq!(client.del(&["key", "other", "missing"]).await.unwrap(), 2);
+    assert_eq!(client.get("key").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn set_expires() {
+    let mut client = Client::connect(start_server().await).await.unwrap();
+    client
+        .set_expires("key", Bytes::from("value"), Duration::from_millis(100))
+        .await
+        .unwrap();
+    assert_eq!(client.get("key").await.unwrap(), Some(Bytes::from("value")));
+    delay_for(Duration::from_millis(200)).await;
+    assert_eq!(client.get("key").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn publish_subscribe() {
+    let addr = start_server().await;
+    let mut publisher = Client::connect(addr).await.unwrap();
+    let mut subscriber = Client::connect(addr)
+        .await
+        .unwrap()
+        .subscribe(vec!["news".to_owned(), "weather".to_owned()])
+        .await
+        .unwrap();
+    assert_eq!(subscriber.channels(), &["news", "weather"]);
+
+    assert_eq!(
+        publisher
+            .publish("news", Bytes::from("hello"))
+            .await
+            .unwrap(),
+        1
+    );
+    assert_eq!(
+        publisher
+            .publish("sports", Bytes::from("hello"))
+            .await
+            .unwrap(),
+        0
+    );
+    assert_eq!(
+        subscriber.next_message().await.unwrap(),
+        Some(Message {
+            channel: "news".to_owned(),
+            pattern: None,
+            message: Bytes::from("hello"),
+        })
+    );
+
+    subscriber
+        .unsubscribe(vec!["news".to_owned()])
+        .await
+        .unwrap();
+    assert_eq!(subscriber.channels(), &["weather"]);
+    assert_eq!(
+        publisher
+            .publish("news", Bytes::from("hello"))
+            .await
+            .unwrap(),
+        0
+    );
+    subscriber.unsubscribe(vec![]).await.unwrap();
+    assert!(subscriber.channels().is_empty());
+}
+
+#[tokio::test]
+async fn psubscribe() {
+    let addr = start_server().await;
+    let mut publisher = Client::connect(addr).await.unwrap();
+    let mut subscriber = Client::connect(addr)
+        .await
+        .unwrap()
+        .psubscribe(vec!["news.*".to_owned()])
+        .await
+        .unwrap();
+    subscriber
+        .subscribe(vec!["news.tech".to_owned()])
+        .await
+        .unwrap();
+
+    // received once for the channel and once for the pattern
+    assert_eq!(
+        publisher
+            .publish("news.tech", Bytes::from("hello"))
+            .await
+            .unwrap(),
+        2
+    );
+    let mut messages = vec![
+        subscriber.next_message().await.unwrap().unwrap(),
+        subscriber.next_message().await.unwrap().unwrap(),
+    ];
+    messages.sort_by(|a, b| a.pattern.cmp(&b.pattern));
+    assert_eq!(messages[0].pattern, None);
+    assert_eq!(messages[1].pattern, Some("news.*".to_owned()));
+    assert!(messages
+        .iter()
+        .all(|m| m.channel == "news.tech" && m.message == "hello"));
+}
+
+#[tokio::test]
+async fn server_error() {
+    let addr = start_server().await;
+    let mut subscriber = Client::connect(addr)
+        .await
+        .unwrap()
+        .subscribe(vec!["news".to_owned()])
+        .await
+        .unwrap();
+    match subscriber.subscribe(vec![]).await {
+        Err(ClientError::Server(msg)) => {
+            assert_eq!(msg, "ERR wrong number of arguments for 'subscribe' command")
+        }
+        res => panic!("unexpected result {:?}", res),
+    }
+}
+
+// The commands of a transaction are not visible to other connections before EXEC
+#[tokio::test]
+async fn transaction() {
+    let addr = start_server().await;
+    let mut client = Client::connect(addr).await.unwrap();
+    let mut other = Client::connect(addr).await.unwrap();
+
+    let mut transaction = client.multi().await.unwrap();
+    transaction.set("key", Bytes::from("value")).await.unwrap();
+    transaction.incr("counter").await.unwrap();
+    transaction.incr("key").await.unwrap();
+    transaction.get("key").await.unwrap();
+    assert_eq!(other.get("key").await.unwrap(), None);
+    assert_eq!(
+        transaction.exec().await.unwrap().unwrap(),
+        vec![
+            Frame::Simple("OK".to_owned()),
+            Frame::Integer(1),
+            Frame::Error("ERR value is not an integer or out of range".to_owned()),
+            Frame::Bulk(Bytes::from("value")),
+        ]
+    );
+    assert_eq!(other.get("counter").await.unwrap(), Some(Bytes::from("1")));
+
+    let mut transaction = client.multi().await.unwrap();
+    transaction.del(&["key"]).await.unwrap();
+    transaction.discard().await.unwrap();
+    assert_eq!(other.get("key").await.unwrap(), Some(Bytes::from("value")));
+}
+
+// A dropped transaction is discarded, so the next commands run right away
+#[tokio::test]
+async fn dropped_transaction() {
+    let addr = start_server().await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    let mut transaction = client.multi().await.unwrap();
+    transaction.set("key", Bytes::from("value")).await.unwrap();
+    drop(transaction);
+    assert_eq!(client.incr("counter").await.unwrap(), 1);
+    assert_eq!(client.get("key").await.unwrap(), None);
+
+    // a new transaction can be started
+    let mut transaction = client.multi().await.unwrap();
+    transaction.incr("counter").await.unwrap();
+    assert_eq!(
+        transaction.exec().await.unwrap().unwrap(),
+        vec![Frame::Integer(2)]
+    );
+}
+
+/// Sets "key" in a transaction, returning whether it is not aborted
+async fn set_in_transaction(client: &mut Client, value: &'static str) -> bool {
+    let mut transaction = client.multi().await.unwrap();
+    transaction.set("key", Bytes::from(value)).await.unwrap();
+    transaction.exec().await.unwrap().is_some()
+}
+
+#[tokio::test]
+async fn watch() {
+    let addr = start_server().await;
+    let mut client = Client::connect(addr).await.unwrap();
+    let mut other = Client::connect(addr).await.unwrap();
+
+    client.watch(&["key"]).await.unwrap();
+    other.set("key", Bytes::from("theirs")).await.unwrap();
+    assert!(!set_in_transaction(&mut client, "mine").await);
+    assert_eq!(other.get("key").await.unwrap(), Some(Bytes::from("theirs")));
+
+    // the keys are unwatched after EXEC, or UNWATCH
+    other.set("key", Bytes::from("theirs")).await.unwrap();
+    assert!(set_in_transaction(&mut client, "mine").await);
+    client.watch(&["key"]).await.unwrap();
+    client.unwatch().await.unwrap();
+    other.set("key", Bytes::from("theirs")).await.unwrap();
+    assert!(set_in_transaction(&mut client, "mine").await);
+    assert_eq!(other.get("key").await.unwrap(), Some(Bytes::from("mine")));
+
+    // a key created after it is watched is a change too
+    other.del(&["key"]).await.unwrap();
+    client.watch(&["key"]).await.unwrap();
+    other.set("key", Bytes::from("theirs")).await.unwrap();
+    assert!(!set_in_transaction(&mut client, "mine").await);
+}
+
+/// Increments a counter `times` with GET and SET, retrying when it changes in between
+async fn increment_watched(addr: SocketAddr, times: usize) {
+    let mut client = Client::connect(addr).await.unwrap();
+    for _ in 0..times {
+        loop {
+            client.watch(&["counter"]).await.unwrap();
+            let n: i64 = match client.get("counter").await.unwrap() {
+                Some(n) => std::str::from_utf8(&n).unwrap().parse().unwrap(),
+                None => 0,
+            };
+            let mut transaction = client.multi().await.unwrap();
+            transaction
+                .set("counter", Bytes::from((n + 1).to_string()))
+                .await
+                .unwrap();
+            if transaction.exec().await.unwrap().is_some() {
+                break;
+            }
+        }
+    }
+}
+
+// No increment is lost between two connections racing on the same key
+#[tokio::test(threaded_scheduler)]
+async fn concurrent_watch() {
+    let addr = start_server().await;
+    let (first, second) = futures::join!(
+        tokio::spawn(increment_watched(addr, 100)),
+        tokio::spawn(increment_watched(addr, 100))
+    );
+    first.unwrap();
+    second.unwrap();
+    let mut client = Client::connect(addr).await.unwrap();
+    assert_eq!(
+        client.get("counter").await.unwrap(),
+        Some(Bytes::from("200"))
+    );
+}
+
+async fn cli(addr: SocketAddr, args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_mini-redis-client"))
+        .arg("--port")
+        .arg(addr.port().to_string())
+        .args(args)
+        .output()
+        .await
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[tokio::test]
+async fn cli_commands() {
+    let addr = start_server().await;
+    assert_eq!(cli(addr, &["set", "key", "value"]).await, "OK\n");
+    assert_eq!(cli(addr, &["get", "key"]).await, "value\n");
+    assert_eq!(cli(addr, &["del", "key", "missing"]).await, "1\n");
+    assert_eq!(cli(addr, &["get", "key"]).await, "(nil)\n");
+    assert_eq!(cli(addr, &["publish", "news", "hello"]).await, "0\n");
+
+    let pings = cli(addr, &["ping", "--repeat", "3", "--interval", "0.01"]).await;
+    assert_eq!(pings.lines().count(), 3);
+    assert!(pings.lines().all(|line| line.starts_with("PONG (")));
+}
+
+#[tokio::test]
+async fn cli_connection_refused() {
+    // bind then drop a listener, so the port is most likely free
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_mini-redis-client"))
+        .args(&["--port", addr.port().to_string().as_str(), "get", "key"])
+        .output()
+        .await
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+}
@@ -0,0 +1,100 @@
+use crate::frame::{Frame, ProtocolError, Scan};
+use bytes::{Buf, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// A tokio codec reading and writing RESP2 frames.
+///
+/// A frame is decoded once all its bytes have arrived. The bytes are scanned as they
+/// arrive, so a large frame split over many reads is not parsed again on each one.
+///
+/// ```no_run
+/// # async fn connect() -> Result<(), Box<dyn std::error::Error>> {
+/// use futures::{SinkExt, StreamExt};
+/// use mini_redis_proto::{Frame, RespCodec};
+/// use tokio::net::TcpStream;
+/// use tokio_util::codec::Framed;
+///
+/// let stream = TcpStream::connect("127.0.0.1:6379").await?;
+/// let mut framed = Framed::new(stream, RespCodec::default());
+/// framed.send(Frame::Array(vec![Frame::bulk("PING")])).await?;
+/// let pong = framed.next().await;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct RespCodec {
+    scan: Scan,
+}
+
+impl Decoder for RespCodec {
+    type Item = Frame;
+    type Error = ProtocolError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Frame>, ProtocolError> {
+        let len = match self.scan.frame_len(src)? {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+        match Frame::parse(&src[..len])? {
+            Some((frame, _)) => {
+                src.advance(len);
+                Ok(Some(frame))
+            }
+            None => unreachable!("the scanned frame is complete"),
+        }
+    }
+}
+
+impl Encoder<Frame> for RespCodec {
+    type Error = ProtocolError;
+
+    fn encode(&mut self, frame: Frame, dst: &mut BytesMut) -> Result<(), ProtocolError> {
+        frame.encode(dst);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Frames are decoded once all their bytes arrive, however the stream is split
+    #[test]
+    fn test_partial_reads() {
+        let mut encoded = BytesMut::new();
+        let frames = vec![
+            Frame::Array(vec![
+                Frame::bulk("SET"),
+                Frame::bulk("key"),
+                Frame::bulk("value"),
+            ]),
+            Frame::Simple("OK".to_owned()),
+            Frame::Integer(7),
+        ];
+        for frame in &frames {
+            RespCodec::default()
+                .encode(frame.clone(), &mut encoded)
+                .unwrap();
+        }
+
+        for chunk_len in 1..encoded.len() {
+            let mut codec = RespCodec::default();
+            let mut src = BytesMut::new();
+            let mut decoded = Vec::new();
+            for chunk in encoded.chunks(chunk_len) {
+                src.extend_from_slice(chunk);
+                while let Some(frame) = codec.decode(&mut src).unwrap() {
+                    decoded.push(frame);
+                }
+            }
+            assert_eq!(decoded, frames);
+            assert!(src.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_invalid() {
+        let mut src = BytesMut::from(&b"!oops\r\n"[..]);
+        assert!(RespCodec::default().decode(&mut src).is_err());
+    }
+}
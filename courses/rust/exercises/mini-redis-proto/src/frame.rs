@@ -0,0 +1,386 @@
+use bytes::{Bytes, BytesMut};
+use std::fmt;
+use std::io;
+
+/// Maximum length of a bulk string, the same as Redis
+const MAX_BULK_LEN: i64 = 512 * 1024 * 1024;
+/// Maximum number of elements in an array
+const MAX_ARRAY_LEN: i64 = 1024 * 1024;
+/// Maximum length of a simple string, error or integer line
+const MAX_LINE_LEN: usize = 64 * 1024;
+/// Maximum nesting of arrays
+const MAX_DEPTH: usize = 32;
+
+/// A RESP2 value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    /// `+OK\r\n`, CR and LF are encoded as spaces
+    Simple(String),
+    /// `-ERR message\r\n`, CR and LF are encoded as spaces
+    Error(String),
+    /// `:42\r\n`
+    Integer(i64),
+    /// `$5\r\nhello\r\n`
+    Bulk(Bytes),
+    /// The null bulk string `$-1\r\n`, also decoded from the null array `*-1\r\n`
+    Null,
+    /// `*2\r\n` followed by the elements
+    Array(Vec<Frame>),
+}
+
+/// Error decoding a RESP2 stream.
+#[derive(Debug)]
+pub enum ProtocolError {
+    /// IO error of the underlying stream
+    Io(io::Error),
+    /// The stream is not valid RESP2, so the connection cannot be used anymore
+    Invalid(String),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProtocolError::Io(e) => write!(f, "{}", e),
+            ProtocolError::Invalid(msg) => write!(f, "Protocol error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+impl From<io::Error> for ProtocolError {
+    fn from(err: io::Error) -> Self {
+        ProtocolError::Io(err)
+    }
+}
+
+impl Frame {
+    /// Creates a bulk string frame.
+    pub fn bulk(data: impl Into<Bytes>) -> Frame {
+        Frame::Bulk(data.into())
+    }
+
+    /// Parses a frame from the start of `buf`.
+    ///
+    /// Returns the frame and the number of bytes it takes, or `None` if `buf` ends
+    /// before the frame does.
+    pub fn parse(buf: &[u8]) -> Result<Option<(Frame, usize)>, ProtocolError> {
+        let mut parser = Parser { buf, pos: 0 };
+        match parser.frame(0) {
+            Ok(frame) => Ok(Some((frame, parser.pos))),
+            Err(ParseError::Incomplete) => Ok(None),
+            Err(ParseError::Invalid(msg)) => Err(ProtocolError::Invalid(msg)),
+        }
+    }
+
+    /// Appends the encoded frame to `dst`.
+    pub fn encode(&self, dst: &mut BytesMut) {
+        match self {
+            Frame::Simple(s) => encode_string(dst, b'+', s),
+            Frame::Error(s) => encode_string(dst, b'-', s),
+            Frame::Integer(n) => encode_line(dst, b':', n.to_string().as_bytes()),
+            Frame::Bulk(data) => {
+                encode_line(dst, b'$', data.len().to_string().as_bytes());
+                dst.extend_from_slice(data);
+                dst.extend_from_slice(b"\r\n");
+            }
+            Frame::Null => dst.extend_from_slice(b"$-1\r\n"),
+            Frame::Array(frames) => {
+                encode_line(dst, b'*', frames.len().to_string().as_bytes());
+                for frame in frames {
+                    frame.encode(dst);
+                }
+            }
+        }
+    }
+}
+
+/// Encodes a simple string or an error, which ends at the first CR or LF.
+///
+/// They often echo bytes sent by clients, so a CR or LF is replaced by a space
+/// instead of injecting the rest of the string as another frame.
+fn encode_string(dst: &mut BytesMut, prefix: u8, s: &str) {
+    dst.extend_from_slice(&[prefix]);
+    dst.extend(
+        s.bytes()
+            .map(|b| if b == b'\r' || b == b'\n' { b' ' } else { b }),
+    );
+    dst.extend_from_slice(b"\r\n");
+}
+
+fn encode_line(dst: &mut BytesMut, prefix: u8, line: &[u8]) {
+    dst.extend_from_slice(&[prefix]);
+    dst.extend_from_slice(line);
+    dst.extend_from_slice(b"\r\n");
+}
+
+enum ParseError {
+    Incomplete,
+    Invalid(String),
+}
+
+fn invalid<T>(msg: impl Into<String>) -> Result<T, ParseError> {
+    Err(ParseError::Invalid(msg.into()))
+}
+
+fn unexpected<T>(b: u8) -> Result<T, ParseError> {
+    invalid(format!("unexpected byte {:?}", b as char))
+}
+
+/// Progress of the search for the end of the frame at the start of a buffer.
+///
+/// `RespCodec` keeps it between calls to `decode`, so a frame arriving in many reads
+/// is scanned only once, and built only when all its bytes are there.
+#[derive(Debug, Default)]
+pub(crate) struct Scan {
+    // position of the first value not scanned yet
+    pos: usize,
+    // number of elements still expected by each array being scanned, outermost first
+    remaining: Vec<usize>,
+}
+
+impl Scan {
+    /// Returns the length of the frame at the start of `buf`, or `None` if `buf`
+    /// ends before the frame does.
+    ///
+    /// `buf` must start with the bytes seen by the previous calls, until one of them
+    /// returns the length or an error.
+    pub(crate) fn frame_len(&mut self, buf: &[u8]) -> Result<Option<usize>, ProtocolError> {
+        loop {
+            let mut parser = Parser { buf, pos: self.pos };
+            match parser.skip(self.remaining.len()) {
+                Ok(Some(len)) => {
+                    self.pos = parser.pos;
+                    self.remaining.push(len);
+                    continue;
+                }
+                Ok(None) => self.pos = parser.pos,
+                Err(ParseError::Incomplete) => return Ok(None),
+                Err(ParseError::Invalid(msg)) => {
+                    *self = Scan::default();
+                    return Err(ProtocolError::Invalid(msg));
+                }
+            }
+
+            // a complete value may complete the arrays containing it too
+            loop {
+                match self.remaining.last_mut() {
+                    None => {
+                        let len = self.pos;
+                        *self = Scan::default();
+                        return Ok(Some(len));
+                    }
+                    Some(n) if *n > 1 => {
+                        *n -= 1;
+                        break;
+                    }
+                    Some(_) => {
+                        self.remaining.pop();
+                    }
+                }
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn frame(&mut self, depth: usize) -> Result<Frame, ParseError> {
+        let prefix = *self.buf.get(self.pos).ok_or(ParseError::Incomplete)?;
+        self.pos += 1;
+        match prefix {
+            b'+' => Ok(Frame::Simple(self.string()?)),
+            b'-' => Ok(Frame::Error(self.string()?)),
+            b':' => Ok(Frame::Integer(self.integer()?)),
+            b'$' => match self.bulk()? {
+                Some(data) => Ok(Frame::Bulk(Bytes::copy_from_slice(data))),
+                None => Ok(Frame::Null),
+            },
+            b'*' => match self.array_len(depth)? {
+                Some(len) => {
+                    // the length is untrusted until the elements arrive
+                    let mut frames = Vec::with_capacity(len.min(1024));
+                    for _ in 0..len {
+                        frames.push(self.frame(depth + 1)?);
+                    }
+                    Ok(Frame::Array(frames))
+                }
+                None => Ok(Frame::Null),
+            },
+            b => unexpected(b),
+        }
+    }
+
+    /// Skips the value at the position, without building it.
+    ///
+    /// For an array, only its length line is skipped and the number of elements is
+    /// returned, unless there is none.
+    fn skip(&mut self, depth: usize) -> Result<Option<usize>, ParseError> {
+        let prefix = *self.buf.get(self.pos).ok_or(ParseError::Incomplete)?;
+        self.pos += 1;
+        match prefix {
+            b'+' | b'-' => {
+                self.line()?;
+            }
+            b':' => {
+                self.integer()?;
+            }
+            b'$' => {
+                self.bulk()?;
+            }
+            b'*' => return Ok(self.array_len(depth)?.filter(|&len| len > 0)),
+            b => return unexpected(b),
+        }
+        Ok(None)
+    }
+
+    /// Returns the data of a bulk string, or `None` for the null bulk string.
+    fn bulk(&mut self) -> Result<Option<&'a [u8]>, ParseError> {
+        match self.integer()? {
+            -1 => Ok(None),
+            len if !(0..=MAX_BULK_LEN).contains(&len) => {
+                invalid(format!("invalid bulk length {}", len))
+            }
+            len => {
+                let len = len as usize;
+                if self.buf.len() < self.pos + len + 2 {
+                    return Err(ParseError::Incomplete);
+                }
+                let data = &self.buf[self.pos..self.pos + len];
+                if &self.buf[self.pos + len..self.pos + len + 2] != b"\r\n" {
+                    return invalid("bulk string not terminated by CRLF");
+                }
+                self.pos += len + 2;
+                Ok(Some(data))
+            }
+        }
+    }
+
+    /// Returns the number of elements of an array, or `None` for the null array.
+    fn array_len(&mut self, depth: usize) -> Result<Option<usize>, ParseError> {
+        match self.integer()? {
+            -1 => Ok(None),
+            len if !(0..=MAX_ARRAY_LEN).contains(&len) => {
+                invalid(format!("invalid multibulk length {}", len))
+            }
+            _ if depth >= MAX_DEPTH => invalid("arrays nested too deeply"),
+            len => Ok(Some(len as usize)),
+        }
+    }
+
+    /// Returns the bytes until the next CRLF, skipping the CRLF.
+    fn line(&mut self) -> Result<&'a [u8], ParseError> {
+        let rest = &self.buf[self.pos..];
+        match rest.windows(2).position(|w| w == b"\r\n") {
+            Some(end) if end > MAX_LINE_LEN => invalid("line too long"),
+            Some(end) => {
+                self.pos += end + 2;
+                Ok(&rest[..end])
+            }
+            None if rest.len() > MAX_LINE_LEN => invalid("line too long"),
+            None => Err(ParseError::Incomplete),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        let line = self.line()?;
+        String::from_utf8(line.to_vec()).or_else(|_| invalid("invalid UTF-8 in simple string"))
+    }
+
+    fn integer(&mut self) -> Result<i64, ParseError> {
+        let line = self.line()?;
+        std::str::from_utf8(line)
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| ParseError::Invalid("invalid integer".to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(frame: Frame) {
+        let mut buf = BytesMut::new();
+        frame.encode(&mut buf);
+        assert_eq!(Frame::parse(&buf).unwrap(), Some((frame, buf.len())));
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        roundtrip(Frame::Simple("OK".to_owned()));
+        roundtrip(Frame::Error("ERR unknown command 'foo'".to_owned()));
+        roundtrip(Frame::Integer(-42));
+        roundtrip(Frame::bulk(&b"hello\r\nworld"[..]));
+        roundtrip(Frame::bulk(&b""[..]));
+        roundtrip(Frame::Null);
+        roundtrip(Frame::Array(vec![]));
+        roundtrip(Frame::Array(vec![
+            Frame::bulk(&b"SET"[..]),
+            Frame::Integer(1),
+            Frame::Array(vec![Frame::Null, Frame::Simple("PONG".to_owned())]),
+        ]));
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut buf = BytesMut::new();
+        Frame::Array(vec![Frame::bulk(&b"GET"[..]), Frame::bulk(&b"key"[..])]).encode(&mut buf);
+        assert_eq!(&buf[..], &b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n"[..]);
+    }
+
+    // A client cannot inject frames through a string echoed back to it
+    #[test]
+    fn test_encode_crlf() {
+        let mut buf = BytesMut::new();
+        Frame::Error("ERR unknown command 'a\r\n+OK'".to_owned()).encode(&mut buf);
+        Frame::Simple("a\nb".to_owned()).encode(&mut buf);
+        assert_eq!(&buf[..], &b"-ERR unknown command 'a  +OK'\r\n+a b\r\n"[..]);
+    }
+
+    // Every prefix of a frame is incomplete rather than invalid
+    #[test]
+    fn test_partial() {
+        let data = b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n:100\r\n";
+        for end in 0..data.len() {
+            assert_eq!(Frame::parse(&data[..end]).unwrap(), None);
+        }
+        let (frame, len) = Frame::parse(&data[..]).unwrap().unwrap();
+        assert_eq!(len, data.len());
+        assert_eq!(
+            frame,
+            Frame::Array(vec![
+                Frame::bulk(&b"SET"[..]),
+                Frame::bulk(&b"key"[..]),
+                Frame::Integer(100),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_null_array() {
+        assert_eq!(Frame::parse(b"*-1\r\n").unwrap(), Some((Frame::Null, 5)));
+    }
+
+    #[test]
+    fn test_invalid() {
+        for data in &[
+            &b"?\r\n"[..],
+            b":abc\r\n",
+            b"$-2\r\n",
+            b"$3\r\nabcde\r\n",
+            b"*-5\r\n",
+            b"$1000000000\r\n",
+        ] {
+            assert!(Frame::parse(data).is_err(), "{:?}", data);
+        }
+        let long_line = vec![b'+'; MAX_LINE_LEN + 2];
+        assert!(Frame::parse(&long_line).is_err());
+        let nested = b"*1\r\n".repeat(MAX_DEPTH + 1);
+        assert!(Frame::parse(&nested).is_err());
+    }
+}
@@ -1,33 +1,15 @@
-use serde::{Deserialize, Serialize};
-
-#[derive(Debug, Deserialize, Serialize)]
-pub enum Request {
-    Ping,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-pub enum Response {
-    Pong,
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use serde_json;
-
-    #[test]
-    fn test_request_ping() {
-        let req = Request::Ping;
-        let mut data = Vec::new();
-        serde_json::to_writer(&mut data, &req).unwrap();
-        eprintln!("{:?}", data);
-    }
-
-    #[test]
-    fn test_request_pong() {
-        let resp = Response::Pong;
-        let mut data = Vec::new();
-        serde_json::to_writer(&mut data, &resp).unwrap();
-        eprintln!("{:?}", data);
-    }
-}
+//! The Redis serialization protocol (RESP2) for mini-redis.
+//!
+//! `RespCodec` reads and writes `Frame`s over a tokio stream, `Request` converts
+//! the frames sent by clients to typed commands, and `Push` the messages pushed by the
+//! server to subscribed clients.
+
+pub use codec::RespCodec;
+pub use frame::{Frame, ProtocolError};
+pub use push::Push;
+pub use request::{CommandError, Request, SetCondition};
+
+mod codec;
+mod frame;
+mod push;
+mod request;
@@ -0,0 +1,217 @@
+use crate::frame::{Frame, ProtocolError};
+use bytes::Bytes;
+
+/// A message pushed by the server to a subscribed client.
+///
+/// Subscription changes are confirmed with the number of channels and patterns the
+/// client is subscribed to afterwards.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Push {
+    /// `["subscribe", channel, count]`
+    Subscribe {
+        /// Subscribed channel
+        channel: String,
+        /// Number of subscriptions
+        count: i64,
+    },
+    /// `["unsubscribe", channel, count]`
+    Unsubscribe {
+        /// Unsubscribed channel, `None` if the client was not subscribed to any
+        channel: Option<String>,
+        /// Number of subscriptions
+        count: i64,
+    },
+    /// `["psubscribe", pattern, count]`
+    Psubscribe {
+        /// Subscribed pattern
+        pattern: String,
+        /// Number of subscriptions
+        count: i64,
+    },
+    /// `["punsubscribe", pattern, count]`
+    Punsubscribe {
+        /// Unsubscribed pattern, `None` if the client was not subscribed to any
+        pattern: Option<String>,
+        /// Number of subscriptions
+        count: i64,
+    },
+    /// `["message", channel, message]`
+    Message {
+        /// Channel the message is published to
+        channel: String,
+        /// Published message
+        message: Bytes,
+    },
+    /// `["pmessage", pattern, channel, message]`
+    Pmessage {
+        /// Pattern matching the channel
+        pattern: String,
+        /// Channel the message is published to
+        channel: String,
+        /// Published message
+        message: Bytes,
+    },
+}
+
+impl Push {
+    /// Returns the message as an array frame.
+    pub fn into_frame(self) -> Frame {
+        let name_or_null = |name: Option<String>| name.map_or(Frame::Null, Frame::bulk);
+        let frames = match self {
+            Push::Subscribe { channel, count } => {
+                vec![
+                    Frame::bulk("subscribe"),
+                    Frame::bulk(channel),
+                    Frame::Integer(count),
+                ]
+            }
+            Push::Unsubscribe { channel, count } => vec![
+                Frame::bulk("unsubscribe"),
+                name_or_null(channel),
+                Frame::Integer(count),
+            ],
+            Push::Psubscribe { pattern, count } => {
+                vec![
+                    Frame::bulk("psubscribe"),
+                    Frame::bulk(pattern),
+                    Frame::Integer(count),
+                ]
+            }
+            Push::Punsubscribe { pattern, count } => vec![
+                Frame::bulk("punsubscribe"),
+                name_or_null(pattern),
+                Frame::Integer(count),
+            ],
+            Push::Message { channel, message } => {
+                vec![
+                    Frame::bulk("message"),
+                    Frame::bulk(channel),
+                    Frame::Bulk(message),
+                ]
+            }
+            Push::Pmessage {
+                pattern,
+                channel,
+                message,
+            } => vec![
+                Frame::bulk("pmessage"),
+                Frame::bulk(pattern),
+                Frame::bulk(channel),
+                Frame::Bulk(message),
+            ],
+        };
+        Frame::Array(frames)
+    }
+
+    /// Parses a message pushed by the server.
+    pub fn from_frame(frame: Frame) -> Result<Push, ProtocolError> {
+        let invalid = |frame: &Frame| ProtocolError::Invalid(format!("invalid push {:?}", frame));
+        let frames = match &frame {
+            Frame::Array(frames) => frames,
+            _ => return Err(invalid(&frame)),
+        };
+        let string = |frame: &Frame| match frame {
+            Frame::Bulk(data) => String::from_utf8(data.to_vec()).ok(),
+            _ => None,
+        };
+        let string_or_null = |frame: &Frame| match frame {
+            Frame::Null => Some(None),
+            frame => string(frame).map(Some),
+        };
+        let push = match frames.as_slice() {
+            [kind, name, Frame::Integer(count)] => {
+                let count = *count;
+                match string(kind).as_deref() {
+                    Some("subscribe") => {
+                        string(name).map(|channel| Push::Subscribe { channel, count })
+                    }
+                    Some("unsubscribe") => {
+                        string_or_null(name).map(|channel| Push::Unsubscribe { channel, count })
+                    }
+                    Some("psubscribe") => {
+                        string(name).map(|pattern| Push::Psubscribe { pattern, count })
+                    }
+                    Some("punsubscribe") => {
+                        string_or_null(name).map(|pattern| Push::Punsubscribe { pattern, count })
+                    }
+                    _ => None,
+                }
+            }
+            [kind, channel, Frame::Bulk(message)] if string(kind).as_deref() == Some("message") => {
+                string(channel).map(|channel| Push::Message {
+                    channel,
+                    message: message.clone(),
+                })
+            }
+            [kind, pattern, channel, Frame::Bulk(message)]
+                if string(kind).as_deref() == Some("pmessage") =>
+            {
+                match (string(pattern), string(channel)) {
+                    (Some(pattern), Some(channel)) => Some(Push::Pmessage {
+                        pattern,
+                        channel,
+                        message: message.clone(),
+                    }),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        push.ok_or_else(|| invalid(&frame))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let pushes = vec![
+            Push::Subscribe {
+                channel: "news".to_owned(),
+                count: 1,
+            },
+            Push::Unsubscribe {
+                channel: None,
+                count: 0,
+            },
+            Push::Psubscribe {
+                pattern: "news.*".to_owned(),
+                count: 2,
+            },
+            Push::Punsubscribe {
+                pattern: Some("news.*".to_owned()),
+                count: 1,
+            },
+            Push::Message {
+                channel: "news".to_owned(),
+                message: Bytes::from("hello"),
+            },
+            Push::Pmessage {
+                pattern: "news.*".to_owned(),
+                channel: "news.tech".to_owned(),
+                message: Bytes::from("hello"),
+            },
+        ];
+        for push in pushes {
+            assert_eq!(Push::from_frame(push.clone().into_frame()).unwrap(), push);
+        }
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Push::from_frame(Frame::Simple("OK".to_owned())).is_err());
+        assert!(Push::from_frame(Frame::Array(vec![
+            Frame::bulk("subscribe"),
+            Frame::Null,
+            Frame::Integer(1),
+        ]))
+        .is_err());
+        assert!(Push::from_frame(Frame::Array(vec![
+            Frame::bulk("message"),
+            Frame::bulk("news"),
+        ]))
+        .is_err());
+    }
+}
@@ -0,0 +1,612 @@
+use crate::frame::Frame;
+use bytes::Bytes;
+use std::fmt;
+use std::time::Duration;
+
+/// A command sent by a client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    /// `PING [message]`
+    Ping(Option<Bytes>),
+    /// `ECHO message`
+    Echo(Bytes),
+    /// `GET key`
+    Get {
+        /// Key to read
+        key: String,
+    },
+    /// `SET key value [EX seconds|PX milliseconds] [NX|XX]`
+    Set {
+        /// Key to write
+        key: String,
+        /// New value
+        value: Bytes,
+        /// Time to live of the key, from `EX` or `PX`
+        expire: Option<Duration>,
+        /// Whether the key must exist or not, from `NX` or `XX`
+        condition: Option<SetCondition>,
+    },
+    /// `DEL key [key ...]`
+    Del {
+        /// Keys to remove
+        keys: Vec<String>,
+    },
+    /// `EXISTS key [key ...]`
+    Exists {
+        /// Keys to look up
+        keys: Vec<String>,
+    },
+    /// `INCR key`
+    Incr {
+        /// Key holding an integer
+        key: String,
+    },
+    /// `EXPIRE key seconds`
+    Expire {
+        /// Key to expire
+        key: String,
+        /// Time to live of the key, a key with a non positive time to live is removed
+        seconds: i64,
+    },
+    /// `TTL key`
+    Ttl {
+        /// Key to get the time to live of
+        key: String,
+    },
+    /// `PERSIST key`
+    Persist {
+        /// Key to remove the time to live of
+        key: String,
+    },
+    /// `BGSAVE`: saves a snapshot of the keys in the background
+    Bgsave,
+    /// `BGREWRITEAOF`: rewrites the append only file in the background
+    Bgrewriteaof,
+    /// `MULTI`: starts queueing the commands of a transaction
+    Multi,
+    /// `EXEC`: runs the queued commands at once
+    Exec,
+    /// `DISCARD`: drops the queued commands
+    Discard,
+    /// `WATCH key [key ...]`
+    Watch {
+        /// Keys which abort the next `EXEC` if they change before it
+        keys: Vec<String>,
+    },
+    /// `UNWATCH`: forgets the watched keys
+    Unwatch,
+    /// `SUBSCRIBE channel [channel ...]`
+    Subscribe {
+        /// Channels to receive the messages of
+        channels: Vec<String>,
+    },
+    /// `UNSUBSCRIBE [channel ...]`
+    Unsubscribe {
+        /// Channels to stop receiving the messages of, all of them if empty
+        channels: Vec<String>,
+    },
+    /// `PSUBSCRIBE pattern [pattern ...]`
+    Psubscribe {
+        /// Glob patterns of the channels to receive the messages of
+        patterns: Vec<String>,
+    },
+    /// `PUNSUBSCRIBE [pattern ...]`
+    Punsubscribe {
+        /// Patterns to stop receiving the messages of, all of them if empty
+        patterns: Vec<String>,
+    },
+    /// `PUBLISH channel message`
+    Publish {
+        /// Channel to send the message to
+        channel: String,
+        /// Message sent to the subscribers
+        message: Bytes,
+    },
+}
+
+/// Condition of a `SET` on the existing key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetCondition {
+    /// `NX`: only set the key if it does not exist
+    NotExists,
+    /// `XX`: only set the key if it exists
+    Exists,
+}
+
+/// A command which cannot be parsed, replied to the client as an error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandError(String);
+
+impl CommandError {
+    fn new(msg: impl Into<String>) -> Self {
+        CommandError(msg.into())
+    }
+
+    fn wrong_args(name: &str) -> Self {
+        CommandError(format!(
+            "ERR wrong number of arguments for '{}' command",
+            name
+        ))
+    }
+
+    /// Returns the error reply.
+    pub fn into_frame(self) -> Frame {
+        Frame::Error(self.0)
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+impl Request {
+    /// Parses a command from an array of bulk strings.
+    ///
+    /// Command names and options are case insensitive.
+    pub fn from_frame(frame: Frame) -> Result<Request, CommandError> {
+        let args = match frame {
+            Frame::Array(frames) if !frames.is_empty() => frames
+                .into_iter()
+                .map(|frame| match frame {
+                    Frame::Bulk(data) => Ok(data),
+                    _ => Err(CommandError::new(
+                        "ERR Protocol error: expected an array of bulk strings",
+                    )),
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            _ => {
+                return Err(CommandError::new(
+                    "ERR Protocol error: expected an array of bulk strings",
+                ))
+            }
+        };
+        let name = String::from_utf8_lossy(&args[0]).to_lowercase();
+        let mut args = Args {
+            name: &name,
+            args: args.into_iter().skip(1),
+        };
+        let req = match name.as_str() {
+            "ping" => Request::Ping(args.next_opt()),
+            "echo" => Request::Echo(args.next()?),
+            "get" => Request::Get {
+                key: args.next_key()?,
+            },
+            "set" => {
+                let key = args.next_key()?;
+                let value = args.next()?;
+                let (mut expire, mut condition) = (None, None);
+                while let Some(option) = args.next_opt() {
+                    match String::from_utf8_lossy(&option).to_lowercase().as_str() {
+                        "ex" if expire.is_none() => {
+                            expire = Some(Duration::from_secs(args.next_expire()?))
+                        }
+                        "px" if expire.is_none() => {
+                            expire = Some(Duration::from_millis(args.next_expire()?))
+                        }
+                        "nx" if condition.is_none() => condition = Some(SetCondition::NotExists),
+                        "xx" if condition.is_none() => condition = Some(SetCondition::Exists),
+                        _ => return Err(CommandError::new("ERR syntax error")),
+                    }
+                }
+                return Ok(Request::Set {
+                    key,
+                    value,
+                    expire,
+                    condition,
+                });
+            }
+            "del" => Request::Del { keys: args.keys()? },
+            "exists" => Request::Exists { keys: args.keys()? },
+            "incr" => Request::Incr {
+                key: args.next_key()?,
+            },
+            "expire" => Request::Expire {
+                key: args.next_key()?,
+                seconds: args.next_integer()?,
+            },
+            "ttl" => Request::Ttl {
+                key: args.next_key()?,
+            },
+            "persist" => Request::Persist {
+                key: args.next_key()?,
+            },
+            "bgsave" => Request::Bgsave,
+            "bgrewriteaof" => Request::Bgrewriteaof,
+            "multi" => Request::Multi,
+            "exec" => Request::Exec,
+            "discard" => Request::Discard,
+            "watch" => Request::Watch { keys: args.keys()? },
+            "unwatch" => Request::Unwatch,
+            "subscribe" => Request::Subscribe {
+                channels: args.keys()?,
+            },
+            "unsubscribe" => Request::Unsubscribe {
+                channels: args.rest()?,
+            },
+            "psubscribe" => Request::Psubscribe {
+                patterns: args.keys()?,
+            },
+            "punsubscribe" => Request::Punsubscribe {
+                patterns: args.rest()?,
+            },
+            "publish" => Request::Publish {
+                channel: args.next_key()?,
+                message: args.next()?,
+            },
+            _ => return Err(CommandError(format!("ERR unknown command '{}'", name))),
+        };
+        args.finish()?;
+        Ok(req)
+    }
+
+    /// Returns the name of the command, in lowercase.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Request::Ping(_) => "ping",
+            Request::Echo(_) => "echo",
+            Request::Get { .. } => "get",
+            Request::Set { .. } => "set",
+            Request::Del { .. } => "del",
+            Request::Exists { .. } => "exists",
+            Request::Incr { .. } => "incr",
+            Request::Expire { .. } => "expire",
+            Request::Ttl { .. } => "ttl",
+            Request::Persist { .. } => "persist",
+            Request::Bgsave => "bgsave",
+            Request::Bgrewriteaof => "bgrewriteaof",
+            Request::Multi => "multi",
+            Request::Exec => "exec",
+            Request::Discard => "discard",
+            Request::Watch { .. } => "watch",
+            Request::Unwatch => "unwatch",
+            Request::Subscribe { .. } => "subscribe",
+            Request::Unsubscribe { .. } => "unsubscribe",
+            Request::Psubscribe { .. } => "psubscribe",
+            Request::Punsubscribe { .. } => "punsubscribe",
+            Request::Publish { .. } => "publish",
+        }
+    }
+
+    /// Returns the command as an array of bulk strings.
+    pub fn into_frame(self) -> Frame {
+        let mut args = Vec::new();
+        match self {
+            Request::Ping(msg) => {
+                args.push(Bytes::from("PING"));
+                args.extend(msg);
+            }
+            Request::Echo(msg) => {
+                args.push(Bytes::from("ECHO"));
+                args.push(msg);
+            }
+            Request::Get { key } => {
+                args.push(Bytes::from("GET"));
+                args.push(Bytes::from(key));
+            }
+            Request::Set {
+                key,
+                value,
+                expire,
+                condition,
+            } => {
+                args.push(Bytes::from("SET"));
+                args.push(Bytes::from(key));
+                args.push(value);
+                match expire {
+                    Some(expire) if expire.subsec_nanos() == 0 => {
+                        args.push(Bytes::from("EX"));
+                        args.push(Bytes::from(expire.as_secs().to_string()));
+                    }
+                    Some(expire) => {
+                        args.push(Bytes::from("PX"));
+                        args.push(Bytes::from(expire.as_millis().to_string()));
+                    }
+                    None => {}
+                }
+                match condition {
+                    Some(SetCondition::NotExists) => args.push(Bytes::from("NX")),
+                    Some(SetCondition::Exists) => args.push(Bytes::from("XX")),
+                    None => {}
+                }
+            }
+            Request::Del { keys } => {
+                args.push(Bytes::from("DEL"));
+                args.extend(keys.into_iter().map(Bytes::from));
+            }
+            Request::Exists { keys } => {
+                args.push(Bytes::from("EXISTS"));
+                args.extend(keys.into_iter().map(Bytes::from));
+            }
+            Request::Incr { key } => {
+                args.push(Bytes::from("INCR"));
+                args.push(Bytes::from(key));
+            }
+            Request::Expire { key, seconds } => {
+                args.push(Bytes::from("EXPIRE"));
+                args.push(Bytes::from(key));
+                args.push(Bytes::from(seconds.to_string()));
+            }
+            Request::Ttl { key } => {
+                args.push(Bytes::from("TTL"));
+                args.push(Bytes::from(key));
+            }
+            Request::Persist { key } => {
+                args.push(Bytes::from("PERSIST"));
+                args.push(Bytes::from(key));
+            }
+            Request::Bgsave => args.push(Bytes::from("BGSAVE")),
+            Request::Bgrewriteaof => args.push(Bytes::from("BGREWRITEAOF")),
+            Request::Multi => args.push(Bytes::from("MULTI")),
+            Request::Exec => args.push(Bytes::from("EXEC")),
+            Request::Discard => args.push(Bytes::from("DISCARD")),
+            Request::Watch { keys } => {
+                args.push(Bytes::from("WATCH"));
+                args.extend(keys.into_iter().map(Bytes::from));
+            }
+            Request::Unwatch => args.push(Bytes::from("UNWATCH")),
+            Request::Subscribe { channels } => {
+                args.push(Bytes::from("SUBSCRIBE"));
+                args.extend(channels.into_iter().map(Bytes::from));
+            }
+            Request::Unsubscribe { channels } => {
+                args.push(Bytes::from("UNSUBSCRIBE"));
+                args.extend(channels.into_iter().map(Bytes::from));
+            }
+            Request::Psubscribe { patterns } => {
+                args.push(Bytes::from("PSUBSCRIBE"));
+                args.extend(patterns.into_iter().map(Bytes::from));
+            }
+            Request::Punsubscribe { patterns } => {
+                args.push(Bytes::from("PUNSUBSCRIBE"));
+                args.extend(patterns.into_iter().map(Bytes::from));
+            }
+            Request::Publish { channel, message } => {
+                args.push(Bytes::from("PUBLISH"));
+                args.push(Bytes::from(channel));
+                args.push(message);
+            }
+        }
+        Frame::Array(args.into_iter().map(Frame::Bulk).collect())
+    }
+}
+
+/// The arguments of a command after its name.
+struct Args<'a, I> {
+    name: &'a str,
+    args: I,
+}
+
+impl<'a, I: Iterator<Item = Bytes>> Args<'a, I> {
+    fn next_opt(&mut self) -> Option<Bytes> {
+        self.args.next()
+    }
+
+    fn next(&mut self) -> Result<Bytes, CommandError> {
+        self.args
+            .next()
+            .ok_or_else(|| CommandError::wrong_args(self.name))
+    }
+
+    fn next_key(&mut self) -> Result<String, CommandError> {
+        key(self.next()?)
+    }
+
+    fn next_integer(&mut self) -> Result<i64, CommandError> {
+        integer(self.next()?)
+    }
+
+    /// Parses a positive number of seconds or milliseconds.
+    fn next_expire(&mut self) -> Result<u64, CommandError> {
+        let arg = self
+            .args
+            .next()
+            .ok_or_else(|| CommandError::new("ERR syntax error"))?;
+        let n = integer(arg)?;
+        if n <= 0 {
+            return Err(CommandError(format!(
+                "ERR invalid expire time in '{}' command",
+                self.name
+            )));
+        }
+        Ok(n as u64)
+    }
+
+    /// Returns the remaining arguments as keys.
+    fn rest(&mut self) -> Result<Vec<String>, CommandError> {
+        self.args.by_ref().map(key).collect()
+    }
+
+    /// Returns the remaining arguments as at least one key.
+    fn keys(&mut self) -> Result<Vec<String>, CommandError> {
+        let keys = self.rest()?;
+        if keys.is_empty() {
+            return Err(CommandError::wrong_args(self.name));
+        }
+        Ok(keys)
+    }
+
+    fn finish(&mut self) -> Result<(), CommandError> {
+        match self.args.next() {
+            Some(_) => Err(CommandError::wrong_args(self.name)),
+            None => Ok(()),
+        }
+    }
+}
+
+fn key(arg: Bytes) -> Result<String, CommandError> {
+    String::from_utf8(arg.to_vec()).map_err(|_| CommandError::new("ERR keys must be valid UTF-8"))
+}
+
+fn integer(arg: Bytes) -> Result<i64, CommandError> {
+    std::str::from_utf8(&arg)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| CommandError::new("ERR value is not an integer or out of range"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RespCodec;
+    use bytes::BytesMut;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    fn command(args: &[&'static str]) -> Frame {
+        Frame::Array(args.iter().map(|&arg| Frame::bulk(arg)).collect())
+    }
+
+    // Requests survive encoding and decoding through the codec
+    fn roundtrip(req: Request) {
+        let mut buf = BytesMut::new();
+        let mut codec = RespCodec::default();
+        codec.encode(req.clone().into_frame(), &mut buf).unwrap();
+        let frame = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(Request::from_frame(frame).unwrap(), req);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        roundtrip(Request::Ping(None));
+        roundtrip(Request::Ping(Some(Bytes::from("hello"))));
+        roundtrip(Request::Echo(Bytes::from("hello world")));
+        roundtrip(Request::Get {
+            key: "key".to_owned(),
+        });
+        roundtrip(Request::Set {
+            key: "key".to_owned(),
+            value: Bytes::from(&b"\x00binary\r\n"[..]),
+            expire: None,
+            condition: None,
+        });
+        roundtrip(Request::Set {
+            key: "key".to_owned(),
+            value: Bytes::from("value"),
+            expire: Some(Duration::from_secs(10)),
+            condition: Some(SetCondition::NotExists),
+        });
+        roundtrip(Request::Set {
+            key: "key".to_owned(),
+            value: Bytes::from("value"),
+            expire: Some(Duration::from_millis(1500)),
+            condition: Some(SetCondition::Exists),
+        });
+        roundtrip(Request::Del {
+            keys: vec!["a".to_owned(), "b".to_owned()],
+        });
+        roundtrip(Request::Exists {
+            keys: vec!["a".to_owned()],
+        });
+        roundtrip(Request::Incr {
+            key: "counter".to_owned(),
+        });
+        roundtrip(Request::Expire {
+            key: "key".to_owned(),
+            seconds: -1,
+        });
+        roundtrip(Request::Ttl {
+            key: "key".to_owned(),
+        });
+        roundtrip(Request::Persist {
+            key: "key".to_owned(),
+        });
+        roundtrip(Request::Bgsave);
+        roundtrip(Request::Bgrewriteaof);
+        roundtrip(Request::Multi);
+        roundtrip(Request::Exec);
+        roundtrip(Request::Discard);
+        roundtrip(Request::Watch {
+            keys: vec!["a".to_owned(), "b".to_owned()],
+        });
+        roundtrip(Request::Unwatch);
+        roundtrip(Request::Subscribe {
+            channels: vec!["news".to_owned(), "weather".to_owned()],
+        });
+        roundtrip(Request::Unsubscribe { channels: vec![] });
+        roundtrip(Request::Psubscribe {
+            patterns: vec!["news.*".to_owned()],
+        });
+        roundtrip(Request::Punsubscribe {
+            patterns: vec!["news.*".to_owned()],
+        });
+        roundtrip(Request::Publish {
+            channel: "news".to_owned(),
+            message: Bytes::from("hello"),
+        });
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        assert_eq!(
+            Request::from_frame(command(&["set", "key", "value", "px", "100", "nX"])).unwrap(),
+            Request::Set {
+                key: "key".to_owned(),
+                value: Bytes::from("value"),
+                expire: Some(Duration::from_millis(100)),
+                condition: Some(SetCondition::NotExists),
+            }
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let error =
+            |args: &[&'static str]| Request::from_frame(command(args)).unwrap_err().to_string();
+        assert_eq!(error(&["FOO"]), "ERR unknown command 'foo'");
+        assert_eq!(
+            error(&["GET"]),
+            "ERR wrong number of arguments for 'get' command"
+        );
+        assert_eq!(
+            error(&["GET", "a", "b"]),
+            "ERR wrong number of arguments for 'get' command"
+        );
+        assert_eq!(
+            error(&["DEL"]),
+            "ERR wrong number of arguments for 'del' command"
+        );
+        assert_eq!(
+            error(&["WATCH"]),
+            "ERR wrong number of arguments for 'watch' command"
+        );
+        assert_eq!(
+            error(&["BGSAVE", "SCHEDULE"]),
+            "ERR wrong number of arguments for 'bgsave' command"
+        );
+        assert_eq!(error(&["SET", "k", "v", "EX"]), "ERR syntax error");
+        assert_eq!(error(&["SET", "k", "v", "NX", "XX"]), "ERR syntax error");
+        assert_eq!(
+            error(&["SET", "k", "v", "EX", "1", "PX", "1"]),
+            "ERR syntax error"
+        );
+        assert_eq!(
+            error(&["SET", "k", "v", "EX", "ten"]),
+            "ERR value is not an integer or out of range"
+        );
+        assert_eq!(
+            error(&["SET", "k", "v", "PX", "0"]),
+            "ERR invalid expire time in 'set' command"
+        );
+        assert_eq!(
+            error(&["EXPIRE", "k"]),
+            "ERR wrong number of arguments for 'expire' command"
+        );
+        assert_eq!(
+            error(&["EXPIRE", "k", "soon"]),
+            "ERR value is not an integer or out of range"
+        );
+        assert_eq!(
+            error(&["SUBSCRIBE"]),
+            "ERR wrong number of arguments for 'subscribe' command"
+        );
+        assert_eq!(
+            error(&["PUBLISH", "news"]),
+            "ERR wrong number of arguments for 'publish' command"
+        );
+        assert!(Request::from_frame(Frame::Array(vec![])).is_err());
+        assert!(Request::from_frame(Frame::Array(vec![Frame::Integer(1)])).is_err());
+    }
+}
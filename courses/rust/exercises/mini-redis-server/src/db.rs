@@ -0,0 +1,1044 @@
+use crate::persist::{self, Aof, AppendFsync, Change, Persistence};
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use kvs::{KvStore, KvsEngine, KvsError};
+use log::{error, info};
+use mini_redis_proto::SetCondition;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+use tokio::task;
+use tokio::time::{self, Instant};
+
+/// Delay before the background task retries after failing to remove expired keys
+const PURGE_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// Interval between syncs of the append only file with `appendfsync everysec`
+const AOF_SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The keys of the server, shared by all the connections.
+///
+/// Keys are kept in memory, optionally persisted to a snapshot and an append only
+/// file, or in a `KvStore` so they survive restarts. Expired keys are removed when
+/// they are read, and by a background task going through the keys in expiration
+/// order, so keys which are never read again do not pile up.
+///
+/// The `KvStore` cannot list its keys, so the background task only knows about the
+/// keys persisted with a time to live by a previous run once they are read, and the
+/// keys cannot be saved to a snapshot.
+///
+/// The database must be created within a tokio runtime, which runs the background
+/// task until the last handle is dropped. The reads and writes of a `KvStore` block on
+/// the disk, so they run in `task::block_in_place` and the runtime must be threaded.
+#[derive(Clone)]
+pub struct Db {
+    shared: Arc<Shared>,
+    // shuts the background task down when the last handle is dropped
+    _shutdown: Arc<Shutdown>,
+}
+
+struct Shutdown(Arc<Shared>);
+
+struct Shared {
+    state: Mutex<State>,
+    // wakes up the background task when a key expires before the others, or on drop
+    background_task: Notify,
+    // the keys are in a `KvStore`, so locking them can block on the disk
+    on_disk: bool,
+}
+
+struct State {
+    storage: Storage,
+    // the keys with a time to live, in expiration order
+    expirations: BTreeSet<(Instant, String)>,
+    // the expiration of the keys in `expirations`
+    deadlines: HashMap<String, Instant>,
+    // the background task needs to be woken up
+    notify: bool,
+    shutdown: bool,
+    aof: Option<Aof>,
+    // where `BGSAVE` saves the keys
+    snapshot: Option<PathBuf>,
+    // a snapshot is being saved
+    saving: bool,
+    // the keys watched by connections
+    watched: HashMap<String, Watched>,
+}
+
+/// A key watched by connections
+#[derive(Default)]
+struct Watched {
+    // incremented on every change
+    version: u64,
+    watchers: usize,
+}
+
+impl Db {
+    /// Creates an empty database kept in memory.
+    pub fn new() -> Db {
+        Db::with_storage(Storage::Memory(HashMap::new()))
+    }
+
+    /// Opens a database persisted in the `KvStore` at `path`.
+    pub fn open(path: &Path) -> Result<Db> {
+        Ok(Db::with_storage(Storage::Kvs(KvStore::open(path)?)))
+    }
+
+    /// Loads a database kept in memory, persisted as set by `persistence`.
+    ///
+    /// The keys of the snapshot are loaded first, then the changes logged in the
+    /// append only file are replayed on top of them.
+    pub fn load(persistence: &Persistence) -> Result<Db> {
+        let mut map = HashMap::new();
+        if let Some(path) = &persistence.snapshot {
+            map.extend(persist::load_snapshot(path)?);
+        }
+        let aof = match &persistence.aof {
+            Some(path) => {
+                for change in persist::read_aof(path)? {
+                    match change {
+                        Change::Set(key, entry) => {
+                            map.insert(key, entry);
+                        }
+                        Change::Del(key) => {
+                            map.remove(&key);
+                        }
+                        Change::FlushAll => map.clear(),
+                    }
+                }
+                Some(Aof::open(path, persistence.appendfsync)?)
+            }
+            None => None,
+        };
+        let now = Instant::now();
+        map.retain(|_, entry| !entry.is_expired(now));
+
+        let mut state = State::new(Storage::Memory(HashMap::new()));
+        for (key, entry) in &map {
+            state.index(key, entry.expires_at);
+        }
+        state.storage = Storage::Memory(map);
+        state.aof = aof;
+        state.snapshot = persistence.snapshot.clone();
+        Ok(Db::from_state(state))
+    }
+
+    fn with_storage(storage: Storage) -> Db {
+        Db::from_state(State::new(storage))
+    }
+
+    fn from_state(state: State) -> Db {
+        let on_disk = match state.storage {
+            Storage::Memory(_) => false,
+            Storage::Kvs(_) => true,
+        };
+        let shared = Arc::new(Shared {
+            state: Mutex::new(state),
+            background_task: Notify::new(),
+            on_disk,
+        });
+        tokio::spawn(run_background_task(shared.clone()));
+        Db {
+            _shutdown: Arc::new(Shutdown(shared.clone())),
+            shared,
+        }
+    }
+
+    /// Locks the keys, so no other connection reads or changes them until the returned
+    /// `Keys` is dropped.
+    pub fn lock(&self) -> Keys<'_> {
+        Keys {
+            shared: &self.shared,
+            state: self.shared.state.lock().unwrap(),
+        }
+    }
+
+    /// Runs `f`, which locks the keys, without holding up the other tasks of the
+    /// runtime if the keys are in a `KvStore`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the keys are in a `KvStore` and the runtime is not threaded.
+    pub fn blocking<T>(&self, f: impl FnOnce() -> T) -> T {
+        self.shared.blocking(f)
+    }
+
+    /// Returns an empty set of watched keys.
+    pub fn watch(&self) -> Watch {
+        Watch {
+            db: self.clone(),
+            versions: HashMap::new(),
+        }
+    }
+}
+
+/// The locked keys of a `Db`, on which the commands run.
+pub struct Keys<'a> {
+    shared: &'a Arc<Shared>,
+    state: MutexGuard<'a, State>,
+}
+
+impl Keys<'_> {
+    /// Returns the value of a key.
+    pub fn get(&mut self, key: &str) -> Result<Option<Bytes>> {
+        Ok(self.state.live(key)?.map(|entry| entry.value))
+    }
+
+    /// Sets the value of a key, replacing its time to live.
+    ///
+    /// Returns `false` if the key is not set because of `condition`.
+    pub fn set(
+        &mut self,
+        key: String,
+        value: Bytes,
+        expire: Option<Duration>,
+        condition: Option<SetCondition>,
+    ) -> Result<bool> {
+        let expires_at = match expire {
+            Some(expire) => Some(expires_at(expire, "set")?),
+            None => None,
+        };
+        if let Some(condition) = condition {
+            let exists = self.state.live(&key)?.is_some();
+            if exists != (condition == SetCondition::Exists) {
+                return Ok(false);
+            }
+        }
+        self.state.insert(key, Entry { value, expires_at })?;
+        Ok(true)
+    }
+
+    /// Removes keys, returning the number of keys removed.
+    pub fn del(&mut self, keys: &[String]) -> Result<i64> {
+        let mut removed = 0;
+        for key in keys {
+            if self.state.live(key)?.is_some() {
+                self.state.remove(key)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Returns the number of existing keys, counting repeated keys every time.
+    pub fn exists(&mut self, keys: &[String]) -> Result<i64> {
+        let mut found = 0;
+        for key in keys {
+            if self.state.live(key)?.is_some() {
+                found += 1;
+            }
+        }
+        Ok(found)
+    }
+
+    /// Increments the integer value of a key, a missing key counting as 0.
+    ///
+    /// The time to live of the key is kept.
+    pub fn incr(&mut self, key: String) -> Result<i64> {
+        let entry = self.state.live(&key)?;
+        let n = match &entry {
+            Some(entry) => std::str::from_utf8(&entry.value)
+                .ok()
+                .and_then(|s| s.parse::<i64>().ok())
+                .ok_or_else(|| anyhow!("value is not an integer or out of range"))?,
+            None => 0,
+        };
+        let n = n
+            .checked_add(1)
+            .ok_or_else(|| anyhow!("increment or decrement would overflow"))?;
+        let entry = Entry {
+            value: Bytes::from(n.to_string()),
+            expires_at: entry.and_then(|entry| entry.expires_at),
+        };
+        self.state.insert(key, entry)?;
+        Ok(n)
+    }
+
+    /// Sets the time to live of a key, removing it right away if `seconds` is not
+    /// positive.
+    ///
+    /// Returns `false` if the key does not exist.
+    pub fn expire(&mut self, key: String, seconds: i64) -> Result<bool> {
+        let expires_at = if seconds > 0 {
+            Some(expires_at(Duration::from_secs(seconds as u64), "expire")?)
+        } else {
+            None
+        };
+        let entry = match self.state.live(&key)? {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+        match expires_at {
+            Some(expires_at) => {
+                let expires_at = Some(expires_at);
+                self.state.insert(
+                    key,
+                    Entry {
+                        expires_at,
+                        ..entry
+                    },
+                )?
+            }
+            None => self.state.remove(&key)?,
+        }
+        Ok(true)
+    }
+
+    /// Returns the time to live of a key in seconds, -1 if the key does not expire, or
+    /// -2 if it does not exist.
+    pub fn ttl(&mut self, key: &str) -> Result<i64> {
+        let ttl = match self.state.live(key)? {
+            Some(Entry {
+                expires_at: Some(expires_at),
+                ..
+            }) => {
+                let ttl = expires_at.saturating_duration_since(Instant::now());
+                // rounded to the closest second, like Redis
+                ((ttl.as_millis() + 500) / 1000) as i64
+            }
+            Some(_) => -1,
+            None => -2,
+        };
+        Ok(ttl)
+    }
+
+    /// Removes the time to live of a key.
+    ///
+    /// Returns `false` if the key does not exist or has no time to live.
+    pub fn persist(&mut self, key: String) -> Result<bool> {
+        match self.state.live(&key)? {
+            Some(entry) if entry.expires_at.is_some() => {
+                let expires_at = None;
+                self.state.insert(
+                    key,
+                    Entry {
+                        expires_at,
+                        ..entry
+                    },
+                )?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Saves a snapshot of the keys in the background.
+    ///
+    /// The keys are copied right away, and written to the snapshot file by a blocking
+    /// task.
+    pub fn bgsave(&mut self) -> Result<()> {
+        let state = &mut *self.state;
+        let path = state
+            .snapshot
+            .clone()
+            .ok_or_else(|| anyhow!("no snapshot file configured"))?;
+        if state.saving {
+            return Err(anyhow!("Background save already in progress"));
+        }
+        let entries = state.storage.entries()?;
+        state.saving = true;
+        let shared = self.shared.clone();
+        task::spawn_blocking(move || {
+            match persist::save_snapshot(&path, &entries) {
+                Ok(()) => info!("Saved {} keys to {}", entries.len(), path.display()),
+                Err(err) => error!("Failed to save {}: {}", path.display(), err),
+            }
+            shared.state.lock().unwrap().saving = false;
+        });
+        Ok(())
+    }
+
+    /// Rewrites the append only file in the background, so it only sets the current
+    /// keys.
+    ///
+    /// The keys are copied right away and written to a new file by a blocking task,
+    /// which replaces the current file once the changes made in the meantime are
+    /// appended to it.
+    pub fn bgrewriteaof(&mut self) -> Result<()> {
+        let entries = self.state.storage.entries()?;
+        let path = self
+            .state
+            .aof
+            .as_mut()
+            .ok_or_else(|| anyhow!("the append only file is disabled"))?
+            .start_rewrite()?;
+        let shared = self.shared.clone();
+        task::spawn_blocking(move || {
+            let written = persist::write_aof(&path, &entries);
+            let mut state = shared.state.lock().unwrap();
+            if let Some(aof) = &mut state.aof {
+                match aof.finish_rewrite(written) {
+                    Ok(()) => info!("Rewrote the append only file with {} keys", entries.len()),
+                    Err(err) => error!("Failed to rewrite the append only file: {}", err),
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+impl Drop for Keys<'_> {
+    fn drop(&mut self) {
+        if std::mem::replace(&mut self.state.notify, false) {
+            self.shared.background_task.notify();
+        }
+    }
+}
+
+/// The keys watched by a connection, with their version when they were watched.
+///
+/// A transaction is aborted if one of them changed since. Versions are only counted
+/// while a key is watched, and the keys are unwatched on drop.
+pub struct Watch {
+    db: Db,
+    versions: HashMap<String, u64>,
+}
+
+impl Watch {
+    /// Watches more keys, keeping the version of the keys already watched.
+    pub fn add(&mut self, keys: Vec<String>) {
+        let mut state = self.db.shared.state.lock().unwrap();
+        for key in keys {
+            if self.versions.contains_key(&key) {
+                continue;
+            }
+            let watched = state.watched.entry(key.clone()).or_default();
+            watched.watchers += 1;
+            self.versions.insert(key, watched.version);
+        }
+    }
+
+    /// Returns whether a watched key changed since it was watched.
+    pub fn is_changed(&self, keys: &Keys) -> bool {
+        self.versions
+            .iter()
+            .any(|(key, version)| keys.state.watched[key].version != *version)
+    }
+
+    /// Unwatches all the keys.
+    pub fn clear(&mut self) {
+        if self.versions.is_empty() {
+            return;
+        }
+        let mut state = self.db.shared.state.lock().unwrap();
+        for (key, _) in self.versions.drain() {
+            let watched = state.watched.get_mut(&key).unwrap();
+            watched.watchers -= 1;
+            if watched.watchers == 0 {
+                state.watched.remove(&key);
+            }
+        }
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl Default for Db {
+    fn default() -> Db {
+        Db::new()
+    }
+}
+
+impl Shared {
+    fn blocking<T>(&self, f: impl FnOnce() -> T) -> T {
+        if self.on_disk {
+            task::block_in_place(f)
+        } else {
+            f()
+        }
+    }
+}
+
+impl Drop for Shutdown {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().shutdown = true;
+        self.0.background_task.notify();
+    }
+}
+
+fn expires_at(expire: Duration, command: &str) -> Result<Instant> {
+    Instant::now()
+        .checked_add(expire)
+        .ok_or_else(|| anyhow!("invalid expire time in '{}' command", command))
+}
+
+/// Removes the expired keys as they expire, and syncs the append only file every
+/// second with `appendfsync everysec`, until the database is dropped
+async fn run_background_task(shared: Arc<Shared>) {
+    let mut next_sync = Instant::now() + AOF_SYNC_INTERVAL;
+    loop {
+        // `None` once the database is dropped
+        let next = shared.blocking(|| {
+            let mut state = shared.state.lock().unwrap();
+            if state.shutdown {
+                return None;
+            }
+            let mut next = match state.purge_expired() {
+                Ok(next) => next,
+                Err(err) => {
+                    error!("Failed to remove expired keys: {}", err);
+                    Some(Instant::now() + PURGE_RETRY_DELAY)
+                }
+            };
+            if let Some(aof) = state.aof.as_mut() {
+                if aof.fsync() == AppendFsync::EverySec {
+                    let now = Instant::now();
+                    if next_sync <= now {
+                        if let Err(err) = aof.sync() {
+                            error!("Failed to sync the append only file: {}", err);
+                        }
+                        next_sync = now + AOF_SYNC_INTERVAL;
+                    }
+                    next = Some(match next {
+                        Some(next) => next.min(next_sync),
+                        None => next_sync,
+                    });
+                }
+            }
+            Some(next)
+        });
+        match next {
+            None => return,
+            Some(Some(when)) => {
+                tokio::select! {
+                    _ = time::delay_until(when) => {}
+                    _ = shared.background_task.notified() => {}
+                }
+            }
+            Some(None) => shared.background_task.notified().await,
+        }
+    }
+}
+
+impl State {
+    fn new(storage: Storage) -> State {
+        State {
+            storage,
+            expirations: BTreeSet::new(),
+            deadlines: HashMap::new(),
+            notify: false,
+            shutdown: false,
+            aof: None,
+            snapshot: None,
+            saving: false,
+            watched: HashMap::new(),
+        }
+    }
+
+    /// Returns the entry of a key unless it is expired, removing it if it is.
+    fn live(&mut self, key: &str) -> Result<Option<Entry>> {
+        match self.storage.get(key)? {
+            Some(entry) if entry.is_expired(Instant::now()) => {
+                self.remove(key)?;
+                Ok(None)
+            }
+            Some(entry) => {
+                // keys persisted by a previous run are indexed once they are read
+                if entry.expires_at.is_some() && !self.deadlines.contains_key(key) {
+                    self.index(key, entry.expires_at);
+                }
+                Ok(Some(entry))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn insert(&mut self, key: String, entry: Entry) -> Result<()> {
+        // logged first, so a change which cannot be persisted is not applied
+        if let Some(aof) = &mut self.aof {
+            aof.log_set(&key, &entry)?;
+        }
+        self.touch(&key);
+        self.index(&key, entry.expires_at);
+        self.storage.insert(key, entry)
+    }
+
+    fn remove(&mut self, key: &str) -> Result<()> {
+        if let Some(aof) = &mut self.aof {
+            aof.log_del(key)?;
+        }
+        self.touch(key);
+        self.index(key, None);
+        self.storage.remove(key)
+    }
+
+    /// Increments the version of a key if it is watched.
+    fn touch(&mut self, key: &str) {
+        if let Some(watched) = self.watched.get_mut(key) {
+            watched.version += 1;
+        }
+    }
+
+    /// Sets the expiration of a key in the index.
+    fn index(&mut self, key: &str, expires_at: Option<Instant>) {
+        if let Some(old) = self.deadlines.remove(key) {
+            self.expirations.remove(&(old, key.to_owned()));
+        }
+        if let Some(expires_at) = expires_at {
+            // the background task sleeps until the first expiration
+            match self.expirations.iter().next() {
+                Some((first, _)) if *first <= expires_at => {}
+                _ => self.notify = true,
+            }
+            self.deadlines.insert(key.to_owned(), expires_at);
+            self.expirations.insert((expires_at, key.to_owned()));
+        }
+    }
+
+    /// Removes the expired keys, returning when the next key expires.
+    fn purge_expired(&mut self) -> Result<Option<Instant>> {
+        let now = Instant::now();
+        while let Some((expires_at, key)) = self.expirations.iter().next().cloned() {
+            if expires_at > now {
+                return Ok(Some(expires_at));
+            }
+            self.remove(&key)?;
+        }
+        Ok(None)
+    }
+}
+
+/// A value and the time it expires at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Entry {
+    pub(crate) value: Bytes,
+    expires_at: Option<Instant>,
+}
+
+impl Entry {
+    /// Creates an entry expiring at a time in milliseconds since the UNIX epoch.
+    pub(crate) fn from_unix_ms(value: Bytes, expires_at: Option<u64>) -> Entry {
+        let expires_at = expires_at.and_then(|ms| {
+            let expires_at = UNIX_EPOCH + Duration::from_millis(ms);
+            let ttl = expires_at
+                .duration_since(SystemTime::now())
+                .unwrap_or_default();
+            // too far away to be represented, so as good as never
+            Instant::now().checked_add(ttl)
+        });
+        Entry { value, expires_at }
+    }
+
+    /// Returns the expiration in milliseconds since the UNIX epoch.
+    ///
+    /// The expiration is converted to the system clock, as it must survive restarts.
+    pub(crate) fn unix_ms(&self) -> Option<u64> {
+        self.expires_at
+            .and_then(|t| {
+                SystemTime::now().checked_add(t.saturating_duration_since(Instant::now()))
+            })
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_millis() as u64)
+    }
+
+    fn is_expired(&self, now: Instant) -> bool {
+        match self.expires_at {
+            Some(expires_at) => expires_at <= now,
+            None => false,
+        }
+    }
+
+    /// Encodes the entry as a `KvStore` value.
+    ///
+    /// The value is `<expiration>:<data>`, where the expiration is in milliseconds
+    /// since the UNIX epoch or 0 for none, and the data is `s` followed by the value
+    /// if it is UTF-8, or `x` followed by the value in hexadecimal.
+    fn encode(&self) -> String {
+        let expires_at = self.unix_ms().unwrap_or(0);
+        match std::str::from_utf8(&self.value) {
+            Ok(value) => format!("{}:s{}", expires_at, value),
+            Err(_) => {
+                let hex: String = self.value.iter().map(|b| format!("{:02x}", b)).collect();
+                format!("{}:x{}", expires_at, hex)
+            }
+        }
+    }
+
+    fn decode(s: &str) -> Result<Entry> {
+        let invalid = || anyhow!("invalid stored value {:?}", s);
+        let mut parts = s.splitn(2, ':');
+        let expires_at: u64 = parts
+            .next()
+            .and_then(|ms| ms.parse().ok())
+            .ok_or_else(invalid)?;
+        let data = parts.next().ok_or_else(invalid)?;
+        let value = if let Some(value) = data.strip_prefix('s') {
+            Bytes::from(value.to_owned())
+        } else if let Some(hex) = data.strip_prefix('x') {
+            if !hex.is_ascii() || hex.len() % 2 != 0 {
+                return Err(invalid());
+            }
+            (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+                .collect::<Option<Vec<u8>>>()
+                .map(Bytes::from)
+                .ok_or_else(invalid)?
+        } else {
+            return Err(invalid());
+        };
+        let expires_at = match expires_at {
+            0 => None,
+            ms => Some(ms),
+        };
+        Ok(Entry::from_unix_ms(value, expires_at))
+    }
+}
+
+/// Where the entries are stored
+enum Storage {
+    Memory(HashMap<String, Entry>),
+    Kvs(KvStore),
+}
+
+impl Storage {
+    /// Returns the keys which are not expired.
+    fn entries(&self) -> Result<Vec<(String, Entry)>> {
+        match self {
+            Storage::Memory(map) => {
+                let now = Instant::now();
+                Ok(map
+                    .iter()
+                    .filter(|(_, entry)| !entry.is_expired(now))
+                    .map(|(key, entry)| (key.clone(), entry.clone()))
+                    .collect())
+            }
+            Storage::Kvs(_) => Err(anyhow!("the keys of a KvStore cannot be listed")),
+        }
+    }
+
+    fn get(&mut self, key: &str) -> Result<Option<Entry>> {
+        match self {
+            Storage::Memory(map) => Ok(map.get(key).cloned()),
+            Storage::Kvs(store) => match store.get(key.to_owned())? {
+                Some(value) => Ok(Some(Entry::decode(&value)?)),
+                None => Ok(None),
+            },
+        }
+    }
+
+    fn insert(&mut self, key: String, entry: Entry) -> Result<()> {
+        match self {
+            Storage::Memory(map) => {
+                map.insert(key, entry);
+            }
+            Storage::Kvs(store) => store.set(key, entry.encode())?,
+        }
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &str) -> Result<()> {
+        match self {
+            Storage::Memory(map) => {
+                map.remove(key);
+            }
+            Storage::Kvs(store) => match store.remove(key.to_owned()) {
+                Ok(()) | Err(KvsError::KeyNotFound) => {}
+                Err(err) => return Err(err.into()),
+            },
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    /// Moves the paused clock forward, and lets the background task run
+    async fn advance(duration: Duration) {
+        time::advance(duration).await;
+        for _ in 0..3 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    /// Returns the keys in the storage, including the expired ones not removed yet
+    fn stored_keys(db: &Db) -> Vec<String> {
+        match &db.shared.state.lock().unwrap().storage {
+            Storage::Memory(map) => {
+                let mut keys: Vec<_> = map.keys().cloned().collect();
+                keys.sort();
+                keys
+            }
+            Storage::Kvs(_) => unreachable!(),
+        }
+    }
+
+    fn set(db: &Db, key: &str, expire: Option<Duration>) {
+        db.lock()
+            .set(key.to_owned(), Bytes::from("value"), expire, None)
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_set_condition() {
+        let db = Db::new();
+        let set = |condition| {
+            db.lock()
+                .set("key".to_owned(), Bytes::from("value"), None, condition)
+                .unwrap()
+        };
+        assert!(!set(Some(SetCondition::Exists)));
+        assert!(set(Some(SetCondition::NotExists)));
+        assert!(!set(Some(SetCondition::NotExists)));
+        assert!(set(Some(SetCondition::Exists)));
+        assert_eq!(db.lock().get("key").unwrap(), Some(Bytes::from("value")));
+    }
+
+    #[tokio::test]
+    async fn test_del_exists() {
+        let db = Db::new();
+        set(&db, "a", None);
+        set(&db, "b", None);
+        let keys = |keys: &[&str]| keys.iter().map(|k| k.to_string()).collect::<Vec<_>>();
+        assert_eq!(db.lock().exists(&keys(&["a", "a", "c"])).unwrap(), 2);
+        assert_eq!(db.lock().del(&keys(&["a", "b", "c"])).unwrap(), 2);
+        assert_eq!(db.lock().exists(&keys(&["a", "b"])).unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_incr() {
+        time::pause();
+        let db = Db::new();
+        assert_eq!(db.lock().incr("n".to_owned()).unwrap(), 1);
+        assert_eq!(db.lock().incr("n".to_owned()).unwrap(), 2);
+        set(&db, "s", None);
+        assert!(db.lock().incr("s".to_owned()).is_err());
+        let max = Bytes::from(i64::MAX.to_string());
+        db.lock().set("max".to_owned(), max, None, None).unwrap();
+        assert!(db.lock().incr("max".to_owned()).is_err());
+
+        // the time to live is kept
+        assert!(db.lock().expire("n".to_owned(), 10).unwrap());
+        assert_eq!(db.lock().incr("n".to_owned()).unwrap(), 3);
+        assert_eq!(db.lock().ttl("n").unwrap(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_expire_ttl() {
+        time::pause();
+        let db = Db::new();
+        assert!(!db.lock().expire("key".to_owned(), 10).unwrap());
+        assert_eq!(db.lock().ttl("key").unwrap(), -2);
+        set(&db, "key", None);
+        assert_eq!(db.lock().ttl("key").unwrap(), -1);
+        assert!(db.lock().expire("key".to_owned(), 10).unwrap());
+        assert_eq!(db.lock().ttl("key").unwrap(), 10);
+
+        advance(Duration::from_millis(9_400)).await;
+        assert_eq!(db.lock().ttl("key").unwrap(), 1);
+        assert_eq!(db.lock().get("key").unwrap(), Some(Bytes::from("value")));
+        advance(Duration::from_millis(600)).await;
+        assert_eq!(db.lock().get("key").unwrap(), None);
+        assert_eq!(db.lock().ttl("key").unwrap(), -2);
+        assert!(!db.lock().expire("key".to_owned(), 10).unwrap());
+
+        set(&db, "key", None);
+        assert!(db.lock().expire("key".to_owned(), 0).unwrap());
+        assert_eq!(db.lock().get("key").unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_set_expire() {
+        time::pause();
+        let db = Db::new();
+        set(&db, "key", Some(Duration::from_millis(1700)));
+        assert_eq!(db.lock().ttl("key").unwrap(), 2);
+        // a SET without expiration removes the time to live
+        set(&db, "key", None);
+        assert_eq!(db.lock().ttl("key").unwrap(), -1);
+        advance(Duration::from_secs(2)).await;
+        assert_eq!(db.lock().get("key").unwrap(), Some(Bytes::from("value")));
+    }
+
+    #[tokio::test]
+    async fn test_persist() {
+        time::pause();
+        let db = Db::new();
+        assert!(!db.lock().persist("key".to_owned()).unwrap());
+        set(&db, "key", Some(Duration::from_secs(10)));
+        assert!(db.lock().persist("key".to_owned()).unwrap());
+        assert!(!db.lock().persist("key".to_owned()).unwrap());
+        assert_eq!(db.lock().ttl("key").unwrap(), -1);
+        advance(Duration::from_secs(20)).await;
+        assert_eq!(stored_keys(&db), vec!["key"]);
+        assert_eq!(db.lock().get("key").unwrap(), Some(Bytes::from("value")));
+    }
+
+    // Expired keys are removed without being read
+    #[tokio::test]
+    async fn test_background_purge() {
+        time::pause();
+        let db = Db::new();
+        set(&db, "c", Some(Duration::from_secs(3)));
+        set(&db, "a", Some(Duration::from_secs(1)));
+        set(&db, "b", Some(Duration::from_secs(2)));
+        set(&db, "d", None);
+        assert_eq!(stored_keys(&db), vec!["a", "b", "c", "d"]);
+
+        advance(Duration::from_millis(1500)).await;
+        assert_eq!(stored_keys(&db), vec!["b", "c", "d"]);
+        // an earlier expiration wakes up the background task
+        set(&db, "e", Some(Duration::from_millis(100)));
+        advance(Duration::from_millis(200)).await;
+        assert_eq!(stored_keys(&db), vec!["b", "c", "d"]);
+        advance(Duration::from_secs(2)).await;
+        assert_eq!(stored_keys(&db), vec!["d"]);
+
+        let state = db.shared.state.lock().unwrap();
+        assert!(state.expirations.is_empty() && state.deadlines.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_watch() {
+        time::pause();
+        let db = Db::new();
+        let mut watch = db.watch();
+        watch.add(vec!["a".to_owned(), "missing".to_owned()]);
+        set(&db, "b", None);
+        db.lock().get("a").unwrap();
+        assert!(!watch.is_changed(&db.lock()));
+        set(&db, "missing", None);
+        assert!(watch.is_changed(&db.lock()));
+        // watching a key again keeps its first version
+        watch.add(vec!["missing".to_owned()]);
+        assert!(watch.is_changed(&db.lock()));
+        watch.clear();
+        assert!(!watch.is_changed(&db.lock()));
+
+        // expiring is a change
+        set(&db, "e", Some(Duration::from_secs(1)));
+        watch.add(vec!["e".to_owned()]);
+        let mut other = db.watch();
+        other.add(vec!["e".to_owned()]);
+        drop(other);
+        assert_eq!(db.shared.state.lock().unwrap().watched["e"].watchers, 1);
+        advance(Duration::from_secs(2)).await;
+        assert!(watch.is_changed(&db.lock()));
+        drop(watch);
+        assert!(db.shared.state.lock().unwrap().watched.is_empty());
+    }
+
+    /// Waits for the snapshot and append only file rewrite in progress
+    async fn wait_background_saves(db: &Db) {
+        loop {
+            {
+                let state = db.shared.state.lock().unwrap();
+                let rewriting = match &state.aof {
+                    Some(aof) => aof.is_rewriting(),
+                    None => false,
+                };
+                if !state.saving && !rewriting {
+                    return;
+                }
+            }
+            time::delay_for(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_load_persisted() {
+        let dir = TempDir::new().unwrap();
+        let persistence = Persistence {
+            snapshot: Some(dir.path().join("dump.mrdb")),
+            aof: Some(dir.path().join("appendonly.aof")),
+            appendfsync: AppendFsync::Always,
+        };
+        let aof_len = || {
+            fs::metadata(dir.path().join("appendonly.aof"))
+                .unwrap()
+                .len()
+        };
+        let value = |db: &Db, key| {
+            db.lock()
+                .get(key)
+                .unwrap()
+                .map(|v| String::from_utf8(v.to_vec()).unwrap())
+        };
+
+        let db = Db::load(&persistence).unwrap();
+        set(&db, "a", None);
+        set(&db, "b", Some(Duration::from_secs(60)));
+        db.lock().incr("n".to_owned()).unwrap();
+        db.lock().incr("n".to_owned()).unwrap();
+        db.lock().bgsave().unwrap();
+        wait_background_saves(&db).await;
+        db.lock().del(&["a".to_owned()]).unwrap();
+        set(&db, "c", None);
+        drop(db);
+
+        // the changes made after the snapshot are replayed on top of it
+        let db = Db::load(&persistence).unwrap();
+        assert_eq!(value(&db, "a"), None);
+        assert_eq!(db.lock().ttl("b").unwrap(), 60);
+        assert_eq!(value(&db, "n"), Some("2".to_owned()));
+        assert_eq!(value(&db, "c"), Some("value".to_owned()));
+
+        let before = aof_len();
+        db.lock().bgrewriteaof().unwrap();
+        wait_background_saves(&db).await;
+        assert!(aof_len() < before);
+        db.lock().del(&["c".to_owned()]).unwrap();
+        drop(db);
+
+        // "a" is still in the snapshot, but not brought back after the rewrite
+        let db = Db::load(&persistence).unwrap();
+        assert_eq!(value(&db, "a"), None);
+        assert_eq!(db.lock().ttl("b").unwrap(), 60);
+        assert_eq!(value(&db, "n"), Some("2".to_owned()));
+        assert_eq!(value(&db, "c"), None);
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn test_persistence_disabled() {
+        let db = Db::new();
+        assert!(db.lock().bgsave().is_err());
+        assert!(db.lock().bgrewriteaof().is_err());
+
+        let dir = TempDir::new().unwrap();
+        let db = Db::open(dir.path()).unwrap();
+        assert!(db.lock().bgrewriteaof().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_entry_encoding() {
+        let entries = vec![
+            Entry {
+                value: Bytes::from("hello:world"),
+                expires_at: None,
+            },
+            Entry {
+                value: Bytes::from(&b"\x00\xff binary"[..]),
+                expires_at: None,
+            },
+        ];
+        for entry in entries {
+            assert_eq!(Entry::decode(&entry.encode()).unwrap(), entry);
+        }
+        // the expiration is stored with a millisecond precision on the system clock
+        let expires_at = Instant::now() + Duration::from_secs(60);
+        let entry = Entry {
+            value: Bytes::from("value"),
+            expires_at: Some(expires_at),
+        };
+        let decoded = Entry::decode(&entry.encode()).unwrap().expires_at.unwrap();
+        let diff = if decoded > expires_at {
+            decoded - expires_at
+        } else {
+            expires_at - decoded
+        };
+        assert!(diff < Duration::from_millis(100), "{:?}", diff);
+
+        for invalid in &["", "abc:sx", "0", "0:y", "0:xabc", "0:xzz", "0:xé"] {
+            assert!(Entry::decode(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+}
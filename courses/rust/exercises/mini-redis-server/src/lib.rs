@@ -0,0 +1,15 @@
+//! A mini Redis server.
+//!
+//! `run` serves the RESP2 clients of a listener, keeping the keys in a `Db` and
+//! delivering the messages published to the subscribers of a `PubSub`. The keys
+//! can be persisted as set by `Persistence`.
+
+pub use db::{Db, Keys, Watch};
+pub use persist::{AppendFsync, Persistence};
+pub use pubsub::PubSub;
+pub use server::run;
+
+mod db;
+mod persist;
+mod pubsub;
+mod server;
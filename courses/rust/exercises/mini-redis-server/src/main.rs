@@ -1,62 +1,59 @@
-
 use anyhow::{anyhow, Result};
-use futures::stream::StreamExt;
-use mini_redis_proto::{Request, Response};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use structopt::StructOpt;
 use tokio::net::TcpListener;
-use tokio::prelude::*;
-
 
-async fn handle_request(buf: &[u8]) -> Result<Option<Response>> {
-    let raw_data = std::str::from_utf8(&buf)?;
-    match serde_json::from_str(raw_data)? {
-        Request::Ping => {
-            eprintln!("Received PING");
-            Ok(Some(Response::Pong))
-        }
-        _ => {
-            Err(anyhow!("Unsupported Request: {:?}", raw_data))
-        }
-    }
+use mini_redis_server::{AppendFsync, Db, Persistence, PubSub};
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "mini-redis-server")]
+struct Opt {
+    /// Address to listen on
+    #[structopt(long, default_value = "127.0.0.1:6379")]
+    addr: SocketAddr,
+    /// Directory of a KvStore to persist the keys in, instead of keeping them in memory
+    #[structopt(long, parse(from_os_str))]
+    dir: Option<PathBuf>,
+    /// Snapshot file saved by BGSAVE, loaded on startup
+    #[structopt(long, parse(from_os_str), conflicts_with = "dir")]
+    snapshot: Option<PathBuf>,
+    /// Append only file logging every change, replayed on startup after the snapshot
+    #[structopt(long, parse(from_os_str), conflicts_with = "dir")]
+    aof: Option<PathBuf>,
+    /// When the append only file is synced to disk: always, everysec or no
+    #[structopt(long, default_value = "everysec")]
+    appendfsync: AppendFsync,
+    /// Number of messages buffered for a subscriber before it is disconnected
+    #[structopt(long, default_value = "1024")]
+    subscriber_buffer: usize,
 }
 
-
 #[tokio::main]
-async fn main() {
+async fn main() -> Result<()> {
     env_logger::init();
+    let opt = Opt::from_args();
+    if opt.subscriber_buffer == 0 {
+        return Err(anyhow!("--subscriber-buffer must be at least 1"));
+    }
 
-    let addr = "127.0.0.1:6379";
-    let mut listener = TcpListener::bind(addr).await.unwrap();
-
-    let server = async move {
-        let mut incoming = listener.incoming();
-        while let Some(conn) = incoming.next().await {
-            match conn {
-                Ok(mut socket) => {
-                    eprintln!("Accepted connection from {:?}", socket.peer_addr());
-                    tokio::spawn(async move {
-                        let mut buf = [0; 64];
-                        let bytes_read = socket.read(&mut buf).await.unwrap();
-                        match handle_request(&buf[..bytes_read]).await {
-                            Ok(result) => {
-                                if let Some(response) = result {
-                                    let resp: String = serde_json::to_string(&response).unwrap();
-                                    socket.write(&resp.as_bytes()).await.unwrap();
-                                }
-                            }
-                            Err(err) => {
-                                println!("ERROR: {}", err);
-                            }
-                        }
-                    });
-                }
-                Err(err) => {
-                    // Handle error by printing to STDOUT.
-                    println!("accept error = {:?}", err);
-                }
-            }
-        }
+    let db = match &opt.dir {
+        Some(dir) => Db::open(dir)?,
+        None => Db::load(&Persistence {
+            snapshot: opt.snapshot,
+            aof: opt.aof,
+            appendfsync: opt.appendfsync,
+        })?,
     };
-
-    println!("Server listening on {}...", addr);
-    server.await;
+    let listener = TcpListener::bind(opt.addr).await?;
+
+    println!("Server listening on {}...", opt.addr);
+    mini_redis_server::run(
+        listener,
+        db,
+        PubSub::new(opt.subscriber_buffer),
+        tokio::signal::ctrl_c(),
+    )
+    .await;
+    Ok(())
 }
@@ -0,0 +1,566 @@
+use crate::db::Entry;
+use anyhow::{anyhow, Result};
+use bytes::{Bytes, BytesMut};
+use log::{error, warn};
+use mini_redis_proto::Frame;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Start of a snapshot file, with the version of the format
+const SNAPSHOT_MAGIC: &[u8] = b"MINIREDIS0001";
+/// Marker of a key in a snapshot
+const SNAPSHOT_ENTRY: u8 = 0x01;
+/// Marker of the end of a snapshot
+const SNAPSHOT_END: u8 = 0xff;
+
+/// Where the keys kept in memory are persisted.
+///
+/// On startup, the snapshot is loaded first and the changes logged in the append only
+/// file are replayed on top of it.
+#[derive(Debug, Clone, Default)]
+pub struct Persistence {
+    /// Snapshot file written by `BGSAVE`
+    pub snapshot: Option<PathBuf>,
+    /// Append only file logging every change
+    pub aof: Option<PathBuf>,
+    /// When the append only file is synced to disk
+    pub appendfsync: AppendFsync,
+}
+
+/// When the append only file is synced to disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AppendFsync {
+    /// `always`: after every change, before replying to the client
+    Always,
+    /// `everysec`: every second, so a crash loses at most a second of changes
+    #[default]
+    EverySec,
+    /// `no`: whenever the operating system flushes its buffers
+    No,
+}
+
+impl FromStr for AppendFsync {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<AppendFsync, String> {
+        match s {
+            "always" => Ok(AppendFsync::Always),
+            "everysec" => Ok(AppendFsync::EverySec),
+            "no" => Ok(AppendFsync::No),
+            _ => Err(format!(
+                "invalid appendfsync policy '{}', expected always, everysec or no",
+                s
+            )),
+        }
+    }
+}
+
+/// The append only file, logging every change in RESP form.
+///
+/// Changes are logged by their result rather than as received: an `INCR` is logged
+/// as a `SET` of the new value, and a time to live as an absolute `PXAT` time. Each
+/// command sets the whole state of a key, so replaying the file on top of a snapshot
+/// taken after the file was started still gives the latest keys. A rewritten file
+/// starts with a `FLUSHALL`, so the keys removed before the rewrite do not come back
+/// from an older snapshot.
+pub(crate) struct Aof {
+    path: PathBuf,
+    file: File,
+    fsync: AppendFsync,
+    // changes written but not synced to disk yet
+    dirty: bool,
+    // changes logged during a rewrite, appended to the new file once it is written
+    rewrite_buffer: Option<Vec<u8>>,
+}
+
+impl Aof {
+    /// Opens the append only file at `path`, creating it if needed.
+    pub(crate) fn open(path: &Path, fsync: AppendFsync) -> Result<Aof> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Aof {
+            path: path.to_owned(),
+            file,
+            fsync,
+            dirty: false,
+            rewrite_buffer: None,
+        })
+    }
+
+    pub(crate) fn fsync(&self) -> AppendFsync {
+        self.fsync
+    }
+
+    /// Logs that `key` is set to `entry`.
+    pub(crate) fn log_set(&mut self, key: &str, entry: &Entry) -> Result<()> {
+        self.log(set_command(key, entry))
+    }
+
+    /// Logs that `key` is removed.
+    pub(crate) fn log_del(&mut self, key: &str) -> Result<()> {
+        self.log(command(vec![
+            Bytes::from("DEL"),
+            Bytes::from(key.to_owned()),
+        ]))
+    }
+
+    fn log(&mut self, command: Frame) -> Result<()> {
+        let mut buf = BytesMut::new();
+        command.encode(&mut buf);
+        self.file.write_all(&buf)?;
+        if let Some(rewrite_buffer) = &mut self.rewrite_buffer {
+            rewrite_buffer.extend_from_slice(&buf);
+        }
+        match self.fsync {
+            AppendFsync::Always => self.file.sync_data()?,
+            AppendFsync::EverySec | AppendFsync::No => self.dirty = true,
+        }
+        Ok(())
+    }
+
+    /// Syncs the changes logged since the last sync to disk.
+    pub(crate) fn sync(&mut self) -> Result<()> {
+        if self.dirty {
+            self.file.sync_data()?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn is_rewriting(&self) -> bool {
+        self.rewrite_buffer.is_some()
+    }
+
+    /// Starts buffering the changes for a rewrite, returning the path to write the
+    /// new file at.
+    pub(crate) fn start_rewrite(&mut self) -> Result<PathBuf> {
+        if self.is_rewriting() {
+            return Err(anyhow!(
+                "Background append only file rewriting already in progress"
+            ));
+        }
+        self.rewrite_buffer = Some(Vec::new());
+        Ok(rewrite_path(&self.path))
+    }
+
+    /// Replaces the file by the new one, once the changes logged since the rewrite
+    /// started are appended to it.
+    ///
+    /// `written` is the result of writing the new file, which is removed if the
+    /// rewrite fails.
+    pub(crate) fn finish_rewrite(&mut self, written: Result<()>) -> Result<()> {
+        let buffer = self.rewrite_buffer.take().unwrap_or_default();
+        let new_path = rewrite_path(&self.path);
+        let result = written.and_then(|()| {
+            let mut file = OpenOptions::new().append(true).open(&new_path)?;
+            file.write_all(&buffer)?;
+            file.sync_data()?;
+            fs::rename(&new_path, &self.path)?;
+            self.file = file;
+            Ok(())
+        });
+        if result.is_err() {
+            let _ = fs::remove_file(&new_path);
+        }
+        result
+    }
+}
+
+impl Drop for Aof {
+    fn drop(&mut self) {
+        if let Err(err) = self.sync() {
+            error!("Failed to sync {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+/// A change read from the append only file
+#[derive(Debug, PartialEq)]
+pub(crate) enum Change {
+    Set(String, Entry),
+    Del(String),
+    FlushAll,
+}
+
+/// Writes an append only file setting the keys of `entries`, to replace the current
+/// one.
+pub(crate) fn write_aof(path: &Path, entries: &[(String, Entry)]) -> Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let mut buf = BytesMut::new();
+    command(vec![Bytes::from("FLUSHALL")]).encode(&mut buf);
+    for (key, entry) in entries {
+        set_command(key, entry).encode(&mut buf);
+        file.write_all(&buf)?;
+        buf.clear();
+    }
+    file.write_all(&buf)?;
+    file.flush()?;
+    file.get_ref().sync_all()?;
+    Ok(())
+}
+
+/// Reads the changes logged in the append only file at `path`, if it exists.
+///
+/// A command cut short at the end of the file, as left by a crash, is truncated from
+/// the file with a warning.
+pub(crate) fn read_aof(path: &Path) -> Result<Vec<Change>> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut changes = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let invalid = |msg: String| {
+            anyhow!(
+                "invalid command at byte {} of {}: {}",
+                pos,
+                path.display(),
+                msg
+            )
+        };
+        let (frame, len) = match Frame::parse(&data[pos..]) {
+            Ok(Some(parsed)) => parsed,
+            Ok(None) => {
+                warn!(
+                    "Truncating the incomplete command at byte {} of {}",
+                    pos,
+                    path.display()
+                );
+                OpenOptions::new()
+                    .write(true)
+                    .open(path)?
+                    .set_len(pos as u64)?;
+                break;
+            }
+            Err(err) => return Err(invalid(err.to_string())),
+        };
+        changes.push(parse_change(frame).ok_or_else(|| invalid("unknown command".to_owned()))?);
+        pos += len;
+    }
+    Ok(changes)
+}
+
+fn parse_change(frame: Frame) -> Option<Change> {
+    let args = match frame {
+        Frame::Array(frames) => frames
+            .into_iter()
+            .map(|frame| match frame {
+                Frame::Bulk(data) => Some(data),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?,
+        _ => return None,
+    };
+    let mut args = args.into_iter();
+    let key = |arg: Bytes| String::from_utf8(arg.to_vec()).ok();
+    let change = match &args.next()?[..] {
+        b"SET" => {
+            let key = key(args.next()?)?;
+            let value = args.next()?;
+            let expires_at = match args.next() {
+                Some(option) if &option[..] == b"PXAT" => {
+                    let ms = std::str::from_utf8(&args.next()?).ok()?.parse().ok()?;
+                    Some(ms)
+                }
+                Some(_) => return None,
+                None => None,
+            };
+            Change::Set(key, Entry::from_unix_ms(value, expires_at))
+        }
+        b"DEL" => Change::Del(key(args.next()?)?),
+        b"FLUSHALL" => Change::FlushAll,
+        _ => return None,
+    };
+    match args.next() {
+        Some(_) => None,
+        None => Some(change),
+    }
+}
+
+fn set_command(key: &str, entry: &Entry) -> Frame {
+    let mut args = vec![
+        Bytes::from("SET"),
+        Bytes::from(key.to_owned()),
+        entry.value.clone(),
+    ];
+    if let Some(ms) = entry.unix_ms() {
+        args.push(Bytes::from("PXAT"));
+        args.push(Bytes::from(ms.to_string()));
+    }
+    command(args)
+}
+
+fn command(args: Vec<Bytes>) -> Frame {
+    Frame::Array(args.into_iter().map(Frame::Bulk).collect())
+}
+
+fn rewrite_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".rewrite")
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Writes a snapshot of the keys of `entries` to `path`.
+///
+/// The snapshot is written to a temporary file renamed to `path` once complete, so
+/// a crash while saving leaves the previous snapshot intact.
+///
+/// The file starts with `SNAPSHOT_MAGIC`, followed by a record per key:
+/// `SNAPSHOT_ENTRY`, the key and the value each prefixed by their length as a
+/// big-endian `u32`, and the expiration in milliseconds since the UNIX epoch as a
+/// big-endian `u64`, 0 for none. `SNAPSHOT_END` ends the file.
+pub(crate) fn save_snapshot(path: &Path, entries: &[(String, Entry)]) -> Result<()> {
+    let temp_path = with_suffix(path, ".tmp");
+    let result = write_snapshot(&temp_path, entries).and_then(|()| {
+        fs::rename(&temp_path, path)?;
+        Ok(())
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+fn write_snapshot(path: &Path, entries: &[(String, Entry)]) -> Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(SNAPSHOT_MAGIC)?;
+    for (key, entry) in entries {
+        file.write_all(&[SNAPSHOT_ENTRY])?;
+        write_bytes(&mut file, key.as_bytes())?;
+        write_bytes(&mut file, &entry.value)?;
+        file.write_all(&entry.unix_ms().unwrap_or(0).to_be_bytes())?;
+    }
+    file.write_all(&[SNAPSHOT_END])?;
+    file.flush()?;
+    file.get_ref().sync_all()?;
+    Ok(())
+}
+
+fn write_bytes(writer: &mut impl Write, bytes: &[u8]) -> Result<()> {
+    if bytes.len() > u32::MAX as usize {
+        return Err(anyhow!("value too long for a snapshot"));
+    }
+    writer.write_all(&(bytes.len() as u32).to_be_bytes())?;
+    writer.write_all(bytes)?;
+    Ok(())
+}
+
+/// Reads the keys of the snapshot at `path`, if it exists.
+pub(crate) fn load_snapshot(path: &Path) -> Result<Vec<(String, Entry)>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    read_snapshot(&mut BufReader::new(file))
+        .map_err(|err| anyhow!("invalid snapshot {}: {}", path.display(), err))
+}
+
+fn read_snapshot(reader: &mut impl Read) -> Result<Vec<(String, Entry)>> {
+    let mut magic = [0; SNAPSHOT_MAGIC.len()];
+    reader.read_exact(&mut magic)?;
+    if magic != SNAPSHOT_MAGIC {
+        return Err(anyhow!("not a mini-redis snapshot"));
+    }
+    let mut entries = Vec::new();
+    loop {
+        let mut marker = [0; 1];
+        reader.read_exact(&mut marker)?;
+        match marker[0] {
+            SNAPSHOT_ENTRY => {
+                let key = String::from_utf8(read_bytes(reader)?)
+                    .map_err(|_| anyhow!("invalid UTF-8 in key"))?;
+                let value = Bytes::from(read_bytes(reader)?);
+                let mut ms = [0; 8];
+                reader.read_exact(&mut ms)?;
+                let expires_at = match u64::from_be_bytes(ms) {
+                    0 => None,
+                    ms => Some(ms),
+                };
+                entries.push((key, Entry::from_unix_ms(value, expires_at)));
+            }
+            SNAPSHOT_END => return Ok(entries),
+            marker => return Err(anyhow!("invalid record marker {:#04x}", marker)),
+        }
+    }
+}
+
+fn read_bytes(reader: &mut impl Read) -> Result<Vec<u8>> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as u64;
+    // read with `take` so a corrupted length does not allocate gigabytes up front
+    let mut bytes = Vec::new();
+    reader.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use tempfile::TempDir;
+
+    fn unix_ms_in(ttl: Duration) -> u64 {
+        (SystemTime::now() + ttl)
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64
+    }
+
+    fn entries() -> Vec<(String, Entry)> {
+        vec![
+            (
+                "text".to_owned(),
+                Entry::from_unix_ms(Bytes::from("value"), None),
+            ),
+            (
+                "binary".to_owned(),
+                Entry::from_unix_ms(Bytes::from(&b"\x00\xff\r\n"[..]), None),
+            ),
+            (
+                "expiring".to_owned(),
+                Entry::from_unix_ms(
+                    Bytes::from("soon"),
+                    Some(unix_ms_in(Duration::from_secs(60))),
+                ),
+            ),
+        ]
+    }
+
+    // The expiration is rounded to milliseconds on the system clock
+    fn assert_same(left: &[(String, Entry)], right: &[(String, Entry)]) {
+        assert_eq!(left.len(), right.len());
+        for ((lkey, lentry), (rkey, rentry)) in left.iter().zip(right) {
+            assert_eq!(lkey, rkey);
+            assert_eq!(lentry.value, rentry.value);
+            match (lentry.unix_ms(), rentry.unix_ms()) {
+                (Some(l), Some(r)) => assert!(l.max(r) - l.min(r) < 100, "{} {}", l, r),
+                (l, r) => assert_eq!(l, r),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_snapshot() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("dump.mrdb");
+        assert!(load_snapshot(&path).unwrap().is_empty());
+
+        save_snapshot(&path, &entries()).unwrap();
+        assert_same(&load_snapshot(&path).unwrap(), &entries());
+        assert!(!with_suffix(&path, ".tmp").exists());
+
+        // a truncated or foreign file is not loaded
+        let data = fs::read(&path).unwrap();
+        for len in &[0, 5, data.len() - 1] {
+            fs::write(&path, &data[..*len]).unwrap();
+            assert!(load_snapshot(&path).is_err(), "{}", len);
+        }
+        fs::write(&path, b"REDIS0009").unwrap();
+        assert!(load_snapshot(&path).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_aof_replay() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("appendonly.aof");
+        assert!(read_aof(&path).unwrap().is_empty());
+
+        let mut aof = Aof::open(&path, AppendFsync::Always).unwrap();
+        for (key, entry) in &entries() {
+            aof.log_set(key, entry).unwrap();
+        }
+        aof.log_del("text").unwrap();
+        drop(aof);
+
+        let changes = read_aof(&path).unwrap();
+        assert_eq!(changes.len(), 4);
+        let sets: Vec<_> = changes[..3]
+            .iter()
+            .map(|change| match change {
+                Change::Set(key, entry) => (key.clone(), entry.clone()),
+                change => panic!("unexpected {:?}", change),
+            })
+            .collect();
+        assert_same(&sets, &entries());
+        assert_eq!(changes[3], Change::Del("text".to_owned()));
+    }
+
+    // A crash in the middle of a write leaves an incomplete command, which is dropped
+    #[tokio::test]
+    async fn test_aof_truncated() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("appendonly.aof");
+        let mut aof = Aof::open(&path, AppendFsync::No).unwrap();
+        aof.log_del("a").unwrap();
+        drop(aof);
+        let complete = fs::metadata(&path).unwrap().len();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"*2\r\n$3\r\nDEL\r\n$1").unwrap();
+
+        assert_eq!(read_aof(&path).unwrap(), vec![Change::Del("a".to_owned())]);
+        assert_eq!(fs::metadata(&path).unwrap().len(), complete);
+
+        fs::write(&path, b"*1\r\n$4\r\nINCR\r\n").unwrap();
+        assert!(read_aof(&path).is_err());
+        fs::write(&path, b"+OK\r\n").unwrap();
+        assert!(read_aof(&path).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_aof_rewrite() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("appendonly.aof");
+        let mut aof = Aof::open(&path, AppendFsync::EverySec).unwrap();
+        for _ in 0..10 {
+            aof.log_del("gone").unwrap();
+        }
+
+        let new_path = aof.start_rewrite().unwrap();
+        assert!(aof.start_rewrite().is_err());
+        aof.log_del("during").unwrap();
+        let written = write_aof(&new_path, &entries()[..1]);
+        aof.finish_rewrite(written).unwrap();
+        aof.log_del("after").unwrap();
+        drop(aof);
+
+        let changes = read_aof(&path).unwrap();
+        assert_eq!(changes.len(), 4);
+        assert_eq!(changes[0], Change::FlushAll);
+        assert_eq!(
+            changes[1],
+            Change::Set(entries()[0].0.clone(), entries()[0].1.clone())
+        );
+        assert_eq!(changes[2], Change::Del("during".to_owned()));
+        assert_eq!(changes[3], Change::Del("after".to_owned()));
+        assert!(!new_path.exists());
+
+        // a failed rewrite keeps the current file
+        let mut aof = Aof::open(&path, AppendFsync::EverySec).unwrap();
+        aof.start_rewrite().unwrap();
+        assert!(aof.finish_rewrite(Err(anyhow!("disk full"))).is_err());
+        aof.start_rewrite().unwrap();
+        drop(aof);
+        assert_eq!(read_aof(&path).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_appendfsync() {
+        assert_eq!("always".parse(), Ok(AppendFsync::Always));
+        assert_eq!("everysec".parse(), Ok(AppendFsync::EverySec));
+        assert_eq!("no".parse(), Ok(AppendFsync::No));
+        assert!("sometimes".parse::<AppendFsync>().is_err());
+    }
+}
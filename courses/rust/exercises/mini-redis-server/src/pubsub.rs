@@ -0,0 +1,420 @@
+use bytes::Bytes;
+use log::warn;
+use mini_redis_proto::Push;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+/// The channels and patterns subscribed to by the connections.
+///
+/// Every subscriber has a buffer of `capacity` messages. A subscriber too slow to
+/// read its messages before the buffer fills up is disconnected, so it cannot hold
+/// up the publishers or make the server run out of memory.
+#[derive(Clone)]
+pub struct PubSub {
+    shared: Arc<Mutex<Shared>>,
+    capacity: usize,
+}
+
+struct Shared {
+    next_id: u64,
+    // a subscriber is disconnected by dropping its sender
+    senders: HashMap<u64, mpsc::Sender<Push>>,
+    channels: HashMap<String, HashSet<u64>>,
+    patterns: HashMap<String, HashSet<u64>>,
+}
+
+impl PubSub {
+    /// Creates a `PubSub` buffering `capacity` messages per subscriber.
+    pub fn new(capacity: usize) -> PubSub {
+        PubSub {
+            shared: Arc::new(Mutex::new(Shared {
+                next_id: 0,
+                senders: HashMap::new(),
+                channels: HashMap::new(),
+                patterns: HashMap::new(),
+            })),
+            capacity,
+        }
+    }
+
+    /// Registers a subscriber, without any subscription.
+    pub fn subscriber(&self) -> Subscriber {
+        let (sender, receiver) = mpsc::channel(self.capacity);
+        let mut shared = self.shared.lock().unwrap();
+        let id = shared.next_id;
+        shared.next_id += 1;
+        shared.senders.insert(id, sender);
+        Subscriber {
+            id,
+            pubsub: self.clone(),
+            receiver,
+            channels: BTreeSet::new(),
+            patterns: BTreeSet::new(),
+        }
+    }
+
+    /// Sends a message to the subscribers of `channel` and of the patterns matching it.
+    ///
+    /// Returns the number of messages sent, a subscriber receiving the message once per
+    /// matching subscription.
+    pub fn publish(&self, channel: &str, message: Bytes) -> i64 {
+        let mut shared = self.shared.lock().unwrap();
+        let mut pushes = Vec::new();
+        if let Some(ids) = shared.channels.get(channel) {
+            for &id in ids {
+                let push = Push::Message {
+                    channel: channel.to_owned(),
+                    message: message.clone(),
+                };
+                pushes.push((id, push));
+            }
+        }
+        for (pattern, ids) in &shared.patterns {
+            if glob_match(pattern, channel) {
+                for &id in ids {
+                    let push = Push::Pmessage {
+                        pattern: pattern.clone(),
+                        channel: channel.to_owned(),
+                        message: message.clone(),
+                    };
+                    pushes.push((id, push));
+                }
+            }
+        }
+
+        let mut sent = 0;
+        for (id, push) in pushes {
+            // the sender is missing if the subscriber was disconnected
+            if let Some(sender) = shared.senders.get_mut(&id) {
+                if sender.try_send(push).is_ok() {
+                    sent += 1;
+                } else {
+                    warn!("Disconnecting subscriber {} with a full buffer", id);
+                    shared.senders.remove(&id);
+                }
+            }
+        }
+        sent
+    }
+}
+
+/// The subscriptions of a connection, removed when it is dropped.
+pub struct Subscriber {
+    id: u64,
+    pubsub: PubSub,
+    receiver: mpsc::Receiver<Push>,
+    channels: BTreeSet<String>,
+    patterns: BTreeSet<String>,
+}
+
+impl Subscriber {
+    /// Returns the number of channels and patterns subscribed to.
+    pub fn count(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+
+    /// Returns the next message published to the subscriptions.
+    ///
+    /// Returns `None` once the subscriber is disconnected for being too slow.
+    pub async fn recv(&mut self) -> Option<Push> {
+        self.receiver.recv().await
+    }
+
+    /// Subscribes to channels.
+    pub fn subscribe(&mut self, channels: Vec<String>) -> Vec<Push> {
+        let mut pushes = Vec::new();
+        for channel in channels {
+            if self.channels.insert(channel.clone()) {
+                let mut shared = self.pubsub.shared.lock().unwrap();
+                let ids = shared.channels.entry(channel.clone()).or_default();
+                ids.insert(self.id);
+            }
+            let count = self.count() as i64;
+            pushes.push(Push::Subscribe { channel, count });
+        }
+        pushes
+    }
+
+    /// Subscribes to glob patterns.
+    pub fn psubscribe(&mut self, patterns: Vec<String>) -> Vec<Push> {
+        let mut pushes = Vec::new();
+        for pattern in patterns {
+            if self.patterns.insert(pattern.clone()) {
+                let mut shared = self.pubsub.shared.lock().unwrap();
+                let ids = shared.patterns.entry(pattern.clone()).or_default();
+                ids.insert(self.id);
+            }
+            let count = self.count() as i64;
+            pushes.push(Push::Psubscribe { pattern, count });
+        }
+        pushes
+    }
+
+    /// Unsubscribes from channels, or from all of them if `channels` is empty.
+    pub fn unsubscribe(&mut self, channels: Vec<String>) -> Vec<Push> {
+        let channels = if channels.is_empty() {
+            self.channels.iter().cloned().collect()
+        } else {
+            channels
+        };
+        if channels.is_empty() {
+            let count = self.count() as i64;
+            return vec![Push::Unsubscribe {
+                channel: None,
+                count,
+            }];
+        }
+        let mut pushes = Vec::new();
+        for channel in channels {
+            if self.channels.remove(&channel) {
+                let mut shared = self.pubsub.shared.lock().unwrap();
+                remove_id(&mut shared.channels, &channel, self.id);
+            }
+            let count = self.count() as i64;
+            pushes.push(Push::Unsubscribe {
+                channel: Some(channel),
+                count,
+            });
+        }
+        pushes
+    }
+
+    /// Unsubscribes from patterns, or from all of them if `patterns` is empty.
+    pub fn punsubscribe(&mut self, patterns: Vec<String>) -> Vec<Push> {
+        let patterns = if patterns.is_empty() {
+            self.patterns.iter().cloned().collect()
+        } else {
+            patterns
+        };
+        if patterns.is_empty() {
+            let count = self.count() as i64;
+            return vec![Push::Punsubscribe {
+                pattern: None,
+                count,
+            }];
+        }
+        let mut pushes = Vec::new();
+        for pattern in patterns {
+            if self.patterns.remove(&pattern) {
+                let mut shared = self.pubsub.shared.lock().unwrap();
+                remove_id(&mut shared.patterns, &pattern, self.id);
+            }
+            let count = self.count() as i64;
+            pushes.push(Push::Punsubscribe {
+                pattern: Some(pattern),
+                count,
+            });
+        }
+        pushes
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        let mut shared = self.pubsub.shared.lock().unwrap();
+        shared.senders.remove(&self.id);
+        for channel in &self.channels {
+            remove_id(&mut shared.channels, channel, self.id);
+        }
+        for pattern in &self.patterns {
+            remove_id(&mut shared.patterns, pattern, self.id);
+        }
+    }
+}
+
+/// Removes a subscriber of a channel or pattern, and the channel or pattern if it has
+/// no subscribers left.
+fn remove_id(subscriptions: &mut HashMap<String, HashSet<u64>>, name: &str, id: u64) {
+    if let Some(ids) = subscriptions.get_mut(name) {
+        ids.remove(&id);
+        if ids.is_empty() {
+            subscriptions.remove(name);
+        }
+    }
+}
+
+/// Matches a channel against a glob pattern, the same way as Redis.
+///
+/// `*` matches any sequence of bytes, `?` any single byte, `[abc]` and `[a-z]` any byte
+/// of the set, `[^abc]` any byte out of it, and `\` escapes the next byte.
+fn glob_match(pattern: &str, channel: &str) -> bool {
+    let (pattern, channel) = (pattern.as_bytes(), channel.as_bytes());
+    let (mut p, mut c) = (0, 0);
+    // where to resume after the last `*` if the rest of the pattern does not match;
+    // backtracking to the last star only keeps matching linear in the pattern length
+    let mut star: Option<(usize, usize)> = None;
+    while c < channel.len() {
+        if pattern.get(p) == Some(&b'*') {
+            p += 1;
+            star = Some((p, c));
+            continue;
+        }
+        if let Some(len) = match_one(&pattern[p..], channel[c]) {
+            p += len;
+            c += 1;
+            continue;
+        }
+        match star {
+            Some((star_p, star_c)) => {
+                // let the star match one more byte
+                p = star_p;
+                c = star_c + 1;
+                star = Some((star_p, c));
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|&b| b == b'*')
+}
+
+/// Returns the length of the element at the start of `pattern` if it matches `b`.
+fn match_one(pattern: &[u8], b: u8) -> Option<usize> {
+    match *pattern.first()? {
+        b'?' => Some(1),
+        b'\\' if pattern.len() > 1 => Some(2).filter(|_| pattern[1] == b),
+        b'[' => {
+            let class = &pattern[1..];
+            let negate = class.first() == Some(&b'^');
+            let mut i = if negate { 1 } else { 0 };
+            let mut matched = false;
+            while i < class.len() && class[i] != b']' {
+                if class[i] == b'\\' && i + 1 < class.len() {
+                    matched |= class[i + 1] == b;
+                    i += 2;
+                } else if i + 2 < class.len() && class[i + 1] == b'-' {
+                    let (start, end) = (class[i].min(class[i + 2]), class[i].max(class[i + 2]));
+                    matched |= (start..=end).contains(&b);
+                    i += 3;
+                } else {
+                    matched |= class[i] == b;
+                    i += 1;
+                }
+            }
+            // an unterminated set ends with the pattern
+            let len = 1 + (i + 1).min(class.len());
+            Some(len).filter(|_| matched != negate)
+        }
+        c => Some(1).filter(|_| c == b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        let cases = [
+            ("news", "news", true),
+            ("news", "newsx", false),
+            ("news.*", "news.tech", true),
+            ("news.*", "news.", true),
+            ("news.*", "new.tech", false),
+            ("*", "", true),
+            ("*.*", "a.b.c", true),
+            ("h?llo", "hello", true),
+            ("h?llo", "hllo", false),
+            ("h[ae]llo", "hallo", true),
+            ("h[ae]llo", "hillo", false),
+            ("h[^e]llo", "hallo", true),
+            ("h[^e]llo", "hello", false),
+            ("h[a-c]llo", "hbllo", true),
+            ("h[c-a]llo", "hbllo", true),
+            ("h[a-c]llo", "hdllo", false),
+            ("h\\*llo", "h*llo", true),
+            ("h\\*llo", "hello", false),
+            ("a*b*c", "axxbyyc", true),
+            ("a*b*c", "axxbyy", false),
+            ("h[ab", "ha", true),
+        ];
+        for &(pattern, channel, expected) in &cases {
+            assert_eq!(
+                glob_match(pattern, channel),
+                expected,
+                "{:?} {:?}",
+                pattern,
+                channel
+            );
+        }
+        // backtracking stays linear with many stars
+        let channel = "a".repeat(10_000);
+        assert!(!glob_match(&format!("{}b", "a*".repeat(100)), &channel));
+    }
+
+    #[test]
+    fn test_publish() {
+        let pubsub = PubSub::new(16);
+        let mut news = pubsub.subscriber();
+        let mut all = pubsub.subscriber();
+        assert_eq!(
+            news.subscribe(vec!["news".to_owned(), "news".to_owned()]),
+            vec![
+                Push::Subscribe {
+                    channel: "news".to_owned(),
+                    count: 1
+                },
+                Push::Subscribe {
+                    channel: "news".to_owned(),
+                    count: 1
+                },
+            ]
+        );
+        all.psubscribe(vec!["*".to_owned()]);
+        all.subscribe(vec!["news".to_owned()]);
+
+        assert_eq!(pubsub.publish("news", Bytes::from("hello")), 3);
+        assert_eq!(pubsub.publish("weather", Bytes::from("sunny")), 1);
+        assert_eq!(
+            news.receiver.try_recv().unwrap(),
+            Push::Message {
+                channel: "news".to_owned(),
+                message: Bytes::from("hello"),
+            }
+        );
+        assert!(news.receiver.try_recv().is_err());
+
+        all.unsubscribe(vec![]);
+        all.punsubscribe(vec![]);
+        assert_eq!(all.count(), 0);
+        drop(news);
+        assert_eq!(pubsub.publish("news", Bytes::from("hello")), 0);
+        let shared = pubsub.shared.lock().unwrap();
+        assert!(shared.channels.is_empty() && shared.patterns.is_empty());
+    }
+
+    #[test]
+    fn test_unsubscribe_none() {
+        let pubsub = PubSub::new(16);
+        let mut subscriber = pubsub.subscriber();
+        assert_eq!(
+            subscriber.unsubscribe(vec![]),
+            vec![Push::Unsubscribe {
+                channel: None,
+                count: 0
+            }]
+        );
+        assert_eq!(
+            subscriber.punsubscribe(vec!["news.*".to_owned()]),
+            vec![Push::Punsubscribe {
+                pattern: Some("news.*".to_owned()),
+                count: 0
+            }]
+        );
+    }
+
+    #[test]
+    fn test_slow_subscriber() {
+        let pubsub = PubSub::new(2);
+        let mut subscriber = pubsub.subscriber();
+        subscriber.subscribe(vec!["news".to_owned()]);
+        assert_eq!(pubsub.publish("news", Bytes::from("1")), 1);
+        assert_eq!(pubsub.publish("news", Bytes::from("2")), 1);
+        assert_eq!(pubsub.publish("news", Bytes::from("3")), 0);
+        assert_eq!(pubsub.publish("news", Bytes::from("4")), 0);
+        // the buffered messages are delivered before the subscriber is closed
+        assert!(subscriber.receiver.try_recv().is_ok());
+        assert!(subscriber.receiver.try_recv().is_ok());
+        assert!(subscriber.receiver.try_recv().is_err());
+    }
+}
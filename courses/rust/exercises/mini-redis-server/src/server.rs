@@ -0,0 +1,328 @@
+use crate::db::{Db, Keys, Watch};
+use crate::pubsub::{PubSub, Subscriber};
+use anyhow::{anyhow, Result};
+use futures::{future, SinkExt, StreamExt};
+use log::{debug, error, info};
+use mini_redis_proto::{Frame, ProtocolError, Push, Request, RespCodec};
+use std::future::Future;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc};
+use tokio_util::codec::Framed;
+
+/// Serves the clients of `listener` until `shutdown` completes.
+///
+/// On shutdown, the server stops accepting connections and waits for the connections
+/// to finish the request they are handling before returning.
+pub async fn run(mut listener: TcpListener, db: Db, pubsub: PubSub, shutdown: impl Future) {
+    // every connection holds a receiver, and is told to close when the sender drops
+    let (notify_shutdown, _) = broadcast::channel(1);
+    // every connection holds a sender, so the receiver completes when they are all done
+    let (shutdown_complete_tx, mut shutdown_complete_rx) = mpsc::channel::<()>(1);
+
+    tokio::select! {
+        _ = accept(&mut listener, &db, &pubsub, &notify_shutdown, &shutdown_complete_tx) => {}
+        _ = shutdown => info!("Shutting down"),
+    }
+
+    drop(notify_shutdown);
+    drop(shutdown_complete_tx);
+    shutdown_complete_rx.recv().await;
+}
+
+async fn accept(
+    listener: &mut TcpListener,
+    db: &Db,
+    pubsub: &PubSub,
+    notify_shutdown: &broadcast::Sender<()>,
+    shutdown_complete: &mpsc::Sender<()>,
+) {
+    loop {
+        match listener.accept().await {
+            Ok((socket, addr)) => {
+                debug!("Accepted connection from {}", addr);
+                let db = db.clone();
+                let pubsub = pubsub.clone();
+                let shutdown = notify_shutdown.subscribe();
+                let shutdown_complete = shutdown_complete.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = serve(socket, db, pubsub, shutdown).await {
+                        error!("Connection from {}: {}", addr, err);
+                    }
+                    drop(shutdown_complete);
+                });
+            }
+            Err(err) => error!("Accept error: {}", err),
+        }
+    }
+}
+
+/// Replies to the requests of a client until it disconnects or the server shuts down.
+///
+/// Once the client subscribes to a channel or pattern, the connection is in push mode:
+/// the published messages are sent as they arrive, and only the subscription commands
+/// and `PING` are accepted until the client unsubscribes from everything.
+///
+/// After `MULTI`, the commands are queued until `EXEC` runs them at once.
+async fn serve(
+    socket: TcpStream,
+    db: Db,
+    pubsub: PubSub,
+    mut shutdown: broadcast::Receiver<()>,
+) -> Result<()> {
+    let mut framed = Framed::new(socket, RespCodec::default());
+    let mut subscriber: Option<Subscriber> = None;
+    let mut transaction = Transaction::new(db.watch());
+    loop {
+        let frame = tokio::select! {
+            frame = framed.next() => frame,
+            push = next_push(&mut subscriber) => {
+                let push = push.ok_or_else(|| anyhow!("Subscriber too slow, closing"))?;
+                framed.send(push.into_frame()).await?;
+                continue;
+            }
+            _ = shutdown.recv() => return Ok(()),
+        };
+        let replies = match frame {
+            Some(Ok(frame)) => match Request::from_frame(frame) {
+                Ok(req) => db
+                    .blocking(|| {
+                        handle_request(&db, &pubsub, &mut subscriber, &mut transaction, req)
+                    })
+                    .unwrap_or_else(|err| vec![error_reply(err)]),
+                Err(err) => {
+                    transaction.abort();
+                    vec![err.into_frame()]
+                }
+            },
+            Some(Err(err @ ProtocolError::Invalid(_))) => {
+                // the rest of the stream cannot be parsed, so the connection is closed
+                framed.send(Frame::Error(format!("ERR {}", err))).await?;
+                return Ok(());
+            }
+            Some(Err(err)) => return Err(err.into()),
+            None => return Ok(()),
+        };
+        for reply in replies {
+            framed.send(reply).await?;
+        }
+    }
+}
+
+/// Waits for the next message of the subscriptions, forever if there are none
+async fn next_push(subscriber: &mut Option<Subscriber>) -> Option<Push> {
+    match subscriber {
+        Some(subscriber) => subscriber.recv().await,
+        None => future::pending().await,
+    }
+}
+
+/// The transaction of a connection
+struct Transaction {
+    watch: Watch,
+    // the commands queued since `MULTI`
+    queued: Option<Vec<Request>>,
+    // a command could not be queued, so `EXEC` fails
+    aborted: bool,
+}
+
+impl Transaction {
+    fn new(watch: Watch) -> Transaction {
+        Transaction {
+            watch,
+            queued: None,
+            aborted: false,
+        }
+    }
+
+    /// Makes `EXEC` fail if a transaction is started
+    fn abort(&mut self) {
+        if self.queued.is_some() {
+            self.aborted = true;
+        }
+    }
+
+    /// Ends the transaction, unwatching the keys
+    fn end(&mut self) {
+        self.queued = None;
+        self.aborted = false;
+        self.watch.clear();
+    }
+}
+
+fn handle_request(
+    db: &Db,
+    pubsub: &PubSub,
+    subscriber: &mut Option<Subscriber>,
+    transaction: &mut Transaction,
+    req: Request,
+) -> Result<Vec<Frame>> {
+    debug!("Received {:?}", req);
+    if transaction.queued.is_some() {
+        return Ok(vec![handle_queued(db, pubsub, transaction, req)?]);
+    }
+    let pushes = match req {
+        Request::Subscribe { channels } => subscriber
+            .get_or_insert_with(|| pubsub.subscriber())
+            .subscribe(channels),
+        Request::Psubscribe { patterns } => subscriber
+            .get_or_insert_with(|| pubsub.subscriber())
+            .psubscribe(patterns),
+        Request::Unsubscribe { channels } => subscriber
+            .get_or_insert_with(|| pubsub.subscriber())
+            .unsubscribe(channels),
+        Request::Punsubscribe { patterns } => subscriber
+            .get_or_insert_with(|| pubsub.subscriber())
+            .punsubscribe(patterns),
+        // in push mode, a PING is answered with a push so it is not mistaken for one
+        Request::Ping(msg) if subscriber.is_some() => {
+            let msg = msg.unwrap_or_default();
+            return Ok(vec![Frame::Array(vec![
+                Frame::bulk("pong"),
+                Frame::Bulk(msg),
+            ])]);
+        }
+        req if subscriber.is_some() => {
+            return Err(anyhow!(
+                "Can't execute '{}': only (P)SUBSCRIBE / (P)UNSUBSCRIBE / PING are allowed in this context",
+                req.name()
+            ));
+        }
+        Request::Multi => {
+            transaction.queued = Some(Vec::new());
+            return Ok(vec![ok()]);
+        }
+        Request::Exec => return Err(anyhow!("EXEC without MULTI")),
+        Request::Discard => return Err(anyhow!("DISCARD without MULTI")),
+        Request::Watch { keys } => {
+            transaction.watch.add(keys);
+            return Ok(vec![ok()]);
+        }
+        Request::Unwatch => {
+            transaction.watch.clear();
+            return Ok(vec![ok()]);
+        }
+        req => return Ok(vec![handle_command(&mut db.lock(), pubsub, req)?]),
+    };
+    // leave push mode once there are no subscriptions left
+    if subscriber.as_ref().map(Subscriber::count) == Some(0) {
+        *subscriber = None;
+    }
+    Ok(pushes.into_iter().map(Push::into_frame).collect())
+}
+
+/// Queues a command of a transaction, or ends it on `EXEC` or `DISCARD`
+fn handle_queued(
+    db: &Db,
+    pubsub: &PubSub,
+    transaction: &mut Transaction,
+    req: Request,
+) -> Result<Frame> {
+    let reply = match req {
+        Request::Exec if transaction.aborted => {
+            transaction.end();
+            Frame::Error("EXECABORT Transaction discarded because of previous errors.".to_owned())
+        }
+        Request::Exec => exec(db, pubsub, transaction),
+        Request::Discard => {
+            transaction.end();
+            ok()
+        }
+        Request::Multi => return Err(anyhow!("MULTI calls can not be nested")),
+        Request::Watch { .. } => return Err(anyhow!("WATCH inside MULTI is not allowed")),
+        Request::Subscribe { .. }
+        | Request::Unsubscribe { .. }
+        | Request::Psubscribe { .. }
+        | Request::Punsubscribe { .. } => {
+            transaction.abort();
+            return Err(anyhow!(
+                "Command '{}' not allowed inside a transaction",
+                req.name()
+            ));
+        }
+        req => {
+            transaction.queued.get_or_insert_with(Vec::new).push(req);
+            Frame::Simple("QUEUED".to_owned())
+        }
+    };
+    Ok(reply)
+}
+
+/// Runs the queued commands with the keys locked, unless a watched key changed
+fn exec(db: &Db, pubsub: &PubSub, transaction: &mut Transaction) -> Frame {
+    let replies = {
+        let mut keys = db.lock();
+        if transaction.watch.is_changed(&keys) {
+            None
+        } else {
+            let queued = transaction.queued.take().unwrap_or_default();
+            let replies = queued.into_iter().map(|req| match req {
+                // the keys are unwatched once the transaction ends anyway
+                Request::Unwatch => ok(),
+                req => handle_command(&mut keys, pubsub, req).unwrap_or_else(error_reply),
+            });
+            Some(replies.collect())
+        }
+    };
+    // after the keys are unlocked, as unwatching locks them
+    transaction.end();
+    match replies {
+        Some(replies) => Frame::Array(replies),
+        None => Frame::Null,
+    }
+}
+
+fn ok() -> Frame {
+    Frame::Simple("OK".to_owned())
+}
+
+fn error_reply(err: anyhow::Error) -> Frame {
+    Frame::Error(format!("ERR {}", err))
+}
+
+fn handle_command(db: &mut Keys, pubsub: &PubSub, req: Request) -> Result<Frame> {
+    let reply = match req {
+        Request::Ping(None) => Frame::Simple("PONG".to_owned()),
+        Request::Ping(Some(msg)) | Request::Echo(msg) => Frame::Bulk(msg),
+        Request::Get { key } => match db.get(&key)? {
+            Some(value) => Frame::Bulk(value),
+            None => Frame::Null,
+        },
+        Request::Set {
+            key,
+            value,
+            expire,
+            condition,
+        } => {
+            if db.set(key, value, expire, condition)? {
+                ok()
+            } else {
+                Frame::Null
+            }
+        }
+        Request::Del { keys } => Frame::Integer(db.del(&keys)?),
+        Request::Exists { keys } => Frame::Integer(db.exists(&keys)?),
+        Request::Incr { key } => Frame::Integer(db.incr(key)?),
+        Request::Expire { key, seconds } => Frame::Integer(db.expire(key, seconds)? as i64),
+        Request::Ttl { key } => Frame::Integer(db.ttl(&key)?),
+        Request::Persist { key } => Frame::Integer(db.persist(key)? as i64),
+        Request::Bgsave => {
+            db.bgsave()?;
+            Frame::Simple("Background saving started".to_owned())
+        }
+        Request::Bgrewriteaof => {
+            db.bgrewriteaof()?;
+            Frame::Simple("Background append only file rewriting started".to_owned())
+        }
+        Request::Publish { channel, message } => Frame::Integer(pubsub.publish(&channel, message)),
+        Request::Subscribe { .. }
+        | Request::Unsubscribe { .. }
+        | Request::Psubscribe { .. }
+        | Request::Punsubscribe { .. }
+        | Request::Multi
+        | Request::Exec
+        | Request::Discard
+        | Request::Watch { .. }
+        | Request::Unwatch => unreachable!("handled by handle_request"),
+    };
+    Ok(reply)
+}
@@ -0,0 +1,18 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use kvs::Request;
+
+/// Small enough for the fuzzer to hit the limit
+const MAX_FRAME_SIZE: u32 = 4096;
+
+fuzz_target!(|data: &[u8]| {
+    // any input is rejected with an error rather than a panic or a huge allocation
+    if let Ok(req) = Request::deserialize(&mut &data[..], MAX_FRAME_SIZE) {
+        let frame = req.serialize(MAX_FRAME_SIZE).unwrap();
+        assert_eq!(
+            Request::deserialize(&mut &frame[..], MAX_FRAME_SIZE).unwrap(),
+            req
+        );
+    }
+});
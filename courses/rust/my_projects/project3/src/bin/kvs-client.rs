@@ -3,7 +3,7 @@ use std::net::{SocketAddr, TcpStream};
 
 use structopt::StructOpt;
 
-use kvs::{Request, Response};
+use kvs::{ProtocolError, ProtocolResult, Request, Response, DEFAULT_MAX_FRAME_SIZE};
 
 #[derive(Debug, StructOpt)]
 pub enum Command {
@@ -29,49 +29,59 @@ struct Args {
     /// Server listening address
     #[structopt(long, default_value = "127.0.0.1:4000", global = true)]
     addr: SocketAddr,
+    /// Maximum size of a request or response frame in bytes [default: 16 MiB]
+    #[structopt(long, global = true)]
+    max_frame_size: Option<u32>,
 }
 
 struct KvsClient {
     reader: BufReader<TcpStream>,
     writer: BufWriter<TcpStream>,
+    max_frame_size: u32,
 }
 
 impl KvsClient {
-    fn connect(dest: SocketAddr) -> io::Result<Self> {
+    fn connect(dest: SocketAddr, max_frame_size: u32) -> io::Result<Self> {
         let stream = TcpStream::connect(dest)?;
         eprintln!("Connecting to {}", dest);
         Ok(Self {
             reader: BufReader::new(stream.try_clone()?),
             writer: BufWriter::new(stream),
+            max_frame_size,
         })
     }
 
-    fn send_request(&mut self, req: &Request) -> io::Result<Response> {
-        self.writer.write_all(&req.serialize()?)?;
+    fn send_request(&mut self, req: &Request) -> ProtocolResult<Response> {
+        self.writer
+            .write_all(&req.serialize(self.max_frame_size)?)?;
         self.writer.flush()?;
-        Response::deserialize(&mut self.reader)
+        Response::deserialize(&mut self.reader, self.max_frame_size)
     }
 }
 
 fn main() -> Result<(), String> {
     let args = Args::from_args();
+    let max_frame_size = args.max_frame_size.unwrap_or(DEFAULT_MAX_FRAME_SIZE);
 
     let resp = match args.subcmd {
         Command::Get { key } => {
             let req = Request::Get { key };
-            KvsClient::connect(args.addr)
+            KvsClient::connect(args.addr, max_frame_size)
+                .map_err(ProtocolError::from)
                 .and_then(|mut client| client.send_request(&req))
                 .map_err(|e| format!("Error fetching key: {}", e))?
         }
         Command::Set { key, value } => {
             let req = Request::Set { key, value };
-            KvsClient::connect(args.addr)
+            KvsClient::connect(args.addr, max_frame_size)
+                .map_err(ProtocolError::from)
                 .and_then(|mut client| client.send_request(&req))
                 .map_err(|e| format!("Error setting key: {}", e))?
         }
         Command::Remove { key } => {
             let req = Request::Remove { key };
-            KvsClient::connect(args.addr)
+            KvsClient::connect(args.addr, max_frame_size)
+                .map_err(ProtocolError::from)
                 .and_then(|mut client| client.send_request(&req))
                 .map_err(|e| format!("Error removing key: {}", e))?
         }
@@ -6,7 +6,10 @@ use std::net::{SocketAddr, TcpListener};
 use log::{debug, error, info, trace};
 use structopt::StructOpt;
 
-use kvs::{KvStore, KvsEngine, KvsError, Request, Response, Result, SledKvsEngine};
+use kvs::{
+    KvStore, KvsEngine, KvsError, ProtocolError, Request, Response, Result, SledKvsEngine,
+    DEFAULT_MAX_FRAME_SIZE,
+};
 
 #[derive(Debug, StructOpt)]
 #[structopt(
@@ -22,6 +25,9 @@ struct Args {
     /// Service listening address
     #[structopt(long, default_value = "127.0.0.1:4000", global = true)]
     addr: SocketAddr,
+    /// Maximum size of a request or response frame in bytes [default: 16 MiB]
+    #[structopt(long)]
+    max_frame_size: Option<u32>,
 }
 
 #[derive(Debug)]
@@ -84,6 +90,20 @@ fn handle_request(req: Request, store: &mut Box<dyn KvsEngine>) -> Result<Respon
     }
 }
 
+/// Write a response, replacing it with an error if it exceeds the maximum frame size
+fn send_response(writer: &mut impl Write, resp: &Response, max_frame_size: u32) -> Result<()> {
+    let frame = match resp.serialize(max_frame_size) {
+        Ok(frame) => frame,
+        Err(e) => {
+            error!("{}", e);
+            Response::Error(e.to_string()).serialize(max_frame_size)?
+        }
+    };
+    writer.write_all(&frame)?;
+    writer.flush()?;
+    Ok(())
+}
+
 fn main() -> Result<()> {
     let args = Args::from_args();
     env_logger::init();
@@ -117,6 +137,7 @@ fn main() -> Result<()> {
         }
     };
 
+    let max_frame_size = args.max_frame_size.unwrap_or(DEFAULT_MAX_FRAME_SIZE);
     let listener = TcpListener::bind(args.addr)?;
     for stream in listener.incoming() {
         if let Ok(stream) = stream {
@@ -125,20 +146,22 @@ fn main() -> Result<()> {
             let mut reader = BufReader::new(stream.try_clone()?);
             let mut writer = BufWriter::new(stream);
 
-            match Request::deserialize(&mut reader) {
+            match Request::deserialize(&mut reader, max_frame_size) {
                 Ok(req) => match handle_request(req, &mut engine) {
-                    Ok(resp) => {
-                        writer.write_all(&resp.serialize()?)?;
-                        writer.flush()?;
-                    }
+                    Ok(resp) => send_response(&mut writer, &resp, max_frame_size)?,
                     Err(e) => {
                         error!("{}", e);
                         let resp = Response::Error(e.to_string());
-                        writer.write_all(&resp.serialize()?)?;
-                        writer.flush()?;
+                        send_response(&mut writer, &resp, max_frame_size)?;
                     }
                 },
-                Err(e) => error!("Invalid Request: {}", e),
+                // the client is gone or sent an incomplete frame
+                Err(ProtocolError::Io(e)) => error!("Invalid Request: {}", e),
+                Err(e) => {
+                    error!("Invalid Request: {}", e);
+                    let resp = Response::Error(format!("Protocol error: {}", e));
+                    send_response(&mut writer, &resp, max_frame_size)?;
+                }
             }
         }
     }
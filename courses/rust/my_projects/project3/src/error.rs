@@ -1,6 +1,8 @@
 use std::io;
 use thiserror::Error;
 
+use crate::proto::ProtocolError;
+
 /// Error type for kvs.
 #[derive(Error, Debug)]
 pub enum KvsError {
@@ -17,6 +19,9 @@ pub enum KvsError {
     /// It indicated a corrupted log or a program bug.
     #[error("Unexpected command type")]
     UnexpectedCommandType,
+    /// Invalid or oversized protocol frame.
+    #[error("Protocol error: {0}")]
+    Protocol(ProtocolError),
 }
 
 impl From<io::Error> for KvsError {
@@ -41,6 +46,12 @@ impl From<sled::Error> for KvsError {
     }
 }
 
+impl From<ProtocolError> for KvsError {
+    fn from(err: ProtocolError) -> KvsError {
+        KvsError::Protocol(err)
+    }
+}
+
 impl From<serde_json::Error> for KvsError {
     fn from(err: serde_json::Error) -> KvsError {
         KvsError::Serde(err)
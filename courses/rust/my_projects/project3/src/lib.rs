@@ -4,7 +4,7 @@
 pub use crate::sled::SledKvsEngine;
 pub use error::{KvsError, Result};
 pub use kv::KvStore;
-pub use proto::{Request, Response};
+pub use proto::{ProtocolError, ProtocolResult, Request, Response, DEFAULT_MAX_FRAME_SIZE};
 
 mod error;
 mod kv;
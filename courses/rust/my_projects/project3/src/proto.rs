@@ -1,10 +1,74 @@
+//! Binary protocol between `kvs-client` and `kvs-server`.
+//!
+//! Each message is sent as a frame:
+//!
+//! ```text
+//! +------------------+-----------+----------------------------------+
+//! | frame length u32 | type u8   | fields: length u32 + UTF-8 bytes |
+//! +------------------+-----------+----------------------------------+
+//! ```
+//!
+//! All integers are big endian. The frame length counts the bytes after itself, and
+//! frames longer than the configured maximum are rejected before they are read.
+//!
+//! `cargo fuzz run request` and `cargo fuzz run response` fuzz the deserialization.
+
 use std::convert::From;
 use std::io::{self, Read, Write};
 
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use thiserror::Error;
+
+/// Default maximum frame size in bytes, excluding the frame length prefix.
+pub const DEFAULT_MAX_FRAME_SIZE: u32 = 16 * 1024 * 1024;
+
+/// Error reading or writing a protocol frame.
+#[derive(Error, Debug)]
+pub enum ProtocolError {
+    /// IO error, including a connection closed in the middle of a frame.
+    #[error("{0}")]
+    Io(io::Error),
+    /// The frame is larger than the maximum frame size.
+    #[error("Frame of {size} bytes exceeds the maximum of {max} bytes")]
+    FrameTooLarge {
+        /// Size of the frame in bytes
+        size: u64,
+        /// Maximum frame size in bytes
+        max: u32,
+    },
+    /// The frame is empty, so it has no message type.
+    #[error("Empty frame")]
+    EmptyFrame,
+    /// Unknown message type byte.
+    #[error("Invalid message type {0}")]
+    InvalidType(u8),
+    /// A field length points past the end of the frame.
+    #[error("Field of {length} bytes exceeds the {remaining} bytes left in the frame")]
+    Truncated {
+        /// Length of the field in bytes
+        length: u32,
+        /// Bytes left in the frame
+        remaining: usize,
+    },
+    /// The frame has bytes after the last field of its message.
+    #[error("{0} unexpected bytes at the end of the frame")]
+    TrailingBytes(usize),
+    /// A field is not valid UTF-8.
+    #[error("Invalid UTF-8 in field")]
+    InvalidUtf8,
+}
+
+impl From<io::Error> for ProtocolError {
+    fn from(err: io::Error) -> ProtocolError {
+        ProtocolError::Io(err)
+    }
+}
+
+/// Result type for the protocol.
+pub type ProtocolResult<T> = std::result::Result<T, ProtocolError>;
 
 /// Request object (client -> server)
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Request {
     /// Fetch a value for a given key
     Get {
@@ -37,58 +101,55 @@ impl From<&Request> for u8 {
 }
 
 impl Request {
-    /// Serialize Request to bytes (to send to server)
-    pub fn serialize(&self) -> std::io::Result<Vec<u8>> {
-        let mut data: Vec<u8> = vec![];
-        data.write_u8(self.into())?;
+    /// Serialize Request to a frame (to send to server)
+    ///
+    /// Fails with `ProtocolError::FrameTooLarge` if the frame would exceed `max_frame_size`.
+    pub fn serialize(&self, max_frame_size: u32) -> ProtocolResult<Vec<u8>> {
+        let mut frame = FrameWriter::new(self.into());
         match self {
             Request::Get { key } | Request::Remove { key } => {
-                let key_bytes = key.as_bytes();
-                data.write_u16::<BigEndian>(key_bytes.len() as u16)?;
-                data.write_all(&key_bytes)?;
+                frame.write_string(key)?;
             }
             Request::Set { key, value } => {
-                let key_bytes = key.as_bytes();
-                data.write_u16::<BigEndian>(key_bytes.len() as u16)?;
-                data.write_all(&key_bytes)?;
-                let value_bytes = value.as_bytes();
-                data.write_u16::<BigEndian>(value_bytes.len() as u16)?;
-                data.write_all(&value_bytes)?;
+                frame.write_string(key)?;
+                frame.write_string(value)?;
             }
         }
-        Ok(data)
+        frame.finish(max_frame_size)
     }
 
-    /// Deserialize Request to bytes (to receive from client)
-    pub fn deserialize(mut buf: &mut impl Read) -> io::Result<Request> {
-        // let mut cursor = Cursor::new(buf);
-        match buf.read_u8()? {
+    /// Deserialize Request from a frame (to receive from client)
+    ///
+    /// A frame exceeding `max_frame_size` is rejected without reading its body, so the
+    /// rest of the stream is not usable afterwards.
+    pub fn deserialize(buf: &mut impl Read, max_frame_size: u32) -> ProtocolResult<Request> {
+        let mut frame = FrameReader::read(buf, max_frame_size)?;
+        let req = match frame.read_u8()? {
             // Get
             1 => {
-                let key = extract_string(&mut buf)?;
-                Ok(Request::Get { key })
+                let key = frame.read_string()?;
+                Request::Get { key }
             }
             // Set
             2 => {
-                let key = extract_string(&mut buf)?;
-                let value = extract_string(&mut buf)?;
-                Ok(Request::Set { key, value })
+                let key = frame.read_string()?;
+                let value = frame.read_string()?;
+                Request::Set { key, value }
             }
             // Remove
             3 => {
-                let key = extract_string(&mut buf)?;
-                Ok(Request::Remove { key })
+                let key = frame.read_string()?;
+                Request::Remove { key }
             }
-            _ => Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Invalid Request Type",
-            )),
-        }
+            t => return Err(ProtocolError::InvalidType(t)),
+        };
+        frame.finish()?;
+        Ok(req)
     }
 }
 
 /// Response object (server)
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Response {
     /// A fetched Value
     /// Response for Get (if key exists)
@@ -112,48 +173,142 @@ impl From<&Response> for u8 {
 }
 
 impl Response {
-    /// Serialize Response to bytes (to send to client)
-    pub fn serialize(&self) -> std::io::Result<Vec<u8>> {
-        let mut data: Vec<u8> = vec![];
-        data.write_u8(self.into())?;
+    /// Serialize Response to a frame (to send to client)
+    ///
+    /// Fails with `ProtocolError::FrameTooLarge` if the frame would exceed `max_frame_size`.
+    pub fn serialize(&self, max_frame_size: u32) -> ProtocolResult<Vec<u8>> {
+        let mut frame = FrameWriter::new(self.into());
         match self {
             Response::Value(resp) | Response::Error(resp) => {
-                let resp_bytes = resp.as_bytes();
-                data.write_u16::<BigEndian>(resp_bytes.len() as u16)?;
-                data.write_all(&resp_bytes)?;
+                frame.write_string(resp)?;
             }
             Response::Ok => (),
         }
-        Ok(data)
+        frame.finish(max_frame_size)
     }
 
-    /// Deserialize Response to bytes (to receive from server)
-    pub fn deserialize(mut buf: &mut impl Read) -> io::Result<Response> {
-        match buf.read_u8()? {
+    /// Deserialize Response from a frame (to receive from server)
+    ///
+    /// A frame exceeding `max_frame_size` is rejected without reading its body.
+    pub fn deserialize(buf: &mut impl Read, max_frame_size: u32) -> ProtocolResult<Response> {
+        let mut frame = FrameReader::read(buf, max_frame_size)?;
+        let resp = match frame.read_u8()? {
             // Value
             1 => {
-                let value = extract_string(&mut buf)?;
-                Ok(Response::Value(value))
+                let value = frame.read_string()?;
+                Response::Value(value)
             }
             // Ok
-            2 => Ok(Response::Ok),
+            2 => Response::Ok,
             // Error
             3 => {
-                let error = extract_string(&mut buf)?;
-                Ok(Response::Error(error))
+                let error = frame.read_string()?;
+                Response::Error(error)
             }
-            _ => Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Invalid Response Type",
-            )),
+            t => return Err(ProtocolError::InvalidType(t)),
+        };
+        frame.finish()?;
+        Ok(resp)
+    }
+}
+
+/// Builds a frame, leaving room for the frame length until the frame is finished
+struct FrameWriter {
+    data: Vec<u8>,
+}
+
+impl FrameWriter {
+    fn new(msg_type: u8) -> Self {
+        let mut data = vec![0u8; 4];
+        data.push(msg_type);
+        FrameWriter { data }
+    }
+
+    /// Write the length (u32) of the string followed by its bytes
+    fn write_string(&mut self, s: &str) -> ProtocolResult<()> {
+        let bytes = s.as_bytes();
+        // a length over `u32` is cut, but such a frame is rejected by `finish` anyway
+        self.data.write_u32::<BigEndian>(bytes.len() as u32)?;
+        self.data.write_all(bytes)?;
+        Ok(())
+    }
+
+    fn finish(mut self, max_frame_size: u32) -> ProtocolResult<Vec<u8>> {
+        let size = self.data.len() - 4;
+        if size > max_frame_size as usize {
+            return Err(ProtocolError::FrameTooLarge {
+                size: size as u64,
+                max: max_frame_size,
+            });
         }
+        (&mut self.data[..4]).write_u32::<BigEndian>(size as u32)?;
+        Ok(self.data)
     }
 }
 
-/// From a given Cursor, read the next length (u16) and extract the string bytes
-fn extract_string(buf: &mut impl Read) -> io::Result<String> {
-    let length = buf.read_u16::<BigEndian>()?;
-    let mut bytes = vec![0u8; length as usize];
-    buf.read_exact(&mut bytes)?;
-    String::from_utf8(bytes).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid utf8"))
+/// The body of a received frame
+struct FrameReader {
+    data: Vec<u8>,
+    pos: usize,
+}
+
+impl FrameReader {
+    /// Read the frame length and then the whole frame
+    fn read(buf: &mut impl Read, max_frame_size: u32) -> ProtocolResult<Self> {
+        let size = buf.read_u32::<BigEndian>()?;
+        if size > max_frame_size {
+            return Err(ProtocolError::FrameTooLarge {
+                size: u64::from(size),
+                max: max_frame_size,
+            });
+        }
+        if size == 0 {
+            return Err(ProtocolError::EmptyFrame);
+        }
+        let mut data = vec![0u8; size as usize];
+        buf.read_exact(&mut data)?;
+        Ok(FrameReader { data, pos: 0 })
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn read_u8(&mut self) -> ProtocolResult<u8> {
+        let b = *self.data.get(self.pos).ok_or(ProtocolError::EmptyFrame)?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    /// Read the next length (u32) and extract the string bytes
+    fn read_string(&mut self) -> ProtocolResult<String> {
+        if self.remaining() < 4 {
+            return Err(ProtocolError::Truncated {
+                length: 4,
+                remaining: self.remaining(),
+            });
+        }
+        let length = (&self.data[self.pos..]).read_u32::<BigEndian>()?;
+        self.pos += 4;
+        if length as usize > self.remaining() {
+            return Err(ProtocolError::Truncated {
+                length,
+                remaining: self.remaining(),
+            });
+        }
+        let end = self.pos + length as usize;
+        let s = std::str::from_utf8(&self.data[self.pos..end])
+            .map_err(|_| ProtocolError::InvalidUtf8)?
+            .to_owned();
+        self.pos = end;
+        Ok(s)
+    }
+
+    /// Check that the whole frame is consumed
+    fn finish(self) -> ProtocolResult<()> {
+        match self.remaining() {
+            0 => Ok(()),
+            n => Err(ProtocolError::TrailingBytes(n)),
+        }
+    }
 }
@@ -0,0 +1,137 @@
+use kvs::{ProtocolError, Request, Response, DEFAULT_MAX_FRAME_SIZE};
+
+fn request_roundtrip(req: Request) {
+    let frame = req.serialize(DEFAULT_MAX_FRAME_SIZE).unwrap();
+    let mut buf = &frame[..];
+    assert_eq!(
+        Request::deserialize(&mut buf, DEFAULT_MAX_FRAME_SIZE).unwrap(),
+        req
+    );
+    assert!(buf.is_empty());
+}
+
+fn response_roundtrip(resp: Response) {
+    let frame = resp.serialize(DEFAULT_MAX_FRAME_SIZE).unwrap();
+    let mut buf = &frame[..];
+    assert_eq!(
+        Response::deserialize(&mut buf, DEFAULT_MAX_FRAME_SIZE).unwrap(),
+        resp
+    );
+    assert!(buf.is_empty());
+}
+
+#[test]
+fn roundtrip() {
+    request_roundtrip(Request::Get {
+        key: "key1".to_owned(),
+    });
+    request_roundtrip(Request::Set {
+        key: "key1".to_owned(),
+        value: "value1".to_owned(),
+    });
+    request_roundtrip(Request::Remove { key: String::new() });
+    response_roundtrip(Response::Value("value1".to_owned()));
+    response_roundtrip(Response::Ok);
+    response_roundtrip(Response::Error("Key 'key1' not found".to_owned()));
+}
+
+// Values over 64 KiB used to be truncated by a u16 length
+#[test]
+fn large_value() {
+    request_roundtrip(Request::Set {
+        key: "key1".to_owned(),
+        value: "v".repeat(100_000),
+    });
+    response_roundtrip(Response::Value("v".repeat(100_000)));
+}
+
+// Frames follow each other on a stream
+#[test]
+fn consecutive_frames() {
+    let mut stream = Request::Get {
+        key: "key1".to_owned(),
+    }
+    .serialize(DEFAULT_MAX_FRAME_SIZE)
+    .unwrap();
+    stream.extend(
+        Request::Remove {
+            key: "key2".to_owned(),
+        }
+        .serialize(DEFAULT_MAX_FRAME_SIZE)
+        .unwrap(),
+    );
+    let mut buf = &stream[..];
+    assert_eq!(
+        Request::deserialize(&mut buf, DEFAULT_MAX_FRAME_SIZE).unwrap(),
+        Request::Get {
+            key: "key1".to_owned()
+        }
+    );
+    assert_eq!(
+        Request::deserialize(&mut buf, DEFAULT_MAX_FRAME_SIZE).unwrap(),
+        Request::Remove {
+            key: "key2".to_owned()
+        }
+    );
+}
+
+#[test]
+fn frame_too_large() {
+    let req = Request::Set {
+        key: "key1".to_owned(),
+        value: "v".repeat(1000),
+    };
+    match req.serialize(100) {
+        Err(ProtocolError::FrameTooLarge { max: 100, .. }) => {}
+        res => panic!("unexpected result: {:?}", res),
+    }
+
+    // the body is not read, so a huge length doesn't allocate
+    let frame = [0xff, 0xff, 0xff, 0xff, 1];
+    match Request::deserialize(&mut &frame[..], DEFAULT_MAX_FRAME_SIZE) {
+        Err(ProtocolError::FrameTooLarge { size, .. }) => assert_eq!(size, 0xffff_ffff),
+        res => panic!("unexpected result: {:?}", res),
+    }
+    let frame = Response::Value("v".repeat(1000))
+        .serialize(DEFAULT_MAX_FRAME_SIZE)
+        .unwrap();
+    match Response::deserialize(&mut &frame[..], 100) {
+        Err(ProtocolError::FrameTooLarge { .. }) => {}
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
+
+#[test]
+fn malformed_frames() {
+    let deserialize = |frame: &[u8]| Request::deserialize(&mut &frame[..], DEFAULT_MAX_FRAME_SIZE);
+    match deserialize(&[0, 0, 0, 0]) {
+        Err(ProtocolError::EmptyFrame) => {}
+        res => panic!("unexpected result: {:?}", res),
+    }
+    match deserialize(&[0, 0, 0, 1, 9]) {
+        Err(ProtocolError::InvalidType(9)) => {}
+        res => panic!("unexpected result: {:?}", res),
+    }
+    // the key length points past the end of the frame
+    match deserialize(&[0, 0, 0, 7, 1, 0, 0, 0, 10, b'k', b'e']) {
+        Err(ProtocolError::Truncated {
+            length: 10,
+            remaining: 2,
+        }) => {}
+        res => panic!("unexpected result: {:?}", res),
+    }
+    // a `Get` has a single field
+    match deserialize(&[0, 0, 0, 7, 1, 0, 0, 0, 1, b'k', b'x']) {
+        Err(ProtocolError::TrailingBytes(1)) => {}
+        res => panic!("unexpected result: {:?}", res),
+    }
+    match deserialize(&[0, 0, 0, 6, 1, 0, 0, 0, 1, 0xff]) {
+        Err(ProtocolError::InvalidUtf8) => {}
+        res => panic!("unexpected result: {:?}", res),
+    }
+    // the stream ends in the middle of the frame
+    match deserialize(&[0, 0, 0, 10, 1, 0, 0]) {
+        Err(ProtocolError::Io(_)) => {}
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
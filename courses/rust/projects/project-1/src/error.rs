@@ -0,0 +1,28 @@
+use failure::Fail;
+use std::io;
+
+/// Error type for kvs.
+#[derive(Fail, Debug)]
+pub enum KvsError {
+    /// IO error.
+    #[fail(display = "{}", _0)]
+    Io(#[cause] io::Error),
+    /// Serialization or deserialization error.
+    #[fail(display = "{}", _0)]
+    Serde(#[cause] serde_json::Error),
+}
+
+impl From<io::Error> for KvsError {
+    fn from(err: io::Error) -> KvsError {
+        KvsError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for KvsError {
+    fn from(err: serde_json::Error) -> KvsError {
+        KvsError::Serde(err)
+    }
+}
+
+/// Result type for kvs.
+pub type Result<T> = std::result::Result<T, KvsError>;
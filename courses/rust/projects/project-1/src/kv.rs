@@ -1,8 +1,19 @@
+use crate::Result;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+const SNAPSHOT_FILE: &str = "kvs.json";
 
 /// The `KvStore` stores string key/value pairs.
 ///
-/// Key/value pairs are stored in a `HashMap` in memory and not persisted to disk.
+/// Key/value pairs are stored in a `HashMap` in memory. A store created with
+/// `KvStore::new` is not persisted to disk. A store created with `KvStore::open`
+/// is loaded from a snapshot file and written back on `save` and on drop.
+///
+/// `KvStore` serializes as a plain map of its key/value pairs.
 ///
 /// Example:
 ///
@@ -13,17 +24,76 @@ use std::collections::HashMap;
 /// let val = store.get("key".to_owned());
 /// assert_eq!(val, Some("value".to_owned()));
 /// ```
-#[derive(Default)]
+#[derive(Default, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct KvStore {
     map: HashMap<String, String>,
+    // directory of the snapshot, `None` for an in-memory store
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    // whether the map changed since it was last loaded or saved
+    #[serde(skip)]
+    dirty: bool,
 }
 
 impl KvStore {
     /// Creates a `KvStore`.
     pub fn new() -> KvStore {
-        KvStore {
-            map: HashMap::new(),
-        }
+        KvStore::default()
+    }
+
+    /// Opens a `KvStore` with the given path.
+    ///
+    /// This will create a new directory if the given one does not exist, and
+    /// load the snapshot if the directory has one.
+    ///
+    /// # Errors
+    ///
+    /// It propagates I/O or deserialization errors while loading the snapshot.
+    pub fn open(path: impl Into<PathBuf>) -> Result<KvStore> {
+        let path = path.into();
+        fs::create_dir_all(&path)?;
+
+        let map = match File::open(snapshot_path(&path)) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file))?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(KvStore {
+            map,
+            path: Some(path),
+            dirty: false,
+        })
+    }
+
+    /// Writes the key/value pairs to the snapshot file.
+    ///
+    /// The snapshot is written to a temporary file which then replaces the old
+    /// one, so a crash never leaves a partially written snapshot behind. This
+    /// does nothing for a store created with `KvStore::new` or without changes
+    /// since the last save.
+    ///
+    /// Dropping the store saves it too, but ignores any error.
+    ///
+    /// # Errors
+    ///
+    /// It propagates I/O or serialization errors while writing the snapshot.
+    pub fn save(&mut self) -> Result<()> {
+        let path = match self.path {
+            Some(ref path) if self.dirty => path,
+            _ => return Ok(()),
+        };
+
+        let tmp_path = path.join(format!("{}.tmp", SNAPSHOT_FILE));
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer(&mut writer, &self.map)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        fs::rename(&tmp_path, snapshot_path(path))?;
+
+        self.dirty = false;
+        Ok(())
     }
 
     /// Sets the value of a string key to a string.
@@ -31,6 +101,7 @@ impl KvStore {
     /// If the key already exists, the previous value will be overwritten.
     pub fn set(&mut self, key: String, value: String) {
         self.map.insert(key, value);
+        self.dirty = true;
     }
 
     /// Gets the string value of a given string key.
@@ -42,6 +113,38 @@ impl KvStore {
 
     /// Remove a given key.
     pub fn remove(&mut self, key: String) {
-        self.map.remove(&key);
+        if self.map.remove(&key).is_some() {
+            self.dirty = true;
+        }
+    }
+
+    /// Returns the number of keys in the store.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the store contains no keys.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Iterates over the keys in arbitrary order.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.map.keys().map(String::as_str)
+    }
+
+    /// Iterates over the key/value pairs in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.map.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+}
+
+impl Drop for KvStore {
+    fn drop(&mut self) {
+        let _ = self.save();
     }
 }
+
+fn snapshot_path(dir: &Path) -> PathBuf {
+    dir.join(SNAPSHOT_FILE)
+}
@@ -1,7 +1,9 @@
 use assert_cmd::prelude::*;
-use kvs::KvStore;
+use kvs::{KvStore, Result};
 use predicates::str::contains;
+use std::fs;
 use std::process::Command;
+use tempfile::TempDir;
 
 // `kvs` with no args should exit with a non-zero code.
 #[test]
@@ -153,3 +155,70 @@ fn remove_key() {
     store.remove("key1".to_owned());
     assert_eq!(store.get("key1".to_owned()), None);
 }
+
+// Should get previously stored value after reopening the store
+#[test]
+fn open_persists_on_drop() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+
+    store.set("key1".to_owned(), "value1".to_owned());
+    store.set("key2".to_owned(), "value2".to_owned());
+    store.remove("key2".to_owned());
+    drop(store);
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned()), Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned()), None);
+    Ok(())
+}
+
+// `save` should replace the snapshot without leaving the temporary file behind
+#[test]
+fn save_snapshot() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+
+    store.set("key1".to_owned(), "value1".to_owned());
+    store.save()?;
+    let entries: Vec<_> = fs::read_dir(temp_dir.path())?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<std::io::Result<_>>()?;
+    assert_eq!(entries, vec!["kvs.json"]);
+
+    let other = KvStore::open(temp_dir.path())?;
+    assert_eq!(other.get("key1".to_owned()), Some("value1".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn iterate_entries() {
+    let mut store = KvStore::new();
+    assert!(store.is_empty());
+
+    store.set("key1".to_owned(), "value1".to_owned());
+    store.set("key2".to_owned(), "value2".to_owned());
+    assert_eq!(store.len(), 2);
+
+    let mut keys: Vec<_> = store.keys().collect();
+    keys.sort();
+    assert_eq!(keys, vec!["key1", "key2"]);
+
+    let mut entries: Vec<_> = store.iter().collect();
+    entries.sort();
+    assert_eq!(entries, vec![("key1", "value1"), ("key2", "value2")]);
+}
+
+// `KvStore` should (de)serialize as a map of its key/value pairs
+#[test]
+fn serde_map() {
+    let mut store = KvStore::new();
+    store.set("key1".to_owned(), "value1".to_owned());
+
+    let json = serde_json::to_string(&store).unwrap();
+    assert_eq!(json, r#"{"key1":"value1"}"#);
+
+    let store: KvStore = serde_json::from_str(r#"{"key2":"value2"}"#).unwrap();
+    assert_eq!(store.get("key2".to_owned()), Some("value2".to_owned()));
+    assert_eq!(store.len(), 1);
+}
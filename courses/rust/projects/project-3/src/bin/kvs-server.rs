@@ -3,16 +3,22 @@ extern crate log;
 #[macro_use]
 extern crate clap;
 
+use kvs::thread_pool::*;
 use kvs::*;
 use log::LevelFilter;
 use std::env::current_dir;
 use std::fs;
 use std::net::SocketAddr;
 use std::process::exit;
+use std::time::Duration;
 use structopt::StructOpt;
 
 const DEFAULT_LISTENING_ADDRESS: &str = "127.0.0.1:4000";
 const DEFAULT_ENGINE: Engine = Engine::kvs;
+const DEFAULT_THREAD_POOL: Pool = Pool::shared_queue;
+const DEFAULT_THREADS: &str = "4";
+const DEFAULT_READ_TIMEOUT: &str = "30";
+const DEFAULT_MAX_CONNECTIONS: &str = "1024";
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "kvs-server")]
@@ -32,6 +38,34 @@ struct Opt {
         raw(possible_values = "&Engine::variants()")
     )]
     engine: Option<Engine>,
+    #[structopt(
+        long,
+        help = "Sets the thread pool serving the connections",
+        value_name = "POOL-NAME",
+        raw(possible_values = "&Pool::variants()")
+    )]
+    pool: Option<Pool>,
+    #[structopt(
+        long,
+        help = "Sets the number of threads in the pool",
+        value_name = "THREADS",
+        raw(default_value = "DEFAULT_THREADS")
+    )]
+    threads: u32,
+    #[structopt(
+        long,
+        help = "Closes connections idle for this many seconds, 0 to disable",
+        value_name = "SECONDS",
+        raw(default_value = "DEFAULT_READ_TIMEOUT")
+    )]
+    read_timeout: u64,
+    #[structopt(
+        long,
+        help = "Sets the maximum number of open connections, including those waiting for a free thread, 0 to disable",
+        value_name = "N",
+        raw(default_value = "DEFAULT_MAX_CONNECTIONS")
+    )]
+    max_connections: usize,
 }
 
 arg_enum! {
@@ -43,6 +77,15 @@ arg_enum! {
     }
 }
 
+arg_enum! {
+    #[allow(non_camel_case_types)]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    enum Pool {
+        shared_queue,
+        naive
+    }
+}
+
 fn main() {
     env_logger::builder().filter_level(LevelFilter::Info).init();
     let mut opt = Opt::from_args();
@@ -64,25 +107,51 @@ fn main() {
 
 fn run(opt: Opt) -> Result<()> {
     let engine = opt.engine.unwrap_or(DEFAULT_ENGINE);
+    let pool = opt.pool.unwrap_or(DEFAULT_THREAD_POOL);
     info!("kvs-server {}", env!("CARGO_PKG_VERSION"));
     info!("Storage engine: {}", engine);
+    info!("Thread pool: {}", pool);
     info!("Listening on {}", opt.addr);
 
     // write engine to engine file
     fs::write(current_dir()?.join("engine"), format!("{}", engine))?;
 
+    match pool {
+        Pool::shared_queue => run_with_pool::<SharedQueueThreadPool>(engine, &opt),
+        Pool::naive => run_with_pool::<NaiveThreadPool>(engine, &opt),
+    }
+}
+
+fn run_with_pool<P: ThreadPool>(engine: Engine, opt: &Opt) -> Result<()> {
+    let pool = P::new(opt.threads)?;
+
     match engine {
-        Engine::kvs => run_with_engine(KvStore::open(current_dir()?)?, opt.addr),
-        Engine::sled => run_with_engine(
+        Engine::kvs => run_with(KvStore::open(current_dir()?)?, pool, opt),
+        Engine::sled => run_with(
             SledKvsEngine::new(sled::Db::start_default(current_dir()?)?),
-            opt.addr,
+            pool,
+            opt,
         ),
     }
 }
 
-fn run_with_engine<E: KvsEngine>(engine: E, addr: SocketAddr) -> Result<()> {
-    let server = KvsServer::new(engine);
-    server.run(addr)
+fn run_with<E, P>(engine: E, pool: P, opt: &Opt) -> Result<()>
+where
+    E: KvsEngine + Send + 'static,
+    P: ThreadPool,
+{
+    let read_timeout = match opt.read_timeout {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    };
+    let max_connections = match opt.max_connections {
+        0 => None,
+        n => Some(n),
+    };
+    let server = KvsServer::new(SyncEngine::new(engine), pool)
+        .read_timeout(read_timeout)
+        .max_connections(max_connections);
+    Ok(server.run(opt.addr)?)
 }
 
 fn current_engine() -> Result<Option<Engine>> {
@@ -1,5 +1,5 @@
-use crate::common::{GetResponse, RemoveResponse, Request, SetResponse};
 use crate::{KvsError, Result};
+use kvs_sync_server::protocol::{GetResponse, RemoveResponse, Request, SetResponse};
 use serde::Deserialize;
 use serde_json::de::{Deserializer, IoRead};
 use std::io::{BufReader, BufWriter, Write};
@@ -1,16 +1,13 @@
 #![deny(missing_docs)]
 //! A simple key/value store.
 
-#[macro_use]
-extern crate log;
-
 pub use client::KvsClient;
 pub use engines::{KvStore, KvsEngine, SledKvsEngine};
 pub use error::{KvsError, Result};
-pub use server::KvsServer;
+pub use kvs_sync_server::{thread_pool, KvsServer};
+pub use server::SyncEngine;
 
 mod client;
-mod common;
 mod engines;
 mod error;
 mod server;
@@ -1,69 +1,37 @@
-use crate::common::{GetResponse, RemoveResponse, Request, SetResponse};
-use crate::{KvsEngine, Result};
-use serde_json::Deserializer;
-use std::io::{BufReader, BufWriter, Write};
-use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use crate::{KvsEngine, KvsError};
+use std::sync::{Arc, Mutex};
 
-/// The server of a key value store.
-pub struct KvsServer<E: KvsEngine> {
-    engine: E,
-}
+/// Shares a storage engine of this project between the threads of `KvsServer`.
+///
+/// The engines of this project need `&mut self`, so the engine is kept behind a
+/// mutex and the requests reach it one at a time.
+pub struct SyncEngine<E: KvsEngine>(Arc<Mutex<E>>);
 
-impl<E: KvsEngine> KvsServer<E> {
-    /// Create a `KvsServer` with a given storage engine.
+impl<E: KvsEngine> SyncEngine<E> {
+    /// Creates a `SyncEngine` wrapping the given engine.
     pub fn new(engine: E) -> Self {
-        KvsServer { engine }
+        SyncEngine(Arc::new(Mutex::new(engine)))
     }
+}
 
-    /// Run the server listening on the given address
-    pub fn run<A: ToSocketAddrs>(mut self, addr: A) -> Result<()> {
-        let listener = TcpListener::bind(addr)?;
-        for stream in listener.incoming() {
-            match stream {
-                Ok(stream) => {
-                    if let Err(e) = self.serve(stream) {
-                        error!("Error on serving client: {}", e);
-                    }
-                }
-                Err(e) => error!("Connection failed: {}", e),
-            }
-        }
-        Ok(())
+impl<E: KvsEngine> Clone for SyncEngine<E> {
+    fn clone(&self) -> Self {
+        SyncEngine(Arc::clone(&self.0))
     }
+}
 
-    fn serve(&mut self, tcp: TcpStream) -> Result<()> {
-        let peer_addr = tcp.peer_addr()?;
-        let reader = BufReader::new(&tcp);
-        let mut writer = BufWriter::new(&tcp);
-        let req_reader = Deserializer::from_reader(reader).into_iter::<Request>();
+impl<E: KvsEngine + Send + 'static> kvs_sync_server::KvsEngine for SyncEngine<E> {
+    type Error = KvsError;
 
-        macro_rules! send_resp {
-            ($resp:expr) => {{
-                let resp = $resp;
-                serde_json::to_writer(&mut writer, &resp)?;
-                writer.flush()?;
-                debug!("Response sent to {}: {:?}", peer_addr, resp);
-            };};
-        }
+    fn set(&self, key: String, value: String) -> Result<(), KvsError> {
+        self.0.lock().unwrap().set(key, value)
+    }
+
+    fn get(&self, key: String) -> Result<Option<String>, KvsError> {
+        self.0.lock().unwrap().get(key)
+    }
 
-        for req in req_reader {
-            let req = req?;
-            debug!("Receive request from {}: {:?}", peer_addr, req);
-            match req {
-                Request::Get { key } => send_resp!(match self.engine.get(key) {
-                    Ok(value) => GetResponse::Ok(value),
-                    Err(e) => GetResponse::Err(format!("{}", e)),
-                }),
-                Request::Set { key, value } => send_resp!(match self.engine.set(key, value) {
-                    Ok(_) => SetResponse::Ok(()),
-                    Err(e) => SetResponse::Err(format!("{}", e)),
-                }),
-                Request::Remove { key } => send_resp!(match self.engine.remove(key) {
-                    Ok(_) => RemoveResponse::Ok(()),
-                    Err(e) => RemoveResponse::Err(format!("{}", e)),
-                }),
-            };
-        }
-        Ok(())
+    fn remove(&self, key: String) -> Result<(), KvsError> {
+        self.0.lock().unwrap().remove(key)
     }
 }
@@ -11,10 +11,14 @@ use std::env::current_dir;
 use std::fs;
 use std::net::SocketAddr;
 use std::process::exit;
+use std::time::Duration;
 use structopt::StructOpt;
 
 const DEFAULT_LISTENING_ADDRESS: &str = "127.0.0.1:4000";
 const DEFAULT_ENGINE: Engine = Engine::kvs;
+const DEFAULT_THREAD_POOL: Pool = Pool::rayon;
+const DEFAULT_READ_TIMEOUT: &str = "30";
+const DEFAULT_MAX_CONNECTIONS: &str = "1024";
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "kvs-server")]
@@ -34,6 +38,33 @@ struct Opt {
         raw(possible_values = "&Engine::variants()")
     )]
     engine: Option<Engine>,
+    #[structopt(
+        long,
+        help = "Sets the thread pool serving the connections",
+        value_name = "POOL-NAME",
+        raw(possible_values = "&Pool::variants()")
+    )]
+    pool: Option<Pool>,
+    #[structopt(
+        long,
+        help = "Sets the number of threads in the pool [default: number of CPUs]",
+        value_name = "THREADS"
+    )]
+    threads: Option<u32>,
+    #[structopt(
+        long,
+        help = "Closes connections idle for this many seconds, 0 to disable",
+        value_name = "SECONDS",
+        raw(default_value = "DEFAULT_READ_TIMEOUT")
+    )]
+    read_timeout: u64,
+    #[structopt(
+        long,
+        help = "Sets the maximum number of open connections, including those waiting for a free thread, 0 to disable",
+        value_name = "N",
+        raw(default_value = "DEFAULT_MAX_CONNECTIONS")
+    )]
+    max_connections: usize,
 }
 
 arg_enum! {
@@ -45,6 +76,16 @@ arg_enum! {
     }
 }
 
+arg_enum! {
+    #[allow(non_camel_case_types)]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    enum Pool {
+        rayon,
+        shared_queue,
+        naive
+    }
+}
+
 fn main() {
     env_logger::builder().filter_level(LevelFilter::Info).init();
     let mut opt = Opt::from_args();
@@ -66,28 +107,48 @@ fn main() {
 
 fn run(opt: Opt) -> Result<()> {
     let engine = opt.engine.unwrap_or(DEFAULT_ENGINE);
+    let pool = opt.pool.unwrap_or(DEFAULT_THREAD_POOL);
     info!("kvs-server {}", env!("CARGO_PKG_VERSION"));
     info!("Storage engine: {}", engine);
+    info!("Thread pool: {}", pool);
     info!("Listening on {}", opt.addr);
 
     // write engine to engine file
     fs::write(current_dir()?.join("engine"), format!("{}", engine))?;
 
-    let pool = RayonThreadPool::new(num_cpus::get() as u32)?;
+    match pool {
+        Pool::rayon => run_with_pool::<RayonThreadPool>(engine, &opt),
+        Pool::shared_queue => run_with_pool::<SharedQueueThreadPool>(engine, &opt),
+        Pool::naive => run_with_pool::<NaiveThreadPool>(engine, &opt),
+    }
+}
+
+fn run_with_pool<P: ThreadPool>(engine: Engine, opt: &Opt) -> Result<()> {
+    let pool = P::new(opt.threads.unwrap_or(num_cpus::get() as u32))?;
 
     match engine {
-        Engine::kvs => run_with(KvStore::open(env::current_dir()?)?, pool, opt.addr),
+        Engine::kvs => run_with(KvStore::open(env::current_dir()?)?, pool, opt),
         Engine::sled => run_with(
             SledKvsEngine::new(sled::Db::start_default(env::current_dir()?)?),
             pool,
-            opt.addr,
+            opt,
         ),
     }
 }
 
-pub fn run_with<E: KvsEngine, P: ThreadPool>(engine: E, pool: P, addr: SocketAddr) -> Result<()> {
-    let server = KvsServer::new(engine, pool);
-    server.run(addr)
+fn run_with<E: KvsEngine, P: ThreadPool>(engine: E, pool: P, opt: &Opt) -> Result<()> {
+    let read_timeout = match opt.read_timeout {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    };
+    let max_connections = match opt.max_connections {
+        0 => None,
+        n => Some(n),
+    };
+    let server = KvsServer::new(engine, pool)
+        .read_timeout(read_timeout)
+        .max_connections(max_connections);
+    Ok(server.run(opt.addr)?)
 }
 
 fn current_engine() -> Result<Option<Engine>> {
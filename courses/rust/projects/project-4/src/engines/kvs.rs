@@ -98,6 +98,8 @@ impl KvStore {
 }
 
 impl KvsEngine for KvStore {
+    type Error = KvsError;
+
     /// Sets the value of a string key to a string.
     ///
     /// If the key already exists, the previous value will be overwritten.
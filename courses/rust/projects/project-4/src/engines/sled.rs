@@ -14,6 +14,8 @@ impl SledKvsEngine {
 }
 
 impl KvsEngine for SledKvsEngine {
+    type Error = KvsError;
+
     fn set(&self, key: String, value: String) -> Result<()> {
         let tree: &Tree = &self.0;
         tree.set(key, value.into_bytes()).map(|_| ())?;
@@ -7,11 +7,8 @@ extern crate log;
 pub use client::KvsClient;
 pub use engines::{KvStore, KvsEngine, SledKvsEngine};
 pub use error::{KvsError, Result};
-pub use server::KvsServer;
+pub use kvs_sync_server::{thread_pool, KvsServer};
 
 mod client;
-mod common;
 mod engines;
 mod error;
-mod server;
-pub mod thread_pool;
@@ -0,0 +1,66 @@
+#[macro_use]
+extern crate criterion;
+
+use criterion::{Bencher, Benchmark, Criterion};
+use kvs::thread_pool::*;
+use kvs::{KvsEngine, SledKvsEngine};
+use tempfile::TempDir;
+use tokio::prelude::*;
+
+const KEY_NUM: usize = 1000;
+const THREADS: u32 = 4;
+
+type Engine = SledKvsEngine<RayonThreadPool>;
+
+fn open(temp_dir: &TempDir) -> Engine {
+    let db = sled::open(temp_dir.path()).unwrap();
+    SledKvsEngine::new(db, THREADS).unwrap()
+}
+
+/// Sets `KEY_NUM` keys concurrently with the engine built by `configure`.
+fn set(b: &mut Bencher, configure: fn(Engine) -> Engine) {
+    let temp_dir = TempDir::new().unwrap();
+    let engine = configure(open(&temp_dir));
+    b.iter(|| {
+        let sets: Vec<_> = (0..KEY_NUM)
+            .map(|i| engine.set(format!("key{}", i), "value".to_owned()))
+            .collect();
+        future::join_all(sets).wait().unwrap();
+    })
+}
+
+/// Gets `KEY_NUM` stored keys concurrently with the engine built by `configure`.
+fn get(b: &mut Bencher, configure: fn(Engine) -> Engine) {
+    let temp_dir = TempDir::new().unwrap();
+    let engine = configure(open(&temp_dir));
+    let sets: Vec<_> = (0..KEY_NUM)
+        .map(|i| engine.set(format!("key{}", i), "value".to_owned()))
+        .collect();
+    future::join_all(sets).wait().unwrap();
+    b.iter(|| {
+        let gets: Vec<_> = (0..KEY_NUM)
+            .map(|i| engine.get(format!("key{}", i)))
+            .collect();
+        for value in future::join_all(gets).wait().unwrap() {
+            assert_eq!(value, Some("value".to_owned()));
+        }
+    })
+}
+
+fn sled_bench(c: &mut Criterion) {
+    let sets = Benchmark::new("flush_every_write", |b| set(b, |engine| engine))
+        .with_function("flush_every_100", |b| {
+            set(b, |engine| engine.flush_every(100))
+        })
+        .with_function("flush_by_sled", |b| set(b, |engine| engine.flush_every(0)))
+        .sample_size(10);
+    c.bench("sled_set", sets);
+
+    let gets = Benchmark::new("pool", |b| get(b, |engine| engine))
+        .with_function("inline", |b| get(b, |engine| engine.inline_reads(true)))
+        .sample_size(10);
+    c.bench("sled_get", gets);
+}
+
+criterion_group!(benches, sled_bench);
+criterion_main!(benches);
@@ -0,0 +1,40 @@
+#[macro_use]
+extern crate criterion;
+
+use criterion::{Bencher, Criterion, ParameterizedBenchmark};
+use kvs::thread_pool::*;
+use kvs::{KvStore, KvsEngine};
+use tempfile::TempDir;
+use tokio::prelude::*;
+
+const KEY_NUM: usize = 1000;
+
+/// Sets and then gets `KEY_NUM` keys concurrently with a `KvStore` running on the pool.
+fn get_set<P: ThreadPool>(b: &mut Bencher, threads: &u32) {
+    let temp_dir = TempDir::new().unwrap();
+    let store = KvStore::<P>::open(temp_dir.path(), *threads).unwrap();
+    b.iter(|| {
+        let sets: Vec<_> = (0..KEY_NUM)
+            .map(|i| store.set(format!("key{}", i), "value".to_owned()))
+            .collect();
+        future::join_all(sets).wait().unwrap();
+        let gets: Vec<_> = (0..KEY_NUM)
+            .map(|i| store.get(format!("key{}", i)))
+            .collect();
+        for value in future::join_all(gets).wait().unwrap() {
+            assert_eq!(value, Some("value".to_owned()));
+        }
+    })
+}
+
+fn thread_pool_bench(c: &mut Criterion) {
+    let bench = ParameterizedBenchmark::new("naive", get_set::<NaiveThreadPool>, vec![2, 4, 8])
+        .with_function("shared_queue", get_set::<SharedQueueThreadPool>)
+        .with_function("rayon", get_set::<RayonThreadPool>)
+        .with_function("work_stealing", get_set::<WorkStealingThreadPool>)
+        .sample_size(10);
+    c.bench("thread_pool_get_set", bench);
+}
+
+criterion_group!(benches, thread_pool_bench);
+criterion_main!(benches);
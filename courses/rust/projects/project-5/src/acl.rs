@@ -0,0 +1,89 @@
+//! Access control of `KvsServer` clients.
+//!
+//! An ACL file is a JSON object mapping each token to the operations it may
+//! perform on keys with given prefixes:
+//!
+//! ```json
+//! {
+//!     "team-a-secret": [
+//!         { "prefix": "team-a/", "ops": ["get", "set", "remove"] },
+//!         { "prefix": "shared/", "ops": ["get"] }
+//!     ],
+//!     "admin-secret": [{ "prefix": "", "ops": ["get", "set", "remove"] }]
+//! }
+//! ```
+//!
+//! A token is allowed an operation on a key if any of its rules allows it.
+
+use crate::{KvsError, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+/// An operation on a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    /// Reading the value of a key
+    Get,
+    /// Setting the value of a key
+    Set,
+    /// Removing a key
+    Remove,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Operation::Get => "get",
+            Operation::Set => "set",
+            Operation::Remove => "remove",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Rule {
+    prefix: String,
+    ops: Vec<Operation>,
+}
+
+/// Tokens and the operations they are allowed to perform.
+///
+/// All clones share the same rules.
+#[derive(Debug, Clone)]
+pub struct Acl {
+    tokens: Arc<HashMap<String, Vec<Rule>>>,
+}
+
+impl Acl {
+    /// Loads the ACL from a JSON file.
+    pub fn open(path: &Path) -> Result<Acl> {
+        let reader = BufReader::new(File::open(path)?);
+        let tokens = serde_json::from_reader(reader)
+            .map_err(|e| KvsError::StringError(format!("Invalid ACL file {:?}: {}", path, e)))?;
+        Ok(Acl {
+            tokens: Arc::new(tokens),
+        })
+    }
+
+    /// Returns whether the token is in the ACL.
+    pub fn contains(&self, token: &str) -> bool {
+        self.tokens.contains_key(token)
+    }
+
+    /// Returns whether the token is allowed to perform `op` on `key`.
+    pub fn allows(&self, token: &str, op: Operation, key: &str) -> bool {
+        match self.tokens.get(token) {
+            Some(rules) => rules
+                .iter()
+                .any(|rule| key.starts_with(&rule.prefix) && rule.ops.contains(&op)),
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// The address of a `KvsServer`: a TCP socket address or the path of a Unix socket.
+///
+/// Parsed from `IP:PORT` or `unix:PATH`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Addr {
+    /// A TCP socket address
+    Tcp(SocketAddr),
+    /// The path of a Unix domain socket
+    Unix(PathBuf),
+}
+
+impl From<SocketAddr> for Addr {
+    fn from(addr: SocketAddr) -> Addr {
+        Addr::Tcp(addr)
+    }
+}
+
+impl FromStr for Addr {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err("Empty Unix socket path".to_owned());
+            }
+            return Ok(Addr::Unix(PathBuf::from(path)));
+        }
+        s.parse()
+            .map(Addr::Tcp)
+            .map_err(|e| format!("Invalid address {}: {}", s, e))
+    }
+}
+
+impl fmt::Display for Addr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Addr::Tcp(addr) => write!(f, "{}", addr),
+            Addr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
@@ -0,0 +1,386 @@
+use kvs::{Addr, KvsClient, KvsError, Request, Result};
+use rand::Rng;
+use serde::Serialize;
+use std::process::exit;
+use std::str::FromStr;
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+use tokio::prelude::*;
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    name = "kvs-bench",
+    about = "Measures the throughput and latency of a running kvs-server"
+)]
+struct Opt {
+    #[structopt(
+        long,
+        help = "Sets the server address",
+        value_name = "IP:PORT|unix:PATH",
+        default_value = "127.0.0.1:4000",
+        parse(try_from_str)
+    )]
+    addr: Addr,
+    #[structopt(
+        short = "c",
+        long = "concurrency",
+        help = "Sets the number of concurrent connections",
+        value_name = "N",
+        default_value = "8"
+    )]
+    concurrency: usize,
+    #[structopt(
+        short = "n",
+        long = "requests",
+        help = "Sets the total number of requests",
+        value_name = "N",
+        default_value = "100000"
+    )]
+    requests: usize,
+    #[structopt(
+        long = "read-ratio",
+        help = "Sets the fraction of requests that are reads, between 0 and 1",
+        value_name = "RATIO",
+        default_value = "0.5"
+    )]
+    read_ratio: f64,
+    #[structopt(
+        long,
+        help = "Sets the number of distinct keys",
+        value_name = "N",
+        default_value = "10000"
+    )]
+    keys: usize,
+    #[structopt(
+        long,
+        help = "Sets how keys are picked",
+        value_name = "DISTRIBUTION",
+        default_value = "uniform",
+        raw(possible_values = "&Distribution::variants()")
+    )]
+    distribution: Distribution,
+    #[structopt(
+        long = "zipf-exponent",
+        help = "Sets the skew of the zipfian distribution",
+        value_name = "S",
+        default_value = "0.99"
+    )]
+    zipf_exponent: f64,
+    #[structopt(
+        long = "value-size",
+        help = "Sets the size of written values in bytes",
+        value_name = "BYTES",
+        default_value = "100"
+    )]
+    value_size: usize,
+    #[structopt(long, help = "Sets every key once before the measurement")]
+    preload: bool,
+    #[structopt(
+        long,
+        help = "Adds a label to the report, e.g. the engine and thread pool",
+        value_name = "LABEL"
+    )]
+    label: Option<String>,
+    #[structopt(long, help = "Prints the report as JSON")]
+    json: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Distribution {
+    Uniform,
+    Zipfian,
+}
+
+impl Distribution {
+    fn variants() -> [&'static str; 2] {
+        ["uniform", "zipfian"]
+    }
+}
+
+impl FromStr for Distribution {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s {
+            "uniform" => Ok(Distribution::Uniform),
+            "zipfian" => Ok(Distribution::Zipfian),
+            _ => Err(format!("Invalid distribution: {}", s)),
+        }
+    }
+}
+
+/// Picks the key index of each request.
+#[derive(Clone)]
+enum KeyChooser {
+    Uniform(usize),
+    // cumulative probability of each key, the first key being the most popular
+    Zipfian(Arc<Vec<f64>>),
+}
+
+impl KeyChooser {
+    fn new(distribution: Distribution, keys: usize, exponent: f64) -> Self {
+        match distribution {
+            Distribution::Uniform => KeyChooser::Uniform(keys),
+            Distribution::Zipfian => {
+                let mut cdf: Vec<f64> = (1..=keys)
+                    .scan(0.0, |sum, rank| {
+                        *sum += 1.0 / (rank as f64).powf(exponent);
+                        Some(*sum)
+                    })
+                    .collect();
+                let total = cdf[keys - 1];
+                for p in &mut cdf {
+                    *p /= total;
+                }
+                KeyChooser::Zipfian(Arc::new(cdf))
+            }
+        }
+    }
+
+    fn choose<R: Rng>(&self, rng: &mut R) -> usize {
+        match self {
+            KeyChooser::Uniform(keys) => rng.gen_range(0, *keys),
+            KeyChooser::Zipfian(cdf) => {
+                let p: f64 = rng.gen();
+                let i = cdf
+                    .binary_search_by(|q| q.partial_cmp(&p).unwrap())
+                    .unwrap_or_else(|i| i);
+                i.min(cdf.len() - 1)
+            }
+        }
+    }
+}
+
+fn key_name(i: usize) -> String {
+    format!("key{:08}", i)
+}
+
+/// Results of the requests sent over one connection.
+#[derive(Default)]
+struct Stats {
+    reads: usize,
+    writes: usize,
+    errors: usize,
+    // latency of each request in nanoseconds
+    latencies: Vec<u64>,
+}
+
+#[derive(Serialize)]
+struct Report {
+    label: Option<String>,
+    concurrency: usize,
+    read_ratio: f64,
+    keys: usize,
+    distribution: Distribution,
+    value_size: usize,
+    requests: usize,
+    reads: usize,
+    writes: usize,
+    errors: usize,
+    elapsed_secs: f64,
+    throughput: f64,
+    latency_us: Latency,
+}
+
+#[derive(Serialize)]
+struct Latency {
+    mean: f64,
+    p50: f64,
+    p99: f64,
+    p999: f64,
+    max: f64,
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    if let Err(e) = run(opt) {
+        eprintln!("{}", e);
+        exit(1);
+    }
+}
+
+fn run(opt: Opt) -> Result<()> {
+    if opt.concurrency == 0 || opt.keys == 0 {
+        return Err(KvsError::StringError(
+            "Concurrency and keys must be positive".to_owned(),
+        ));
+    }
+    if !(0.0..=1.0).contains(&opt.read_ratio) {
+        return Err(KvsError::StringError(
+            "Read ratio must be between 0 and 1".to_owned(),
+        ));
+    }
+    let value = "x".repeat(opt.value_size);
+    if opt.preload {
+        preload(&opt.addr, opt.keys, &value)?;
+    }
+
+    let chooser = KeyChooser::new(opt.distribution, opt.keys, opt.zipf_exponent);
+    // connections are opened before the clock starts
+    let barrier = Arc::new(Barrier::new(opt.concurrency + 1));
+    let mut handles = Vec::with_capacity(opt.concurrency);
+    for i in 0..opt.concurrency {
+        let client = connect(&opt.addr)?;
+        let requests =
+            opt.requests / opt.concurrency + (i < opt.requests % opt.concurrency) as usize;
+        let (chooser, barrier, value) = (chooser.clone(), barrier.clone(), value.clone());
+        let (addr, read_ratio) = (opt.addr.clone(), opt.read_ratio);
+        handles.push(thread::spawn(move || {
+            barrier.wait();
+            drive(client, &addr, requests, read_ratio, &chooser, &value)
+        }));
+    }
+    barrier.wait();
+    let start = Instant::now();
+    let mut stats = Stats::default();
+    for handle in handles {
+        let conn_stats = handle
+            .join()
+            .map_err(|_| KvsError::StringError("Connection thread panicked".to_owned()))??;
+        stats.reads += conn_stats.reads;
+        stats.writes += conn_stats.writes;
+        stats.errors += conn_stats.errors;
+        stats.latencies.extend(conn_stats.latencies);
+    }
+    let elapsed = start.elapsed();
+
+    let report = report(&opt, stats, elapsed);
+    if opt.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_report(&report);
+    }
+    Ok(())
+}
+
+fn connect(addr: &Addr) -> Result<KvsClient> {
+    match addr {
+        Addr::Tcp(addr) => KvsClient::connect(*addr).wait(),
+        Addr::Unix(path) => KvsClient::connect_unix(path).wait(),
+    }
+}
+
+/// Sets every key in pipelined batches.
+fn preload(addr: &Addr, keys: usize, value: &str) -> Result<()> {
+    let mut client = connect(addr)?;
+    let mut i = 0;
+    while i < keys {
+        let end = (i + 1000).min(keys);
+        let requests = (i..end)
+            .map(|i| Request::Set {
+                key: key_name(i),
+                value: value.to_owned(),
+            })
+            .collect();
+        let (results, returned) = client.pipeline(requests).wait()?;
+        client = returned;
+        for result in results {
+            result?;
+        }
+        i = end;
+    }
+    Ok(())
+}
+
+/// Sends requests one at a time over a connection, reconnecting after failed requests.
+fn drive(
+    client: KvsClient,
+    addr: &Addr,
+    requests: usize,
+    read_ratio: f64,
+    chooser: &KeyChooser,
+    value: &str,
+) -> Result<Stats> {
+    let mut rng = rand::thread_rng();
+    let mut stats = Stats {
+        latencies: Vec::with_capacity(requests),
+        ..Stats::default()
+    };
+    let mut client = Some(client);
+    for _ in 0..requests {
+        let key = key_name(chooser.choose(&mut rng));
+        let read = rng.gen::<f64>() < read_ratio;
+        let conn = match client.take() {
+            Some(conn) => conn,
+            None => connect(addr)?,
+        };
+        let start = Instant::now();
+        let res = if read {
+            stats.reads += 1;
+            conn.get(key).map(|(_, conn)| conn).wait()
+        } else {
+            stats.writes += 1;
+            conn.set(key, value.to_owned()).wait()
+        };
+        stats.latencies.push(duration_nanos(start.elapsed()));
+        match res {
+            Ok(conn) => client = Some(conn),
+            // the client is consumed by a failed request
+            Err(_) => stats.errors += 1,
+        }
+    }
+    Ok(stats)
+}
+
+fn duration_nanos(d: Duration) -> u64 {
+    d.as_secs() * 1_000_000_000 + u64::from(d.subsec_nanos())
+}
+
+fn report(opt: &Opt, mut stats: Stats, elapsed: Duration) -> Report {
+    stats.latencies.sort_unstable();
+    let latencies = &stats.latencies;
+    let micros = |nanos: u64| nanos as f64 / 1000.0;
+    let percentile = |q: f64| match latencies.len() {
+        0 => 0.0,
+        len => {
+            let rank = (len as f64 * q).ceil() as usize;
+            micros(latencies[rank.max(1).min(len) - 1])
+        }
+    };
+    let mean = match latencies.len() {
+        0 => 0.0,
+        len => micros(latencies.iter().sum::<u64>()) / len as f64,
+    };
+    let elapsed_secs = duration_nanos(elapsed) as f64 / 1e9;
+    Report {
+        label: opt.label.clone(),
+        concurrency: opt.concurrency,
+        read_ratio: opt.read_ratio,
+        keys: opt.keys,
+        distribution: opt.distribution,
+        value_size: opt.value_size,
+        requests: latencies.len(),
+        reads: stats.reads,
+        writes: stats.writes,
+        errors: stats.errors,
+        elapsed_secs,
+        throughput: latencies.len() as f64 / elapsed_secs,
+        latency_us: Latency {
+            mean,
+            p50: percentile(0.5),
+            p99: percentile(0.99),
+            p999: percentile(0.999),
+            max: latencies.last().cloned().map(micros).unwrap_or(0.0),
+        },
+    }
+}
+
+fn print_report(report: &Report) {
+    if let Some(ref label) = report.label {
+        println!("Label:       {}", label);
+    }
+    println!(
+        "Requests:    {} ({} reads, {} writes, {} errors)",
+        report.requests, report.reads, report.writes, report.errors
+    );
+    println!("Elapsed:     {:.3} s", report.elapsed_secs);
+    println!("Throughput:  {:.0} requests/s", report.throughput);
+    let latency = &report.latency_us;
+    println!(
+        "Latency:     mean {:.0} us, p50 {:.0} us, p99 {:.0} us, p99.9 {:.0} us, max {:.0} us",
+        latency.mean, latency.p50, latency.p99, latency.p999, latency.max
+    );
+}
@@ -1,6 +1,13 @@
 use clap::AppSettings;
-use kvs::{KvsClient, Result};
-use std::net::SocketAddr;
+use kvs::dump::{read_entries, DumpFormat, DumpWriter, Entry};
+use kvs::script::{parse_line, OutputFormat, Printer};
+use kvs::{Addr, KvsClient, KvsError, Request, Result};
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::PathBuf;
 use std::process::exit;
 use structopt::StructOpt;
 use tokio::prelude::*;
@@ -26,11 +33,13 @@ enum Command {
         #[structopt(
             long,
             help = "Sets the server address",
-            value_name = "IP:PORT",
+            value_name = "IP:PORT|unix:PATH",
             default_value = "127.0.0.1:4000",
             parse(try_from_str)
         )]
-        addr: SocketAddr,
+        addr: Addr,
+        #[structopt(flatten)]
+        conn: ConnectOpt,
     },
     #[structopt(name = "set", about = "Set the value of a string key to a string")]
     Set {
@@ -41,11 +50,13 @@ enum Command {
         #[structopt(
             long,
             help = "Sets the server address",
-            value_name = "IP:PORT",
+            value_name = "IP:PORT|unix:PATH",
             default_value = "127.0.0.1:4000",
             parse(try_from_str)
         )]
-        addr: SocketAddr,
+        addr: Addr,
+        #[structopt(flatten)]
+        conn: ConnectOpt,
     },
     #[structopt(name = "rm", about = "Remove a given string key")]
     Remove {
@@ -54,42 +65,457 @@ enum Command {
         #[structopt(
             long,
             help = "Sets the server address",
-            value_name = "IP:PORT",
+            value_name = "IP:PORT|unix:PATH",
             default_value = "127.0.0.1:4000",
             parse(try_from_str)
         )]
-        addr: SocketAddr,
+        addr: Addr,
+        #[structopt(flatten)]
+        conn: ConnectOpt,
+    },
+    #[structopt(
+        name = "shell",
+        about = "Run commands interactively over one connection"
+    )]
+    Shell {
+        #[structopt(
+            long,
+            help = "Sets the server address",
+            value_name = "IP:PORT|unix:PATH",
+            default_value = "127.0.0.1:4000",
+            parse(try_from_str)
+        )]
+        addr: Addr,
+        #[structopt(flatten)]
+        conn: ConnectOpt,
+        #[structopt(
+            long,
+            help = "Sets the output format",
+            value_name = "FORMAT",
+            default_value = "plain",
+            raw(possible_values = "&OutputFormat::variants()")
+        )]
+        format: OutputFormat,
+    },
+    #[structopt(
+        name = "exec",
+        about = "Run a script of commands pipelined over one connection"
+    )]
+    Exec {
+        #[structopt(
+            short = "f",
+            long = "file",
+            help = "Reads the commands from the file instead of stdin",
+            value_name = "FILE",
+            parse(from_os_str)
+        )]
+        file: Option<PathBuf>,
+        #[structopt(
+            long,
+            help = "Sets the server address",
+            value_name = "IP:PORT|unix:PATH",
+            default_value = "127.0.0.1:4000",
+            parse(try_from_str)
+        )]
+        addr: Addr,
+        #[structopt(flatten)]
+        conn: ConnectOpt,
+        #[structopt(
+            long,
+            help = "Sets the output format",
+            value_name = "FORMAT",
+            default_value = "plain",
+            raw(possible_values = "&OutputFormat::variants()")
+        )]
+        format: OutputFormat,
+    },
+    #[structopt(
+        name = "export",
+        about = "Write the keys and values to stdout as a dump"
+    )]
+    Export {
+        #[structopt(
+            long,
+            help = "Exports only the keys starting with the prefix",
+            value_name = "PREFIX",
+            default_value = ""
+        )]
+        prefix: String,
+        #[structopt(
+            long,
+            help = "Sets the dump format",
+            value_name = "FORMAT",
+            default_value = "jsonl",
+            raw(possible_values = "&DumpFormat::variants()")
+        )]
+        format: DumpFormat,
+        #[structopt(
+            long = "batch-size",
+            help = "Sets the number of keys listed and values read per batch",
+            value_name = "N",
+            default_value = "1000"
+        )]
+        batch_size: usize,
+        #[structopt(
+            long,
+            help = "Sets the server address",
+            value_name = "IP:PORT|unix:PATH",
+            default_value = "127.0.0.1:4000",
+            parse(try_from_str)
+        )]
+        addr: Addr,
+        #[structopt(flatten)]
+        conn: ConnectOpt,
+    },
+    #[structopt(name = "import", about = "Set the keys and values of a dump file")]
+    Import {
+        #[structopt(name = "FILE", help = "The dump file", parse(from_os_str))]
+        file: PathBuf,
+        #[structopt(
+            long,
+            help = "Sets the dump format, guessed from the file extension by default",
+            value_name = "FORMAT",
+            raw(possible_values = "&DumpFormat::variants()")
+        )]
+        format: Option<DumpFormat>,
+        #[structopt(
+            long = "batch-size",
+            help = "Sets the number of values written per pipeline",
+            value_name = "N",
+            default_value = "1000"
+        )]
+        batch_size: usize,
+        #[structopt(
+            long,
+            help = "Sets the server address",
+            value_name = "IP:PORT|unix:PATH",
+            default_value = "127.0.0.1:4000",
+            parse(try_from_str)
+        )]
+        addr: Addr,
+        #[structopt(flatten)]
+        conn: ConnectOpt,
     },
 }
 
+#[derive(StructOpt, Debug)]
+struct ConnectOpt {
+    #[structopt(
+        long,
+        help = "Authenticates to the server with the token",
+        value_name = "TOKEN",
+        env = "KVS_TOKEN"
+    )]
+    token: Option<String>,
+    #[structopt(
+        long,
+        help = "Connects with TLS, verifying the server against the PEM CA certificate file",
+        value_name = "FILE",
+        parse(from_os_str)
+    )]
+    ca: Option<PathBuf>,
+    #[structopt(
+        long = "client-cert",
+        help = "Presents the PEM certificate chain file to the server",
+        value_name = "FILE",
+        raw(requires_all = r#"&["ca", "client_key"]"#),
+        parse(from_os_str)
+    )]
+    client_cert: Option<PathBuf>,
+    #[structopt(
+        long = "client-key",
+        help = "Sets the PEM private key file of the client certificate",
+        value_name = "FILE",
+        requires = "client_cert",
+        parse(from_os_str)
+    )]
+    client_key: Option<PathBuf>,
+    #[structopt(
+        long = "server-name",
+        help = "Sets the DNS name the server certificate is verified against",
+        value_name = "NAME",
+        default_value = "localhost"
+    )]
+    server_name: String,
+}
+
+/// Exit code when the server denies the request.
+const EXIT_PERMISSION_DENIED: i32 = 2;
+/// Exit code when the server is too busy to handle the request.
+const EXIT_SERVER_BUSY: i32 = 3;
+
 fn main() {
     let opt = Opt::from_args();
     if let Err(e) = run(opt) {
         eprintln!("{}", e);
-        exit(1);
+        match e {
+            KvsError::PermissionDenied(_) => exit(EXIT_PERMISSION_DENIED),
+            KvsError::ServerBusy => exit(EXIT_SERVER_BUSY),
+            _ => exit(1),
+        }
     }
 }
 
 fn run(opt: Opt) -> Result<()> {
     match opt.command {
-        Command::Get { key, addr } => {
-            let client = KvsClient::connect(addr);
+        Command::Get { key, addr, conn } => {
+            let client = connect(addr, conn)?;
             if let (Some(value), _) = client.and_then(move |client| client.get(key)).wait()? {
                 println!("{}", value);
             } else {
                 println!("Key not found");
             }
         }
-        Command::Set { key, value, addr } => {
-            let client = KvsClient::connect(addr);
+        Command::Set {
+            key,
+            value,
+            addr,
+            conn,
+        } => {
+            let client = connect(addr, conn)?;
             client
                 .and_then(move |client| client.set(key, value))
                 .wait()?;
         }
-        Command::Remove { key, addr } => {
-            let client = KvsClient::connect(addr);
+        Command::Remove { key, addr, conn } => {
+            let client = connect(addr, conn)?;
             client.and_then(move |client| client.remove(key)).wait()?;
         }
+        Command::Shell { addr, conn, format } => {
+            let client = connect(addr, conn)?.wait()?;
+            shell(client, format)?;
+        }
+        Command::Exec {
+            file,
+            addr,
+            conn,
+            format,
+        } => {
+            let input: Box<dyn BufRead> = match file {
+                Some(file) => Box::new(BufReader::new(File::open(file)?)),
+                None => Box::new(BufReader::new(io::stdin())),
+            };
+            let client = connect(addr, conn)?;
+            exec(client.wait()?, input, format)?;
+        }
+        Command::Export {
+            prefix,
+            format,
+            batch_size,
+            addr,
+            conn,
+        } => {
+            let client = connect(addr, conn)?;
+            export(client.wait()?, prefix, format, batch_size.max(1))?;
+        }
+        Command::Import {
+            file,
+            format,
+            batch_size,
+            addr,
+            conn,
+        } => {
+            let format = format.unwrap_or_else(|| DumpFormat::from_path(&file));
+            let input = File::open(file)?;
+            let client = connect(addr, conn)?;
+            import(client.wait()?, input, format, batch_size.max(1))?;
+        }
+    }
+    Ok(())
+}
+
+fn connect(
+    addr: Addr,
+    opt: ConnectOpt,
+) -> Result<Box<dyn Future<Item = KvsClient, Error = KvsError>>> {
+    let client: Box<dyn Future<Item = KvsClient, Error = KvsError>> = match (addr, opt.ca) {
+        (Addr::Tcp(addr), Some(ref ca)) => {
+            let client_cert = match (&opt.client_cert, &opt.client_key) {
+                (Some(cert), Some(key)) => Some((cert.as_path(), key.as_path())),
+                _ => None,
+            };
+            let config = kvs::tls::client_config(ca, client_cert)?;
+            Box::new(KvsClient::connect_tls(addr, &opt.server_name, config))
+        }
+        (Addr::Tcp(addr), None) => Box::new(KvsClient::connect(addr)),
+        (Addr::Unix(_), Some(_)) => {
+            return Err(KvsError::StringError(
+                "TLS is not supported over Unix sockets".to_owned(),
+            ));
+        }
+        (Addr::Unix(path), None) => Box::new(KvsClient::connect_unix(path)),
+    };
+    match opt.token {
+        Some(token) => Ok(Box::new(client.and_then(move |client| client.auth(token)))),
+        None => Ok(client),
+    }
+}
+
+/// Reads commands with line editing and runs each one when entered.
+fn shell(mut client: KvsClient, format: OutputFormat) -> Result<()> {
+    let mut editor = Editor::<()>::new();
+    let history = env::var_os("HOME").map(|home| PathBuf::from(home).join(".kvs_history"));
+    if let Some(ref history) = history {
+        // there is no history on the first run
+        let _ = editor.load_history(history);
+    }
+    let stdout = io::stdout();
+    let mut printer = Printer::new(format, stdout.lock());
+    loop {
+        let line = match editor.readline("kvs> ") {
+            Ok(line) => line,
+            // Ctrl-C discards the current line
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(KvsError::StringError(format!("{}", e))),
+        };
+        editor.add_history_entry(line.as_str());
+        if line.trim() == "exit" || line.trim() == "quit" {
+            break;
+        }
+        match parse_line(&line) {
+            Ok(Some(req)) => {
+                let (results, returned) = client.pipeline(vec![req.clone()]).wait()?;
+                client = returned;
+                printer.print(None, Some(&req), &results[0])?;
+            }
+            Ok(None) => continue,
+            Err(e) => printer.print(None, None, &Err(e))?,
+        }
+        printer.flush()?;
+    }
+    if let Some(ref history) = history {
+        if let Err(e) = editor.save_history(history) {
+            eprintln!("Failed to save history to {:?}: {}", history, e);
+        }
+    }
+    Ok(())
+}
+
+/// Runs all commands of a script in one pipeline and prints the result of each line.
+fn exec(client: KvsClient, input: Box<dyn BufRead>, format: OutputFormat) -> Result<()> {
+    // line number and the parsed request of each command line
+    let mut commands: Vec<(usize, Result<Request>)> = Vec::new();
+    for (i, line) in input.lines().enumerate() {
+        match parse_line(&line?) {
+            Ok(Some(req)) => commands.push((i + 1, Ok(req))),
+            Ok(None) => {}
+            Err(e) => commands.push((i + 1, Err(e))),
+        }
+    }
+    let requests = commands
+        .iter()
+        .filter_map(|(_, req)| req.as_ref().ok().cloned())
+        .collect();
+    let (results, _) = client.pipeline(requests).wait()?;
+
+    let stdout = io::stdout();
+    let mut printer = Printer::new(format, stdout.lock());
+    let mut results = results.into_iter();
+    let total = commands.len();
+    let mut failures = 0;
+    for (line, req) in commands {
+        let (req, result) = match req {
+            Ok(req) => (Some(req), results.next().expect("missing result")),
+            Err(e) => (None, Err(e)),
+        };
+        if result.is_err() {
+            failures += 1;
+        }
+        printer.print(Some(line), req.as_ref(), &result)?;
+    }
+    printer.flush()?;
+    if failures > 0 {
+        return Err(KvsError::StringError(format!(
+            "{} of {} commands failed",
+            failures, total
+        )));
+    }
+    Ok(())
+}
+
+/// Writes the keys starting with `prefix` and their values to stdout, listing the keys
+/// in pages and reading the values in pipelined batches of `batch_size`.
+fn export(client: KvsClient, prefix: String, format: DumpFormat, batch_size: usize) -> Result<()> {
+    let stdout = io::stdout();
+    let mut writer = DumpWriter::new(format, stdout.lock());
+    let mut client = client;
+    let mut start_after = None;
+    let mut exported = 0;
+    loop {
+        let (keys, returned) = client
+            .scan_page(prefix.clone(), start_after.take(), batch_size)
+            .wait()?;
+        let requests = keys
+            .iter()
+            .map(|key| Request::Get { key: key.clone() })
+            .collect();
+        let (results, returned) = returned.pipeline(requests).wait()?;
+        client = returned;
+        let last_page = keys.len() < batch_size;
+        start_after = keys.last().cloned();
+        for (key, result) in keys.into_iter().zip(results) {
+            // keys removed since the scan are skipped
+            if let Some(value) = result? {
+                writer.write(&Entry { key, value })?;
+                exported += 1;
+            }
+        }
+        if last_page {
+            break;
+        }
+    }
+    writer.flush()?;
+    eprintln!("Exported {} entries", exported);
+    Ok(())
+}
+
+/// Sets the entries of a dump in pipelined batches, reporting progress and failed
+/// entries to stderr.
+fn import(client: KvsClient, input: File, format: DumpFormat, batch_size: usize) -> Result<()> {
+    let mut entries = read_entries(format, input);
+    let mut client = client;
+    let mut imported = 0;
+    let mut failures = 0;
+    loop {
+        let batch: Vec<_> = entries.by_ref().take(batch_size).collect();
+        if batch.is_empty() {
+            break;
+        }
+        // line numbers of the entries sent in the pipeline
+        let mut lines = Vec::with_capacity(batch.len());
+        let mut requests = Vec::with_capacity(batch.len());
+        for (line, entry) in batch {
+            match entry {
+                Ok(Entry { key, value }) => {
+                    lines.push(line);
+                    requests.push(Request::Set { key, value });
+                }
+                Err(e) => {
+                    eprintln!("Line {}: {}", line, e);
+                    failures += 1;
+                }
+            }
+        }
+        let (results, returned) = client.pipeline(requests).wait()?;
+        client = returned;
+        for (line, result) in lines.into_iter().zip(results) {
+            match result {
+                Ok(_) => imported += 1,
+                Err(e) => {
+                    eprintln!("Line {}: {}", line, e);
+                    failures += 1;
+                }
+            }
+        }
+        eprintln!("Imported {} entries", imported);
+    }
+    if failures > 0 {
+        return Err(KvsError::StringError(format!(
+            "{} of {} entries failed",
+            failures,
+            imported + failures
+        )));
     }
     Ok(())
 }
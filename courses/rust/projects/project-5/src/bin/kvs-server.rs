@@ -3,13 +3,15 @@ extern crate log;
 #[macro_use]
 extern crate clap;
 
+use kvs::acl::Acl;
 use kvs::thread_pool::*;
-use kvs::{KvStore, KvsEngine, KvsServer, Result, SledKvsEngine};
+use kvs::{Addr, KvStore, KvsEngine, KvsServer, Result, SledKvsEngine};
 use log::LevelFilter;
 use std::env;
 use std::env::current_dir;
 use std::fs;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::process::exit;
 use structopt::StructOpt;
 
@@ -21,12 +23,13 @@ const DEFAULT_ENGINE: Engine = Engine::kvs;
 struct Opt {
     #[structopt(
         long,
-        help = "Sets the listening address",
-        value_name = "IP:PORT",
+        help = "Sets the listening address, repeated to listen on several addresses",
+        value_name = "IP:PORT|unix:PATH",
         raw(default_value = "DEFAULT_LISTENING_ADDRESS"),
+        raw(number_of_values = "1"),
         parse(try_from_str)
     )]
-    addr: SocketAddr,
+    addr: Vec<Addr>,
     #[structopt(
         long,
         help = "Sets the storage engine",
@@ -34,6 +37,77 @@ struct Opt {
         raw(possible_values = "&Engine::variants()")
     )]
     engine: Option<Engine>,
+    #[structopt(
+        long = "metrics-addr",
+        help = "Serves Prometheus metrics over HTTP on the given address",
+        value_name = "IP:PORT",
+        parse(try_from_str)
+    )]
+    metrics_addr: Option<SocketAddr>,
+    #[structopt(
+        long = "resp-addr",
+        help = "Accepts Redis protocol (RESP2) connections on the given address",
+        value_name = "IP:PORT",
+        parse(try_from_str)
+    )]
+    resp_addr: Option<SocketAddr>,
+    #[structopt(
+        long = "http-addr",
+        help = "Serves the HTTP/JSON gateway on the given address",
+        value_name = "IP:PORT",
+        parse(try_from_str)
+    )]
+    http_addr: Option<SocketAddr>,
+    #[structopt(
+        long = "tls-cert",
+        help = "Accepts only TLS connections using the PEM certificate chain file",
+        value_name = "FILE",
+        requires = "tls_key",
+        parse(from_os_str)
+    )]
+    tls_cert: Option<PathBuf>,
+    #[structopt(
+        long = "tls-key",
+        help = "Sets the PEM private key file of the TLS certificate",
+        value_name = "FILE",
+        requires = "tls_cert",
+        parse(from_os_str)
+    )]
+    tls_key: Option<PathBuf>,
+    #[structopt(
+        long = "tls-client-ca",
+        help = "Requires clients to present a certificate signed by a CA in the PEM file",
+        value_name = "FILE",
+        requires = "tls_cert",
+        parse(from_os_str)
+    )]
+    tls_client_ca: Option<PathBuf>,
+    #[structopt(
+        long,
+        help = "Requires clients to authenticate with a token in the JSON ACL file",
+        value_name = "FILE",
+        raw(conflicts_with_all = r#"&["resp_addr", "http_addr"]"#),
+        parse(from_os_str)
+    )]
+    acl: Option<PathBuf>,
+    #[structopt(
+        long = "queue-capacity",
+        help = "Bounds the queue of the engine thread pool, rejecting requests while it is full",
+        value_name = "N"
+    )]
+    queue_capacity: Option<usize>,
+    #[structopt(
+        long = "sled-inline-reads",
+        help = "Serves sled reads on the network threads instead of the engine thread pool"
+    )]
+    sled_inline_reads: bool,
+    #[structopt(
+        long = "sled-flush-every",
+        help = "Flushes sled to disk after every N writes, 0 to leave it to sled",
+        value_name = "N",
+        default_value = "1"
+    )]
+    sled_flush_every: usize,
 }
 
 arg_enum! {
@@ -68,30 +142,95 @@ fn run(opt: Opt) -> Result<()> {
     let engine = opt.engine.unwrap_or(DEFAULT_ENGINE);
     info!("kvs-server {}", env!("CARGO_PKG_VERSION"));
     info!("Storage engine: {}", engine);
-    info!("Listening on {}", opt.addr);
+    for addr in &opt.addr {
+        info!("Listening on {}", addr);
+    }
+    if let Some(ref acl) = opt.acl {
+        info!("Access control list: {}", acl.display());
+    }
+    if opt.tls_cert.is_some() {
+        info!(
+            "TLS enabled, client certificates {}",
+            if opt.tls_client_ca.is_some() {
+                "required"
+            } else {
+                "not required"
+            }
+        );
+    }
+    if let Some(metrics_addr) = opt.metrics_addr {
+        info!("Serving metrics on {}", metrics_addr);
+    }
+    if let Some(resp_addr) = opt.resp_addr {
+        info!("Listening for RESP connections on {}", resp_addr);
+    }
+    if let Some(http_addr) = opt.http_addr {
+        info!("Serving HTTP gateway on {}", http_addr);
+    }
+    if let Some(capacity) = opt.queue_capacity {
+        info!("Thread pool queue capacity: {}", capacity);
+    }
 
     // write engine to engine file
     fs::write(current_dir()?.join("engine"), format!("{}", engine))?;
 
     let concurrency = num_cpus::get() as u32;
-    match engine {
-        Engine::kvs => run_with(
+    // requests are rejected instead of queued only by a bounded thread pool
+    match (engine, opt.queue_capacity) {
+        (Engine::kvs, None) => run_with(
             KvStore::<RayonThreadPool>::open(env::current_dir()?, concurrency)?,
-            opt.addr,
+            opt,
         ),
-        Engine::sled => run_with(
-            SledKvsEngine::<RayonThreadPool>::new(
-                sled::Db::start_default(env::current_dir()?)?,
+        (Engine::kvs, Some(capacity)) => {
+            let pool = SharedQueueThreadPool::with_capacity(concurrency, capacity)?;
+            run_with(
+                KvStore::open_with_pool(env::current_dir()?, concurrency, pool)?,
+                opt,
+            )
+        }
+        (Engine::sled, None) => {
+            let engine = SledKvsEngine::<RayonThreadPool>::new(
+                sled::open(env::current_dir()?)?,
                 concurrency,
-            )?,
-            opt.addr,
-        ),
+            )?;
+            run_with(configure_sled(engine, &opt), opt)
+        }
+        (Engine::sled, Some(capacity)) => {
+            let pool = SharedQueueThreadPool::with_capacity(concurrency, capacity)?;
+            let engine = SledKvsEngine::with_pool(sled::open(env::current_dir()?)?, pool);
+            run_with(configure_sled(engine, &opt), opt)
+        }
     }
 }
 
-pub fn run_with<E: KvsEngine>(engine: E, addr: SocketAddr) -> Result<()> {
-    let server = KvsServer::new(engine);
-    server.run(addr)
+fn configure_sled<P: ThreadPool>(engine: SledKvsEngine<P>, opt: &Opt) -> SledKvsEngine<P> {
+    engine
+        .inline_reads(opt.sled_inline_reads)
+        .flush_every(opt.sled_flush_every)
+}
+
+fn run_with<E: KvsEngine>(engine: E, opt: Opt) -> Result<()> {
+    let mut server = KvsServer::new(engine);
+    if let Some(metrics_addr) = opt.metrics_addr {
+        server = server.with_metrics(metrics_addr);
+    }
+    if let Some(resp_addr) = opt.resp_addr {
+        server = server.with_resp(resp_addr);
+    }
+    if let Some(http_addr) = opt.http_addr {
+        server = server.with_http(http_addr);
+    }
+    if let Some(ref acl) = opt.acl {
+        server = server.with_acl(Acl::open(acl)?);
+    }
+    if let (Some(cert), Some(key)) = (&opt.tls_cert, &opt.tls_key) {
+        let config = kvs::tls::server_config(cert, key, opt.tls_client_ca.as_deref())?;
+        server = server.with_tls(config);
+    }
+    for addr in opt.addr {
+        server = server.with_listener(addr);
+    }
+    server.run_listeners()
 }
 
 fn current_engine() -> Result<Option<Engine>> {
@@ -1,44 +1,94 @@
 use crate::common::{Request, Response};
-use crate::KvsError;
+use crate::tls::ClientConfig;
+use crate::{KvsError, Result};
 use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
 use tokio::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
 use tokio::io::{ReadHalf, WriteHalf};
-use tokio::net::TcpStream;
+use tokio::net::{TcpStream, UnixStream};
 use tokio::prelude::*;
+use tokio_rustls::webpki::DNSNameRef;
+use tokio_rustls::TlsConnector;
 use tokio_serde_json::{ReadJson, WriteJson};
 
+/// A byte stream connected to the server, either plain TCP or TLS.
+trait Connection: AsyncRead + AsyncWrite + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Send> Connection for T {}
+
 /// Key value store client
 pub struct KvsClient {
-    read_json: ReadJson<FramedRead<ReadHalf<TcpStream>, LengthDelimitedCodec>, Response>,
-    write_json: WriteJson<FramedWrite<WriteHalf<TcpStream>, LengthDelimitedCodec>, Request>,
+    read_json: ReadJson<FramedRead<ReadHalf<Box<dyn Connection>>, LengthDelimitedCodec>, Response>,
+    write_json:
+        WriteJson<FramedWrite<WriteHalf<Box<dyn Connection>>, LengthDelimitedCodec>, Request>,
 }
 
 impl KvsClient {
     /// Connect to `addr` to access `KvsServer`.
     pub fn connect(addr: SocketAddr) -> impl Future<Item = Self, Error = KvsError> {
         TcpStream::connect(&addr)
-            .map(|tcp| {
-                let (read_half, write_half) = tcp.split();
-                let read_json =
-                    ReadJson::new(FramedRead::new(read_half, LengthDelimitedCodec::new()));
-                let write_json =
-                    WriteJson::new(FramedWrite::new(write_half, LengthDelimitedCodec::new()));
-                KvsClient {
-                    read_json,
-                    write_json,
-                }
-            })
+            .map(KvsClient::new)
             .map_err(|e| e.into())
     }
 
+    /// Connect to the Unix socket at `path` to access `KvsServer`.
+    pub fn connect_unix<P: AsRef<Path>>(path: P) -> impl Future<Item = Self, Error = KvsError> {
+        UnixStream::connect(path)
+            .map(KvsClient::new)
+            .map_err(|e| e.into())
+    }
+
+    /// Connect to `addr` to access `KvsServer` over TLS.
+    ///
+    /// The server certificate must be valid for the DNS name `domain`.
+    /// See `kvs::tls::client_config` for loading the configuration from files.
+    pub fn connect_tls(
+        addr: SocketAddr,
+        domain: &str,
+        config: Arc<ClientConfig>,
+    ) -> impl Future<Item = Self, Error = KvsError> {
+        let domain = DNSNameRef::try_from_ascii_str(domain)
+            .map(|domain| domain.to_owned())
+            .map_err(|_| KvsError::StringError(format!("Invalid DNS name: {}", domain)));
+        let connector = TlsConnector::from(config);
+        future::result(domain).and_then(move |domain| {
+            TcpStream::connect(&addr)
+                .and_then(move |tcp| connector.connect(domain.as_ref(), tcp))
+                .map(KvsClient::new)
+                .map_err(|e| e.into())
+        })
+    }
+
+    fn new<S: AsyncRead + AsyncWrite + Send + 'static>(stream: S) -> Self {
+        let stream: Box<dyn Connection> = Box::new(stream);
+        let (read_half, write_half) = stream.split();
+        let read_json = ReadJson::new(FramedRead::new(read_half, LengthDelimitedCodec::new()));
+        let write_json = WriteJson::new(FramedWrite::new(write_half, LengthDelimitedCodec::new()));
+        KvsClient {
+            read_json,
+            write_json,
+        }
+    }
+
+    /// Authenticate with a token to the server.
+    ///
+    /// Subsequent requests on this connection are checked against the access control
+    /// list of the token. Fails with `KvsError::PermissionDenied` if the token is unknown.
+    pub fn auth(self, token: String) -> impl Future<Item = Self, Error = KvsError> {
+        self.send_request(Request::Auth { token })
+            .and_then(move |(resp, client)| match resp {
+                Some(Response::Auth) => Ok(client),
+                resp => Err(response_error(resp)),
+            })
+    }
+
     /// Get the value of a given key from the server.
     pub fn get(self, key: String) -> impl Future<Item = (Option<String>, Self), Error = KvsError> {
         self.send_request(Request::Get { key })
             .and_then(move |(resp, client)| match resp {
                 Some(Response::Get(value)) => Ok((value, client)),
-                Some(Response::Err(msg)) => Err(KvsError::StringError(msg)),
-                Some(_) => Err(KvsError::StringError("Invalid response".to_owned())),
-                None => Err(KvsError::StringError("No response received".to_owned())),
+                resp => Err(response_error(resp)),
             })
     }
 
@@ -47,9 +97,7 @@ impl KvsClient {
         self.send_request(Request::Set { key, value })
             .and_then(move |(resp, client)| match resp {
                 Some(Response::Set) => Ok(client),
-                Some(Response::Err(msg)) => Err(KvsError::StringError(msg)),
-                Some(_) => Err(KvsError::StringError("Invalid response".to_owned())),
-                None => Err(KvsError::StringError("No response received".to_owned())),
+                resp => Err(response_error(resp)),
             })
     }
 
@@ -58,31 +106,157 @@ impl KvsClient {
         self.send_request(Request::Remove { key })
             .and_then(move |(resp, client)| match resp {
                 Some(Response::Remove) => Ok(client),
-                Some(Response::Err(msg)) => Err(KvsError::StringError(msg)),
-                Some(_) => Err(KvsError::StringError("Invalid response".to_owned())),
-                None => Err(KvsError::StringError("No response received".to_owned())),
+                resp => Err(response_error(resp)),
             })
     }
 
-    fn send_request(
+    /// List the keys starting with `prefix` in the server, in order.
+    ///
+    /// Keys the client is not allowed to read are not listed. All keys are sent in one
+    /// response, which fails if it exceeds the frame length limit; use `scan_page` to
+    /// list many keys.
+    pub fn scan(self, prefix: String) -> impl Future<Item = (Vec<String>, Self), Error = KvsError> {
+        self.scan_request(Request::Scan {
+            prefix,
+            start_after: None,
+            limit: None,
+        })
+    }
+
+    /// List at most `limit` keys starting with `prefix` in the server, in order.
+    ///
+    /// Only the keys after `start_after` are listed, so the next page starts after the
+    /// last key of this one. Fewer than `limit` keys are returned on the last page.
+    pub fn scan_page(
+        self,
+        prefix: String,
+        start_after: Option<String>,
+        limit: usize,
+    ) -> impl Future<Item = (Vec<String>, Self), Error = KvsError> {
+        self.scan_request(Request::Scan {
+            prefix,
+            start_after,
+            limit: Some(limit),
+        })
+    }
+
+    fn scan_request(
+        self,
+        req: Request,
+    ) -> impl Future<Item = (Vec<String>, Self), Error = KvsError> {
+        self.send_request(req)
+            .and_then(move |(resp, client)| match resp {
+                Some(Response::Scan(keys)) => Ok((keys, client)),
+                resp => Err(response_error(resp)),
+            })
+    }
+
+    /// Send requests without waiting for the response of each one in between.
+    ///
+    /// Returns the result of each request in order, which is the value for `Get`
+    /// requests and `None` for others. Error responses are returned as errors of
+    /// the requests, while an IO error fails the whole pipeline. `Scan` requests
+    /// are not supported, use `scan` instead.
+    pub fn pipeline(
+        self,
+        requests: Vec<Request>,
+    ) -> impl Future<Item = (Vec<Result<Option<String>>>, Self), Error = KvsError> {
+        let count = requests.len();
+        // responses are read while sending, so neither side blocks on a full socket buffer
+        let send = stream::iter_ok::<_, KvsError>(requests)
+            .fold(self.write_json, |write_json, req| {
+                write_json.send(req).map_err(KvsError::from)
+            });
+        let receive = future::loop_fn(
+            (self.read_json, Vec::with_capacity(count)),
+            move |(read_json, mut results)| {
+                if results.len() == count {
+                    return future::Either::A(future::ok(future::Loop::Break((
+                        read_json, results,
+                    ))));
+                }
+                future::Either::B(
+                    read_json
+                        .into_future()
+                        .map_err(|(err, _)| err.into())
+                        .and_then(move |(resp, read_json)| {
+                            let result = match resp {
+                                Some(Response::Get(value)) => Ok(value),
+                                Some(Response::Set)
+                                | Some(Response::Remove)
+                                | Some(Response::Auth) => Ok(None),
+                                Some(Response::Scan(_)) => Err(KvsError::StringError(
+                                    "Scan requests cannot be pipelined".to_owned(),
+                                )),
+                                None => {
+                                    return Err(KvsError::StringError(
+                                        "No response received".to_owned(),
+                                    ))
+                                }
+                                resp => Err(response_error(resp)),
+                            };
+                            results.push(result);
+                            Ok(future::Loop::Continue((read_json, results)))
+                        }),
+                )
+            },
+        );
+        send.join(receive)
+            .map(|(write_json, (read_json, results))| {
+                let client = KvsClient {
+                    read_json,
+                    write_json,
+                };
+                (results, client)
+            })
+    }
+
+    pub(crate) fn send_request(
         self,
         req: Request,
     ) -> impl Future<Item = (Option<Response>, Self), Error = KvsError> {
+        self.write_request(req).and_then(KvsClient::read_response)
+    }
+
+    /// Writes a request without waiting for the response.
+    ///
+    /// On error, the request has not been sent entirely, so the server ignores it.
+    pub(crate) fn write_request(self, req: Request) -> impl Future<Item = Self, Error = KvsError> {
         let read_json = self.read_json;
         self.write_json
             .send(req)
-            .and_then(move |write_json| {
-                read_json
-                    .into_future()
-                    .map(move |(resp, read_json)| {
-                        let client = KvsClient {
-                            read_json,
-                            write_json,
-                        };
-                        (resp, client)
-                    })
-                    .map_err(|(err, _)| err)
+            .map(move |write_json| KvsClient {
+                read_json,
+                write_json,
             })
             .map_err(|e| e.into())
     }
+
+    /// Reads the response to a request, or `None` if the server closed the connection.
+    pub(crate) fn read_response(
+        self,
+    ) -> impl Future<Item = (Option<Response>, Self), Error = KvsError> {
+        let write_json = self.write_json;
+        self.read_json
+            .into_future()
+            .map(move |(resp, read_json)| {
+                let client = KvsClient {
+                    read_json,
+                    write_json,
+                };
+                (resp, client)
+            })
+            .map_err(|(err, _)| err.into())
+    }
+}
+
+/// Converts a response other than the expected one into an error.
+fn response_error(resp: Option<Response>) -> KvsError {
+    match resp {
+        Some(Response::Err(msg)) => KvsError::StringError(msg),
+        Some(Response::Denied(msg)) => KvsError::PermissionDenied(msg),
+        Some(Response::ServerBusy) => KvsError::ServerBusy,
+        Some(_) => KvsError::StringError("Invalid response".to_owned()),
+        None => KvsError::StringError("No response received".to_owned()),
+    }
 }
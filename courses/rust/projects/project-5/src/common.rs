@@ -1,10 +1,55 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+/// A request to `KvsServer`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Request {
-    Get { key: String },
-    Set { key: String, value: String },
-    Remove { key: String },
+    /// Get the value of a key
+    Get {
+        /// A string key
+        key: String,
+    },
+    /// Set the value of a key
+    Set {
+        /// A string key
+        key: String,
+        /// The string value of the key
+        value: String,
+    },
+    /// Remove a key
+    Remove {
+        /// A string key
+        key: String,
+    },
+    /// List the keys starting with a prefix, in order
+    Scan {
+        /// A key prefix, which is empty to list all keys
+        prefix: String,
+        /// Only list the keys after this one, to continue a previous scan
+        #[serde(default)]
+        start_after: Option<String>,
+        /// The maximum number of keys listed, to keep a response within the frame length
+        /// limit
+        #[serde(default)]
+        limit: Option<usize>,
+    },
+    /// Authenticate the connection with a token
+    Auth {
+        /// A token in the ACL of the server
+        token: String,
+    },
+}
+
+impl Request {
+    /// Name of the request type, used as a metrics label.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Request::Get { .. } => "get",
+            Request::Set { .. } => "set",
+            Request::Remove { .. } => "remove",
+            Request::Scan { .. } => "scan",
+            Request::Auth { .. } => "auth",
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -12,5 +57,11 @@ pub enum Response {
     Get(Option<String>),
     Set,
     Remove,
+    Scan(Vec<String>),
+    Auth,
     Err(String),
+    /// The request is denied by the access control of the server
+    Denied(String),
+    /// The request is rejected because the thread pool queue of the server is full
+    ServerBusy,
 }
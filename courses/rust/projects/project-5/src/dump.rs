@@ -0,0 +1,137 @@
+//! Dump files of `kvs-client export` and `kvs-client import`.
+//!
+//! A JSON lines dump holds one `{"key": "...", "value": "..."}` object per line.
+//! A CSV dump starts with a `key,value` header row followed by one row per entry.
+
+use crate::{KvsError, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// A key value pair of a dump.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// The key
+    pub key: String,
+    /// The value of the key
+    pub value: String,
+}
+
+/// Format of a dump file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// One JSON object per line
+    Jsonl,
+    /// CSV with a `key,value` header row
+    Csv,
+}
+
+impl DumpFormat {
+    /// Names of the formats, accepted by `FromStr`.
+    pub fn variants() -> [&'static str; 2] {
+        ["jsonl", "csv"]
+    }
+
+    /// Guesses the format of a file from its extension, JSON lines unless it is `.csv`.
+    pub fn from_path(path: &Path) -> DumpFormat {
+        match path.extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => DumpFormat::Csv,
+            _ => DumpFormat::Jsonl,
+        }
+    }
+}
+
+impl FromStr for DumpFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s {
+            "jsonl" => Ok(DumpFormat::Jsonl),
+            "csv" => Ok(DumpFormat::Csv),
+            _ => Err(format!("Invalid dump format: {}", s)),
+        }
+    }
+}
+
+/// Reads the entries of a dump.
+///
+/// Each item holds the line number of the entry, so invalid entries can be reported
+/// and skipped. Empty lines of JSON lines dumps are skipped.
+pub fn read_entries<'a, R: Read + 'a>(
+    format: DumpFormat,
+    reader: R,
+) -> Box<dyn Iterator<Item = (usize, Result<Entry>)> + 'a> {
+    match format {
+        DumpFormat::Jsonl => Box::new(
+            BufReader::new(reader)
+                .lines()
+                .enumerate()
+                .filter(|(_, line)| match line {
+                    Ok(line) => !line.trim().is_empty(),
+                    Err(_) => true,
+                })
+                .map(|(i, line)| {
+                    let entry = line
+                        .map_err(KvsError::from)
+                        .and_then(|line| serde_json::from_str(&line).map_err(KvsError::from));
+                    (i + 1, entry)
+                }),
+        ),
+        DumpFormat::Csv => Box::new(csv::Reader::from_reader(reader).into_records().map(
+            |record| match record {
+                Ok(record) => {
+                    let line = record.position().map(|pos| pos.line()).unwrap_or(0);
+                    let entry = record.deserialize(None).map_err(KvsError::from);
+                    (line as usize, entry)
+                }
+                Err(e) => {
+                    let line = e.position().map(|pos| pos.line()).unwrap_or(0);
+                    (line as usize, Err(e.into()))
+                }
+            },
+        )),
+    }
+}
+
+/// Writes entries of a dump in a `DumpFormat`.
+pub struct DumpWriter<W: Write> {
+    out: Output<W>,
+}
+
+enum Output<W: Write> {
+    Jsonl(W),
+    Csv(Box<csv::Writer<W>>),
+}
+
+impl<W: Write> DumpWriter<W> {
+    /// Creates a dump writer writing to `writer`.
+    pub fn new(format: DumpFormat, writer: W) -> Self {
+        let out = match format {
+            DumpFormat::Jsonl => Output::Jsonl(writer),
+            DumpFormat::Csv => Output::Csv(Box::new(csv::Writer::from_writer(writer))),
+        };
+        DumpWriter { out }
+    }
+
+    /// Writes an entry.
+    pub fn write(&mut self, entry: &Entry) -> Result<()> {
+        match self.out {
+            Output::Jsonl(ref mut writer) => {
+                serde_json::to_writer(&mut *writer, entry)?;
+                writeln!(writer)?;
+            }
+            Output::Csv(ref mut writer) => writer.serialize(entry)?,
+        }
+        Ok(())
+    }
+
+    /// Flushes the written entries.
+    pub fn flush(&mut self) -> Result<()> {
+        match self.out {
+            Output::Jsonl(ref mut writer) => writer.flush()?,
+            Output::Csv(ref mut writer) => writer.flush()?,
+        }
+        Ok(())
+    }
+}
@@ -3,21 +3,19 @@ use std::collections::BTreeMap;
 use std::ffi::OsStr;
 use std::fs::{self, File, OpenOptions};
 use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
-use std::ops::Range;
+use std::ops::{Deref, Range};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Condvar, Mutex};
 
-use crossbeam::queue::ArrayQueue;
 use crossbeam_skiplist::SkipMap;
 use serde::{Deserialize, Serialize};
 use serde_json::Deserializer;
 use tokio::prelude::*;
-use tokio::sync::oneshot;
 
-use super::KvsEngine;
+use super::{run_in_pool, KvsEngine};
 use crate::thread_pool::ThreadPool;
-use crate::{KvsError, Result};
+use crate::{EngineStats, KvsError, Result};
 
 const COMPACTION_THRESHOLD: u64 = 1024 * 1024;
 
@@ -49,7 +47,8 @@ pub struct KvStore<P: ThreadPool> {
     index: Arc<SkipMap<String, CommandPos>>,
     writer: Arc<Mutex<KvStoreWriter>>,
     thread_pool: P,
-    reader_pool: Arc<ArrayQueue<KvStoreReader>>,
+    reader_pool: Arc<ReaderPool>,
+    compaction_stats: Arc<CompactionStats>,
 }
 
 impl<P: ThreadPool> KvStore<P> {
@@ -58,11 +57,30 @@ impl<P: ThreadPool> KvStore<P> {
     /// This will create a new directory if the given one does not exist.
     ///
     /// `concurrency` specifies how many threads at most can read the database at the same time.
+    /// More reads wait for one of them to finish.
     ///
     /// # Errors
     ///
     /// It propagates I/O or deserialization errors during the log replay.
     pub fn open(path: impl Into<PathBuf>, concurrency: u32) -> Result<Self> {
+        KvStore::open_with_pool(path, concurrency, P::new(concurrency)?)
+    }
+
+    /// Opens a `KvStore` with the given path, running operations in the given thread pool.
+    ///
+    /// This allows a thread pool created otherwise than by `ThreadPool::new`, e.g. with
+    /// a bounded queue. If the thread pool has more than `concurrency` threads, reads beyond
+    /// `concurrency` block their threads until a reader is free.
+    pub fn open_with_pool(
+        path: impl Into<PathBuf>,
+        concurrency: u32,
+        thread_pool: P,
+    ) -> Result<Self> {
+        if concurrency == 0 {
+            return Err(KvsError::StringError(
+                "concurrency must not be zero".to_owned(),
+            ));
+        }
         let path = Arc::new(path.into());
         fs::create_dir_all(&*path)?;
 
@@ -81,6 +99,7 @@ impl<P: ThreadPool> KvStore<P> {
         let current_gen = gen_list.last().unwrap_or(&0) + 1;
         let writer = new_log_file(&path, current_gen)?;
         let safe_point = Arc::new(AtomicU64::new(0));
+        let compaction_stats = Arc::new(CompactionStats::default());
 
         let reader = KvStoreReader {
             path: Arc::clone(&path),
@@ -95,14 +114,10 @@ impl<P: ThreadPool> KvStore<P> {
             uncompacted,
             path: Arc::clone(&path),
             index: Arc::clone(&index),
+            compaction_stats: Arc::clone(&compaction_stats),
         };
 
-        let thread_pool = P::new(concurrency)?;
-        let reader_pool = Arc::new(ArrayQueue::new(concurrency as usize));
-        for _ in 1..concurrency {
-            reader_pool.push(reader.clone()).unwrap();
-        }
-        reader_pool.push(reader).unwrap();
+        let reader_pool = Arc::new(ReaderPool::new(vec![reader; concurrency as usize]));
 
         Ok(KvStore {
             path,
@@ -110,6 +125,7 @@ impl<P: ThreadPool> KvStore<P> {
             writer: Arc::new(Mutex::new(writer)),
             thread_pool,
             reader_pool,
+            compaction_stats,
         })
     }
 }
@@ -124,17 +140,9 @@ impl<P: ThreadPool> KvsEngine for KvStore<P> {
     /// It propagates I/O or serialization errors during writing the log.
     fn set(&self, key: String, value: String) -> Box<dyn Future<Item = (), Error = KvsError> + Send> {
         let writer = self.writer.clone();
-        let (tx, rx) = oneshot::channel();
-        self.thread_pool.spawn(move || {
-            let res = writer.lock().unwrap().set(key, value);
-            if tx.send(res).is_err() {
-                error!("Receiving end is dropped");
-            }
-        });
-        Box::new(
-            rx.map_err(|e| KvsError::StringError(format!("{}", e)))
-                .flatten(),
-        )
+        run_in_pool(&self.thread_pool, move || {
+            writer.lock().unwrap().set(key, value)
+        })
     }
 
     /// Gets the string value of a given string key.
@@ -143,32 +151,19 @@ impl<P: ThreadPool> KvsEngine for KvStore<P> {
     fn get(&self, key: String) -> Box<dyn Future<Item = Option<String>, Error = KvsError> + Send> {
         let reader_pool = self.reader_pool.clone();
         let index = self.index.clone();
-        let (tx, rx) = oneshot::channel();
-        self.thread_pool.spawn(move || {
-            let res = (|| {
-                if let Some(cmd_pos) = index.get(&key) {
-                    let reader = reader_pool.pop().unwrap();
-                    let res = if let Command::Set { value, .. } =
-                        reader.read_command(*cmd_pos.value())?
-                    {
-                        Ok(Some(value))
-                    } else {
-                        Err(KvsError::UnexpectedCommandType)
-                    };
-                    reader_pool.push(reader).unwrap();
-                    res
+        let read = move || -> Result<Option<String>> {
+            if let Some(cmd_pos) = index.get(&key) {
+                let reader = reader_pool.take();
+                if let Command::Set { value, .. } = reader.read_command(*cmd_pos.value())? {
+                    Ok(Some(value))
                 } else {
-                    Ok(None)
+                    Err(KvsError::UnexpectedCommandType)
                 }
-            })();
-            if tx.send(res).is_err() {
-                error!("Receiving end is dropped");
+            } else {
+                Ok(None)
             }
-        });
-        Box::new(
-            rx.map_err(|e| KvsError::StringError(format!("{}", e)))
-                .flatten(),
-        )
+        };
+        run_in_pool(&self.thread_pool, read)
     }
 
     /// Removes a given key.
@@ -180,20 +175,57 @@ impl<P: ThreadPool> KvsEngine for KvStore<P> {
     /// It propagates I/O or serialization errors during writing the log.
     fn remove(&self, key: String) -> Box<dyn Future<Item = (), Error = KvsError> + Send> {
         let writer = self.writer.clone();
-        let (tx, rx) = oneshot::channel();
-        self.thread_pool.spawn(move || {
-            let res = writer.lock().unwrap().remove(key);
-            if tx.send(res).is_err() {
-                error!("Receiving end is dropped");
-            }
-        });
-        Box::new(
-            rx.map_err(|e| KvsError::StringError(format!("{}", e)))
-                .flatten(),
-        )
+        run_in_pool(&self.thread_pool, move || {
+            writer.lock().unwrap().remove(key)
+        })
+    }
+
+    /// Returns all keys starting with the given prefix in ascending order.
+    ///
+    /// The keys are read from the in-memory index, so no thread pool job is needed.
+    fn scan(&self, prefix: String) -> Box<dyn Future<Item = Vec<String>, Error = KvsError> + Send> {
+        let keys = self
+            .index
+            .range(prefix.clone()..)
+            .map(|entry| entry.key().clone())
+            .take_while(|key| key.starts_with(&prefix))
+            .collect();
+        Box::new(future::ok(keys))
+    }
+
+    /// Returns a snapshot of the engine statistics.
+    ///
+    /// The disk usage is the total size of the log files in the directory.
+    fn stats(&self) -> EngineStats {
+        let disk_usage = sorted_gen_list(&self.path)
+            .map(|gen_list| {
+                gen_list
+                    .into_iter()
+                    // a stale log file may be deleted by a compaction meanwhile
+                    .flat_map(|gen| fs::metadata(log_path(&self.path, gen)))
+                    .map(|metadata| metadata.len())
+                    .sum()
+            })
+            .unwrap_or(0);
+        EngineStats {
+            disk_usage,
+            compactions: self.compaction_stats.count.load(Ordering::SeqCst),
+            compacted_bytes: self.compaction_stats.bytes.load(Ordering::SeqCst),
+            queued_jobs: self.thread_pool.queued_jobs(),
+            queue_capacity: self.thread_pool.queue_capacity(),
+        }
     }
 }
 
+/// Counters of finished compactions, shared by the writer and all `KvStore` handles.
+#[derive(Default)]
+struct CompactionStats {
+    // number of compactions
+    count: AtomicU64,
+    // number of stale bytes removed by compactions
+    bytes: AtomicU64,
+}
+
 /// A single thread reader.
 ///
 /// Each `KvStore` instance has its own `KvStoreReader` and
@@ -264,6 +296,58 @@ impl Clone for KvStoreReader {
     }
 }
 
+/// The `KvStoreReader`s shared by the threads running reads.
+struct ReaderPool {
+    readers: Mutex<Vec<KvStoreReader>>,
+    returned: Condvar,
+}
+
+impl ReaderPool {
+    fn new(readers: Vec<KvStoreReader>) -> ReaderPool {
+        ReaderPool {
+            readers: Mutex::new(readers),
+            returned: Condvar::new(),
+        }
+    }
+
+    /// Takes a free reader, waiting for one if all of them are in use.
+    fn take(&self) -> PooledReader<'_> {
+        let mut readers = self.readers.lock().unwrap();
+        loop {
+            if let Some(reader) = readers.pop() {
+                return PooledReader {
+                    pool: self,
+                    reader: Some(reader),
+                };
+            }
+            readers = self.returned.wait(readers).unwrap();
+        }
+    }
+}
+
+/// A reader taken from a `ReaderPool`, which returns it when dropped.
+struct PooledReader<'a> {
+    pool: &'a ReaderPool,
+    reader: Option<KvStoreReader>,
+}
+
+impl Deref for PooledReader<'_> {
+    type Target = KvStoreReader;
+
+    fn deref(&self) -> &KvStoreReader {
+        self.reader.as_ref().unwrap()
+    }
+}
+
+impl Drop for PooledReader<'_> {
+    fn drop(&mut self) {
+        if let Some(reader) = self.reader.take() {
+            self.pool.readers.lock().unwrap().push(reader);
+            self.pool.returned.notify_one();
+        }
+    }
+}
+
 struct KvStoreWriter {
     reader: KvStoreReader,
     writer: BufWriterWithPos<File>,
@@ -273,6 +357,7 @@ struct KvStoreWriter {
     uncompacted: u64,
     path: Arc<PathBuf>,
     index: Arc<SkipMap<String, CommandPos>>,
+    compaction_stats: Arc<CompactionStats>,
 }
 
 impl KvStoreWriter {
@@ -361,6 +446,10 @@ impl KvStoreWriter {
                 error!("{:?} cannot be deleted: {}", file_path, e);
             }
         }
+        self.compaction_stats.count.fetch_add(1, Ordering::SeqCst);
+        self.compaction_stats
+            .bytes
+            .fetch_add(self.uncompacted, Ordering::SeqCst);
         self.uncompacted = 0;
 
         Ok(())
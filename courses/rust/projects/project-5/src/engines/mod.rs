@@ -1,13 +1,18 @@
 pub use self::kvs::KvStore;
 pub use self::sled::SledKvsEngine;
-use crate::KvsError;
+use crate::thread_pool::ThreadPool;
+use crate::{KvsError, Result};
+use serde::Serialize;
 
-use tokio::prelude::Future;
+use tokio::prelude::*;
 
 mod kvs;
 mod sled;
 
 /// Trait for a key value storage engine.
+///
+/// Engines running operations in a thread pool with a bounded queue fail them with
+/// `KvsError::ServerBusy` while the queue is full.
 pub trait KvsEngine: Clone + Send + 'static {
     /// Sets the value of a string key to a string.
     ///
@@ -25,4 +30,45 @@ pub trait KvsEngine: Clone + Send + 'static {
     ///
     /// It returns `KvsError::KeyNotFound` if the given key is not found.
     fn remove(&self, key: String) -> Box<dyn Future<Item = (), Error = KvsError> + Send>;
+
+    /// Returns all keys starting with the given prefix in ascending order.
+    fn scan(&self, prefix: String) -> Box<dyn Future<Item = Vec<String>, Error = KvsError> + Send>;
+
+    /// Returns a snapshot of the engine statistics.
+    ///
+    /// Engines which do not track some of the statistics report zero for them.
+    fn stats(&self) -> EngineStats {
+        EngineStats::default()
+    }
+}
+
+/// Statistics of a storage engine.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct EngineStats {
+    /// Total size of the data files in bytes.
+    pub disk_usage: u64,
+    /// Number of compactions finished since the engine was opened.
+    pub compactions: u64,
+    /// Number of stale bytes removed by compactions since the engine was opened.
+    pub compacted_bytes: u64,
+    /// Number of jobs waiting in the thread pool of the engine.
+    pub queued_jobs: usize,
+    /// Maximum number of jobs waiting in the thread pool, if its queue is bounded.
+    pub queue_capacity: Option<usize>,
+}
+
+/// Runs an engine operation in the thread pool.
+///
+/// Fails with `KvsError::ServerBusy` instead of waiting if the queue of the thread pool
+/// is full.
+fn run_in_pool<P, F, R>(pool: &P, op: F) -> Box<dyn Future<Item = R, Error = KvsError> + Send>
+where
+    P: ThreadPool,
+    F: FnOnce() -> Result<R> + Send + 'static,
+    R: Send + 'static,
+{
+    match pool.try_spawn_with_handle(op) {
+        Some(handle) => Box::new(handle.flatten()),
+        None => Box::new(future::err(KvsError::ServerBusy)),
+    }
 }
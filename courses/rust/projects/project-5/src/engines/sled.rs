@@ -1,14 +1,26 @@
+use super::run_in_pool;
 use crate::thread_pool::ThreadPool;
-use crate::{KvsEngine, KvsError, Result};
+use crate::{EngineStats, KvsEngine, KvsError, Result};
+use futures::compat::Compat;
 use sled::Db;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use tokio::prelude::*;
-use tokio::sync::oneshot;
 
 /// Wrapper of `sled::Db`
+///
+/// By default every operation runs in the thread pool and every write is flushed to
+/// disk before it completes. The flush is asynchronous, so the pool thread is free
+/// again once the write is applied. `inline_reads` and `flush_every` trade some of
+/// that for throughput.
 #[derive(Clone)]
 pub struct SledKvsEngine<P: ThreadPool> {
     pool: P,
     db: Db,
+    inline_reads: bool,
+    flush_every: usize,
+    // number of writes since the engine was created, shared by all clones
+    writes: Arc<AtomicUsize>,
 }
 
 impl<P: ThreadPool> SledKvsEngine<P> {
@@ -18,67 +30,122 @@ impl<P: ThreadPool> SledKvsEngine<P> {
     /// threads in the thread pool.
     pub fn new(db: Db, concurrency: u32) -> Result<Self> {
         let pool = P::new(concurrency)?;
-        Ok(SledKvsEngine { pool, db })
+        Ok(SledKvsEngine::with_pool(db, pool))
+    }
+
+    /// Creates a `SledKvsEngine` from `sled::Db`, running operations in the given thread
+    /// pool.
+    pub fn with_pool(db: Db, pool: P) -> Self {
+        SledKvsEngine {
+            pool,
+            db,
+            inline_reads: false,
+            flush_every: 1,
+            writes: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Runs `get` on the calling thread instead of the thread pool.
+    ///
+    /// Reads in sled do not wait for writers, so handing a read to the pool usually
+    /// costs more than the read itself. A read missing the page cache blocks the
+    /// calling thread on disk I/O though. `scan` still runs in the pool because its
+    /// cost grows with the number of keys returned.
+    pub fn inline_reads(mut self, inline_reads: bool) -> Self {
+        self.inline_reads = inline_reads;
+        self
+    }
+
+    /// Flushes to disk only after every `n` writes instead of after each one.
+    ///
+    /// With `0` writes are never flushed explicitly and reach the disk through the
+    /// periodic flush of sled. Either way a crash can lose the writes which were not
+    /// flushed yet.
+    pub fn flush_every(mut self, n: usize) -> Self {
+        self.flush_every = n;
+        self
+    }
+}
+
+/// Flushes `db` if the write just done is the `every`-th since the last flush.
+///
+/// The flush runs on the threads of sled, and the returned future completes once it
+/// is done.
+fn flush_write(
+    db: Db,
+    writes: &AtomicUsize,
+    every: usize,
+) -> Box<dyn Future<Item = (), Error = KvsError> + Send> {
+    if every != 0 && writes.fetch_add(1, Ordering::SeqCst) % every == every - 1 {
+        let flush = async move { db.flush_async().await };
+        Box::new(Compat::new(Box::pin(flush)).map(|_| ()).from_err())
+    } else {
+        Box::new(future::ok(()))
     }
 }
 
 impl<P: ThreadPool> KvsEngine for SledKvsEngine<P> {
     fn set(&self, key: String, value: String) -> Box<dyn Future<Item = (), Error = KvsError> + Send> {
         let db = self.db.clone();
-        let (tx, rx) = oneshot::channel();
-        self.pool.spawn(move || {
-            let res = db
-                .set(key, value.into_bytes())
-                .and_then(|_| db.flush())
-                .map(|_| ())
-                .map_err(KvsError::from);
-            if tx.send(res).is_err() {
-                error!("Receiving end is dropped");
-            }
-        });
+        let writes = Arc::clone(&self.writes);
+        let flush_every = self.flush_every;
+        let set = move || -> Result<Db> {
+            db.insert(key, value.into_bytes())?;
+            Ok(db)
+        };
         Box::new(
-            rx.map_err(|e| KvsError::StringError(format!("{}", e)))
-                .flatten(),
+            run_in_pool(&self.pool, set).and_then(move |db| flush_write(db, &writes, flush_every)),
         )
     }
 
     fn get(&self, key: String) -> Box<dyn Future<Item = Option<String>, Error = KvsError> + Send> {
         let db = self.db.clone();
-        let (tx, rx) = oneshot::channel();
-        self.pool.spawn(move || {
-            let res = (move || {
-                Ok(db
-                    .get(key)?
-                    .map(|i_vec| AsRef::<[u8]>::as_ref(&i_vec).to_vec())
-                    .map(String::from_utf8)
-                    .transpose()?)
-            })();
-            if tx.send(res).is_err() {
-                error!("Receiving end is dropped");
-            }
-        });
-        Box::new(
-            rx.map_err(|e| KvsError::StringError(format!("{}", e)))
-                .flatten(),
-        )
+        let read = move || -> Result<Option<String>> {
+            Ok(db
+                .get(key)?
+                .map(|i_vec| AsRef::<[u8]>::as_ref(&i_vec).to_vec())
+                .map(String::from_utf8)
+                .transpose()?)
+        };
+        if self.inline_reads {
+            Box::new(future::result(read()))
+        } else {
+            run_in_pool(&self.pool, read)
+        }
     }
 
     fn remove(&self, key: String) -> Box<dyn Future<Item = (), Error = KvsError> + Send> {
         let db = self.db.clone();
-        let (tx, rx) = oneshot::channel();
-        self.pool.spawn(move || {
-            let res = (|| {
-                db.del(key)?.ok_or(KvsError::KeyNotFound)?;
-                db.flush()?;
-                Ok(())
-            })();
-            if tx.send(res).is_err() {
-                error!("Receiving end is dropped");
-            }
-        });
+        let writes = Arc::clone(&self.writes);
+        let flush_every = self.flush_every;
+        let remove = move || -> Result<Db> {
+            db.remove(key)?.ok_or(KvsError::KeyNotFound)?;
+            Ok(db)
+        };
         Box::new(
-            rx.map_err(|e| KvsError::StringError(format!("{}", e)))
-                .flatten(),
+            run_in_pool(&self.pool, remove)
+                .and_then(move |db| flush_write(db, &writes, flush_every)),
         )
     }
+
+    fn scan(&self, prefix: String) -> Box<dyn Future<Item = Vec<String>, Error = KvsError> + Send> {
+        let db = self.db.clone();
+        let scan = move || -> Result<Vec<String>> {
+            db.scan_prefix(prefix)
+                .map(|res| -> Result<String> {
+                    let (key, _) = res?;
+                    Ok(String::from_utf8(AsRef::<[u8]>::as_ref(&key).to_vec())?)
+                })
+                .collect()
+        };
+        run_in_pool(&self.pool, scan)
+    }
+
+    fn stats(&self) -> EngineStats {
+        EngineStats {
+            queued_jobs: self.pool.queued_jobs(),
+            queue_capacity: self.pool.queue_capacity(),
+            ..EngineStats::default()
+        }
+    }
 }
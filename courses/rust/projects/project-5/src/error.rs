@@ -24,11 +24,42 @@ pub enum KvsError {
     /// Sled error
     #[fail(display = "sled error: {}", _0)]
     Sled(#[cause] sled::Error),
+    /// CSV reading or writing error
+    #[fail(display = "CSV error: {}", _0)]
+    Csv(#[cause] csv::Error),
+    /// A thread pool job panicked before returning its result
+    #[fail(display = "Thread pool job panicked")]
+    JobPanicked,
+    /// The thread pool queue is full, so the request is rejected instead of queued
+    #[fail(display = "Server busy")]
+    ServerBusy,
+    /// The client is not allowed to perform the request
+    #[fail(display = "Permission denied: {}", _0)]
+    PermissionDenied(String),
     /// Error with a string message
     #[fail(display = "{}", _0)]
     StringError(String),
 }
 
+impl KvsError {
+    /// Name of the error variant, used as a metrics label.
+    pub(crate) fn label(&self) -> &'static str {
+        match self {
+            KvsError::Io(_) => "io",
+            KvsError::Serde(_) => "serde",
+            KvsError::KeyNotFound => "key_not_found",
+            KvsError::UnexpectedCommandType => "unexpected_command_type",
+            KvsError::Utf8(_) => "utf8",
+            KvsError::Sled(_) => "sled",
+            KvsError::Csv(_) => "csv",
+            KvsError::JobPanicked => "job_panicked",
+            KvsError::ServerBusy => "server_busy",
+            KvsError::PermissionDenied(_) => "permission_denied",
+            KvsError::StringError(_) => "string_error",
+        }
+    }
+}
+
 impl From<io::Error> for KvsError {
     fn from(err: io::Error) -> KvsError {
         KvsError::Io(err)
@@ -53,5 +84,11 @@ impl From<sled::Error> for KvsError {
     }
 }
 
+impl From<csv::Error> for KvsError {
+    fn from(err: csv::Error) -> KvsError {
+        KvsError::Csv(err)
+    }
+}
+
 /// Result type for kvs
 pub type Result<T> = std::result::Result<T, KvsError>;
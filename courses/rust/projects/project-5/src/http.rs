@@ -0,0 +1,242 @@
+//! An HTTP/JSON gateway for `KvsEngine`s.
+//!
+//! | Request                   | Response                                 |
+//! |---------------------------|------------------------------------------|
+//! | `GET /keys/{key}`         | `{"key": "k", "value": "v"}`             |
+//! | `PUT /keys/{key}`         | `204 No Content`, body `{"value": "v"}`  |
+//! | `DELETE /keys/{key}`      | `204 No Content`                         |
+//! | `GET /keys?prefix={p}`    | `{"keys": ["k1", "k2"]}`                 |
+//! | `GET /healthz`            | `{"status": "ok"}`                       |
+//! | `GET /stats`              | engine statistics                        |
+//!
+//! Errors are replied as `{"error": "message"}` with a status code mapped from
+//! `KvsError`, e.g. `404 Not Found` for `KvsError::KeyNotFound` and
+//! `503 Service Unavailable` for `KvsError::ServerBusy`. A request body larger
+//! than 1 MiB is rejected with `413 Payload Too Large`.
+
+use crate::{KvsEngine, KvsError, Result};
+use hyper::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use hyper::service::service_fn;
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::net::SocketAddr;
+use tokio::prelude::*;
+
+type ResponseFuture = Box<dyn Future<Item = Response<Body>, Error = hyper::Error> + Send>;
+
+/// Maximum size of a request body in bytes.
+const MAX_BODY_LEN: usize = 1024 * 1024;
+
+#[derive(Deserialize)]
+struct SetBody {
+    value: String,
+}
+
+/// Creates an HTTP server serving the gateway on the given address.
+///
+/// The returned future runs the server and should be spawned onto the tokio runtime.
+pub fn serve<E: KvsEngine>(
+    addr: SocketAddr,
+    engine: E,
+) -> Result<impl Future<Item = (), Error = ()>> {
+    let new_service = move || {
+        let engine = engine.clone();
+        service_fn(move |req| route(&engine, req))
+    };
+    let server = Server::try_bind(&addr)
+        .map_err(|e| KvsError::StringError(format!("{}", e)))?
+        .serve(new_service)
+        .map_err(|e| error!("HTTP server error: {}", e));
+    Ok(server)
+}
+
+fn route<E: KvsEngine>(engine: &E, req: Request<Body>) -> ResponseFuture {
+    let path = req.uri().path().to_owned();
+    let query = req.uri().query().unwrap_or("").to_owned();
+    match (req.method(), path.as_str()) {
+        (&Method::GET, "/healthz") => reply(StatusCode::OK, &json!({ "status": "ok" })),
+        (&Method::GET, "/stats") => reply(StatusCode::OK, &engine.stats()),
+        (&Method::GET, "/keys") => {
+            let prefix = match query_param(&query, "prefix").map(form_decode) {
+                None => String::new(),
+                Some(Some(prefix)) => prefix,
+                Some(None) => {
+                    return error_reply(StatusCode::BAD_REQUEST, "Invalid prefix encoding")
+                }
+            };
+            respond(
+                engine
+                    .scan(prefix)
+                    .map(|keys| json_response(StatusCode::OK, &json!({ "keys": keys }))),
+            )
+        }
+        (method, path) if path.starts_with("/keys/") => {
+            let key = match percent_decode(&path["/keys/".len()..]) {
+                Some(ref key) if key.is_empty() => {
+                    return error_reply(StatusCode::NOT_FOUND, "Not found");
+                }
+                Some(key) => key,
+                None => return error_reply(StatusCode::BAD_REQUEST, "Invalid key encoding"),
+            };
+            match *method {
+                Method::GET => {
+                    respond(engine.get(key.clone()).and_then(move |value| match value {
+                        Some(value) => Ok(json_response(
+                            StatusCode::OK,
+                            &json!({ "key": key, "value": value }),
+                        )),
+                        None => Err(KvsError::KeyNotFound),
+                    }))
+                }
+                Method::PUT => {
+                    let engine = engine.clone();
+                    Box::new(read_body(req).and_then(move |body| {
+                        let body = match body {
+                            Some(body) => body,
+                            None => {
+                                return error_reply(
+                                    StatusCode::PAYLOAD_TOO_LARGE,
+                                    "Request body too large",
+                                );
+                            }
+                        };
+                        match serde_json::from_slice::<SetBody>(&body) {
+                            Ok(body) => respond(
+                                engine
+                                    .set(key, body.value)
+                                    .map(|_| empty_response(StatusCode::NO_CONTENT)),
+                            ),
+                            Err(e) => error_reply(StatusCode::BAD_REQUEST, &format!("{}", e)),
+                        }
+                    }))
+                }
+                Method::DELETE => respond(
+                    engine
+                        .remove(key)
+                        .map(|_| empty_response(StatusCode::NO_CONTENT)),
+                ),
+                _ => error_reply(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed"),
+            }
+        }
+        _ => error_reply(StatusCode::NOT_FOUND, "Not found"),
+    }
+}
+
+/// Reads the whole request body, or returns `None` if it is larger than `MAX_BODY_LEN`.
+///
+/// A too large body is detected from `Content-Length` before reading anything, and
+/// otherwise by stopping as soon as the received chunks exceed the limit.
+fn read_body(
+    req: Request<Body>,
+) -> Box<dyn Future<Item = Option<Vec<u8>>, Error = hyper::Error> + Send> {
+    let declared_len = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok())
+        .and_then(|len| len.parse::<u64>().ok());
+    if let Some(len) = declared_len {
+        if len > MAX_BODY_LEN as u64 {
+            return Box::new(future::ok(None));
+        }
+    }
+    // `None` as the error stands for a too large body
+    let body = req
+        .into_body()
+        .map_err(Some)
+        .fold(Vec::new(), |mut body, chunk| {
+            if body.len() + chunk.len() > MAX_BODY_LEN {
+                return Err(None);
+            }
+            body.extend_from_slice(&chunk);
+            Ok(body)
+        });
+    Box::new(body.then(|res| match res {
+        Ok(body) => Ok(Some(body)),
+        Err(None) => Ok(None),
+        Err(Some(e)) => Err(e),
+    }))
+}
+
+/// Replies the error of an engine operation with the mapped status code.
+fn respond<F>(fut: F) -> ResponseFuture
+where
+    F: Future<Item = Response<Body>, Error = KvsError> + Send + 'static,
+{
+    Box::new(fut.or_else(|e| {
+        Ok(json_response(
+            status_code(&e),
+            &json!({ "error": format!("{}", e) }),
+        ))
+    }))
+}
+
+/// Maps an engine error to the HTTP status code.
+fn status_code(err: &KvsError) -> StatusCode {
+    match err {
+        KvsError::KeyNotFound => StatusCode::NOT_FOUND,
+        KvsError::ServerBusy => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn reply<T: Serialize>(status: StatusCode, body: &T) -> ResponseFuture {
+    Box::new(future::ok(json_response(status, body)))
+}
+
+fn error_reply(status: StatusCode, msg: &str) -> ResponseFuture {
+    reply(status, &json!({ "error": msg }))
+}
+
+fn json_response<T: Serialize>(status: StatusCode, body: &T) -> Response<Body> {
+    // serializing a `serde_json::Value` or a plain struct never fails
+    let body = serde_json::to_vec(body).expect("unserializable response body");
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+fn empty_response(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// Returns the encoded value of a parameter in a `application/x-www-form-urlencoded` query.
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|pair| {
+            let mut pair = pair.splitn(2, '=');
+            Some((pair.next()?, pair.next().unwrap_or("")))
+        })
+        .find(|&(key, _)| key == name)
+        .map(|(_, value)| value)
+}
+
+/// Decodes a query parameter value. Returns `None` if its encoding is invalid.
+fn form_decode(value: &str) -> Option<String> {
+    percent_decode(&value.replace('+', " "))
+}
+
+/// Decodes `%XX` escapes. Returns `None` if the escapes or the result are invalid.
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            let hex = std::str::from_utf8(&hex).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
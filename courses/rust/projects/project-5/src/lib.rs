@@ -4,14 +4,26 @@
 #[macro_use]
 extern crate log;
 
+pub use addr::Addr;
 pub use client::KvsClient;
-pub use engines::{KvStore, KvsEngine, SledKvsEngine};
+pub use common::Request;
+pub use engines::{EngineStats, KvStore, KvsEngine, SledKvsEngine};
 pub use error::{KvsError, Result};
+pub use pool::KvsClientPool;
 pub use server::KvsServer;
 
+pub mod acl;
+mod addr;
 mod client;
 mod common;
+pub mod dump;
 mod engines;
 mod error;
+mod http;
+mod metrics;
+mod pool;
+mod resp;
+pub mod script;
 mod server;
 pub mod thread_pool;
+pub mod tls;
@@ -0,0 +1,184 @@
+use crate::{EngineStats, KvsEngine, KvsError, Result};
+use hyper::header::CONTENT_TYPE;
+use hyper::service::service_fn_ok;
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::prelude::*;
+
+/// Upper bounds of the request latency histogram buckets in seconds.
+const LATENCY_BUCKETS: [f64; 10] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.1, 1.0,
+];
+
+/// Metrics collected by a `KvsServer`.
+///
+/// All clones share the same counters.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    // request name to its latency histogram
+    requests: Arc<Mutex<BTreeMap<&'static str, Histogram>>>,
+    // error label to its count
+    errors: Arc<Mutex<BTreeMap<&'static str, u64>>>,
+}
+
+#[derive(Default)]
+struct Histogram {
+    // non-cumulative count of each bucket in `LATENCY_BUCKETS`
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Metrics {
+    /// Records a handled request of the given type.
+    pub fn observe_request(&self, name: &'static str, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        let mut requests = self.requests.lock().unwrap();
+        let histogram = requests.entry(name).or_default();
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|&le| secs <= le) {
+            histogram.buckets[i] += 1;
+        }
+        histogram.count += 1;
+        histogram.sum += secs;
+    }
+
+    /// Records an error returned to a client.
+    pub fn observe_error(&self, err: &KvsError) {
+        *self.errors.lock().unwrap().entry(err.label()).or_insert(0) += 1;
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format.
+    pub fn render(&self, stats: &EngineStats) -> String {
+        let mut out = String::new();
+        // writing to a `String` never fails
+        self.render_to(&mut out, stats).unwrap();
+        out
+    }
+
+    fn render_to(&self, out: &mut String, stats: &EngineStats) -> std::fmt::Result {
+        let requests = self.requests.lock().unwrap();
+        writeln!(out, "# HELP kvs_requests_total Number of handled requests.")?;
+        writeln!(out, "# TYPE kvs_requests_total counter")?;
+        for (name, histogram) in requests.iter() {
+            writeln!(
+                out,
+                "kvs_requests_total{{request=\"{}\"}} {}",
+                name, histogram.count
+            )?;
+        }
+        writeln!(
+            out,
+            "# HELP kvs_request_duration_seconds Latency of handled requests."
+        )?;
+        writeln!(out, "# TYPE kvs_request_duration_seconds histogram")?;
+        for (name, histogram) in requests.iter() {
+            let mut cumulative = 0;
+            for (le, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets.iter()) {
+                cumulative += count;
+                writeln!(
+                    out,
+                    "kvs_request_duration_seconds_bucket{{request=\"{}\",le=\"{}\"}} {}",
+                    name, le, cumulative
+                )?;
+            }
+            writeln!(
+                out,
+                "kvs_request_duration_seconds_bucket{{request=\"{}\",le=\"+Inf\"}} {}",
+                name, histogram.count
+            )?;
+            writeln!(
+                out,
+                "kvs_request_duration_seconds_sum{{request=\"{}\"}} {}",
+                name, histogram.sum
+            )?;
+            writeln!(
+                out,
+                "kvs_request_duration_seconds_count{{request=\"{}\"}} {}",
+                name, histogram.count
+            )?;
+        }
+        drop(requests);
+
+        writeln!(
+            out,
+            "# HELP kvs_errors_total Number of errors returned to clients."
+        )?;
+        writeln!(out, "# TYPE kvs_errors_total counter")?;
+        for (kind, count) in self.errors.lock().unwrap().iter() {
+            writeln!(out, "kvs_errors_total{{kind=\"{}\"}} {}", kind, count)?;
+        }
+
+        writeln!(
+            out,
+            "# HELP kvs_engine_disk_bytes Size of the engine data on disk."
+        )?;
+        writeln!(out, "# TYPE kvs_engine_disk_bytes gauge")?;
+        writeln!(out, "kvs_engine_disk_bytes {}", stats.disk_usage)?;
+        writeln!(
+            out,
+            "# HELP kvs_engine_compactions_total Number of finished compactions."
+        )?;
+        writeln!(out, "# TYPE kvs_engine_compactions_total counter")?;
+        writeln!(out, "kvs_engine_compactions_total {}", stats.compactions)?;
+        writeln!(
+            out,
+            "# HELP kvs_engine_compacted_bytes_total Stale bytes removed by compactions."
+        )?;
+        writeln!(out, "# TYPE kvs_engine_compacted_bytes_total counter")?;
+        writeln!(
+            out,
+            "kvs_engine_compacted_bytes_total {}",
+            stats.compacted_bytes
+        )?;
+        writeln!(
+            out,
+            "# HELP kvs_thread_pool_queued_jobs Jobs waiting in the engine thread pool."
+        )?;
+        writeln!(out, "# TYPE kvs_thread_pool_queued_jobs gauge")?;
+        writeln!(out, "kvs_thread_pool_queued_jobs {}", stats.queued_jobs)?;
+        if let Some(capacity) = stats.queue_capacity {
+            writeln!(
+                out,
+                "# HELP kvs_thread_pool_queue_capacity Maximum jobs waiting in the engine thread pool."
+            )?;
+            writeln!(out, "# TYPE kvs_thread_pool_queue_capacity gauge")?;
+            writeln!(out, "kvs_thread_pool_queue_capacity {}", capacity)?;
+        }
+        Ok(())
+    }
+}
+
+/// Creates an HTTP server serving the metrics at `GET /metrics` on `listener`.
+///
+/// The returned future runs the server and should be spawned onto the tokio runtime.
+pub fn serve<E: KvsEngine>(
+    listener: TcpListener,
+    engine: E,
+    metrics: Metrics,
+) -> Result<impl Future<Item = (), Error = ()>> {
+    let new_service = move || {
+        let engine = engine.clone();
+        let metrics = metrics.clone();
+        service_fn_ok(
+            move |req: Request<Body>| match (req.method(), req.uri().path()) {
+                (&Method::GET, "/metrics") => Response::builder()
+                    .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+                    .body(Body::from(metrics.render(&engine.stats())))
+                    .unwrap(),
+                _ => Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body(Body::empty())
+                    .unwrap(),
+            },
+        )
+    };
+    let server = Server::from_tcp(listener)
+        .map_err(|e| KvsError::StringError(format!("{}", e)))?
+        .serve(new_service)
+        .map_err(|e| error!("Metrics server error: {}", e));
+    Ok(server)
+}
@@ -0,0 +1,378 @@
+use crate::common::{Request, Response};
+use crate::tls::ClientConfig;
+use crate::{KvsClient, KvsError};
+use std::collections::VecDeque;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::prelude::*;
+use tokio::sync::oneshot;
+use tokio::timer::{Delay, Timeout};
+
+type BoxFuture<T> = Box<dyn Future<Item = T, Error = KvsError> + Send>;
+
+/// A pool of connections to a `KvsServer`.
+///
+/// Requests are sent over up to `size` connections, which are opened on demand and
+/// reopened after they break, e.g. because the server restarted. If all connections
+/// are busy, requests wait for one to be returned to the pool.
+///
+/// Unlike `KvsClient`, a failed request doesn't consume the pool, and requests failing
+/// with an IO error before they were sent, e.g. when connecting, are retried with
+/// exponential backoff. Once sent, only `Get` requests are retried, including after
+/// timeouts, because other requests may have been applied.
+///
+/// The pool uses the tokio timer, so its futures must run on a tokio runtime.
+/// All clones share the same connections.
+#[derive(Clone)]
+pub struct KvsClientPool {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    addr: SocketAddr,
+    size: usize,
+    connect_timeout: Duration,
+    request_timeout: Duration,
+    idle_timeout: Duration,
+    retries: u32,
+    backoff: Duration,
+    tls: Option<(String, Arc<ClientConfig>)>,
+    token: Option<String>,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    // returned connections and when they were last used, the most recently used last
+    idle: Vec<(KvsClient, Instant)>,
+    // number of slots taken by idle connections and `Conn`s
+    open: usize,
+    // requests waiting for a `Conn` when `open` reached the pool size
+    waiters: VecDeque<oneshot::Sender<Conn>>,
+}
+
+/// A slot of the pool taken by a request, holding a connection unless it has to be
+/// (re)opened.
+///
+/// Dropping a `Conn`, e.g. after an error or a timeout, closes the connection and
+/// frees the slot.
+struct Conn {
+    client: Option<KvsClient>,
+    shared: Arc<Shared>,
+    // cleared when the slot is passed on instead of freed
+    holds_slot: bool,
+}
+
+impl KvsClientPool {
+    /// Create a pool of connections to the server at `addr`.
+    ///
+    /// No connection is opened until the first request.
+    pub fn new(addr: SocketAddr) -> Self {
+        KvsClientPool {
+            shared: Arc::new(Shared {
+                addr,
+                size: 4,
+                connect_timeout: Duration::from_secs(3),
+                request_timeout: Duration::from_secs(5),
+                idle_timeout: Duration::from_secs(60),
+                retries: 2,
+                backoff: Duration::from_millis(50),
+                tls: None,
+                token: None,
+                state: Mutex::default(),
+            }),
+        }
+    }
+
+    /// Set the maximum number of connections. The default is 4.
+    pub fn with_size(self, size: usize) -> Self {
+        assert!(size > 0, "pool size must be positive");
+        self.configure(|shared| shared.size = size)
+    }
+
+    /// Set the timeout of opening a connection, including the TLS handshake and
+    /// authentication. The default is 3 seconds.
+    pub fn with_connect_timeout(self, timeout: Duration) -> Self {
+        self.configure(|shared| shared.connect_timeout = timeout)
+    }
+
+    /// Set the timeout of a request attempt, including waiting for a connection.
+    /// The default is 5 seconds.
+    pub fn with_request_timeout(self, timeout: Duration) -> Self {
+        self.configure(|shared| shared.request_timeout = timeout)
+    }
+
+    /// Close connections unused for the given duration, instead of reusing them.
+    /// The default is 60 seconds.
+    pub fn with_idle_timeout(self, timeout: Duration) -> Self {
+        self.configure(|shared| shared.idle_timeout = timeout)
+    }
+
+    /// Set how many times a failed request is retried, and the delay before
+    /// the first retry, which doubles on each retry. The default is 2 retries
+    /// starting at 50 milliseconds.
+    pub fn with_retries(self, retries: u32, backoff: Duration) -> Self {
+        self.configure(|shared| {
+            shared.retries = retries;
+            shared.backoff = backoff;
+        })
+    }
+
+    /// Connect with TLS. See `KvsClient::connect_tls`.
+    pub fn with_tls(self, domain: &str, config: Arc<ClientConfig>) -> Self {
+        let domain = domain.to_owned();
+        self.configure(|shared| shared.tls = Some((domain, config)))
+    }
+
+    /// Authenticate each connection with the token. See `KvsClient::auth`.
+    pub fn with_token(self, token: String) -> Self {
+        self.configure(|shared| shared.token = Some(token))
+    }
+
+    fn configure(self, f: impl FnOnce(&mut Shared)) -> Self {
+        let mut shared = Arc::try_unwrap(self.shared)
+            .unwrap_or_else(|_| panic!("the pool must be configured before it is cloned"));
+        f(&mut shared);
+        KvsClientPool {
+            shared: Arc::new(shared),
+        }
+    }
+
+    /// Get the value of a given key from the server.
+    pub fn get(&self, key: String) -> impl Future<Item = Option<String>, Error = KvsError> {
+        self.request(Request::Get { key }, true)
+            .and_then(|resp| match resp {
+                Response::Get(value) => Ok(value),
+                _ => Err(invalid_response()),
+            })
+    }
+
+    /// Set the value of a string key in the server.
+    pub fn set(&self, key: String, value: String) -> impl Future<Item = (), Error = KvsError> {
+        self.request(Request::Set { key, value }, false)
+            .and_then(|resp| match resp {
+                Response::Set => Ok(()),
+                _ => Err(invalid_response()),
+            })
+    }
+
+    /// Remove a string key in the server.
+    pub fn remove(&self, key: String) -> impl Future<Item = (), Error = KvsError> {
+        self.request(Request::Remove { key }, false)
+            .and_then(|resp| match resp {
+                Response::Remove => Ok(()),
+                _ => Err(invalid_response()),
+            })
+    }
+
+    /// Sends a request, retrying it after IO errors unless it may have been applied.
+    ///
+    /// `idempotent` requests are retried even after they were sent.
+    fn request(
+        &self,
+        req: Request,
+        idempotent: bool,
+    ) -> impl Future<Item = Response, Error = KvsError> {
+        let shared = self.shared.clone();
+        let retries = shared.retries;
+        future::loop_fn((0, shared.backoff), move |(attempt, backoff)| {
+            let sent = Arc::new(AtomicBool::new(false));
+            Shared::request(&shared, req.clone(), sent.clone()).then(move |res| -> BoxFuture<_> {
+                match res {
+                    Ok(resp) => Box::new(future::ok(future::Loop::Break(resp))),
+                    Err(KvsError::Io(ref e))
+                        if attempt < retries && (idempotent || !sent.load(Ordering::SeqCst)) =>
+                    {
+                        debug!("Retrying request in {:?} after error: {}", backoff, e);
+                        Box::new(
+                            Delay::new(Instant::now() + backoff)
+                                .map_err(|e| KvsError::StringError(format!("Timer error: {}", e)))
+                                .map(move |_| future::Loop::Continue((attempt + 1, backoff * 2))),
+                        )
+                    }
+                    Err(e) => Box::new(future::err(e)),
+                }
+            })
+        })
+    }
+}
+
+impl Shared {
+    /// Sends a request over a pooled connection.
+    ///
+    /// Error responses are returned as errors, and the connection is kept unless the
+    /// request failed with an IO or serialization error. `sent` is set once the request
+    /// may have reached the server.
+    fn request(shared: &Arc<Shared>, req: Request, sent: Arc<AtomicBool>) -> BoxFuture<Response> {
+        let attempt = Shared::checkout(shared)
+            .and_then(Conn::connect)
+            .and_then(move |mut conn| {
+                let client = conn.client.take().unwrap();
+                sent.store(true, Ordering::SeqCst);
+                client
+                    .write_request(req)
+                    .map_err(move |e| {
+                        // the server ignores a request it did not receive entirely
+                        sent.store(false, Ordering::SeqCst);
+                        e
+                    })
+                    .and_then(KvsClient::read_response)
+                    .map(move |(resp, client)| {
+                        conn.client = Some(client);
+                        (resp, conn)
+                    })
+            })
+            .and_then(|(resp, conn)| match resp {
+                // the connection is dropped, so a new one is opened for the next request
+                None => Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Connection closed by the server",
+                )
+                .into()),
+                Some(resp) => {
+                    conn.checkin();
+                    match resp {
+                        Response::Err(msg) => Err(KvsError::StringError(msg)),
+                        Response::Denied(msg) => Err(KvsError::PermissionDenied(msg)),
+                        Response::ServerBusy => Err(KvsError::ServerBusy),
+                        resp => Ok(resp),
+                    }
+                }
+            });
+        Box::new(timeout(attempt, shared.request_timeout, "Request"))
+    }
+
+    /// Takes an idle connection or a free slot, waiting for one if the pool is exhausted.
+    fn checkout(shared: &Arc<Shared>) -> BoxFuture<Conn> {
+        let mut state = shared.state.lock().unwrap();
+        if let Some((client, last_used)) = state.idle.pop() {
+            // a stale connection may have been closed by the server, so its slot is reused
+            // for a new connection
+            let client = if last_used.elapsed() < shared.idle_timeout {
+                Some(client)
+            } else {
+                None
+            };
+            return Box::new(future::ok(Conn::new(shared, client)));
+        }
+        if state.open < shared.size {
+            state.open += 1;
+            return Box::new(future::ok(Conn::new(shared, None)));
+        }
+        let (tx, rx) = oneshot::channel();
+        state.waiters.push_back(tx);
+        Box::new(rx.map_err(|_| KvsError::StringError("Connection pool is closed".to_owned())))
+    }
+}
+
+impl Conn {
+    fn new(shared: &Arc<Shared>, client: Option<KvsClient>) -> Self {
+        Conn {
+            client,
+            shared: shared.clone(),
+            holds_slot: true,
+        }
+    }
+
+    /// Opens the connection of the slot if it has none.
+    fn connect(mut self) -> BoxFuture<Conn> {
+        if self.client.is_some() {
+            return Box::new(future::ok(self));
+        }
+        let shared = self.shared.clone();
+        let client: BoxFuture<KvsClient> = match shared.tls {
+            Some((ref domain, ref config)) => {
+                Box::new(KvsClient::connect_tls(shared.addr, domain, config.clone()))
+            }
+            None => Box::new(KvsClient::connect(shared.addr)),
+        };
+        let client: BoxFuture<KvsClient> = match shared.token {
+            Some(ref token) => {
+                let token = token.clone();
+                Box::new(client.and_then(move |client| client.auth(token)))
+            }
+            None => client,
+        };
+        Box::new(
+            timeout(client, shared.connect_timeout, "Connect").map(move |client| {
+                self.client = Some(client);
+                self
+            }),
+        )
+    }
+
+    /// Returns the connection to the pool, handing it to a waiting request if any.
+    fn checkin(mut self) {
+        // the slot is passed on with the connection
+        self.holds_slot = false;
+        let mut client = self.client.take();
+        loop {
+            let mut state = self.shared.state.lock().unwrap();
+            let waiter = match state.waiters.pop_front() {
+                Some(waiter) => waiter,
+                None => {
+                    if let Some(client) = client {
+                        state.idle.push((client, Instant::now()));
+                    }
+                    return;
+                }
+            };
+            drop(state);
+            match waiter.send(Conn::new(&self.shared, client)) {
+                Ok(()) => return,
+                // the waiting request timed out, try the next one
+                Err(mut conn) => {
+                    conn.holds_slot = false;
+                    client = conn.client.take();
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Conn {
+    fn drop(&mut self) {
+        if !self.holds_slot {
+            return;
+        }
+        let waiter = {
+            let mut state = self.shared.state.lock().unwrap();
+            let waiter = state.waiters.pop_front();
+            if waiter.is_none() {
+                state.open -= 1;
+            }
+            waiter
+        };
+        if let Some(waiter) = waiter {
+            // The freed slot is handed over for a new connection. If the waiting request
+            // timed out, the returned `Conn` is dropped and handed over to the next one.
+            let _ = waiter.send(Conn::new(&self.shared, None));
+        }
+    }
+}
+
+fn timeout<F>(
+    fut: F,
+    duration: Duration,
+    what: &'static str,
+) -> impl Future<Item = F::Item, Error = KvsError>
+where
+    F: Future<Error = KvsError>,
+{
+    Timeout::new(fut, duration).map_err(move |e| {
+        if e.is_elapsed() {
+            io::Error::new(io::ErrorKind::TimedOut, format!("{} timed out", what)).into()
+        } else if e.is_inner() {
+            e.into_inner().unwrap()
+        } else {
+            KvsError::StringError(format!("Timer error: {}", e.into_timer().unwrap()))
+        }
+    })
+}
+
+fn invalid_response() -> KvsError {
+    KvsError::StringError("Invalid response".to_owned())
+}
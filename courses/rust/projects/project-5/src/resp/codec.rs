@@ -0,0 +1,289 @@
+use bytes::BytesMut;
+use std::io;
+use std::str;
+use tokio::codec::{Decoder, Encoder};
+
+/// Maximum length of a bulk string, the same as Redis.
+const MAX_BULK_LEN: i64 = 512 * 1024 * 1024;
+/// Maximum number of elements in an array.
+const MAX_ARRAY_LEN: i64 = 1024 * 1024;
+/// Maximum length of a line without CRLF, including inline commands.
+const MAX_LINE_LEN: usize = 64 * 1024;
+/// Maximum number of arrays nested in a value.
+const MAX_DEPTH: usize = 32;
+
+/// A RESP2 value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// Simple string, e.g. `+OK\r\n`
+    Simple(String),
+    /// Error reply, e.g. `-ERR unknown command\r\n`
+    Error(String),
+    /// Integer, e.g. `:1\r\n`
+    Integer(i64),
+    /// Bulk string, e.g. `$5\r\nhello\r\n`
+    Bulk(Vec<u8>),
+    /// Null bulk string, `$-1\r\n`
+    Nil,
+    /// Array of values, e.g. `*1\r\n$4\r\nPING\r\n`
+    Array(Vec<Value>),
+}
+
+/// Encodes and decodes RESP2 values.
+///
+/// Besides arrays, the decoder also accepts inline commands, which are lines
+/// of space-separated arguments, so the server can be used through telnet.
+#[derive(Default)]
+pub struct RespCodec {
+    scan: Scan,
+}
+
+impl Decoder for RespCodec {
+    type Item = Value;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> io::Result<Option<Value>> {
+        let parsed = if buf.is_empty() {
+            None
+        } else if is_type_byte(buf[0]) {
+            match self.scan.frame_len(buf)? {
+                Some(len) => parse(&buf[..len], 0)?,
+                None => None,
+            }
+        } else {
+            parse_inline(buf)?
+        };
+        Ok(parsed.map(|(value, len)| {
+            buf.split_to(len);
+            value
+        }))
+    }
+}
+
+impl Encoder for RespCodec {
+    type Item = Value;
+    type Error = io::Error;
+
+    fn encode(&mut self, value: Value, dst: &mut BytesMut) -> io::Result<()> {
+        write_value(&value, dst);
+        Ok(())
+    }
+}
+
+fn is_type_byte(b: u8) -> bool {
+    b == b'+' || b == b'-' || b == b':' || b == b'$' || b == b'*'
+}
+
+/// Progress of the search for the end of the frame at the start of the buffer.
+///
+/// It is kept between calls to `decode`, so a large frame arriving in many reads
+/// is scanned only once.
+#[derive(Default)]
+struct Scan {
+    // position of the first value not scanned yet
+    pos: usize,
+    // number of values still expected by each array being scanned, outermost first
+    remaining: Vec<i64>,
+}
+
+impl Scan {
+    /// Returns the length of the frame, or `None` if more bytes are needed.
+    ///
+    /// Only the framing is checked here: lengths, CRLFs and the nesting depth.
+    fn frame_len(&mut self, buf: &[u8]) -> io::Result<Option<usize>> {
+        loop {
+            let pos = self.pos;
+            if pos >= buf.len() {
+                return Ok(None);
+            }
+            let (line, next) = match read_line(buf, pos + 1)? {
+                Some(res) => res,
+                None => return Ok(None),
+            };
+            let next = match buf[pos] {
+                b'+' | b'-' | b':' => next,
+                b'$' => match parse_int(line)? {
+                    -1 => next,
+                    len if !(0..=MAX_BULK_LEN).contains(&len) => {
+                        return Err(protocol_error("invalid bulk length"));
+                    }
+                    len => {
+                        let end = next + len as usize;
+                        if buf.len() < end + 2 {
+                            return Ok(None);
+                        }
+                        end + 2
+                    }
+                },
+                b'*' => match parse_int(line)? {
+                    -1 | 0 => next,
+                    len if !(0..=MAX_ARRAY_LEN).contains(&len) => {
+                        return Err(protocol_error("invalid multibulk length"));
+                    }
+                    _ if self.remaining.len() >= MAX_DEPTH => {
+                        return Err(protocol_error("nesting too deep"));
+                    }
+                    len => {
+                        self.remaining.push(len);
+                        self.pos = next;
+                        continue;
+                    }
+                },
+                b => {
+                    return Err(protocol_error(&format!(
+                        "unexpected type byte '{}'",
+                        char::from(b)
+                    )));
+                }
+            };
+            self.pos = next;
+
+            // a complete value may complete the arrays containing it too
+            loop {
+                match self.remaining.last_mut() {
+                    None => {
+                        self.pos = 0;
+                        return Ok(Some(next));
+                    }
+                    Some(n) if *n > 1 => {
+                        *n -= 1;
+                        break;
+                    }
+                    Some(_) => {
+                        self.remaining.pop();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Parses a value starting at `pos`.
+///
+/// Returns the value and the position after it, or `None` if more bytes are needed.
+/// The nesting depth is not limited here, so `buf` must have been checked by `Scan`.
+fn parse(buf: &[u8], pos: usize) -> io::Result<Option<(Value, usize)>> {
+    if pos >= buf.len() {
+        return Ok(None);
+    }
+    let (line, mut next) = match read_line(buf, pos + 1)? {
+        Some(res) => res,
+        None => return Ok(None),
+    };
+    let value = match buf[pos] {
+        b'+' => Value::Simple(to_str(line)?.to_owned()),
+        b'-' => Value::Error(to_str(line)?.to_owned()),
+        b':' => Value::Integer(parse_int(line)?),
+        b'$' => match parse_int(line)? {
+            -1 => Value::Nil,
+            len if !(0..=MAX_BULK_LEN).contains(&len) => {
+                return Err(protocol_error("invalid bulk length"));
+            }
+            len => {
+                let end = next + len as usize;
+                if buf.len() < end + 2 {
+                    return Ok(None);
+                }
+                if &buf[end..end + 2] != b"\r\n" {
+                    return Err(protocol_error("expected CRLF after bulk string"));
+                }
+                let bulk = buf[next..end].to_vec();
+                next = end + 2;
+                Value::Bulk(bulk)
+            }
+        },
+        b'*' => match parse_int(line)? {
+            -1 => Value::Nil,
+            len if !(0..=MAX_ARRAY_LEN).contains(&len) => {
+                return Err(protocol_error("invalid multibulk length"));
+            }
+            len => {
+                let mut values = Vec::with_capacity(len as usize);
+                for _ in 0..len {
+                    match parse(buf, next)? {
+                        Some((value, after)) => {
+                            values.push(value);
+                            next = after;
+                        }
+                        None => return Ok(None),
+                    }
+                }
+                Value::Array(values)
+            }
+        },
+        b => {
+            return Err(protocol_error(&format!(
+                "unexpected type byte '{}'",
+                char::from(b)
+            )));
+        }
+    };
+    Ok(Some((value, next)))
+}
+
+/// Parses an inline command into an array of bulk strings.
+fn parse_inline(buf: &[u8]) -> io::Result<Option<(Value, usize)>> {
+    let end = match buf.iter().position(|&b| b == b'\n') {
+        Some(end) => end,
+        None if buf.len() > MAX_LINE_LEN => return Err(protocol_error("too big inline request")),
+        None => return Ok(None),
+    };
+    let line = to_str(&buf[..end])?;
+    let args = line
+        .split_whitespace()
+        .map(|arg| Value::Bulk(arg.as_bytes().to_vec()))
+        .collect();
+    Ok(Some((Value::Array(args), end + 1)))
+}
+
+/// Reads a line terminated by CRLF starting at `pos`.
+///
+/// Returns the line without CRLF and the position after CRLF.
+fn read_line(buf: &[u8], pos: usize) -> io::Result<Option<(&[u8], usize)>> {
+    match buf[pos..].windows(2).position(|w| w == b"\r\n") {
+        Some(len) => Ok(Some((&buf[pos..pos + len], pos + len + 2))),
+        None if buf.len() - pos > MAX_LINE_LEN => Err(protocol_error("too long line")),
+        None => Ok(None),
+    }
+}
+
+fn to_str(bytes: &[u8]) -> io::Result<&str> {
+    str::from_utf8(bytes).map_err(|_| protocol_error("invalid UTF-8"))
+}
+
+fn parse_int(bytes: &[u8]) -> io::Result<i64> {
+    to_str(bytes)?
+        .parse()
+        .map_err(|_| protocol_error("invalid integer"))
+}
+
+fn protocol_error(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn write_value(value: &Value, dst: &mut BytesMut) {
+    match value {
+        Value::Simple(s) => write_line(dst, b'+', s.as_bytes()),
+        Value::Error(s) => write_line(dst, b'-', s.as_bytes()),
+        Value::Integer(i) => write_line(dst, b':', i.to_string().as_bytes()),
+        Value::Bulk(bulk) => {
+            write_line(dst, b'$', bulk.len().to_string().as_bytes());
+            dst.extend_from_slice(bulk);
+            dst.extend_from_slice(b"\r\n");
+        }
+        Value::Nil => dst.extend_from_slice(b"$-1\r\n"),
+        Value::Array(values) => {
+            write_line(dst, b'*', values.len().to_string().as_bytes());
+            for value in values {
+                write_value(value, dst);
+            }
+        }
+    }
+}
+
+fn write_line(dst: &mut BytesMut, type_byte: u8, line: &[u8]) {
+    dst.reserve(line.len() + 3);
+    dst.extend_from_slice(&[type_byte]);
+    dst.extend_from_slice(line);
+    dst.extend_from_slice(b"\r\n");
+}
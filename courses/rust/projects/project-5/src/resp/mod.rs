@@ -0,0 +1,525 @@
+//! A Redis protocol (RESP2) front-end for `KvsEngine`s.
+//!
+//! Supported commands are `PING`, `GET`, `SET`, `DEL`, `EXISTS`, `MGET`, `MSET`,
+//! `SCAN` and `EXPIRE`.
+
+use self::codec::{RespCodec, Value};
+use crate::{EngineStats, KvsEngine, KvsError, Result};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::codec::{FramedRead, FramedWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::prelude::*;
+use tokio::timer::Delay;
+
+mod codec;
+
+/// Default number of keys returned by `SCAN`.
+const DEFAULT_SCAN_COUNT: usize = 10;
+
+type ReplyFuture = Box<dyn Future<Item = Value, Error = KvsError> + Send>;
+
+/// Creates a server accepting RESP connections on the given address.
+///
+/// The time to live of the keys is kept in `expirations`, which the other front-ends
+/// clear when they write a key through `ExpiringEngine`.
+///
+/// The returned future runs the server and should be spawned onto the tokio runtime.
+pub fn serve<E: KvsEngine>(
+    addr: SocketAddr,
+    engine: E,
+    expirations: Expirations,
+) -> Result<impl Future<Item = (), Error = ()>> {
+    let listener = TcpListener::bind(&addr)?;
+    let server = listener
+        .incoming()
+        .map_err(|e| error!("IO error: {}", e))
+        .for_each(move |tcp| {
+            let conn = Connection {
+                engine: engine.clone(),
+                expirations: expirations.clone(),
+            };
+            tokio::spawn(
+                serve_conn(conn, tcp).map_err(|e| error!("Error on serving RESP client: {}", e)),
+            );
+            Ok(())
+        });
+    Ok(server)
+}
+
+/// Handles commands of a connection one by one.
+///
+/// Pipelined commands are buffered by the decoder and their replies are written
+/// in the same order.
+fn serve_conn<E: KvsEngine>(
+    conn: Connection<E>,
+    tcp: TcpStream,
+) -> impl Future<Item = (), Error = KvsError> {
+    let (read_half, write_half) = tcp.split();
+    let reader = FramedRead::new(read_half, RespCodec::default());
+    let writer = FramedWrite::new(write_half, RespCodec::default());
+    future::loop_fn((reader, writer), move |(reader, writer)| {
+        let conn = conn.clone();
+        reader.into_future().then(
+            move |res| -> Box<dyn Future<Item = _, Error = KvsError> + Send> {
+                match res {
+                    Ok((Some(frame), reader)) => {
+                        let reply = match parse_command(frame) {
+                            Ok(ref args) if args.is_empty() => {
+                                return Box::new(future::ok(future::Loop::Continue((
+                                    reader, writer,
+                                ))));
+                            }
+                            Ok(args) => conn.execute(args),
+                            Err(e) => Box::new(future::ok(e)),
+                        };
+                        Box::new(
+                            reply
+                                .or_else(|e| Ok(Value::Error(format!("ERR {}", e))))
+                                .and_then(move |reply| writer.send(reply).map_err(KvsError::from))
+                                .map(move |writer| future::Loop::Continue((reader, writer))),
+                        )
+                    }
+                    Ok((None, _)) => Box::new(future::ok(future::Loop::Break(()))),
+                    // The rest of the stream cannot be trusted after a protocol error,
+                    // so the connection is closed after replying the error.
+                    Err((e, _)) => Box::new(
+                        writer
+                            .send(Value::Error(format!("ERR Protocol error: {}", e)))
+                            .map(|_| future::Loop::Break(()))
+                            .map_err(KvsError::from),
+                    ),
+                }
+            },
+        )
+    })
+}
+
+/// Converts a request frame to the command arguments.
+///
+/// Returns the error reply if the frame is not an array of bulk strings.
+fn parse_command(frame: Value) -> std::result::Result<Vec<String>, Value> {
+    let invalid = || Value::Error("ERR Protocol error: expected an array of bulk strings".into());
+    match frame {
+        Value::Array(values) => values
+            .into_iter()
+            .map(|value| match value {
+                Value::Bulk(bulk) => String::from_utf8(bulk).map_err(|_| invalid()),
+                _ => Err(invalid()),
+            })
+            .collect(),
+        _ => Err(invalid()),
+    }
+}
+
+/// State shared by the commands of a connection.
+#[derive(Clone)]
+struct Connection<E: KvsEngine> {
+    engine: E,
+    expirations: Expirations,
+}
+
+impl<E: KvsEngine> Connection<E> {
+    fn execute(&self, mut args: Vec<String>) -> ReplyFuture {
+        let name = args.remove(0).to_ascii_lowercase();
+        match (name.as_str(), args.len()) {
+            ("ping", 0) => ok(Value::Simple("PONG".to_owned())),
+            ("ping", 1) => ok(bulk(args.remove(0))),
+            ("get", 1) => self.get(args.remove(0)),
+            ("set", n) if n >= 2 => self.set(args),
+            ("del", n) if n >= 1 => self.del(args),
+            ("exists", n) if n >= 1 => self.exists(args),
+            ("mget", n) if n >= 1 => self.mget(args),
+            ("mset", n) if n >= 2 && n % 2 == 0 => self.mset(args),
+            ("scan", n) if n >= 1 => self.scan(args),
+            ("expire", 2) => self.expire(args),
+            ("ping", _)
+            | ("get", _)
+            | ("set", _)
+            | ("del", _)
+            | ("exists", _)
+            | ("mget", _)
+            | ("mset", _)
+            | ("scan", _)
+            | ("expire", _) => err(format!(
+                "ERR wrong number of arguments for '{}' command",
+                name
+            )),
+            _ => err(format!("ERR unknown command '{}'", name)),
+        }
+    }
+
+    fn get(&self, key: String) -> ReplyFuture {
+        Box::new(self.get_value(key).map(|value| match value {
+            Some(value) => bulk(value),
+            None => Value::Nil,
+        }))
+    }
+
+    /// `SET key value [EX seconds | PX milliseconds]`
+    fn set(&self, mut args: Vec<String>) -> ReplyFuture {
+        let ttl = match args.len() {
+            2 => None,
+            4 => match (
+                args[2].to_ascii_lowercase().as_str(),
+                args[3].parse::<u64>(),
+            ) {
+                ("ex", Ok(secs)) if secs > 0 => Some(Duration::from_secs(secs)),
+                ("px", Ok(millis)) if millis > 0 => Some(Duration::from_millis(millis)),
+                ("ex", _) | ("px", _) => return err(invalid_expire_time("set")),
+                _ => return err("ERR syntax error".to_owned()),
+            },
+            _ => return err("ERR syntax error".to_owned()),
+        };
+        let deadline = match ttl.map(|ttl| expires_at(ttl, "set")) {
+            Some(Ok(deadline)) => Some(deadline),
+            Some(Err(e)) => return ok(e),
+            None => None,
+        };
+        args.truncate(2);
+        let value = args.pop().unwrap();
+        let key = args.pop().unwrap();
+        let engine = self.engine.clone();
+        let expirations = self.expirations.clone();
+        Box::new(engine.set(key.clone(), value).map(move |_| {
+            match deadline {
+                Some(deadline) => expirations.expire_at(&engine, key, deadline),
+                None => expirations.persist(&key),
+            }
+            Value::Simple("OK".to_owned())
+        }))
+    }
+
+    /// Replies the number of removed keys.
+    fn del(&self, keys: Vec<String>) -> ReplyFuture {
+        let removals: Vec<_> = keys
+            .into_iter()
+            .map(|key| {
+                let expired = self.expirations.is_expired(&key);
+                self.expirations.persist(&key);
+                self.engine.remove(key).then(move |res| match res {
+                    Ok(()) if !expired => Ok(1),
+                    Ok(()) | Err(KvsError::KeyNotFound) => Ok(0),
+                    Err(e) => Err(e),
+                })
+            })
+            .collect();
+        Box::new(future::join_all(removals).map(|counts| Value::Integer(counts.iter().sum())))
+    }
+
+    /// Replies the number of existing keys. A key given twice is counted twice.
+    fn exists(&self, keys: Vec<String>) -> ReplyFuture {
+        let gets: Vec<_> = keys.into_iter().map(|key| self.get_value(key)).collect();
+        Box::new(future::join_all(gets).map(|values| {
+            Value::Integer(values.iter().filter(|value| value.is_some()).count() as i64)
+        }))
+    }
+
+    fn mget(&self, keys: Vec<String>) -> ReplyFuture {
+        let gets: Vec<_> = keys.into_iter().map(|key| self.get(key)).collect();
+        Box::new(future::join_all(gets).map(Value::Array))
+    }
+
+    fn mset(&self, args: Vec<String>) -> ReplyFuture {
+        let mut args = args.into_iter();
+        let mut sets = Vec::new();
+        while let (Some(key), Some(value)) = (args.next(), args.next()) {
+            self.expirations.persist(&key);
+            sets.push(self.engine.set(key, value));
+        }
+        Box::new(future::join_all(sets).map(|_| Value::Simple("OK".to_owned())))
+    }
+
+    /// `SCAN cursor [MATCH pattern] [COUNT count]`
+    ///
+    /// The cursor is the number of matching keys already returned in key order.
+    /// So unlike Redis, a key may be skipped if keys before it are removed during
+    /// the iteration.
+    fn scan(&self, args: Vec<String>) -> ReplyFuture {
+        let cursor = match args[0].parse::<usize>() {
+            Ok(cursor) => cursor,
+            Err(_) => return err("ERR invalid cursor".to_owned()),
+        };
+        let mut pattern = "*".to_owned();
+        let mut count = DEFAULT_SCAN_COUNT;
+        let mut options = args[1..].iter();
+        while let Some(option) = options.next() {
+            match (option.to_ascii_lowercase().as_str(), options.next()) {
+                ("match", Some(p)) => pattern = p.clone(),
+                ("count", Some(c)) => match c.parse::<usize>() {
+                    Ok(c) if c > 0 => count = c,
+                    _ => return err("ERR value is not an integer or out of range".to_owned()),
+                },
+                _ => return err("ERR syntax error".to_owned()),
+            }
+        }
+        // Only keys beginning with the literal prefix of the pattern can match.
+        let prefix: String = pattern
+            .chars()
+            .take_while(|c| !"*?[\\".contains(*c))
+            .collect();
+        let expirations = self.expirations.clone();
+        Box::new(self.engine.scan(prefix).map(move |keys| {
+            let keys: Vec<String> = keys
+                .into_iter()
+                .filter(|key| glob_match(pattern.as_bytes(), key.as_bytes()))
+                .filter(|key| !expirations.is_expired(key))
+                .collect();
+            let end = cursor.saturating_add(count).min(keys.len());
+            let next_cursor = if end < keys.len() { end } else { 0 };
+            let page = keys
+                .into_iter()
+                .skip(cursor)
+                .take(end.saturating_sub(cursor))
+                .map(bulk)
+                .collect();
+            Value::Array(vec![bulk(next_cursor.to_string()), Value::Array(page)])
+        }))
+    }
+
+    /// `EXPIRE key seconds`
+    ///
+    /// Replies 1 if the time to live is set, or 0 if the key does not exist.
+    fn expire(&self, args: Vec<String>) -> ReplyFuture {
+        let secs = match args[1].parse::<i64>() {
+            Ok(secs) => secs,
+            Err(_) => return err("ERR value is not an integer or out of range".to_owned()),
+        };
+        // a non-positive time to live deletes the key instead
+        let deadline = if secs > 0 {
+            match expires_at(Duration::from_secs(secs as u64), "expire") {
+                Ok(deadline) => Some(deadline),
+                Err(e) => return ok(e),
+            }
+        } else {
+            None
+        };
+        let key = args[0].clone();
+        let engine = self.engine.clone();
+        let expirations = self.expirations.clone();
+        Box::new(
+            self.get_value(key.clone())
+                .and_then(move |value| -> ReplyFuture {
+                    match (value, deadline) {
+                        (None, _) => ok(Value::Integer(0)),
+                        (Some(_), Some(deadline)) => {
+                            expirations.expire_at(&engine, key, deadline);
+                            ok(Value::Integer(1))
+                        }
+                        (Some(_), None) => {
+                            expirations.persist(&key);
+                            Box::new(engine.remove(key).then(|res| match res {
+                                Ok(()) | Err(KvsError::KeyNotFound) => Ok(Value::Integer(1)),
+                                Err(e) => Err(e),
+                            }))
+                        }
+                    }
+                }),
+        )
+    }
+
+    /// Gets the value of a key, treating expired keys as missing.
+    fn get_value(
+        &self,
+        key: String,
+    ) -> Box<dyn Future<Item = Option<String>, Error = KvsError> + Send> {
+        if self.expirations.is_expired(&key) {
+            Box::new(future::ok(None))
+        } else {
+            self.engine.get(key)
+        }
+    }
+}
+
+/// Returns the deadline of a key expiring after `ttl`, or the error reply if it is
+/// too far away to be represented.
+fn expires_at(ttl: Duration, command: &str) -> std::result::Result<Instant, Value> {
+    Instant::now()
+        .checked_add(ttl)
+        .ok_or_else(|| Value::Error(invalid_expire_time(command)))
+}
+
+fn invalid_expire_time(command: &str) -> String {
+    format!("ERR invalid expire time in '{}' command", command)
+}
+
+/// Deadlines of keys with a time to live, shared by all RESP connections.
+///
+/// Expired keys are treated as missing immediately and removed from the engine
+/// by a timer. The deadlines are only kept in memory, so they are lost when the
+/// server restarts.
+#[derive(Clone, Default)]
+pub struct Expirations(Arc<Mutex<HashMap<String, Instant>>>);
+
+impl Expirations {
+    fn is_expired(&self, key: &str) -> bool {
+        match self.0.lock().unwrap().get(key) {
+            Some(&deadline) => deadline <= Instant::now(),
+            None => false,
+        }
+    }
+
+    /// Removes the time to live of a key.
+    fn persist(&self, key: &str) {
+        self.0.lock().unwrap().remove(key);
+    }
+
+    /// Sets the deadline of a key and schedules its removal.
+    fn expire_at<E: KvsEngine>(&self, engine: &E, key: String, deadline: Instant) {
+        self.0.lock().unwrap().insert(key.clone(), deadline);
+        let engine = engine.clone();
+        let deadlines = self.0.clone();
+        let removal = Delay::new(deadline)
+            .map_err(|e| error!("Timer error: {}", e))
+            .and_then(move |_| -> Box<dyn Future<Item = (), Error = ()> + Send> {
+                let mut deadlines = deadlines.lock().unwrap();
+                // The key may be overwritten or given another deadline meanwhile.
+                if deadlines.get(&key) != Some(&deadline) {
+                    return Box::new(future::ok(()));
+                }
+                deadlines.remove(&key);
+                Box::new(engine.remove(key).then(|res| {
+                    match res {
+                        Ok(()) | Err(KvsError::KeyNotFound) => {}
+                        Err(e) => error!("Failed to remove an expired key: {}", e),
+                    }
+                    Ok(())
+                }))
+            });
+        tokio::spawn(removal);
+    }
+}
+
+/// An engine clearing the time to live set through RESP of the keys it writes.
+///
+/// The native protocol and the HTTP gateway write through it, so a key they overwrite
+/// or remove is not removed again later by the timer of its old time to live.
+#[derive(Clone)]
+pub struct ExpiringEngine<E: KvsEngine> {
+    engine: E,
+    // `None` if the RESP front-end is disabled, so no key has a time to live
+    expirations: Option<Expirations>,
+}
+
+impl<E: KvsEngine> ExpiringEngine<E> {
+    pub fn new(engine: E, expirations: Option<Expirations>) -> Self {
+        ExpiringEngine {
+            engine,
+            expirations,
+        }
+    }
+
+    fn persist(&self, key: &str) {
+        if let Some(expirations) = &self.expirations {
+            expirations.persist(key);
+        }
+    }
+}
+
+impl<E: KvsEngine> KvsEngine for ExpiringEngine<E> {
+    fn set(&self, key: String, value: String) -> Box<dyn Future<Item = (), Error = KvsError> + Send> {
+        self.persist(&key);
+        self.engine.set(key, value)
+    }
+
+    fn get(&self, key: String) -> Box<dyn Future<Item = Option<String>, Error = KvsError> + Send> {
+        self.engine.get(key)
+    }
+
+    fn remove(&self, key: String) -> Box<dyn Future<Item = (), Error = KvsError> + Send> {
+        self.persist(&key);
+        self.engine.remove(key)
+    }
+
+    fn scan(&self, prefix: String) -> Box<dyn Future<Item = Vec<String>, Error = KvsError> + Send> {
+        self.engine.scan(prefix)
+    }
+
+    fn stats(&self) -> EngineStats {
+        self.engine.stats()
+    }
+}
+
+/// Matches a key against a glob-style pattern supporting `*`, `?`, `[...]` and `\`.
+fn glob_match(pattern: &[u8], key: &[u8]) -> bool {
+    let (mut p, mut k) = (0, 0);
+    // where to resume after the last `*` if the rest of the pattern does not match;
+    // backtracking to the last star only keeps matching linear in the pattern length
+    let mut star: Option<(usize, usize)> = None;
+    while k < key.len() {
+        if pattern.get(p) == Some(&b'*') {
+            p += 1;
+            star = Some((p, k));
+            continue;
+        }
+        if let Some(len) = match_one(&pattern[p..], key[k]) {
+            p += len;
+            k += 1;
+            continue;
+        }
+        match star {
+            Some((star_p, star_k)) => {
+                // let the star match one more byte
+                p = star_p;
+                k = star_k + 1;
+                star = Some((star_p, k));
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|&b| b == b'*')
+}
+
+/// Returns the length of the element at the start of `pattern` if it matches `c`.
+fn match_one(pattern: &[u8], c: u8) -> Option<usize> {
+    match *pattern.first()? {
+        b'?' => Some(1),
+        b'[' => match pattern[1..].iter().position(|&b| b == b']') {
+            Some(end) => {
+                let (negated, class) = match &pattern[1..=end] {
+                    [b'^', class @ ..] => (true, class),
+                    class => (false, class),
+                };
+                Some(end + 2).filter(|_| class_match(class, c) != negated)
+            }
+            // an unclosed bracket matches literally
+            None => Some(1).filter(|_| c == b'['),
+        },
+        b'\\' if pattern.len() > 1 => Some(2).filter(|_| pattern[1] == c),
+        p => Some(1).filter(|_| p == c),
+    }
+}
+
+/// Matches a byte against the content of a `[...]` class, like `a-z0`.
+fn class_match(class: &[u8], c: u8) -> bool {
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == b'-' {
+            let (lo, hi) = (class[i].min(class[i + 2]), class[i].max(class[i + 2]));
+            if lo <= c && c <= hi {
+                return true;
+            }
+            i += 3;
+        } else {
+            if class[i] == c {
+                return true;
+            }
+            i += 1;
+        }
+    }
+    false
+}
+
+fn bulk(s: String) -> Value {
+    Value::Bulk(s.into_bytes())
+}
+
+fn ok(value: Value) -> ReplyFuture {
+    Box::new(future::ok(value))
+}
+
+fn err(msg: String) -> ReplyFuture {
+    ok(Value::Error(msg))
+}
@@ -0,0 +1,221 @@
+//! Command parsing and result output of the `kvs-client` shell and scripts.
+//!
+//! Each line holds one command: `get KEY`, `set KEY VALUE` or `rm KEY`.
+//! Arguments are separated by whitespace and can be double-quoted to contain
+//! whitespace, with `\"` and `\\` escapes. Empty lines and lines starting with `#`
+//! are skipped.
+
+use crate::{KvsError, Request, Result};
+use serde::Serialize;
+use std::io::Write;
+use std::str::FromStr;
+
+/// Parses a line into a request. Returns `None` for empty and comment lines.
+pub fn parse_line(line: &str) -> Result<Option<Request>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    let mut args = split_args(line)?;
+    let name = args.remove(0);
+    let req = match (name.to_ascii_lowercase().as_str(), args.len()) {
+        ("get", 1) => Request::Get {
+            key: args.remove(0),
+        },
+        ("set", 2) => Request::Set {
+            value: args.pop().unwrap(),
+            key: args.pop().unwrap(),
+        },
+        ("rm", 1) => Request::Remove {
+            key: args.remove(0),
+        },
+        ("get", _) | ("set", _) | ("rm", _) => {
+            return Err(KvsError::StringError(format!(
+                "Wrong number of arguments for '{}'",
+                name
+            )));
+        }
+        _ => {
+            return Err(KvsError::StringError(format!("Unknown command '{}'", name)));
+        }
+    };
+    Ok(Some(req))
+}
+
+/// Splits a line into arguments, unquoting double-quoted ones.
+fn split_args(line: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while let Some(c) = chars.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            chars.next();
+        }
+        let mut arg = String::new();
+        match chars.peek() {
+            None => return Ok(args),
+            Some('"') => {
+                chars.next();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ '"') | Some(c @ '\\') => arg.push(c),
+                            _ => return Err(KvsError::StringError("Invalid escape".to_owned())),
+                        },
+                        Some(c) => arg.push(c),
+                        None => return Err(KvsError::StringError("Unterminated quote".to_owned())),
+                    }
+                }
+                match chars.peek() {
+                    Some(c) if !c.is_whitespace() => {
+                        return Err(KvsError::StringError(
+                            "Expected whitespace after quote".to_owned(),
+                        ));
+                    }
+                    _ => {}
+                }
+            }
+            Some(_) => {
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() {
+                        break;
+                    }
+                    arg.push(c);
+                    chars.next();
+                }
+            }
+        }
+        args.push(arg);
+    }
+}
+
+/// Output format of command results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human readable text, the same as single commands of `kvs-client`
+    Plain,
+    /// One JSON object per line
+    Json,
+    /// CSV with a header row
+    Csv,
+}
+
+impl OutputFormat {
+    /// Names of the formats, accepted by `FromStr`.
+    pub fn variants() -> [&'static str; 3] {
+        ["plain", "json", "csv"]
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s {
+            "plain" => Ok(OutputFormat::Plain),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(format!("Invalid output format: {}", s)),
+        }
+    }
+}
+
+/// A result as a JSON object or a CSV row.
+#[derive(Serialize)]
+struct Record<'a> {
+    line: Option<usize>,
+    command: &'a str,
+    key: Option<&'a str>,
+    status: &'a str,
+    value: Option<&'a str>,
+    error: Option<String>,
+}
+
+/// Writes command results in an `OutputFormat`.
+pub struct Printer<W: Write> {
+    out: Output<W>,
+}
+
+enum Output<W: Write> {
+    Plain(W),
+    Json(W),
+    Csv(Box<csv::Writer<W>>),
+}
+
+impl<W: Write> Printer<W> {
+    /// Creates a printer writing to `writer`.
+    pub fn new(format: OutputFormat, writer: W) -> Self {
+        let out = match format {
+            OutputFormat::Plain => Output::Plain(writer),
+            OutputFormat::Json => Output::Json(writer),
+            OutputFormat::Csv => Output::Csv(Box::new(csv::Writer::from_writer(writer))),
+        };
+        Printer { out }
+    }
+
+    /// Prints the result of a command.
+    ///
+    /// `line` is the line number of the command in a script. `req` is `None` if the
+    /// line could not be parsed, then `result` is the parse error.
+    pub fn print(
+        &mut self,
+        line: Option<usize>,
+        req: Option<&Request>,
+        result: &Result<Option<String>>,
+    ) -> Result<()> {
+        let (command, key) = match req {
+            Some(Request::Get { key }) => ("get", Some(key.as_str())),
+            Some(Request::Set { key, .. }) => ("set", Some(key.as_str())),
+            Some(Request::Remove { key }) => ("rm", Some(key.as_str())),
+            Some(Request::Scan { prefix, .. }) => ("scan", Some(prefix.as_str())),
+            Some(Request::Auth { .. }) => ("auth", None),
+            None => ("", None),
+        };
+        let status = match (req, result) {
+            (Some(Request::Get { .. }), Ok(None)) => "not_found",
+            (_, Ok(_)) => "ok",
+            (_, Err(_)) => "error",
+        };
+        let record = Record {
+            line,
+            command,
+            key,
+            status,
+            value: result.as_ref().ok().and_then(|value| value.as_deref()),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        };
+        match self.out {
+            Output::Plain(ref mut writer) => {
+                if let Some(line) = line {
+                    write!(writer, "{}: ", line)?;
+                }
+                match result {
+                    Ok(Some(value)) => writeln!(writer, "{}", value)?,
+                    Ok(None) if status == "not_found" => writeln!(writer, "Key not found")?,
+                    Ok(None) => writeln!(writer, "OK")?,
+                    Err(e) => writeln!(writer, "Error: {}", e)?,
+                }
+            }
+            Output::Json(ref mut writer) => {
+                serde_json::to_writer(&mut *writer, &record)?;
+                writeln!(writer)?;
+            }
+            Output::Csv(ref mut writer) => {
+                writer.serialize(record)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Flushes the written results.
+    pub fn flush(&mut self) -> Result<()> {
+        match self.out {
+            Output::Plain(ref mut writer) | Output::Json(ref mut writer) => writer.flush()?,
+            Output::Csv(ref mut writer) => writer.flush()?,
+        }
+        Ok(())
+    }
+}
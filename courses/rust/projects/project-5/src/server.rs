@@ -1,59 +1,339 @@
+use crate::acl::{Acl, Operation};
 use crate::common::{Request, Response};
-use crate::{KvsEngine, KvsError, Result};
-use std::net::SocketAddr;
+use crate::http;
+use crate::metrics::{self, Metrics};
+use crate::resp;
+use crate::tls::ServerConfig;
+use crate::{Addr, KvsEngine, KvsError, Result};
+use std::fs;
+use std::io;
+use std::net::{self, SocketAddr};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
 use tokio::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::{TcpListener, UnixListener};
 use tokio::prelude::*;
+use tokio::reactor::Handle;
+use tokio_rustls::TlsAcceptor;
 use tokio_serde_json::{ReadJson, WriteJson};
 
 /// The server of a key value store.
 pub struct KvsServer<E: KvsEngine> {
     engine: E,
+    metrics_addr: Option<SocketAddr>,
+    metrics_listener: Option<net::TcpListener>,
+    resp_addr: Option<SocketAddr>,
+    http_addr: Option<SocketAddr>,
+    tls: Option<TlsAcceptor>,
+    acl: Option<Acl>,
+    listen_addrs: Vec<Addr>,
+    listeners: Vec<net::TcpListener>,
 }
 
 impl<E: KvsEngine> KvsServer<E> {
     /// Create a `KvsServer` with a given storage engine.
     pub fn new(engine: E) -> Self {
-        KvsServer { engine }
+        KvsServer {
+            engine,
+            metrics_addr: None,
+            metrics_listener: None,
+            resp_addr: None,
+            http_addr: None,
+            tls: None,
+            acl: None,
+            listen_addrs: Vec::new(),
+            listeners: Vec::new(),
+        }
+    }
+
+    /// Serve Prometheus metrics over HTTP at `GET /metrics` on the given address.
+    pub fn with_metrics(mut self, addr: SocketAddr) -> Self {
+        self.metrics_addr = Some(addr);
+        self
+    }
+
+    /// Serve Prometheus metrics over HTTP at `GET /metrics` on a bound listener.
+    pub fn with_metrics_listener(mut self, listener: net::TcpListener) -> Self {
+        self.metrics_listener = Some(listener);
+        self
+    }
+
+    /// Also accept Redis protocol (RESP2) connections on the given address.
+    ///
+    /// Keys written through the native protocol or the HTTP gateway lose the time to
+    /// live set through RESP.
+    pub fn with_resp(mut self, addr: SocketAddr) -> Self {
+        self.resp_addr = Some(addr);
+        self
+    }
+
+    /// Also serve the HTTP/JSON gateway on the given address.
+    ///
+    /// The gateway shares the storage engine, and its thread pool, with the server.
+    pub fn with_http(mut self, addr: SocketAddr) -> Self {
+        self.http_addr = Some(addr);
+        self
+    }
+
+    /// Require clients to connect with TLS using the given configuration.
+    ///
+    /// See `kvs::tls::server_config` for loading the configuration from files.
+    pub fn with_tls(mut self, config: Arc<ServerConfig>) -> Self {
+        self.tls = Some(TlsAcceptor::from(config));
+        self
+    }
+
+    /// Require clients to authenticate with a token and restrict their requests by the ACL.
+    ///
+    /// The ACL is enforced only on the native protocol, so it cannot be combined with
+    /// the RESP and HTTP front-ends.
+    pub fn with_acl(mut self, acl: Acl) -> Self {
+        self.acl = Some(acl);
+        self
+    }
+
+    /// Accept connections on an address, which can be a Unix socket.
+    ///
+    /// TLS is only used on TCP addresses. Access to a Unix socket is controlled by the
+    /// permissions of its file, and a socket file left by a stopped server is replaced.
+    pub fn with_listener(mut self, addr: Addr) -> Self {
+        self.listen_addrs.push(addr);
+        self
+    }
+
+    /// Accept connections on a bound TCP listener, e.g. one bound to a port picked by
+    /// the OS.
+    pub fn with_tcp_listener(mut self, listener: net::TcpListener) -> Self {
+        self.listeners.push(listener);
+        self
     }
 
     /// Run the server listening on the given address
     pub fn run(self, addr: SocketAddr) -> Result<()> {
-        let listener = TcpListener::bind(&addr)?;
-        let server = listener
-            .incoming()
-            .map_err(|e| error!("IO error: {}", e))
-            .for_each(move |tcp| {
-                let engine = self.engine.clone();
-                serve(engine, tcp).map_err(|e| error!("Error on serving client: {}", e))
-            });
-        tokio::run(server);
+        self.with_listener(Addr::Tcp(addr)).run_listeners()
+    }
+
+    /// Run the server accepting connections from the given listener
+    pub fn run_on(self, listener: net::TcpListener) -> Result<()> {
+        self.with_tcp_listener(listener).run_listeners()
+    }
+
+    /// Run the server listening on the addresses added by `with_listener` and the
+    /// listeners added by `with_tcp_listener`.
+    ///
+    /// Every connection is served in its own task, so connections are served
+    /// concurrently while the requests of a single connection are handled in order.
+    pub fn run_listeners(self) -> Result<()> {
+        if self.listen_addrs.is_empty() && self.listeners.is_empty() {
+            return Err(KvsError::StringError("No address to listen on".to_owned()));
+        }
+        if self.acl.is_some() && (self.resp_addr.is_some() || self.http_addr.is_some()) {
+            return Err(KvsError::StringError(
+                "The ACL cannot be enforced by the RESP and HTTP front-ends".to_owned(),
+            ));
+        }
+        let metrics = Metrics::default();
+        // listeners and the servers running beside them
+        let mut services: Vec<Box<dyn Future<Item = (), Error = ()> + Send>> = Vec::new();
+        let expirations = self.resp_addr.map(|_| resp::Expirations::default());
+        if let Some(resp_addr) = self.resp_addr {
+            services.push(Box::new(resp::serve(
+                resp_addr,
+                self.engine.clone(),
+                expirations.clone().unwrap(),
+            )?));
+        }
+        // the other front-ends clear the time to live set through RESP of the keys
+        // they write
+        let engine = resp::ExpiringEngine::new(self.engine, expirations);
+        let metrics_listener = match (self.metrics_listener, self.metrics_addr) {
+            (Some(listener), _) => Some(listener),
+            (None, Some(addr)) => Some(net::TcpListener::bind(addr)?),
+            (None, None) => None,
+        };
+        if let Some(metrics_listener) = metrics_listener {
+            services.push(Box::new(metrics::serve(
+                metrics_listener,
+                engine.clone(),
+                metrics.clone(),
+            )?));
+        }
+        if let Some(http_addr) = self.http_addr {
+            services.push(Box::new(http::serve(http_addr, engine.clone())?));
+        }
+        for addr in &self.listen_addrs {
+            services.push(listen(
+                addr,
+                engine.clone(),
+                metrics.clone(),
+                self.tls.clone(),
+                self.acl.clone(),
+            )?);
+        }
+        for listener in self.listeners {
+            let incoming = TcpListener::from_std(listener, &Handle::default())?.incoming();
+            services.push(Box::new(accept(
+                incoming,
+                engine.clone(),
+                metrics.clone(),
+                self.tls.clone(),
+                self.acl.clone(),
+            )));
+        }
+        tokio::run(future::lazy(move || {
+            for service in services {
+                tokio::spawn(service);
+            }
+            Ok(())
+        }));
         Ok(())
     }
 }
 
-fn serve<E: KvsEngine>(engine: E, tcp: TcpStream) -> impl Future<Item = (), Error = KvsError> {
-    let (read_half, write_half) = tcp.split();
+/// Binds `addr` and returns the future accepting and serving its connections.
+fn listen<E: KvsEngine>(
+    addr: &Addr,
+    engine: E,
+    metrics: Metrics,
+    tls: Option<TlsAcceptor>,
+    acl: Option<Acl>,
+) -> Result<Box<dyn Future<Item = (), Error = ()> + Send>> {
+    match addr {
+        Addr::Tcp(addr) => {
+            let incoming = TcpListener::bind(addr)?.incoming();
+            Ok(Box::new(accept(incoming, engine, metrics, tls, acl)))
+        }
+        Addr::Unix(path) => {
+            remove_stale_socket(path)?;
+            let incoming = UnixListener::bind(path)?.incoming();
+            Ok(Box::new(accept(incoming, engine, metrics, None, acl)))
+        }
+    }
+}
+
+/// Removes a Unix socket file no server is listening on, so the path can be bound again.
+fn remove_stale_socket(path: &Path) -> Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    // other files are left for the bind to fail on
+    if !metadata.file_type().is_socket() {
+        return Ok(());
+    }
+    match UnixStream::connect(path) {
+        Err(ref e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+            warn!("Removing stale socket file {:?}", path);
+            fs::remove_file(path)?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Serves each accepted connection on its own task.
+fn accept<E, I>(
+    incoming: I,
+    engine: E,
+    metrics: Metrics,
+    tls: Option<TlsAcceptor>,
+    acl: Option<Acl>,
+) -> impl Future<Item = (), Error = ()>
+where
+    E: KvsEngine,
+    I: Stream<Error = io::Error>,
+    I::Item: AsyncRead + AsyncWrite + Send + 'static,
+{
+    incoming
+        .map_err(|e| error!("IO error: {}", e))
+        .for_each(move |stream| {
+            let engine = engine.clone();
+            let metrics = metrics.clone();
+            let session = Session::new(acl.clone());
+            let conn = match tls {
+                Some(ref acceptor) => future::Either::A(
+                    acceptor
+                        .accept(stream)
+                        .map_err(KvsError::from)
+                        .and_then(move |stream| serve(engine, metrics, session, stream)),
+                ),
+                None => future::Either::B(serve(engine, metrics, session, stream)),
+            };
+            // a slow or idle connection must not hold up the others, e.g. the other
+            // connections of a client pool
+            tokio::spawn(conn.map_err(|e| error!("Error on serving client: {}", e)));
+            Ok(())
+        })
+}
+
+fn serve<E, S>(
+    engine: E,
+    metrics: Metrics,
+    mut session: Session,
+    stream: S,
+) -> impl Future<Item = (), Error = KvsError>
+where
+    E: KvsEngine,
+    S: AsyncRead + AsyncWrite,
+{
+    let (read_half, write_half) = stream.split();
     let read_json = ReadJson::new(FramedRead::new(read_half, LengthDelimitedCodec::new()));
+    let request_metrics = metrics.clone();
     let resp_stream = read_json
         .map_err(KvsError::from)
         .and_then(
-            move |req| -> Box<dyn Future<Item = Response, Error = KvsError> + Send> {
-                match req {
-                    Request::Get { key } => Box::new(engine.get(key).map(Response::Get)),
-                    Request::Set { key, value } => {
-                        Box::new(engine.set(key, value).map(|_| Response::Set))
-                    }
-                    Request::Remove { key } => {
-                        Box::new(engine.remove(key).map(|_| Response::Remove))
-                    }
-                }
+            move |req: Request| -> Box<dyn Future<Item = Response, Error = KvsError> + Send> {
+                let name = req.name();
+                let start = Instant::now();
+                let resp: Box<dyn Future<Item = Response, Error = KvsError> + Send> =
+                    match session.authorize(&req) {
+                        Err(e) => Box::new(future::err(e)),
+                        Ok(()) => match req {
+                            Request::Get { key } => Box::new(engine.get(key).map(Response::Get)),
+                            Request::Set { key, value } => {
+                                Box::new(engine.set(key, value).map(|_| Response::Set))
+                            }
+                            Request::Remove { key } => {
+                                Box::new(engine.remove(key).map(|_| Response::Remove))
+                            }
+                            Request::Scan {
+                                prefix,
+                                start_after,
+                                limit,
+                            } => {
+                                let readable = session.readable();
+                                Box::new(engine.scan(prefix).map(move |keys| {
+                                    let keys = keys.into_iter().filter(|key| readable(key));
+                                    Response::Scan(page(keys, start_after, limit))
+                                }))
+                            }
+                            Request::Auth { token } => Box::new(future::result(
+                                session.authenticate(token).map(|_| Response::Auth),
+                            )),
+                        },
+                    };
+                let metrics = request_metrics.clone();
+                Box::new(resp.then(move |resp| {
+                    metrics.observe_request(name, start.elapsed());
+                    resp
+                }))
             },
         )
-        .then(|resp| -> Result<Response> {
+        .then(move |resp| -> Result<Response> {
             match resp {
                 Ok(resp) => Ok(resp),
-                Err(e) => Ok(Response::Err(format!("{}", e))),
+                Err(e) => {
+                    metrics.observe_error(&e);
+                    match e {
+                        KvsError::PermissionDenied(msg) => Ok(Response::Denied(msg)),
+                        KvsError::ServerBusy => Ok(Response::ServerBusy),
+                        e => Ok(Response::Err(format!("{}", e))),
+                    }
+                }
             }
         });
     let write_json = WriteJson::new(FramedWrite::new(write_half, LengthDelimitedCodec::new()));
@@ -62,3 +342,86 @@ fn serve<E: KvsEngine>(engine: E, tcp: TcpStream) -> impl Future<Item = (), Erro
         .send_all(resp_stream)
         .map(|_| ())
 }
+
+/// Returns the keys after `start_after`, at most `limit` of them.
+///
+/// The keys must be in order.
+fn page(
+    keys: impl Iterator<Item = String>,
+    start_after: Option<String>,
+    limit: Option<usize>,
+) -> Vec<String> {
+    let keys = keys.skip_while(|key| match start_after {
+        Some(ref after) => key <= after,
+        None => false,
+    });
+    match limit {
+        Some(limit) => keys.take(limit).collect(),
+        None => keys.collect(),
+    }
+}
+
+/// Access control state of a connection.
+struct Session {
+    acl: Option<Acl>,
+    // the token accepted by the last `Auth` request
+    token: Option<String>,
+}
+
+impl Session {
+    fn new(acl: Option<Acl>) -> Self {
+        Session { acl, token: None }
+    }
+
+    /// Handles an `Auth` request. Any token is accepted if the server has no ACL.
+    fn authenticate(&mut self, token: String) -> Result<()> {
+        match self.acl {
+            Some(ref acl) if !acl.contains(&token) => {
+                self.token = None;
+                Err(KvsError::PermissionDenied("invalid token".to_owned()))
+            }
+            _ => {
+                self.token = Some(token);
+                Ok(())
+            }
+        }
+    }
+
+    /// Checks whether the request is allowed before handling it.
+    fn authorize(&self, req: &Request) -> Result<()> {
+        let acl = match self.acl {
+            Some(ref acl) => acl,
+            None => return Ok(()),
+        };
+        let access = match req {
+            Request::Get { key } => Some((Operation::Get, key)),
+            Request::Set { key, .. } => Some((Operation::Set, key)),
+            Request::Remove { key } => Some((Operation::Remove, key)),
+            // the scanned keys are filtered by `readable`
+            Request::Scan { .. } => None,
+            Request::Auth { .. } => return Ok(()),
+        };
+        match (&self.token, access) {
+            (None, _) => Err(KvsError::PermissionDenied(
+                "authentication required".to_owned(),
+            )),
+            (Some(_), None) => Ok(()),
+            (Some(token), Some((op, key))) if acl.allows(token, op, key) => Ok(()),
+            (Some(_), Some((op, key))) => Err(KvsError::PermissionDenied(format!(
+                "{} on key {:?}",
+                op, key
+            ))),
+        }
+    }
+
+    /// Returns whether the connection may read the key.
+    fn readable(&self) -> impl Fn(&str) -> bool + Send + 'static {
+        let acl = self.acl.clone();
+        let token = self.token.clone();
+        move |key| match (&acl, &token) {
+            (None, _) => true,
+            (Some(acl), Some(token)) => acl.allows(token, Operation::Get, key),
+            (Some(_), None) => false,
+        }
+    }
+}
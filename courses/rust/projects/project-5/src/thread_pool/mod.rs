@@ -1,15 +1,19 @@
 //! This module provides various thread pools. All thread pools should implement
 //! the `ThreadPool` trait.
 
-use crate::Result;
+use crate::{KvsError, Result};
+use tokio::prelude::*;
+use tokio::sync::oneshot;
 
 mod naive;
 mod rayon;
 mod shared_queue;
+mod work_stealing;
 
 pub use self::naive::NaiveThreadPool;
 pub use self::rayon::RayonThreadPool;
 pub use self::shared_queue::SharedQueueThreadPool;
+pub use self::work_stealing::WorkStealingThreadPool;
 
 /// The trait that all thread pools should implement.
 pub trait ThreadPool: Clone + Send + 'static {
@@ -27,7 +31,93 @@ pub trait ThreadPool: Clone + Send + 'static {
     /// Spawning always succeeds, but if the function panics the threadpool continues
     /// to operate with the same number of threads &mdash; the thread count is not
     /// reduced nor is the thread pool destroyed, corrupted or invalidated.
+    ///
+    /// If the thread pool has a bounded queue which is full, this blocks until there is
+    /// room. Use `try_spawn` to fail instead.
     fn spawn<F>(&self, job: F)
     where
         F: FnOnce() + Send + 'static;
+
+    /// Spawns a function into the thread pool unless its queue is full.
+    ///
+    /// Returns the function back if the thread pool has a bounded queue with no room
+    /// left. Thread pools with unbounded queues always accept the function.
+    fn try_spawn<F>(&self, job: F) -> std::result::Result<(), F>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.spawn(job);
+        Ok(())
+    }
+
+    /// Spawns a function into the thread pool, returning a handle to its result.
+    ///
+    /// The handle is a future resolving to the return value of the function, or to
+    /// `KvsError::JobPanicked` if the function panics. Dropping the handle doesn't
+    /// cancel the function.
+    fn spawn_with_handle<F, R>(&self, f: F) -> JoinHandle<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        self.spawn(move || {
+            // the result is not wanted if the handle is dropped
+            let _ = tx.send(f());
+        });
+        JoinHandle { rx }
+    }
+
+    /// Spawns a function into the thread pool unless its queue is full, returning a
+    /// handle to its result.
+    ///
+    /// Returns `None`, dropping the function, if the queue has no room left.
+    fn try_spawn_with_handle<F, R>(&self, f: F) -> Option<JoinHandle<R>>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        self.try_spawn(move || {
+            let _ = tx.send(f());
+        })
+        .ok()?;
+        Some(JoinHandle { rx })
+    }
+
+    /// Returns the number of spawned jobs which are not picked up by any thread yet.
+    ///
+    /// Thread pools which cannot tell the number return zero.
+    fn queued_jobs(&self) -> usize {
+        0
+    }
+
+    /// Returns the maximum number of queued jobs, or `None` if the queue is unbounded.
+    fn queue_capacity(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// A handle to the result of a function spawned by `ThreadPool::spawn_with_handle`.
+pub struct JoinHandle<R> {
+    // the sender is dropped without sending if the function panics
+    rx: oneshot::Receiver<R>,
+}
+
+impl<R> JoinHandle<R> {
+    /// Blocks the current thread until the function returns.
+    ///
+    /// Returns `KvsError::JobPanicked` if the function panics.
+    pub fn join(self) -> Result<R> {
+        self.wait()
+    }
+}
+
+impl<R> Future for JoinHandle<R> {
+    type Item = R;
+    type Error = KvsError;
+
+    fn poll(&mut self) -> Poll<R, KvsError> {
+        self.rx.poll().map_err(|_| KvsError::JobPanicked)
+    }
 }
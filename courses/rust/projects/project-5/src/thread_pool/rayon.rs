@@ -1,24 +1,43 @@
 use super::ThreadPool;
 use crate::{KvsError, Result};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 /// Wrapper of rayon::ThreadPool
 #[derive(Clone)]
-pub struct RayonThreadPool(Arc<rayon::ThreadPool>);
+pub struct RayonThreadPool {
+    pool: Arc<rayon::ThreadPool>,
+    // number of spawned jobs which have not started yet
+    queued: Arc<AtomicUsize>,
+}
 
 impl ThreadPool for RayonThreadPool {
     fn new(threads: u32) -> Result<Self> {
         let pool = rayon::ThreadPoolBuilder::new()
             .num_threads(threads as usize)
+            // rayon aborts the process on a panicking job without a handler
+            .panic_handler(|_| error!("A job of the thread pool panicked"))
             .build()
             .map_err(|e| KvsError::StringError(format!("{}", e)))?;
-        Ok(RayonThreadPool(Arc::new(pool)))
+        Ok(RayonThreadPool {
+            pool: Arc::new(pool),
+            queued: Arc::new(AtomicUsize::new(0)),
+        })
     }
 
     fn spawn<F>(&self, job: F)
     where
         F: FnOnce() + Send + 'static,
     {
-        self.0.spawn(job)
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        let queued = Arc::clone(&self.queued);
+        self.pool.spawn(move || {
+            queued.fetch_sub(1, Ordering::SeqCst);
+            job()
+        })
+    }
+
+    fn queued_jobs(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
     }
 }
@@ -1,7 +1,10 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
 use super::ThreadPool;
-use crate::Result;
+use crate::{KvsError, Result};
 
 use crossbeam::channel::{self, Receiver, Sender};
 
@@ -11,60 +14,343 @@ use crossbeam::channel::{self, Receiver, Sender};
 /// A thread pool using a shared queue inside.
 ///
 /// If a spawned task panics, the old thread will be destroyed and a new one will be
-/// created. It fails silently when any failure to create the thread at the OS level
-/// is captured after the thread pool is created. So, the thread number in the pool
-/// can decrease to zero, then spawning a task to the thread pool will panic.
+/// created. If the new thread fails to spawn at the OS level, it is spawned again on
+/// the next call of `spawn` or `resize`, and meanwhile the queued tasks are run by the
+/// remaining threads.
+///
+/// The number of threads can be changed with `resize`, and `shutdown` waits for the
+/// queued tasks to finish.
+///
+/// The queue is unbounded unless the pool is created by `with_capacity`, in which case
+/// `spawn` blocks and `try_spawn` fails while the queue is full.
 #[derive(Clone)]
 pub struct SharedQueueThreadPool {
-    tx: Sender<Box<dyn FnOnce() + Send + 'static>>,
+    tx: Sender<Message>,
+    shared: Arc<Shared>,
+}
+
+enum Message {
+    Run(Box<dyn FnOnce() + Send + 'static>),
+    // asks the receiving thread to exit, sent to shrink or shut down the pool
+    Exit,
+}
+
+struct Shared {
+    rx: Receiver<Message>,
+    state: Mutex<State>,
+    // notified when a thread exits
+    exited: Condvar,
+    // number of spawned tasks not picked up by any thread yet
+    queued: AtomicUsize,
+    // maximum of `queued`, if the queue is bounded
+    capacity: Option<usize>,
+    // notified when a thread picks up a task from a bounded queue
+    space: Condvar,
+    space_lock: Mutex<()>,
+    // set when a thread failed to spawn, so it is spawned again later
+    missing: AtomicBool,
+    shut_down: AtomicBool,
+}
+
+#[derive(Default)]
+struct State {
+    // the number of threads the pool should have
+    target: usize,
+    // the number of running threads, including those about to receive `Message::Exit`
+    live: usize,
+    // the number of `Message::Exit` in the queue
+    exiting: usize,
 }
 
 impl ThreadPool for SharedQueueThreadPool {
     fn new(threads: u32) -> Result<Self> {
-        let (tx, rx) = channel::unbounded::<Box<dyn FnOnce() + Send + 'static>>();
-        for _ in 0..threads {
-            let rx = TaskReceiver(rx.clone());
-            thread::Builder::new().spawn(move || run_tasks(rx))?;
-        }
-        Ok(SharedQueueThreadPool { tx })
+        SharedQueueThreadPool::start(threads, None)
     }
 
-    /// Spawns a function into the thread pool.
+    /// Spawns a function into the thread pool, blocking while a bounded queue is full.
     ///
     /// # Panics
     ///
-    /// Panics if the thread pool has no thread.
+    /// Panics if the thread pool is shut down.
     fn spawn<F>(&self, job: F)
     where
         F: FnOnce() + Send + 'static,
     {
-        self.tx
-            .send(Box::new(job))
-            .expect("The thread pool has no thread.");
+        self.before_spawn();
+        if !self.shared.reserve() {
+            let mut guard = self.shared.space_lock.lock().unwrap();
+            while !self.shared.reserve() {
+                guard = self.shared.space.wait(guard).unwrap();
+            }
+        }
+        self.send_job(Box::new(job));
+    }
+
+    /// Spawns a function into the thread pool unless the queue is full.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the thread pool is shut down.
+    fn try_spawn<F>(&self, job: F) -> std::result::Result<(), F>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.before_spawn();
+        if !self.shared.reserve() {
+            return Err(job);
+        }
+        self.send_job(Box::new(job));
+        Ok(())
+    }
+
+    fn queued_jobs(&self) -> usize {
+        self.shared.queued.load(Ordering::SeqCst)
+    }
+
+    fn queue_capacity(&self) -> Option<usize> {
+        self.shared.capacity
     }
 }
 
-#[derive(Clone)]
-struct TaskReceiver(Receiver<Box<dyn FnOnce() + Send + 'static>>);
+impl SharedQueueThreadPool {
+    /// Creates a thread pool whose queue holds at most `capacity` tasks not picked up
+    /// by any thread yet.
+    ///
+    /// Returns an error if `capacity` is zero or any thread fails to spawn.
+    pub fn with_capacity(threads: u32, capacity: usize) -> Result<Self> {
+        if capacity == 0 {
+            return Err(KvsError::StringError(
+                "The queue capacity must be positive".to_owned(),
+            ));
+        }
+        SharedQueueThreadPool::start(threads, Some(capacity))
+    }
+
+    fn start(threads: u32, capacity: Option<usize>) -> Result<Self> {
+        // `Message::Exit` is never blocked by a full queue, the capacity is enforced
+        // by `queued`
+        let (tx, rx) = channel::unbounded();
+        let shared = Arc::new(Shared {
+            rx,
+            state: Mutex::new(State {
+                target: threads as usize,
+                ..State::default()
+            }),
+            exited: Condvar::new(),
+            queued: AtomicUsize::new(0),
+            capacity,
+            space: Condvar::new(),
+            space_lock: Mutex::new(()),
+            missing: AtomicBool::new(false),
+            shut_down: AtomicBool::new(false),
+        });
+        // threads spawned before a failure exit when `tx` is dropped
+        spawn_missing(&shared)?;
+        Ok(SharedQueueThreadPool { tx, shared })
+    }
 
-impl Drop for TaskReceiver {
+    fn before_spawn(&self) {
+        assert!(
+            !self.shared.shut_down.load(Ordering::SeqCst),
+            "The thread pool is shut down."
+        );
+        if self.shared.missing.load(Ordering::SeqCst) {
+            if let Err(e) = spawn_missing(&self.shared) {
+                error!("Failed to spawn a thread: {}", e);
+            }
+        }
+    }
+
+    /// Queues a task whose place is reserved.
+    ///
+    /// The pool is checked under the state lock, which `shutdown` holds while it
+    /// queues `Message::Exit`, so a task is either queued before the threads are asked
+    /// to exit or not at all.
+    fn send_job(&self, job: Box<dyn FnOnce() + Send + 'static>) {
+        {
+            let _state = self.shared.state.lock().unwrap();
+            if !self.shared.shut_down.load(Ordering::SeqCst) {
+                // the pool holds a receiver, so sending never fails
+                self.tx.send(Message::Run(job)).unwrap();
+                return;
+            }
+        }
+        // the pool was shut down after `before_spawn`
+        self.shared.release();
+        panic!("The thread pool is shut down.");
+    }
+
+    /// Returns the number of running threads.
+    ///
+    /// After the pool is shrunk, the removed threads keep running until they finish
+    /// the tasks queued before.
+    pub fn threads(&self) -> usize {
+        self.shared.state.lock().unwrap().live
+    }
+
+    /// Changes the number of threads.
+    ///
+    /// New threads are spawned immediately, while removed threads exit after the tasks
+    /// queued before. Returns an error if `threads` is zero, the pool is shut down or a
+    /// thread fails to spawn.
+    pub fn resize(&self, threads: u32) -> Result<()> {
+        if threads == 0 {
+            return Err(KvsError::StringError(
+                "The thread pool needs at least one thread".to_owned(),
+            ));
+        }
+        {
+            let mut state = self.shared.state.lock().unwrap();
+            if self.shared.shut_down.load(Ordering::SeqCst) {
+                return Err(KvsError::StringError(
+                    "The thread pool is shut down".to_owned(),
+                ));
+            }
+            state.target = threads as usize;
+            let remaining = state.live.saturating_sub(state.exiting);
+            for _ in state.target..remaining {
+                state.exiting += 1;
+                self.tx.send(Message::Exit).unwrap();
+            }
+        }
+        spawn_missing(&self.shared)
+    }
+
+    /// Shuts down the pool, waiting for the queued tasks to finish and the threads to
+    /// exit.
+    ///
+    /// Returns an error if the threads are still running after `timeout`, in which
+    /// case they exit in the background. Spawning into a pool which is shut down panics,
+    /// including through its clones.
+    pub fn shutdown(&self, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.state.lock().unwrap();
+        if !self.shared.shut_down.swap(true, Ordering::SeqCst) {
+            state.target = 0;
+            // queued behind all spawned tasks, so the queue is drained first
+            for _ in state.exiting..state.live {
+                state.exiting += 1;
+                self.tx.send(Message::Exit).unwrap();
+            }
+        }
+        while state.live > 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(KvsError::StringError(format!(
+                    "Timed out waiting for {} threads to finish",
+                    state.live
+                )));
+            }
+            state = self
+                .shared
+                .exited
+                .wait_timeout(state, deadline - now)
+                .unwrap()
+                .0;
+        }
+        Ok(())
+    }
+}
+
+impl Shared {
+    /// Takes a place in the queue for a task, returning `false` if the queue is full.
+    fn reserve(&self) -> bool {
+        let capacity = match self.capacity {
+            Some(capacity) => capacity,
+            None => {
+                self.queued.fetch_add(1, Ordering::SeqCst);
+                return true;
+            }
+        };
+        let mut queued = self.queued.load(Ordering::SeqCst);
+        while queued < capacity {
+            match self.queued.compare_exchange_weak(
+                queued,
+                queued + 1,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(_) => return true,
+                Err(current) => queued = current,
+            }
+        }
+        false
+    }
+
+    /// Frees the place of a task picked up by a thread.
+    fn release(&self) {
+        self.queued.fetch_sub(1, Ordering::SeqCst);
+        if self.capacity.is_some() {
+            // taking the lock pairs with the check in `spawn` before it waits
+            let _guard = self.space_lock.lock().unwrap();
+            self.space.notify_one();
+        }
+    }
+}
+
+/// Spawns threads until the pool has the target number of threads.
+fn spawn_missing(shared: &Arc<Shared>) -> Result<()> {
+    let mut state = shared.state.lock().unwrap();
+    // a thread panicking while the pool shuts down leaves an extra `Message::Exit`
+    while state.live.saturating_sub(state.exiting) < state.target {
+        // the worker is created by the new thread, so a failed spawn doesn't drop it
+        let worker_shared = shared.clone();
+        let spawned = thread::Builder::new().spawn(move || {
+            run_tasks(Worker {
+                shared: worker_shared,
+                exit_requested: false,
+            })
+        });
+        if let Err(e) = spawned {
+            shared.missing.store(true, Ordering::SeqCst);
+            return Err(e.into());
+        }
+        state.live += 1;
+    }
+    shared.missing.store(false, Ordering::SeqCst);
+    Ok(())
+}
+
+/// A running thread of the pool.
+struct Worker {
+    shared: Arc<Shared>,
+    // set when the thread received `Message::Exit`
+    exit_requested: bool,
+}
+
+impl Drop for Worker {
     fn drop(&mut self) {
+        {
+            let mut state = self.shared.state.lock().unwrap();
+            state.live -= 1;
+            if self.exit_requested {
+                state.exiting -= 1;
+            }
+        }
+        self.shared.exited.notify_all();
         if thread::panicking() {
-            let rx = self.clone();
-            if let Err(e) = thread::Builder::new().spawn(move || run_tasks(rx)) {
+            if let Err(e) = spawn_missing(&self.shared) {
                 error!("Failed to spawn a thread: {}", e);
             }
         }
     }
 }
 
-fn run_tasks(rx: TaskReceiver) {
+fn run_tasks(mut worker: Worker) {
     loop {
-        match rx.0.recv() {
-            Ok(task) => {
+        match worker.shared.rx.recv() {
+            Ok(Message::Run(task)) => {
+                worker.shared.release();
                 task();
             }
-            Err(_) => debug!("Thread exits because the thread pool is destroyed."),
+            Ok(Message::Exit) => {
+                worker.exit_requested = true;
+                return;
+            }
+            Err(_) => {
+                debug!("Thread exits because the thread pool is destroyed.");
+                return;
+            }
         }
     }
 }
@@ -0,0 +1,190 @@
+use std::iter;
+use std::mem;
+use std::sync::atomic::{self, AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use super::ThreadPool;
+use crate::{KvsError, Result};
+
+use crossbeam::deque::{Injector, Stealer, Worker};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A thread pool where each thread has its own queue and steals jobs from the others
+/// when it runs out of work.
+///
+/// Spawned jobs are pushed to a global queue, from which threads take jobs in batches
+/// into their local queues. An idle thread takes jobs from the global queue first, then
+/// from the local queues of the other threads, and sleeps if all queues are empty.
+///
+/// Like `SharedQueueThreadPool`, a thread whose job panics is replaced by a new one
+/// taking over its local queue. If the new thread fails to spawn, the jobs in its queue
+/// are stolen by the remaining threads.
+#[derive(Clone)]
+pub struct WorkStealingThreadPool {
+    handle: Arc<PoolHandle>,
+}
+
+/// Shuts down the threads when the last clone of the pool is dropped.
+struct PoolHandle {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    injector: Injector<Job>,
+    stealers: Vec<Stealer<Job>>,
+    // number of spawned jobs not taken by any thread yet
+    queued: AtomicUsize,
+    // number of threads sleeping or about to sleep on `wakeup`
+    sleeping: AtomicUsize,
+    lock: Mutex<()>,
+    wakeup: Condvar,
+    shutdown: AtomicBool,
+}
+
+/// The local queue of a thread.
+struct WorkerState {
+    local: Worker<Job>,
+    shared: Arc<Shared>,
+}
+
+impl ThreadPool for WorkStealingThreadPool {
+    fn new(threads: u32) -> Result<Self> {
+        if threads == 0 {
+            return Err(KvsError::StringError(
+                "The thread pool needs at least one thread".to_owned(),
+            ));
+        }
+        let locals: Vec<Worker<Job>> = (0..threads).map(|_| Worker::new_fifo()).collect();
+        let shared = Arc::new(Shared {
+            injector: Injector::new(),
+            stealers: locals.iter().map(Worker::stealer).collect(),
+            queued: AtomicUsize::new(0),
+            sleeping: AtomicUsize::new(0),
+            lock: Mutex::new(()),
+            wakeup: Condvar::new(),
+            shutdown: AtomicBool::new(false),
+        });
+        // threads spawned before a failure are shut down when the handle is dropped
+        let handle = Arc::new(PoolHandle {
+            shared: shared.clone(),
+        });
+        for local in locals {
+            let state = WorkerState {
+                local,
+                shared: shared.clone(),
+            };
+            thread::Builder::new().spawn(move || run_tasks(state))?;
+        }
+        Ok(WorkStealingThreadPool { handle })
+    }
+
+    fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let shared = &self.handle.shared;
+        shared.queued.fetch_add(1, Ordering::SeqCst);
+        shared.injector.push(Box::new(job));
+        shared.wake_one();
+    }
+
+    fn queued_jobs(&self) -> usize {
+        self.handle.shared.queued.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for PoolHandle {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        let _guard = self.shared.lock.lock().unwrap();
+        self.shared.wakeup.notify_all();
+    }
+}
+
+impl Shared {
+    /// Wakes up a sleeping thread, if any, after jobs are queued.
+    fn wake_one(&self) {
+        // pairs with the fence in `sleep`, so either the sleeping thread sees the
+        // queued job or it is counted here
+        atomic::fence(Ordering::SeqCst);
+        if self.sleeping.load(Ordering::SeqCst) > 0 {
+            let _guard = self.lock.lock().unwrap();
+            self.wakeup.notify_one();
+        }
+    }
+
+    /// Returns `true` if a job is waiting in the global queue or in a local queue.
+    ///
+    /// A batch taken from the global queue sits in the local queue of one thread, which
+    /// may be busy with a long job, so the local queues count too.
+    fn has_jobs(&self) -> bool {
+        !self.injector.is_empty() || self.stealers.iter().any(|s| !s.is_empty())
+    }
+
+    /// Blocks the thread until a job is spawned or the pool is shut down.
+    fn sleep(&self) {
+        let guard = self.lock.lock().unwrap();
+        self.sleeping.fetch_add(1, Ordering::SeqCst);
+        atomic::fence(Ordering::SeqCst);
+        if !self.has_jobs() && !self.shutdown.load(Ordering::SeqCst) {
+            let _guard = self.wakeup.wait(guard).unwrap();
+        }
+        self.sleeping.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl WorkerState {
+    /// Takes a job from the local queue, the global queue or another thread.
+    fn find_task(&self) -> Option<Job> {
+        let shared = &self.shared;
+        let job = self.local.pop().or_else(|| {
+            iter::repeat_with(|| {
+                shared
+                    .injector
+                    .steal_batch_and_pop(&self.local)
+                    .or_else(|| shared.stealers.iter().map(Stealer::steal).collect())
+            })
+            .find(|s| !s.is_retry())
+            .and_then(|s| s.success())
+        })?;
+        shared.queued.fetch_sub(1, Ordering::SeqCst);
+        // let a sleeping thread take the remaining jobs, e.g. the rest of a batch, in
+        // case this job runs for long
+        if shared.has_jobs() {
+            shared.wake_one();
+        }
+        Some(job)
+    }
+}
+
+impl Drop for WorkerState {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            let local = mem::replace(&mut self.local, Worker::new_fifo());
+            let shared = self.shared.clone();
+            let spawned = thread::Builder::new().spawn(move || {
+                run_tasks(WorkerState { local, shared });
+            });
+            if let Err(e) = spawned {
+                error!("Failed to spawn a thread: {}", e);
+                // the queued jobs of the dead thread are still stolen by the others
+                self.shared.wake_one();
+            }
+        }
+    }
+}
+
+fn run_tasks(state: WorkerState) {
+    loop {
+        match state.find_task() {
+            Some(task) => task(),
+            None if state.shared.shutdown.load(Ordering::SeqCst) => {
+                debug!("Thread exits because the thread pool is destroyed.");
+                return;
+            }
+            None => state.shared.sleep(),
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! This module loads TLS configurations of `KvsServer` and `KvsClient` from
+//! PEM-encoded certificate and private key files.
+
+use crate::{KvsError, Result};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+use tokio_rustls::rustls::internal::pemfile;
+use tokio_rustls::rustls::{
+    AllowAnyAuthenticatedClient, Certificate, NoClientAuth, PrivateKey, RootCertStore,
+};
+
+pub use tokio_rustls::rustls::{ClientConfig, ServerConfig};
+
+/// Loads the TLS configuration of a server.
+///
+/// `cert` contains the certificate chain of the server and `key` contains its private
+/// key, in either PKCS#8 or RSA format. If `client_ca` is given, clients must present
+/// a certificate signed by one of the CA certificates in the file.
+pub fn server_config(
+    cert: &Path,
+    key: &Path,
+    client_ca: Option<&Path>,
+) -> Result<Arc<ServerConfig>> {
+    let verifier = match client_ca {
+        Some(client_ca) => AllowAnyAuthenticatedClient::new(load_root_store(client_ca)?),
+        None => NoClientAuth::new(),
+    };
+    let mut config = ServerConfig::new(verifier);
+    config
+        .set_single_cert(load_certs(cert)?, load_private_key(key)?)
+        .map_err(|e| KvsError::StringError(format!("Invalid server certificate: {}", e)))?;
+    Ok(Arc::new(config))
+}
+
+/// Loads the TLS configuration of a client.
+///
+/// The server certificate is verified against the CA certificates in `ca`.
+/// If `client_cert` is given, it is a pair of the client certificate chain file and
+/// the private key file presented to servers requiring client authentication.
+pub fn client_config(ca: &Path, client_cert: Option<(&Path, &Path)>) -> Result<Arc<ClientConfig>> {
+    let mut config = ClientConfig::new();
+    config.root_store = load_root_store(ca)?;
+    if let Some((cert, key)) = client_cert {
+        config.set_single_client_cert(load_certs(cert)?, load_private_key(key)?);
+    }
+    Ok(Arc::new(config))
+}
+
+fn load_root_store(path: &Path) -> Result<RootCertStore> {
+    let mut store = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        store.add(&cert).map_err(|e| {
+            KvsError::StringError(format!("Invalid CA certificate in {:?}: {}", path, e))
+        })?;
+    }
+    Ok(store)
+}
+
+fn load_certs(path: &Path) -> Result<Vec<Certificate>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    match pemfile::certs(&mut reader) {
+        Ok(ref certs) if certs.is_empty() => Err(KvsError::StringError(format!(
+            "No certificate found in {:?}",
+            path
+        ))),
+        Ok(certs) => Ok(certs),
+        Err(_) => Err(KvsError::StringError(format!(
+            "Invalid certificate file {:?}",
+            path
+        ))),
+    }
+}
+
+fn load_private_key(path: &Path) -> Result<PrivateKey> {
+    let invalid = || KvsError::StringError(format!("Invalid private key file {:?}", path));
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut keys = pemfile::pkcs8_private_keys(&mut reader).map_err(|_| invalid())?;
+    if keys.is_empty() {
+        let mut reader = BufReader::new(File::open(path)?);
+        keys = pemfile::rsa_private_keys(&mut reader).map_err(|_| invalid())?;
+    }
+    keys.into_iter()
+        .next()
+        .ok_or_else(|| KvsError::StringError(format!("No private key found in {:?}", path)))
+}
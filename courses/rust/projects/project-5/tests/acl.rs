@@ -0,0 +1,188 @@
+use assert_cmd::prelude::*;
+use predicates::str::{contains, is_empty};
+use std::fs;
+use std::process::Command;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+const ACL: &str = r#"{
+    "team-a": [
+        { "prefix": "a/", "ops": ["get", "set", "remove"] },
+        { "prefix": "shared/", "ops": ["get"] }
+    ],
+    "admin": [{ "prefix": "", "ops": ["get", "set", "remove"] }]
+}"#;
+
+fn client(temp_dir: &TempDir, addr: &str, token: Option<&str>, args: &[&str]) -> Command {
+    let mut cmd = Command::cargo_bin("kvs-client").unwrap();
+    cmd.args(args)
+        .args(&["--addr", addr])
+        .env_remove("KVS_TOKEN")
+        .current_dir(temp_dir);
+    if let Some(token) = token {
+        cmd.args(&["--token", token]);
+    }
+    cmd
+}
+
+// Requests are allowed only for the key prefixes and operations granted to the token.
+#[test]
+fn acl_access_server() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("acl.json"), ACL).unwrap();
+    let addr = "127.0.0.1:4050";
+    let (sender, receiver) = mpsc::sync_channel(0);
+    let mut child = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(&["--addr", addr, "--acl", "acl.json"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    let handle = thread::spawn(move || {
+        let _ = receiver.recv(); // wait for main thread to finish
+        child.kill().expect("server exited before killed");
+    });
+    thread::sleep(Duration::from_secs(1));
+
+    client(&temp_dir, addr, Some("team-a"), &["set", "a/key", "value1"])
+        .assert()
+        .success()
+        .stdout(is_empty());
+    client(&temp_dir, addr, Some("team-a"), &["get", "a/key"])
+        .assert()
+        .success()
+        .stdout("value1\n");
+    client(
+        &temp_dir,
+        addr,
+        Some("admin"),
+        &["set", "shared/key", "value2"],
+    )
+    .assert()
+    .success();
+    client(&temp_dir, addr, Some("team-a"), &["get", "shared/key"])
+        .assert()
+        .success()
+        .stdout("value2\n");
+
+    // operations outside of the granted prefixes and operations
+    client(
+        &temp_dir,
+        addr,
+        Some("team-a"),
+        &["set", "shared/key", "value3"],
+    )
+    .assert()
+    .code(2)
+    .stderr(contains("Permission denied"));
+    client(&temp_dir, addr, Some("team-a"), &["rm", "shared/key"])
+        .assert()
+        .code(2)
+        .stderr(contains("Permission denied"));
+    client(&temp_dir, addr, Some("team-a"), &["get", "b/key"])
+        .assert()
+        .code(2)
+        .stderr(contains("Permission denied"));
+    // unknown or missing tokens
+    client(&temp_dir, addr, Some("team-b"), &["get", "a/key"])
+        .assert()
+        .code(2)
+        .stderr(contains("invalid token"));
+    client(&temp_dir, addr, None, &["get", "a/key"])
+        .assert()
+        .code(2)
+        .stderr(contains("authentication required"));
+
+    // other errors keep their exit code
+    client(&temp_dir, addr, Some("team-a"), &["rm", "a/missing"])
+        .assert()
+        .code(1)
+        .stderr(contains("Key not found"));
+
+    // the token can be given by the environment
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["get", "shared/key", "--addr", addr])
+        .env("KVS_TOKEN", "admin")
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("value2\n");
+
+    client(&temp_dir, addr, Some("admin"), &["get", "a/key"])
+        .assert()
+        .success()
+        .stdout("value1\n");
+
+    sender.send(()).unwrap();
+    handle.join().unwrap();
+}
+
+// Servers without ACL accept any token.
+#[test]
+fn acl_disabled() {
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4051";
+    let (sender, receiver) = mpsc::sync_channel(0);
+    let mut child = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(&["--addr", addr])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    let handle = thread::spawn(move || {
+        let _ = receiver.recv(); // wait for main thread to finish
+        child.kill().expect("server exited before killed");
+    });
+    thread::sleep(Duration::from_secs(1));
+
+    client(
+        &temp_dir,
+        addr,
+        Some("anything"),
+        &["set", "key1", "value1"],
+    )
+    .assert()
+    .success();
+    client(&temp_dir, addr, None, &["get", "key1"])
+        .assert()
+        .success()
+        .stdout("value1\n");
+
+    sender.send(()).unwrap();
+    handle.join().unwrap();
+}
+
+// The ACL cannot be combined with front-ends that do not enforce it, and must be valid.
+#[test]
+fn cli_acl_invalid() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("acl.json"), ACL).unwrap();
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(&["--acl", "acl.json", "--resp-addr", "127.0.0.1:4052"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(&["--acl", "acl.json", "--http-addr", "127.0.0.1:4052"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+
+    fs::write(
+        temp_dir.path().join("invalid.json"),
+        r#"{ "token": [{ "prefix": "a/", "ops": ["drop"] }] }"#,
+    )
+    .unwrap();
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(&["--acl", "invalid.json", "--addr", "127.0.0.1:4052"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("Invalid ACL file"));
+}
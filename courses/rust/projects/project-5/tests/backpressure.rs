@@ -0,0 +1,104 @@
+use kvs::thread_pool::{SharedQueueThreadPool, ThreadPool};
+use kvs::{KvStore, KvsClient, KvsEngine, KvsError, KvsServer};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{mpsc, Arc, Barrier};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::prelude::*;
+
+/// Keeps the only thread of the pool busy and fills its queue, until the returned
+/// sender is sent to.
+fn fill(pool: &SharedQueueThreadPool) -> mpsc::Sender<()> {
+    let (tx, rx) = mpsc::channel::<()>();
+    let started = Arc::new(Barrier::new(2));
+    {
+        let started = Arc::clone(&started);
+        pool.spawn(move || {
+            started.wait();
+            rx.recv().unwrap();
+        });
+    }
+    started.wait();
+    while pool.try_spawn(|| {}).is_ok() {}
+    tx
+}
+
+/// Waits until a thread picked up the tasks filling the queue, so it has room again.
+fn wait_for_room(pool: &SharedQueueThreadPool) {
+    while pool.queued_jobs() > 0 {
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+// Engine operations fail instead of queueing while the thread pool queue is full.
+#[test]
+fn engine_server_busy() {
+    let temp_dir = TempDir::new().unwrap();
+    let pool = SharedQueueThreadPool::with_capacity(1, 1).unwrap();
+    let store = KvStore::open_with_pool(temp_dir.path(), 1, pool.clone()).unwrap();
+    store
+        .set("key1".to_owned(), "value1".to_owned())
+        .wait()
+        .unwrap();
+
+    let release = fill(&pool);
+    match store.get("key1".to_owned()).wait() {
+        Err(KvsError::ServerBusy) => {}
+        res => panic!("unexpected result: {:?}", res),
+    }
+    assert_eq!(store.stats().queued_jobs, 1);
+    assert_eq!(store.stats().queue_capacity, Some(1));
+
+    release.send(()).unwrap();
+    wait_for_room(&pool);
+    assert_eq!(
+        store.get("key1".to_owned()).wait().unwrap(),
+        Some("value1".to_owned())
+    );
+}
+
+// `KvsServer` replies `ServerBusy` to the client and counts the rejected requests.
+#[test]
+fn server_busy_response() {
+    let temp_dir = TempDir::new().unwrap();
+    let pool = SharedQueueThreadPool::with_capacity(1, 1).unwrap();
+    let store = KvStore::open_with_pool(temp_dir.path(), 1, pool.clone()).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let metrics_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let metrics_addr = metrics_listener.local_addr().unwrap();
+    thread::spawn(move || {
+        KvsServer::new(store)
+            .with_metrics_listener(metrics_listener)
+            .run_on(listener)
+            .unwrap()
+    });
+
+    let release = fill(&pool);
+    let client = KvsClient::connect(addr).wait().unwrap();
+    match client.set("key1".to_owned(), "value1".to_owned()).wait() {
+        Err(KvsError::ServerBusy) => {}
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("the request is queued beyond the capacity"),
+    }
+
+    let mut stream = TcpStream::connect(metrics_addr).unwrap();
+    stream.write_all(b"GET /metrics HTTP/1.0\r\n\r\n").unwrap();
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp).unwrap();
+    assert!(resp.contains("kvs_errors_total{kind=\"server_busy\"} 1"));
+    assert!(resp.contains("kvs_thread_pool_queued_jobs 1"));
+    assert!(resp.contains("kvs_thread_pool_queue_capacity 1"));
+
+    release.send(()).unwrap();
+    wait_for_room(&pool);
+    let client = KvsClient::connect(addr).wait().unwrap();
+    let client = client
+        .set("key1".to_owned(), "value1".to_owned())
+        .wait()
+        .unwrap();
+    let (value, _) = client.get("key1".to_owned()).wait().unwrap();
+    assert_eq!(value, Some("value1".to_owned()));
+}
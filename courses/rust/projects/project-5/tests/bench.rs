@@ -0,0 +1,91 @@
+use assert_cmd::prelude::*;
+use predicates::str::contains;
+use serde_json::Value;
+use std::process::Command;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+/// Runs `kvs-server` on `addr` until the returned sender is used.
+fn start_server(temp_dir: &TempDir, addr: &str) -> (mpsc::SyncSender<()>, thread::JoinHandle<()>) {
+    let (sender, receiver) = mpsc::sync_channel(0);
+    let mut child = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(&["--addr", addr])
+        .current_dir(temp_dir)
+        .spawn()
+        .unwrap();
+    let handle = thread::spawn(move || {
+        let _ = receiver.recv(); // wait for main thread to finish
+        child.kill().expect("server exited before killed");
+    });
+    thread::sleep(Duration::from_secs(1));
+    (sender, handle)
+}
+
+// `kvs-bench` sends the requested mix of reads and writes and reports them.
+#[test]
+fn cli_bench() {
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4090";
+    let (sender, handle) = start_server(&temp_dir, addr);
+
+    let output = Command::cargo_bin("kvs-bench")
+        .unwrap()
+        .args(&["--addr", addr, "-c", "4", "-n", "1001", "--keys", "100"])
+        .args(&["--distribution", "zipfian", "--preload", "--json"])
+        .args(&["--label", "kvs/rayon"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let report: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["label"], "kvs/rayon");
+    assert_eq!(report["distribution"], "zipfian");
+    assert_eq!(report["requests"], 1001);
+    assert_eq!(report["errors"], 0);
+    let reads = report["reads"].as_u64().unwrap();
+    let writes = report["writes"].as_u64().unwrap();
+    assert_eq!(reads + writes, 1001);
+    assert!(reads > 0 && writes > 0);
+    let latency = &report["latency_us"];
+    let p50 = latency["p50"].as_f64().unwrap();
+    let p99 = latency["p99"].as_f64().unwrap();
+    let p999 = latency["p999"].as_f64().unwrap();
+    assert!(0.0 < p50 && p50 <= p99 && p99 <= p999 && p999 <= latency["max"].as_f64().unwrap());
+    assert!(report["throughput"].as_f64().unwrap() > 0.0);
+
+    // preloaded keys are readable
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["get", "key00000099", "--addr", addr])
+        .assert()
+        .success()
+        .stdout(format!("{}\n", "x".repeat(100)));
+
+    Command::cargo_bin("kvs-bench")
+        .unwrap()
+        .args(&["--addr", addr, "-n", "100", "--read-ratio", "1"])
+        .assert()
+        .success()
+        .stdout(contains("Requests:    100 (100 reads, 0 writes, 0 errors)"));
+
+    sender.send(()).unwrap();
+    handle.join().unwrap();
+}
+
+// Invalid options fail before connecting.
+#[test]
+fn cli_bench_invalid_options() {
+    Command::cargo_bin("kvs-bench")
+        .unwrap()
+        .args(&["--addr", "127.0.0.1:4091", "--read-ratio", "1.5"])
+        .assert()
+        .failure()
+        .stderr(contains("Read ratio must be between 0 and 1"));
+    Command::cargo_bin("kvs-bench")
+        .unwrap()
+        .args(&["--addr", "127.0.0.1:4091", "--distribution", "normal"])
+        .assert()
+        .failure();
+}
@@ -1,6 +1,8 @@
 use assert_cmd::prelude::*;
 use predicates::str::{contains, is_empty};
 use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::net::TcpStream;
 use std::process::Command;
 use std::sync::mpsc;
 use std::thread;
@@ -335,3 +337,67 @@ fn cli_access_server_kvs_engine() {
 fn cli_access_server_sled_engine() {
     cli_access_server("sled", "127.0.0.1:4005");
 }
+
+// `kvs-server` serves Prometheus metrics over HTTP when `--metrics-addr` is given.
+#[test]
+fn cli_server_metrics() {
+    let (sender, receiver) = mpsc::sync_channel(0);
+    let temp_dir = TempDir::new().unwrap();
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(&[
+            "--addr",
+            "127.0.0.1:4006",
+            "--metrics-addr",
+            "127.0.0.1:4007",
+        ])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    let handle = thread::spawn(move || {
+        let _ = receiver.recv(); // wait for main thread to finish
+        child.kill().expect("server exited before killed");
+    });
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["set", "key1", "value1", "--addr", "127.0.0.1:4006"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["get", "key1", "--addr", "127.0.0.1:4006"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["rm", "key2", "--addr", "127.0.0.1:4006"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+
+    let mut stream = TcpStream::connect("127.0.0.1:4007").unwrap();
+    stream.write_all(b"GET /metrics HTTP/1.0\r\n\r\n").unwrap();
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp).unwrap();
+    assert!(resp.contains("200 OK"));
+    assert!(resp.contains("kvs_requests_total{request=\"set\"} 1"));
+    assert!(resp.contains("kvs_requests_total{request=\"get\"} 1"));
+    assert!(resp.contains("kvs_requests_total{request=\"remove\"} 1"));
+    assert!(resp.contains("kvs_request_duration_seconds_count{request=\"set\"} 1"));
+    assert!(resp.contains("kvs_errors_total{kind=\"key_not_found\"} 1"));
+    assert!(resp.contains("kvs_engine_disk_bytes"));
+    assert!(resp.contains("kvs_thread_pool_queued_jobs"));
+
+    let mut stream = TcpStream::connect("127.0.0.1:4007").unwrap();
+    stream.write_all(b"GET /unknown HTTP/1.0\r\n\r\n").unwrap();
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp).unwrap();
+    assert!(resp.contains("404 Not Found"));
+
+    sender.send(()).unwrap();
+    handle.join().unwrap();
+}
@@ -0,0 +1,181 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use predicates::str::contains;
+use std::fs;
+use std::process::Command;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+/// Runs `kvs-server` on `addr` until the returned sender is used.
+fn start_server(temp_dir: &TempDir, addr: &str) -> (mpsc::SyncSender<()>, thread::JoinHandle<()>) {
+    let (sender, receiver) = mpsc::sync_channel(0);
+    let mut child = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(&["--addr", addr])
+        .current_dir(temp_dir)
+        .spawn()
+        .unwrap();
+    let handle = thread::spawn(move || {
+        let _ = receiver.recv(); // wait for main thread to finish
+        child.kill().expect("server exited before killed");
+    });
+    thread::sleep(Duration::from_secs(1));
+    (sender, handle)
+}
+
+fn client_exec(temp_dir: &TempDir, addr: &str, script: &str) {
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["exec", "--addr", addr])
+        .current_dir(temp_dir)
+        .with_stdin()
+        .buffer(script)
+        .assert()
+        .success();
+}
+
+// An export imported to another server restores the same entries.
+#[test]
+fn cli_export_import_jsonl() {
+    let source_dir = TempDir::new().unwrap();
+    let (source_sender, source_handle) = start_server(&source_dir, "127.0.0.1:4080");
+    let target_dir = TempDir::new().unwrap();
+    let (target_sender, target_handle) = start_server(&target_dir, "127.0.0.1:4081");
+
+    let mut script = String::new();
+    for i in 0..250 {
+        script.push_str(&format!("set user:{:03} \"name {}\"\n", i, i));
+    }
+    script.push_str("set other:1 \"line\\\\break \\\"quoted\\\"\"\n");
+    client_exec(&source_dir, "127.0.0.1:4080", &script);
+
+    // small batches span several pipelines
+    let output = Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["export", "--addr", "127.0.0.1:4080", "--batch-size", "100"])
+        .current_dir(&source_dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let dump = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(dump.lines().count(), 251);
+    assert!(dump.contains("{\"key\":\"other:1\",\"value\":\"line\\\\break \\\"quoted\\\"\"}\n"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Exported 251 entries"));
+    fs::write(target_dir.path().join("dump.jsonl"), dump).unwrap();
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["import", "dump.jsonl", "--addr", "127.0.0.1:4081"])
+        .args(&["--batch-size", "100"])
+        .current_dir(&target_dir)
+        .assert()
+        .success()
+        .stderr(contains("Imported 100 entries").and(contains("Imported 251 entries")));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["get", "user:123", "--addr", "127.0.0.1:4081"])
+        .current_dir(&target_dir)
+        .assert()
+        .success()
+        .stdout("name 123\n");
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["get", "other:1", "--addr", "127.0.0.1:4081"])
+        .current_dir(&target_dir)
+        .assert()
+        .success()
+        .stdout("line\\break \"quoted\"\n");
+
+    source_sender.send(()).unwrap();
+    source_handle.join().unwrap();
+    target_sender.send(()).unwrap();
+    target_handle.join().unwrap();
+}
+
+// `--prefix` limits the export, and CSV dumps are written and read.
+#[test]
+fn cli_export_import_csv() {
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4082";
+    let (sender, handle) = start_server(&temp_dir, addr);
+    client_exec(
+        &temp_dir,
+        addr,
+        "set a:1 one\nset a:2 \"two, 2\"\nset b:1 three\n",
+    );
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&[
+            "export", "--prefix", "a:", "--format", "csv", "--addr", addr,
+        ])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("key,value\na:1,one\na:2,\"two, 2\"\n");
+
+    fs::write(
+        temp_dir.path().join("dump.csv"),
+        "key,value\nc:1,four\nc:2,\"five, 5\"\n",
+    )
+    .unwrap();
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["import", "dump.csv", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["export", "--prefix", "c:", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("{\"key\":\"c:1\",\"value\":\"four\"}\n{\"key\":\"c:2\",\"value\":\"five, 5\"}\n");
+
+    sender.send(()).unwrap();
+    handle.join().unwrap();
+}
+
+// Invalid entries are reported by line number, and the valid ones are still imported.
+#[test]
+fn cli_import_invalid_entries() {
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4083";
+    let (sender, handle) = start_server(&temp_dir, addr);
+    fs::write(
+        temp_dir.path().join("dump.jsonl"),
+        "{\"key\":\"key1\",\"value\":\"value1\"}\n\
+         not json\n\
+         \n\
+         {\"key\":\"key2\"}\n\
+         {\"key\":\"key3\",\"value\":\"value3\"}\n",
+    )
+    .unwrap();
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["import", "dump.jsonl", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(
+            contains("Line 2: ")
+                .and(contains("Line 4: "))
+                .and(contains("Imported 2 entries"))
+                .and(contains("2 of 4 entries failed")),
+        );
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["get", "key3", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("value3\n");
+
+    sender.send(()).unwrap();
+    handle.join().unwrap();
+}
@@ -0,0 +1,180 @@
+use assert_cmd::prelude::*;
+use kvs::thread_pool::RayonThreadPool;
+use kvs::{KvStore, KvsClientPool, KvsError, KvsServer};
+use std::io;
+use std::net::TcpListener;
+use std::process::{Child, Command};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+use tokio::prelude::*;
+use tokio::runtime::Runtime;
+
+fn start_server(addr: &str) -> TempDir {
+    let temp_dir = TempDir::new().unwrap();
+    let engine = KvStore::<RayonThreadPool>::open(temp_dir.path(), 4).unwrap();
+    let addr = addr.parse().unwrap();
+    thread::spawn(move || KvsServer::new(engine).run(addr).unwrap());
+    thread::sleep(Duration::from_secs(1));
+    temp_dir
+}
+
+fn spawn_server_process(temp_dir: &TempDir, addr: &str) -> Child {
+    let child = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(&["--addr", addr])
+        .current_dir(temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    child
+}
+
+// Concurrent requests share the connections of the pool.
+#[test]
+fn pool_concurrent_requests() {
+    let _temp_dir = start_server("127.0.0.1:4060");
+    let pool = KvsClientPool::new("127.0.0.1:4060".parse().unwrap()).with_size(2);
+    let mut runtime = Runtime::new().unwrap();
+
+    let sets: Vec<_> = (0..100)
+        .map(|i| pool.set(format!("key{}", i), format!("value{}", i)))
+        .collect();
+    runtime.block_on(future::join_all(sets)).unwrap();
+    let gets: Vec<_> = (0..100).map(|i| pool.get(format!("key{}", i))).collect();
+    let values = runtime.block_on(future::join_all(gets)).unwrap();
+    for (i, value) in values.into_iter().enumerate() {
+        assert_eq!(value, Some(format!("value{}", i)));
+    }
+
+    // error responses don't break the pool
+    let res = runtime.block_on(pool.remove("missing".to_owned()));
+    match res {
+        Err(KvsError::StringError(ref msg)) if msg == "Key not found" => {}
+        res => panic!("unexpected result: {:?}", res),
+    }
+    assert_eq!(
+        runtime.block_on(pool.get("key1".to_owned())).unwrap(),
+        Some("value1".to_owned())
+    );
+    assert_eq!(
+        runtime.block_on(pool.get("missing".to_owned())).unwrap(),
+        None
+    );
+}
+
+// Connections broken by a server restart are reopened, and reads are retried.
+#[test]
+fn pool_reconnects_after_server_restart() {
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4061";
+    let pool = KvsClientPool::new(addr.parse().unwrap())
+        .with_size(2)
+        .with_retries(5, Duration::from_millis(100));
+    let mut runtime = Runtime::new().unwrap();
+
+    let mut server = spawn_server_process(&temp_dir, addr);
+    runtime
+        .block_on(pool.set("key1".to_owned(), "value1".to_owned()))
+        .unwrap();
+    server.kill().unwrap();
+    server.wait().unwrap();
+
+    let mut server = spawn_server_process(&temp_dir, addr);
+    assert_eq!(
+        runtime.block_on(pool.get("key1".to_owned())).unwrap(),
+        Some("value1".to_owned())
+    );
+    runtime
+        .block_on(pool.set("key2".to_owned(), "value2".to_owned()))
+        .unwrap();
+    server.kill().unwrap();
+    server.wait().unwrap();
+
+    // the server is down
+    let res = runtime.block_on(pool.get("key1".to_owned()));
+    assert!(res.is_err());
+    let res = runtime.block_on(pool.set("key3".to_owned(), "value3".to_owned()));
+    assert!(res.is_err());
+
+    let mut server = spawn_server_process(&temp_dir, addr);
+    assert_eq!(
+        runtime.block_on(pool.get("key2".to_owned())).unwrap(),
+        Some("value2".to_owned())
+    );
+    server.kill().unwrap();
+    server.wait().unwrap();
+}
+
+// Requests to an unresponsive server time out instead of hanging forever.
+#[test]
+fn pool_request_timeout() {
+    let listener = TcpListener::bind("127.0.0.1:4062").unwrap();
+    thread::spawn(move || {
+        // accept connections and never reply
+        let conns: Vec<_> = listener.incoming().collect();
+        drop(conns);
+    });
+    let pool = KvsClientPool::new("127.0.0.1:4062".parse().unwrap())
+        .with_request_timeout(Duration::from_millis(200))
+        .with_retries(2, Duration::from_millis(10));
+    let mut runtime = Runtime::new().unwrap();
+
+    let start = Instant::now();
+    match runtime.block_on(pool.get("key1".to_owned())) {
+        Err(KvsError::Io(ref e)) if e.kind() == io::ErrorKind::TimedOut => {}
+        res => panic!("unexpected result: {:?}", res),
+    }
+    // three attempts
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(600), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(3), "{:?}", elapsed);
+
+    let start = Instant::now();
+    match runtime.block_on(pool.set("key1".to_owned(), "value1".to_owned())) {
+        Err(KvsError::Io(ref e)) if e.kind() == io::ErrorKind::TimedOut => {}
+        res => panic!("unexpected result: {:?}", res),
+    }
+    // not retried
+    assert!(start.elapsed() < Duration::from_millis(600));
+}
+
+// Requests fail when the server cannot be reached.
+#[test]
+fn pool_connection_refused() {
+    let pool = KvsClientPool::new("127.0.0.1:4063".parse().unwrap())
+        .with_retries(1, Duration::from_millis(10));
+    let mut runtime = Runtime::new().unwrap();
+    match runtime.block_on(pool.get("key1".to_owned())) {
+        Err(KvsError::Io(_)) => {}
+        res => panic!("unexpected result: {:?}", res),
+    }
+    let res = runtime.block_on(future::lazy(move || {
+        pool.set("key1".to_owned(), "v".to_owned())
+    }));
+    assert!(res.is_err());
+}
+
+// Writes are retried when they fail before being sent, e.g. while the server starts.
+#[test]
+fn pool_retries_writes_before_sent() {
+    let temp_dir = TempDir::new().unwrap();
+    let engine = KvStore::<RayonThreadPool>::open(temp_dir.path(), 4).unwrap();
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(300));
+        KvsServer::new(engine)
+            .run("127.0.0.1:4064".parse().unwrap())
+            .unwrap()
+    });
+    let pool = KvsClientPool::new("127.0.0.1:4064".parse().unwrap())
+        .with_retries(5, Duration::from_millis(100));
+    let mut runtime = Runtime::new().unwrap();
+
+    runtime
+        .block_on(pool.set("key1".to_owned(), "value1".to_owned()))
+        .unwrap();
+    assert_eq!(
+        runtime.block_on(pool.get("key1".to_owned())).unwrap(),
+        Some("value1".to_owned())
+    );
+}
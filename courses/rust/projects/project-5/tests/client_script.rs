@@ -0,0 +1,166 @@
+use assert_cmd::prelude::*;
+use predicates::str::contains;
+use std::fs;
+use std::process::Command;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+/// Runs `kvs-server` on `addr` until the returned sender is used.
+fn start_server(temp_dir: &TempDir, addr: &str) -> (mpsc::SyncSender<()>, thread::JoinHandle<()>) {
+    let (sender, receiver) = mpsc::sync_channel(0);
+    let mut child = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(&["--addr", addr])
+        .current_dir(temp_dir)
+        .spawn()
+        .unwrap();
+    let handle = thread::spawn(move || {
+        let _ = receiver.recv(); // wait for main thread to finish
+        child.kill().expect("server exited before killed");
+    });
+    thread::sleep(Duration::from_secs(1));
+    (sender, handle)
+}
+
+const SCRIPT: &str = r#"# a comment
+set key1 value1
+set "key 2" "value \"2\""
+
+get key1
+get "key 2"
+get key3
+rm key3
+unknown key1
+rm key1
+get key1
+"#;
+
+// `kvs-client exec` reports the result of each line, and fails if any command failed.
+#[test]
+fn cli_exec_script() {
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4070";
+    let (sender, handle) = start_server(&temp_dir, addr);
+    fs::write(temp_dir.path().join("script.txt"), SCRIPT).unwrap();
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["exec", "-f", "script.txt", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stdout(
+            "2: OK\n\
+             3: OK\n\
+             5: value1\n\
+             6: value \"2\"\n\
+             7: Key not found\n\
+             8: Error: Key not found\n\
+             9: Error: Unknown command 'unknown'\n\
+             10: OK\n\
+             11: Key not found\n",
+        )
+        .stderr(contains("2 of 9 commands failed"));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["exec", "--addr", addr, "--format", "json"])
+        .current_dir(&temp_dir)
+        .with_stdin()
+        .buffer("get \"key 2\"\nset key1 value3\nget key4\nrm key4\n")
+        .assert()
+        .failure()
+        .stdout(
+            "{\"line\":1,\"command\":\"get\",\"key\":\"key 2\",\"status\":\"ok\",\"value\":\"value \\\"2\\\"\",\"error\":null}\n\
+             {\"line\":2,\"command\":\"set\",\"key\":\"key1\",\"status\":\"ok\",\"value\":null,\"error\":null}\n\
+             {\"line\":3,\"command\":\"get\",\"key\":\"key4\",\"status\":\"not_found\",\"value\":null,\"error\":null}\n\
+             {\"line\":4,\"command\":\"rm\",\"key\":\"key4\",\"status\":\"error\",\"value\":null,\"error\":\"Key not found\"}\n",
+        );
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["exec", "--addr", addr, "--format", "csv"])
+        .current_dir(&temp_dir)
+        .with_stdin()
+        .buffer("get key1\nget \"key 2\"\nget key4\n")
+        .assert()
+        .success()
+        .stdout(
+            "line,command,key,status,value,error\n\
+             1,get,key1,ok,value3,\n\
+             2,get,key 2,ok,\"value \"\"2\"\"\",\n\
+             3,get,key4,not_found,,\n",
+        );
+
+    sender.send(()).unwrap();
+    handle.join().unwrap();
+}
+
+// Long scripts are pipelined without deadlocking on full socket buffers.
+#[test]
+fn cli_exec_long_script() {
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4071";
+    let (sender, handle) = start_server(&temp_dir, addr);
+    let mut script = String::new();
+    let mut expected = String::new();
+    for i in 0..2000 {
+        script.push_str(&format!("set key{} {}\n", i, "x".repeat(1000)));
+        expected.push_str(&format!("{}: OK\n", i + 1));
+    }
+    for i in 0..2000 {
+        script.push_str(&format!("get key{}\n", i));
+        expected.push_str(&format!("{}: {}\n", i + 2001, "x".repeat(1000)));
+    }
+    fs::write(temp_dir.path().join("script.txt"), script).unwrap();
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["exec", "--file", "script.txt", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(expected);
+
+    sender.send(()).unwrap();
+    handle.join().unwrap();
+}
+
+// `kvs-client shell` runs each entered command over one connection.
+#[test]
+fn cli_shell() {
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4072";
+    let (sender, handle) = start_server(&temp_dir, addr);
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["shell", "--addr", addr])
+        .env("HOME", temp_dir.path())
+        .current_dir(&temp_dir)
+        .with_stdin()
+        .buffer("set key1 value1\nget key1\nbad\nrm key2\nget key2\nexit\nget key1\n")
+        .assert()
+        .success()
+        .stdout(contains(
+            "OK\nvalue1\nError: Unknown command 'bad'\nError: Key not found\nKey not found\n",
+        ));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["shell", "--addr", addr, "--format", "json"])
+        .env("HOME", temp_dir.path())
+        .current_dir(&temp_dir)
+        .with_stdin()
+        .buffer("get key1\n")
+        .assert()
+        .success()
+        .stdout(contains(
+            "{\"line\":null,\"command\":\"get\",\"key\":\"key1\",\"status\":\"ok\",\"value\":\"value1\",\"error\":null}\n",
+        ));
+
+    sender.send(()).unwrap();
+    handle.join().unwrap();
+}
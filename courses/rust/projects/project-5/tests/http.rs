@@ -0,0 +1,184 @@
+use kvs::thread_pool::RayonThreadPool;
+use kvs::{KvStore, KvsServer};
+use serde_json::{json, Value};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+/// Starts a server with an HTTP gateway in the background.
+///
+/// The returned directory holds the data and must outlive the test.
+fn start_server(addr: &str, http_addr: &str) -> TempDir {
+    let temp_dir = TempDir::new().unwrap();
+    let store = KvStore::<RayonThreadPool>::open(temp_dir.path(), 4).unwrap();
+    let server = KvsServer::new(store).with_http(http_addr.parse().unwrap());
+    let addr = addr.parse().unwrap();
+    thread::spawn(move || server.run(addr).unwrap());
+    thread::sleep(Duration::from_secs(1));
+    temp_dir
+}
+
+/// Sends an HTTP/1.0 request and returns the status code and the body.
+fn request(addr: &str, method: &str, path: &str, body: Option<&str>) -> (u16, String) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    let body = body.unwrap_or("");
+    write!(
+        stream,
+        "{} {} HTTP/1.0\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        body.len(),
+        body
+    )
+    .unwrap();
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp).unwrap();
+    let status = resp[9..12].parse().unwrap();
+    let body = match resp.find("\r\n\r\n") {
+        Some(pos) => resp[pos + 4..].to_owned(),
+        None => String::new(),
+    };
+    (status, body)
+}
+
+fn json_body(body: &str) -> Value {
+    serde_json::from_str(body).unwrap()
+}
+
+#[test]
+fn http_get_put_delete() {
+    let _dir = start_server("127.0.0.1:4030", "127.0.0.1:4031");
+    let addr = "127.0.0.1:4031";
+
+    let (status, body) = request(addr, "GET", "/keys/key1", None);
+    assert_eq!(status, 404);
+    assert_eq!(json_body(&body), json!({ "error": "Key not found" }));
+
+    let (status, body) = request(addr, "PUT", "/keys/key1", Some(r#"{"value":"value1"}"#));
+    assert_eq!(status, 204);
+    assert!(body.is_empty());
+
+    let (status, body) = request(addr, "GET", "/keys/key1", None);
+    assert_eq!(status, 200);
+    assert_eq!(
+        json_body(&body),
+        json!({ "key": "key1", "value": "value1" })
+    );
+
+    let (status, _) = request(addr, "PUT", "/keys/key1", Some(r#"{"value":"value2"}"#));
+    assert_eq!(status, 204);
+    let (_, body) = request(addr, "GET", "/keys/key1", None);
+    assert_eq!(
+        json_body(&body),
+        json!({ "key": "key1", "value": "value2" })
+    );
+
+    let (status, _) = request(addr, "DELETE", "/keys/key1", None);
+    assert_eq!(status, 204);
+    let (status, _) = request(addr, "GET", "/keys/key1", None);
+    assert_eq!(status, 404);
+    let (status, body) = request(addr, "DELETE", "/keys/key1", None);
+    assert_eq!(status, 404);
+    assert_eq!(json_body(&body), json!({ "error": "Key not found" }));
+}
+
+#[test]
+fn http_encoded_key() {
+    let _dir = start_server("127.0.0.1:4032", "127.0.0.1:4033");
+    let addr = "127.0.0.1:4033";
+
+    let (status, _) = request(addr, "PUT", "/keys/a%20b%2Fc", Some(r#"{"value":"v"}"#));
+    assert_eq!(status, 204);
+    let (status, body) = request(addr, "GET", "/keys/a%20b%2Fc", None);
+    assert_eq!(status, 200);
+    assert_eq!(json_body(&body), json!({ "key": "a b/c", "value": "v" }));
+}
+
+#[test]
+fn http_list_keys() {
+    let _dir = start_server("127.0.0.1:4034", "127.0.0.1:4035");
+    let addr = "127.0.0.1:4035";
+
+    for key in &["user:2", "user:1", "group:1"] {
+        let path = format!("/keys/{}", key);
+        let (status, _) = request(addr, "PUT", &path, Some(r#"{"value":"v"}"#));
+        assert_eq!(status, 204);
+    }
+
+    let (status, body) = request(addr, "GET", "/keys?prefix=user%3A", None);
+    assert_eq!(status, 200);
+    assert_eq!(json_body(&body), json!({ "keys": ["user:1", "user:2"] }));
+
+    let (status, body) = request(addr, "GET", "/keys", None);
+    assert_eq!(status, 200);
+    assert_eq!(
+        json_body(&body),
+        json!({ "keys": ["group:1", "user:1", "user:2"] })
+    );
+}
+
+#[test]
+fn http_health_and_stats() {
+    let _dir = start_server("127.0.0.1:4036", "127.0.0.1:4037");
+    let addr = "127.0.0.1:4037";
+
+    let (status, body) = request(addr, "GET", "/healthz", None);
+    assert_eq!(status, 200);
+    assert_eq!(json_body(&body), json!({ "status": "ok" }));
+
+    let (status, _) = request(addr, "PUT", "/keys/key1", Some(r#"{"value":"value1"}"#));
+    assert_eq!(status, 204);
+    let (status, body) = request(addr, "GET", "/stats", None);
+    assert_eq!(status, 200);
+    let stats = json_body(&body);
+    assert!(stats["disk_usage"].as_u64().unwrap() > 0);
+    assert_eq!(stats["compactions"], json!(0));
+}
+
+#[test]
+fn http_bad_requests() {
+    let _dir = start_server("127.0.0.1:4038", "127.0.0.1:4039");
+    let addr = "127.0.0.1:4039";
+
+    let (status, body) = request(addr, "PUT", "/keys/key1", Some("not json"));
+    assert_eq!(status, 400);
+    assert!(json_body(&body)["error"].is_string());
+
+    let (status, _) = request(addr, "POST", "/keys/key1", Some(r#"{"value":"v"}"#));
+    assert_eq!(status, 405);
+
+    let (status, _) = request(addr, "GET", "/unknown", None);
+    assert_eq!(status, 404);
+
+    // malformed escapes are rejected instead of being decoded to something else
+    let (status, _) = request(addr, "GET", "/keys?prefix=user%3", None);
+    assert_eq!(status, 400);
+    let (status, _) = request(addr, "GET", "/keys/%+1", None);
+    assert_eq!(status, 400);
+}
+
+#[test]
+fn http_body_too_large() {
+    let _dir = start_server("127.0.0.1:4042", "127.0.0.1:4043");
+    let addr = "127.0.0.1:4043";
+
+    // rejected from the declared length without reading the body
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "PUT /keys/key1 HTTP/1.0\r\nContent-Length: {}\r\n\r\n",
+        2 * 1024 * 1024
+    )
+    .unwrap();
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp).unwrap();
+    assert_eq!(&resp[9..12], "413");
+
+    // a body just below the limit is accepted
+    let value = "v".repeat(1024 * 1024 - 20);
+    let body = json!({ "value": value }).to_string();
+    let (status, _) = request(addr, "PUT", "/keys/key1", Some(&body));
+    assert_eq!(status, 204);
+}
@@ -1,4 +1,4 @@
-use kvs::thread_pool::RayonThreadPool;
+use kvs::thread_pool::{RayonThreadPool, ThreadPool};
 use kvs::{KvStore, KvsEngine, KvsError, Result};
 use tempfile::TempDir;
 use tokio::prelude::*;
@@ -237,3 +237,25 @@ fn concurrent_get() -> Result<()> {
 
     Ok(())
 }
+
+// Reads beyond the concurrency of the store wait for a free reader
+#[test]
+fn concurrent_get_more_threads_than_readers() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let pool = RayonThreadPool::new(8)?;
+    let store = KvStore::open_with_pool(temp_dir.path(), 2, pool)?;
+    for i in 0..100 {
+        store
+            .set(format!("key{}", i), format!("value{}", i))
+            .wait()?;
+    }
+
+    let gets = (0..1000).map(|i| {
+        let key_id = i % 100;
+        store.get(format!("key{}", key_id)).map(move |res| {
+            assert_eq!(res, Some(format!("value{}", key_id)));
+        })
+    });
+    future::join_all(gets).wait()?;
+    Ok(())
+}
@@ -0,0 +1,356 @@
+use kvs::thread_pool::RayonThreadPool;
+use kvs::{KvStore, KvsClient, KvsServer};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::prelude::*;
+
+/// A reply parsed by the hand-rolled RESP client.
+#[derive(Debug, PartialEq)]
+enum Reply {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(String),
+    Nil,
+    Array(Vec<Reply>),
+}
+
+fn simple(s: &str) -> Reply {
+    Reply::Simple(s.to_owned())
+}
+
+fn bulk(s: &str) -> Reply {
+    Reply::Bulk(s.to_owned())
+}
+
+struct RespClient {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl RespClient {
+    fn connect(addr: &str) -> RespClient {
+        let writer = TcpStream::connect(addr).unwrap();
+        writer
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        RespClient {
+            reader: BufReader::new(writer.try_clone().unwrap()),
+            writer,
+        }
+    }
+
+    fn send(&mut self, args: &[&str]) {
+        self.writer.write_all(&encode(args)).unwrap();
+    }
+
+    fn call(&mut self, args: &[&str]) -> Reply {
+        self.send(args);
+        self.read_reply()
+    }
+
+    fn read_line(&mut self) -> String {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).unwrap();
+        assert!(line.ends_with("\r\n"), "invalid line: {:?}", line);
+        line.truncate(line.len() - 2);
+        line
+    }
+
+    fn read_reply(&mut self) -> Reply {
+        let line = self.read_line();
+        let (type_byte, rest) = line.split_at(1);
+        match type_byte {
+            "+" => Reply::Simple(rest.to_owned()),
+            "-" => Reply::Error(rest.to_owned()),
+            ":" => Reply::Integer(rest.parse().unwrap()),
+            "$" => match rest.parse::<i64>().unwrap() {
+                -1 => Reply::Nil,
+                len => {
+                    let mut buf = vec![0; len as usize + 2];
+                    self.reader.read_exact(&mut buf).unwrap();
+                    buf.truncate(len as usize);
+                    Reply::Bulk(String::from_utf8(buf).unwrap())
+                }
+            },
+            "*" => {
+                let len = rest.parse::<usize>().unwrap();
+                Reply::Array((0..len).map(|_| self.read_reply()).collect())
+            }
+            _ => panic!("invalid reply: {:?}", line),
+        }
+    }
+}
+
+fn encode(args: &[&str]) -> Vec<u8> {
+    let mut buf = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        buf.extend(format!("${}\r\n{}\r\n", arg.len(), arg).into_bytes());
+    }
+    buf
+}
+
+/// Starts a server with a RESP listener in the background.
+///
+/// The returned directory holds the data and must outlive the test.
+fn start_server(addr: &str, resp_addr: &str) -> TempDir {
+    let temp_dir = TempDir::new().unwrap();
+    let store = KvStore::<RayonThreadPool>::open(temp_dir.path(), 4).unwrap();
+    let server = KvsServer::new(store).with_resp(resp_addr.parse().unwrap());
+    let addr = addr.parse().unwrap();
+    thread::spawn(move || server.run(addr).unwrap());
+    thread::sleep(Duration::from_secs(1));
+    temp_dir
+}
+
+#[test]
+fn resp_ping() {
+    let _dir = start_server("127.0.0.1:4010", "127.0.0.1:4011");
+    let mut client = RespClient::connect("127.0.0.1:4011");
+    assert_eq!(client.call(&["PING"]), simple("PONG"));
+    assert_eq!(client.call(&["ping", "hello"]), bulk("hello"));
+}
+
+#[test]
+fn resp_get_set_del_exists() {
+    let _dir = start_server("127.0.0.1:4012", "127.0.0.1:4013");
+    let mut client = RespClient::connect("127.0.0.1:4013");
+    assert_eq!(client.call(&["GET", "key1"]), Reply::Nil);
+    assert_eq!(client.call(&["SET", "key1", "value1"]), simple("OK"));
+    assert_eq!(client.call(&["SET", "key2", "value2"]), simple("OK"));
+    assert_eq!(client.call(&["GET", "key1"]), bulk("value1"));
+    assert_eq!(client.call(&["SET", "key1", "value3"]), simple("OK"));
+    assert_eq!(client.call(&["GET", "key1"]), bulk("value3"));
+    assert_eq!(
+        client.call(&["EXISTS", "key1", "key2", "key3", "key1"]),
+        Reply::Integer(3)
+    );
+    assert_eq!(client.call(&["DEL", "key1", "key3"]), Reply::Integer(1));
+    assert_eq!(client.call(&["GET", "key1"]), Reply::Nil);
+    assert_eq!(client.call(&["EXISTS", "key1"]), Reply::Integer(0));
+}
+
+#[test]
+fn resp_mget_mset() {
+    let _dir = start_server("127.0.0.1:4014", "127.0.0.1:4015");
+    let mut client = RespClient::connect("127.0.0.1:4015");
+    assert_eq!(
+        client.call(&["MSET", "a", "1", "b", "2", "c", "3"]),
+        simple("OK")
+    );
+    assert_eq!(
+        client.call(&["MGET", "a", "x", "c"]),
+        Reply::Array(vec![bulk("1"), Reply::Nil, bulk("3")])
+    );
+}
+
+#[test]
+fn resp_scan() {
+    let _dir = start_server("127.0.0.1:4016", "127.0.0.1:4017");
+    let mut client = RespClient::connect("127.0.0.1:4017");
+    for i in 0..25 {
+        let key = format!("user:{:02}", i);
+        assert_eq!(client.call(&["SET", &key, "value"]), simple("OK"));
+    }
+    assert_eq!(client.call(&["SET", "other", "value"]), simple("OK"));
+
+    let mut keys = Vec::new();
+    let mut cursor = "0".to_owned();
+    loop {
+        let reply = client.call(&["SCAN", &cursor, "MATCH", "user:*", "COUNT", "10"]);
+        match reply {
+            Reply::Array(mut reply) => {
+                match reply.pop() {
+                    Some(Reply::Array(page)) => keys.extend(page),
+                    other => panic!("unexpected page: {:?}", other),
+                }
+                cursor = match reply.pop() {
+                    Some(Reply::Bulk(cursor)) => cursor,
+                    other => panic!("unexpected cursor: {:?}", other),
+                };
+            }
+            other => panic!("unexpected reply: {:?}", other),
+        }
+        if cursor == "0" {
+            break;
+        }
+    }
+    let expected: Vec<_> = (0..25)
+        .map(|i| Reply::Bulk(format!("user:{:02}", i)))
+        .collect();
+    assert_eq!(keys, expected);
+
+    assert_eq!(
+        client.call(&["SCAN", "0", "MATCH", "user:1?"]),
+        Reply::Array(vec![
+            bulk("0"),
+            Reply::Array(
+                (10..20)
+                    .map(|i| Reply::Bulk(format!("user:{}", i)))
+                    .collect()
+            )
+        ])
+    );
+}
+
+#[test]
+fn resp_expire() {
+    let _dir = start_server("127.0.0.1:4018", "127.0.0.1:4019");
+    let mut client = RespClient::connect("127.0.0.1:4019");
+    assert_eq!(client.call(&["EXPIRE", "key1", "1"]), Reply::Integer(0));
+    assert_eq!(client.call(&["SET", "key1", "value1"]), simple("OK"));
+    assert_eq!(client.call(&["EXPIRE", "key1", "1"]), Reply::Integer(1));
+    assert_eq!(
+        client.call(&["SET", "key2", "value2", "PX", "500"]),
+        simple("OK")
+    );
+    assert_eq!(client.call(&["SET", "key3", "value3"]), simple("OK"));
+    assert_eq!(client.call(&["EXPIRE", "key3", "1"]), Reply::Integer(1));
+    // overwriting a key removes its time to live
+    assert_eq!(client.call(&["SET", "key3", "value4"]), simple("OK"));
+    assert_eq!(client.call(&["GET", "key1"]), bulk("value1"));
+
+    thread::sleep(Duration::from_millis(1500));
+    assert_eq!(client.call(&["GET", "key1"]), Reply::Nil);
+    assert_eq!(client.call(&["GET", "key2"]), Reply::Nil);
+    assert_eq!(client.call(&["GET", "key3"]), bulk("value4"));
+    assert_eq!(client.call(&["EXPIRE", "key3", "0"]), Reply::Integer(1));
+    assert_eq!(client.call(&["EXISTS", "key3"]), Reply::Integer(0));
+}
+
+// A time to live too large to be represented is rejected instead of panicking.
+#[test]
+fn resp_expire_overflow() {
+    let _dir = start_server("127.0.0.1:4044", "127.0.0.1:4045");
+    let mut client = RespClient::connect("127.0.0.1:4045");
+    assert_eq!(client.call(&["SET", "key1", "value1"]), simple("OK"));
+    assert_eq!(
+        client.call(&["EXPIRE", "key1", "9223372036854775807"]),
+        Reply::Error("ERR invalid expire time in 'expire' command".to_owned())
+    );
+    assert_eq!(
+        client.call(&["SET", "key2", "value2", "EX", "18446744073709551615"]),
+        Reply::Error("ERR invalid expire time in 'set' command".to_owned())
+    );
+    assert_eq!(client.call(&["GET", "key1"]), bulk("value1"));
+    assert_eq!(client.call(&["GET", "key2"]), Reply::Nil);
+}
+
+// Writing a key through the native protocol removes its time to live.
+#[test]
+fn resp_expire_native_overwrite() {
+    let _dir = start_server("127.0.0.1:4046", "127.0.0.1:4047");
+    let mut client = RespClient::connect("127.0.0.1:4047");
+    assert_eq!(
+        client.call(&["SET", "key1", "value1", "PX", "500"]),
+        simple("OK")
+    );
+    let native = KvsClient::connect("127.0.0.1:4046".parse().unwrap())
+        .wait()
+        .unwrap();
+    native
+        .set("key1".to_owned(), "value2".to_owned())
+        .wait()
+        .unwrap();
+
+    thread::sleep(Duration::from_millis(1000));
+    assert_eq!(client.call(&["GET", "key1"]), bulk("value2"));
+}
+
+#[test]
+fn resp_pipelining() {
+    let _dir = start_server("127.0.0.1:4020", "127.0.0.1:4021");
+    let mut client = RespClient::connect("127.0.0.1:4021");
+    let mut batch = Vec::new();
+    for i in 0..100 {
+        batch.extend(encode(&["SET", &format!("key{}", i), &i.to_string()]));
+        batch.extend(encode(&["GET", &format!("key{}", i)]));
+    }
+    batch.extend(b"PING\r\n");
+    client.writer.write_all(&batch).unwrap();
+    for i in 0..100 {
+        assert_eq!(client.read_reply(), simple("OK"));
+        assert_eq!(client.read_reply(), Reply::Bulk(i.to_string()));
+    }
+    assert_eq!(client.read_reply(), simple("PONG"));
+}
+
+#[test]
+fn resp_errors() {
+    let _dir = start_server("127.0.0.1:4022", "127.0.0.1:4023");
+    let mut client = RespClient::connect("127.0.0.1:4023");
+    match client.call(&["FLUSHALL"]) {
+        Reply::Error(e) => assert!(e.starts_with("ERR unknown command")),
+        other => panic!("unexpected reply: {:?}", other),
+    }
+    match client.call(&["GET"]) {
+        Reply::Error(e) => assert!(e.starts_with("ERR wrong number of arguments")),
+        other => panic!("unexpected reply: {:?}", other),
+    }
+    match client.call(&["SET", "key", "value", "EX", "abc"]) {
+        Reply::Error(e) => assert!(e.starts_with("ERR invalid expire time")),
+        other => panic!("unexpected reply: {:?}", other),
+    }
+    // the connection is still usable after command errors
+    assert_eq!(client.call(&["PING"]), simple("PONG"));
+
+    // a protocol error is replied and then the connection is closed
+    client.writer.write_all(b"*1\r\n$abc\r\n").unwrap();
+    match client.read_reply() {
+        Reply::Error(e) => assert!(e.starts_with("ERR Protocol error")),
+        other => panic!("unexpected reply: {:?}", other),
+    }
+    let mut rest = Vec::new();
+    client.reader.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+}
+
+#[test]
+fn resp_partial_frames() {
+    let _dir = start_server("127.0.0.1:4026", "127.0.0.1:4027");
+    let mut client = RespClient::connect("127.0.0.1:4027");
+    // the decoder resumes scanning where the previous read stopped
+    for byte in encode(&["SET", "key", "value"]) {
+        client.writer.write_all(&[byte]).unwrap();
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(client.read_reply(), simple("OK"));
+    assert_eq!(client.call(&["GET", "key"]), bulk("value"));
+}
+
+#[test]
+fn resp_nesting_too_deep() {
+    let _dir = start_server("127.0.0.1:4028", "127.0.0.1:4029");
+    let mut client = RespClient::connect("127.0.0.1:4029");
+    client.writer.write_all(&b"*1\r\n".repeat(100_000)).unwrap();
+    match client.read_reply() {
+        Reply::Error(e) => assert_eq!(e, "ERR Protocol error: nesting too deep"),
+        other => panic!("unexpected reply: {:?}", other),
+    }
+
+    // the server survives and serves other connections
+    let mut client = RespClient::connect("127.0.0.1:4029");
+    assert_eq!(client.call(&["PING"]), simple("PONG"));
+}
+
+// Patterns with many stars must not take exponential time to reject a key
+#[test]
+fn resp_scan_pathological_pattern() {
+    let _dir = start_server("127.0.0.1:4024", "127.0.0.1:4025");
+    let mut client = RespClient::connect("127.0.0.1:4025");
+    let key = "a".repeat(200);
+    assert_eq!(client.call(&["SET", &key, "value"]), simple("OK"));
+
+    let pattern = format!("{}b", "a*".repeat(12));
+    assert_eq!(
+        client.call(&["SCAN", "0", "MATCH", &pattern]),
+        Reply::Array(vec![bulk("0"), Reply::Array(vec![])])
+    );
+    assert_eq!(
+        client.call(&["SCAN", "0", "MATCH", "a*[a-c]?a*a"]),
+        Reply::Array(vec![bulk("0"), Reply::Array(vec![bulk(&key)])])
+    );
+}
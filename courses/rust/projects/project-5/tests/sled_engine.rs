@@ -0,0 +1,56 @@
+use kvs::thread_pool::RayonThreadPool;
+use kvs::{KvsEngine, KvsError, Result, SledKvsEngine};
+use tempfile::TempDir;
+use tokio::prelude::*;
+
+type Engine = SledKvsEngine<RayonThreadPool>;
+
+fn open(temp_dir: &TempDir) -> Result<Engine> {
+    let db = sled::open(temp_dir.path())?;
+    SledKvsEngine::new(db, 2)
+}
+
+fn set_get_remove(engine: Engine) -> Result<()> {
+    for i in 0..10 {
+        engine
+            .set(format!("key{}", i), format!("value{}", i))
+            .wait()?;
+    }
+    for i in 0..10 {
+        assert_eq!(
+            engine.get(format!("key{}", i)).wait()?,
+            Some(format!("value{}", i))
+        );
+    }
+
+    engine.remove("key1".to_owned()).wait()?;
+    assert_eq!(engine.get("key1".to_owned()).wait()?, None);
+    match engine.remove("key1".to_owned()).wait() {
+        Err(KvsError::KeyNotFound) => {}
+        res => panic!("unexpected result: {:?}", res),
+    }
+    Ok(())
+}
+
+#[test]
+fn pooled_operations() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    set_get_remove(open(&temp_dir)?)
+}
+
+// Reads on the calling thread should see the writes done in the pool
+#[test]
+fn inline_reads() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    set_get_remove(open(&temp_dir)?.inline_reads(true))
+}
+
+// Writes should behave the same whichever flush interval is used
+#[test]
+fn flush_every() -> Result<()> {
+    for &n in &[0, 3] {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        set_get_remove(open(&temp_dir)?.flush_every(n))?;
+    }
+    Ok(())
+}
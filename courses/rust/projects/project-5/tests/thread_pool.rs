@@ -1,10 +1,14 @@
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::sync::{mpsc, Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use kvs::thread_pool::*;
-use kvs::Result;
+use kvs::{KvsError, Result};
 
 use crossbeam_utils::sync::WaitGroup;
+use tokio::prelude::*;
 
 fn spawn_counter<P: ThreadPool>(pool: P) -> Result<()> {
     const TASK_NUM: usize = 20;
@@ -46,6 +50,50 @@ fn spawn_panic_task<P: ThreadPool>() -> Result<()> {
     spawn_counter(pool)
 }
 
+fn spawn_with_handle<P: ThreadPool>() -> Result<()> {
+    const TASK_NUM: usize = 100;
+
+    let pool = P::new(4)?;
+    // handles can be blocked on
+    for i in 0..TASK_NUM {
+        assert_eq!(pool.spawn_with_handle(move || i * 2).join()?, i * 2);
+    }
+
+    // or awaited together
+    let handles: Vec<_> = (0..TASK_NUM)
+        .map(|i| pool.spawn_with_handle(move || i * 2))
+        .collect();
+    let results = future::join_all(handles).wait()?;
+    assert_eq!(results, (0..TASK_NUM).map(|i| i * 2).collect::<Vec<_>>());
+
+    // panics are returned as errors, and the pool keeps working
+    for _ in 0..TASK_NUM {
+        let handle = pool.spawn_with_handle(|| -> usize {
+            panic_control::disable_hook_in_current_thread();
+            panic!();
+        });
+        match handle.join() {
+            Err(KvsError::JobPanicked) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+    assert_eq!(pool.spawn_with_handle(|| 42).join()?, 42);
+
+    // dropping the handle doesn't cancel the job
+    let wg = WaitGroup::new();
+    let counter = Arc::new(AtomicUsize::new(0));
+    {
+        let (counter, wg) = (Arc::clone(&counter), wg.clone());
+        drop(pool.spawn_with_handle(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            drop(wg);
+        }));
+    }
+    wg.wait();
+    assert_eq!(counter.load(Ordering::SeqCst), 1);
+    Ok(())
+}
+
 #[test]
 fn naive_thread_pool_spawn_counter() -> Result<()> {
     let pool = NaiveThreadPool::new(4)?;
@@ -64,7 +112,335 @@ fn rayon_thread_pool_spawn_counter() -> Result<()> {
     spawn_counter(pool)
 }
 
+#[test]
+fn work_stealing_thread_pool_spawn_counter() -> Result<()> {
+    let pool = WorkStealingThreadPool::new(4)?;
+    spawn_counter(pool)
+}
+
 #[test]
 fn shared_queue_thread_pool_panic_task() -> Result<()> {
     spawn_panic_task::<SharedQueueThreadPool>()
 }
+
+#[test]
+fn rayon_thread_pool_panic_task() -> Result<()> {
+    spawn_panic_task::<RayonThreadPool>()
+}
+
+#[test]
+fn work_stealing_thread_pool_panic_task() -> Result<()> {
+    spawn_panic_task::<WorkStealingThreadPool>()
+}
+
+#[test]
+fn work_stealing_thread_pool_nested_spawn() -> Result<()> {
+    const TASK_NUM: usize = 100;
+    const CHILD_NUM: usize = 10;
+
+    let pool = WorkStealingThreadPool::new(4)?;
+    let wg = WaitGroup::new();
+    let counter = Arc::new(AtomicUsize::new(0));
+
+    for _ in 0..TASK_NUM {
+        let (inner_pool, counter, wg) = (pool.clone(), Arc::clone(&counter), wg.clone());
+        pool.spawn(move || {
+            for _ in 0..CHILD_NUM {
+                let (counter, wg) = (Arc::clone(&counter), wg.clone());
+                inner_pool.spawn(move || {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    drop(wg);
+                });
+            }
+            drop(wg);
+        })
+    }
+
+    wg.wait();
+    assert_eq!(counter.load(Ordering::SeqCst), TASK_NUM * CHILD_NUM);
+    assert_eq!(pool.queued_jobs(), 0);
+    Ok(())
+}
+
+// Jobs left in the local queue of a busy thread are taken by the idle ones, so jobs
+// which all wait for each other still finish.
+#[test]
+fn work_stealing_thread_pool_busy_thread_batch() -> Result<()> {
+    const THREADS: usize = 4;
+
+    let pool = WorkStealingThreadPool::new(THREADS as u32)?;
+    for _ in 0..50 {
+        let barrier = Arc::new(Barrier::new(THREADS));
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..THREADS {
+            let (barrier, tx) = (Arc::clone(&barrier), tx.clone());
+            pool.spawn(move || {
+                barrier.wait();
+                tx.send(()).unwrap();
+            });
+        }
+        for _ in 0..THREADS {
+            rx.recv_timeout(Duration::from_secs(5))
+                .expect("a job is stuck in the local queue of a busy thread");
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn work_stealing_thread_pool_zero_threads() {
+    assert!(WorkStealingThreadPool::new(0).is_err());
+}
+
+#[test]
+fn naive_thread_pool_spawn_with_handle() -> Result<()> {
+    spawn_with_handle::<NaiveThreadPool>()
+}
+
+#[test]
+fn shared_queue_thread_pool_spawn_with_handle() -> Result<()> {
+    spawn_with_handle::<SharedQueueThreadPool>()
+}
+
+#[test]
+fn rayon_thread_pool_spawn_with_handle() -> Result<()> {
+    spawn_with_handle::<RayonThreadPool>()
+}
+
+#[test]
+fn work_stealing_thread_pool_spawn_with_handle() -> Result<()> {
+    spawn_with_handle::<WorkStealingThreadPool>()
+}
+
+/// Waits until the pool has the given number of threads.
+fn wait_for_threads(pool: &SharedQueueThreadPool, threads: usize) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while pool.threads() != threads {
+        assert!(Instant::now() < deadline, "{} threads", pool.threads());
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn shared_queue_thread_pool_keeps_threads_after_panics() -> Result<()> {
+    let pool = SharedQueueThreadPool::new(4)?;
+    for _ in 0..100 {
+        pool.spawn(|| {
+            panic_control::disable_hook_in_current_thread();
+            panic!();
+        })
+    }
+    spawn_counter(pool.clone())?;
+    wait_for_threads(&pool, 4);
+    Ok(())
+}
+
+#[test]
+fn shared_queue_thread_pool_shutdown() -> Result<()> {
+    const TASK_NUM: usize = 20;
+
+    let pool = SharedQueueThreadPool::new(4)?;
+    let counter = Arc::new(AtomicUsize::new(0));
+    for _ in 0..TASK_NUM {
+        let counter = Arc::clone(&counter);
+        pool.spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            counter.fetch_add(1, Ordering::SeqCst);
+        })
+    }
+
+    // queued tasks are finished before the threads exit
+    pool.shutdown(Duration::from_secs(5))?;
+    assert_eq!(counter.load(Ordering::SeqCst), TASK_NUM);
+    assert_eq!(pool.threads(), 0);
+    assert_eq!(pool.queued_jobs(), 0);
+    assert!(pool.resize(4).is_err());
+    // shutting down again is a no-op
+    pool.shutdown(Duration::from_secs(5))
+}
+
+#[test]
+fn shared_queue_thread_pool_shutdown_timeout() -> Result<()> {
+    let pool = SharedQueueThreadPool::new(1)?;
+    pool.spawn(|| thread::sleep(Duration::from_millis(500)));
+
+    let start = Instant::now();
+    assert!(pool.shutdown(Duration::from_millis(100)).is_err());
+    assert!(start.elapsed() < Duration::from_millis(400));
+    // the threads keep exiting in the background
+    pool.shutdown(Duration::from_secs(5))?;
+    assert_eq!(pool.threads(), 0);
+    Ok(())
+}
+
+#[test]
+#[should_panic(expected = "The thread pool is shut down.")]
+fn shared_queue_thread_pool_spawn_after_shutdown() {
+    let pool = SharedQueueThreadPool::new(2).unwrap();
+    pool.clone().shutdown(Duration::from_secs(5)).unwrap();
+    pool.spawn(|| {});
+}
+
+// A task spawned while the pool shuts down either runs or makes `spawn` panic.
+#[test]
+fn shared_queue_thread_pool_spawn_during_shutdown() -> Result<()> {
+    let pool = SharedQueueThreadPool::new(2)?;
+    let spawned = Arc::new(AtomicUsize::new(0));
+    let ran = Arc::new(AtomicUsize::new(0));
+    let spawner = {
+        let pool = pool.clone();
+        let spawned = Arc::clone(&spawned);
+        let ran = Arc::clone(&ran);
+        thread::spawn(move || loop {
+            let ran = Arc::clone(&ran);
+            let res = panic::catch_unwind(AssertUnwindSafe(|| {
+                pool.spawn(move || {
+                    ran.fetch_add(1, Ordering::SeqCst);
+                })
+            }));
+            if res.is_err() {
+                return;
+            }
+            spawned.fetch_add(1, Ordering::SeqCst);
+        })
+    };
+    thread::sleep(Duration::from_millis(50));
+    pool.shutdown(Duration::from_secs(5))?;
+    spawner.join().unwrap();
+    assert_eq!(ran.load(Ordering::SeqCst), spawned.load(Ordering::SeqCst));
+    assert_eq!(pool.queued_jobs(), 0);
+    Ok(())
+}
+
+#[test]
+fn shared_queue_thread_pool_resize() -> Result<()> {
+    let pool = SharedQueueThreadPool::new(2)?;
+    assert_eq!(pool.threads(), 2);
+
+    // all threads are busy at the same time
+    pool.resize(6)?;
+    assert_eq!(pool.threads(), 6);
+    let barrier = Arc::new(Barrier::new(7));
+    for _ in 0..6 {
+        let barrier = Arc::clone(&barrier);
+        pool.spawn(move || {
+            barrier.wait();
+        })
+    }
+    barrier.wait();
+
+    pool.resize(1)?;
+    wait_for_threads(&pool, 1);
+    spawn_counter(pool.clone())?;
+
+    pool.resize(3)?;
+    assert_eq!(pool.threads(), 3);
+    assert!(pool.resize(0).is_err());
+    assert_eq!(pool.threads(), 3);
+    spawn_counter(pool)
+}
+
+#[test]
+fn shared_queue_thread_pool_bounded() -> Result<()> {
+    let pool = SharedQueueThreadPool::with_capacity(1, 2)?;
+    assert_eq!(pool.queue_capacity(), Some(2));
+
+    // the only thread is busy until `tx` is sent to
+    let (tx, rx) = mpsc::channel::<()>();
+    let started = Arc::new(Barrier::new(2));
+    {
+        let started = Arc::clone(&started);
+        pool.spawn(move || {
+            started.wait();
+            rx.recv().unwrap();
+        });
+    }
+    started.wait();
+
+    let counter = Arc::new(AtomicUsize::new(0));
+    for _ in 0..2 {
+        let counter = Arc::clone(&counter);
+        assert!(pool
+            .try_spawn(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            })
+            .is_ok());
+    }
+    assert_eq!(pool.queued_jobs(), 2);
+
+    // the rejected job is returned
+    let rejected = {
+        let counter = Arc::clone(&counter);
+        pool.try_spawn(move || {
+            counter.fetch_add(10, Ordering::SeqCst);
+        })
+    };
+    let job = match rejected {
+        Ok(()) => panic!("a job is queued beyond the capacity"),
+        Err(job) => job,
+    };
+    job();
+    assert_eq!(counter.load(Ordering::SeqCst), 10);
+    assert!(pool.try_spawn_with_handle(|| ()).is_none());
+
+    // `spawn` waits for room in the queue
+    let spawner = {
+        let (pool, counter) = (pool.clone(), Arc::clone(&counter));
+        thread::spawn(move || {
+            pool.spawn(move || {
+                counter.fetch_add(100, Ordering::SeqCst);
+            })
+        })
+    };
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(pool.queued_jobs(), 2);
+    tx.send(()).unwrap();
+    spawner.join().unwrap();
+
+    pool.shutdown(Duration::from_secs(5))?;
+    assert_eq!(counter.load(Ordering::SeqCst), 112);
+    Ok(())
+}
+
+#[test]
+fn shared_queue_thread_pool_zero_capacity() {
+    assert!(SharedQueueThreadPool::with_capacity(2, 0).is_err());
+}
+
+#[test]
+fn rayon_thread_pool_try_spawn() -> Result<()> {
+    let pool = RayonThreadPool::new(2)?;
+    assert_eq!(pool.queue_capacity(), None);
+    assert_eq!(pool.try_spawn_with_handle(|| 42).unwrap().join()?, 42);
+    Ok(())
+}
+
+#[test]
+fn rayon_thread_pool_queued_jobs() -> Result<()> {
+    let pool = RayonThreadPool::new(1)?;
+
+    // the only thread is busy until `tx` is sent to
+    let (tx, rx) = mpsc::channel::<()>();
+    let started = Arc::new(Barrier::new(2));
+    {
+        let started = Arc::clone(&started);
+        pool.spawn(move || {
+            started.wait();
+            rx.recv().unwrap();
+        });
+    }
+    started.wait();
+    assert_eq!(pool.queued_jobs(), 0);
+
+    let wg = WaitGroup::new();
+    for _ in 0..2 {
+        let wg = wg.clone();
+        pool.spawn(move || drop(wg));
+    }
+    assert_eq!(pool.queued_jobs(), 2);
+
+    tx.send(()).unwrap();
+    wg.wait();
+    assert_eq!(pool.queued_jobs(), 0);
+    Ok(())
+}
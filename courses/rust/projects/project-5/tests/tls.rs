@@ -0,0 +1,339 @@
+use assert_cmd::prelude::*;
+use kvs::thread_pool::RayonThreadPool;
+use kvs::{tls, KvStore, KvsClient, KvsServer};
+use predicates::str::{contains, is_empty};
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa,
+};
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::net::{SocketAddr, TcpStream};
+use std::path::Path;
+use std::process::Command;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::runtime::Runtime;
+use tokio_rustls::rustls::internal::pemfile;
+use tokio_rustls::rustls::{ClientSession, Session};
+use tokio_rustls::webpki::DNSNameRef;
+
+/// Generates a CA, a server certificate for `localhost` and a client certificate,
+/// all signed by the CA, plus a client certificate signed by another CA, and writes
+/// them as PEM files into `dir`.
+fn generate_certs(dir: &Path) {
+    let mut params = CertificateParams::new(Vec::new());
+    params
+        .distinguished_name
+        .push(DnType::CommonName, "kvs test CA");
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = Certificate::from_params(params).unwrap();
+    fs::write(dir.join("ca.pem"), ca.serialize_pem().unwrap()).unwrap();
+
+    let mut params = CertificateParams::new(vec!["localhost".to_owned()]);
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    let server = Certificate::from_params(params).unwrap();
+    fs::write(
+        dir.join("server.pem"),
+        server.serialize_pem_with_signer(&ca).unwrap(),
+    )
+    .unwrap();
+    fs::write(dir.join("server.key"), server.serialize_private_key_pem()).unwrap();
+
+    let mut params = CertificateParams::new(vec!["client".to_owned()]);
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+    let client = Certificate::from_params(params).unwrap();
+    fs::write(
+        dir.join("client.pem"),
+        client.serialize_pem_with_signer(&ca).unwrap(),
+    )
+    .unwrap();
+    fs::write(dir.join("client.key"), client.serialize_private_key_pem()).unwrap();
+
+    // a CA unknown to the server and the client
+    let mut params = CertificateParams::new(Vec::new());
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let other_ca = Certificate::from_params(params).unwrap();
+    fs::write(dir.join("other-ca.pem"), other_ca.serialize_pem().unwrap()).unwrap();
+    let mut params = CertificateParams::new(vec!["client".to_owned()]);
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+    let other_client = Certificate::from_params(params).unwrap();
+    fs::write(
+        dir.join("other-client.pem"),
+        other_client.serialize_pem_with_signer(&other_ca).unwrap(),
+    )
+    .unwrap();
+    fs::write(
+        dir.join("other-client.key"),
+        other_client.serialize_private_key_pem(),
+    )
+    .unwrap();
+}
+
+/// Runs `kvs-server` with the given extra arguments until the returned sender is used.
+fn start_server(
+    temp_dir: &TempDir,
+    addr: &str,
+    args: &[&str],
+) -> (mpsc::SyncSender<()>, thread::JoinHandle<()>) {
+    let (sender, receiver) = mpsc::sync_channel(0);
+    let mut child = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(&["--addr", addr])
+        .args(args)
+        .current_dir(temp_dir)
+        .spawn()
+        .unwrap();
+    let handle = thread::spawn(move || {
+        let _ = receiver.recv(); // wait for main thread to finish
+        child.kill().expect("server exited before killed");
+    });
+    thread::sleep(Duration::from_secs(1));
+    (sender, handle)
+}
+
+fn client(temp_dir: &TempDir, args: &[&str]) -> Command {
+    let mut cmd = Command::cargo_bin("kvs-client").unwrap();
+    cmd.args(args).current_dir(temp_dir);
+    cmd
+}
+
+// Clients verifying the server certificate can access a TLS server.
+#[test]
+fn tls_access_server() {
+    let temp_dir = TempDir::new().unwrap();
+    generate_certs(temp_dir.path());
+    let addr = "127.0.0.1:4040";
+    let (sender, handle) = start_server(
+        &temp_dir,
+        addr,
+        &["--tls-cert", "server.pem", "--tls-key", "server.key"],
+    );
+
+    client(
+        &temp_dir,
+        &["set", "key1", "value1", "--addr", addr, "--ca", "ca.pem"],
+    )
+    .assert()
+    .success()
+    .stdout(is_empty());
+    client(
+        &temp_dir,
+        &["get", "key1", "--addr", addr, "--ca", "ca.pem"],
+    )
+    .assert()
+    .success()
+    .stdout("value1\n");
+    client(&temp_dir, &["rm", "key1", "--addr", addr, "--ca", "ca.pem"])
+        .assert()
+        .success()
+        .stdout(is_empty());
+    client(&temp_dir, &["rm", "key1", "--addr", addr, "--ca", "ca.pem"])
+        .assert()
+        .failure()
+        .stderr(contains("Key not found"));
+
+    // plain TCP clients are rejected
+    client(&temp_dir, &["get", "key1", "--addr", addr])
+        .assert()
+        .failure();
+    // the server certificate is not signed by the given CA
+    client(
+        &temp_dir,
+        &["get", "key1", "--addr", addr, "--ca", "other-ca.pem"],
+    )
+    .assert()
+    .failure();
+    // the server certificate is not valid for the name
+    client(
+        &temp_dir,
+        &[
+            "get",
+            "key1",
+            "--addr",
+            addr,
+            "--ca",
+            "ca.pem",
+            "--server-name",
+            "example.com",
+        ],
+    )
+    .assert()
+    .failure();
+
+    // the server keeps serving after failed handshakes
+    client(
+        &temp_dir,
+        &["get", "key1", "--addr", addr, "--ca", "ca.pem"],
+    )
+    .assert()
+    .success()
+    .stdout("Key not found\n");
+
+    sender.send(()).unwrap();
+    handle.join().unwrap();
+}
+
+// With `--tls-client-ca`, only clients presenting a certificate signed by the CA are served.
+#[test]
+fn mutual_tls_access_server() {
+    let temp_dir = TempDir::new().unwrap();
+    generate_certs(temp_dir.path());
+    let addr = "127.0.0.1:4041";
+    let (sender, handle) = start_server(
+        &temp_dir,
+        addr,
+        &[
+            "--tls-cert",
+            "server.pem",
+            "--tls-key",
+            "server.key",
+            "--tls-client-ca",
+            "ca.pem",
+        ],
+    );
+
+    let client_cert = [
+        "--addr",
+        addr,
+        "--ca",
+        "ca.pem",
+        "--client-cert",
+        "client.pem",
+        "--client-key",
+        "client.key",
+    ];
+    client(&temp_dir, &["set", "key1", "value1"])
+        .args(&client_cert)
+        .assert()
+        .success()
+        .stdout(is_empty());
+    client(&temp_dir, &["get", "key1"])
+        .args(&client_cert)
+        .assert()
+        .success()
+        .stdout("value1\n");
+
+    // no client certificate
+    client(
+        &temp_dir,
+        &["get", "key1", "--addr", addr, "--ca", "ca.pem"],
+    )
+    .assert()
+    .failure();
+    // a client certificate signed by another CA
+    client(
+        &temp_dir,
+        &[
+            "get",
+            "key1",
+            "--addr",
+            addr,
+            "--ca",
+            "ca.pem",
+            "--client-cert",
+            "other-client.pem",
+            "--client-key",
+            "other-client.key",
+        ],
+    )
+    .assert()
+    .failure();
+
+    client(&temp_dir, &["get", "key1"])
+        .args(&client_cert)
+        .assert()
+        .success()
+        .stdout("value1\n");
+
+    sender.send(()).unwrap();
+    handle.join().unwrap();
+}
+
+// The library handshakes with the test certificates, on both the server and the client.
+#[test]
+fn tls_handshake() {
+    let temp_dir = TempDir::new().unwrap();
+    let dir = temp_dir.path();
+    generate_certs(dir);
+    let data_dir = TempDir::new().unwrap();
+    let engine = KvStore::<RayonThreadPool>::open(data_dir.path(), 4).unwrap();
+    let server_config =
+        tls::server_config(&dir.join("server.pem"), &dir.join("server.key"), None).unwrap();
+    let addr: SocketAddr = "127.0.0.1:4048".parse().unwrap();
+    thread::spawn(move || {
+        KvsServer::new(engine)
+            .with_tls(server_config)
+            .run(addr)
+            .unwrap()
+    });
+    thread::sleep(Duration::from_secs(1));
+    let client_config = tls::client_config(&dir.join("ca.pem"), None).unwrap();
+
+    // the server presents its certificate in a handshake driven by rustls directly
+    let mut tcp = TcpStream::connect(addr).unwrap();
+    let name = DNSNameRef::try_from_ascii_str("localhost").unwrap();
+    let mut session = ClientSession::new(&client_config, name);
+    while session.is_handshaking() || session.wants_write() {
+        if session.wants_write() {
+            session.write_tls(&mut tcp).unwrap();
+        } else {
+            assert!(session.read_tls(&mut tcp).unwrap() > 0);
+            session.process_new_packets().unwrap();
+        }
+    }
+    let mut reader = BufReader::new(File::open(dir.join("server.pem")).unwrap());
+    let server_cert = pemfile::certs(&mut reader).unwrap();
+    assert_eq!(session.get_peer_certificates(), Some(server_cert));
+
+    // requests of `KvsClient` are served over TLS
+    let mut runtime = Runtime::new().unwrap();
+    let client = runtime
+        .block_on(KvsClient::connect_tls(addr, "localhost", client_config))
+        .unwrap();
+    let client = runtime
+        .block_on(client.set("key1".to_owned(), "value1".to_owned()))
+        .unwrap();
+    let (value, _) = runtime.block_on(client.get("key1".to_owned())).unwrap();
+    assert_eq!(value, Some("value1".to_owned()));
+
+    // the server certificate is not signed by the CA of the client
+    let other_config = tls::client_config(&dir.join("other-ca.pem"), None).unwrap();
+    let res = runtime.block_on(KvsClient::connect_tls(addr, "localhost", other_config));
+    assert!(res.is_err());
+}
+
+// TLS options must be given in pairs.
+#[test]
+fn cli_tls_invalid_options() {
+    let temp_dir = TempDir::new().unwrap();
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(&["--tls-cert", "server.pem"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(&["--tls-client-ca", "ca.pem"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+    client(&temp_dir, &["get", "key1", "--client-cert", "client.pem"])
+        .assert()
+        .failure();
+    client(
+        &temp_dir,
+        &[
+            "get",
+            "key1",
+            "--client-cert",
+            "client.pem",
+            "--client-key",
+            "client.key",
+        ],
+    )
+    .assert()
+    .failure();
+}
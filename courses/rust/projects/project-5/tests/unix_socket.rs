@@ -0,0 +1,149 @@
+use assert_cmd::prelude::*;
+use kvs::thread_pool::RayonThreadPool;
+use kvs::{Addr, KvStore, KvsClient, KvsServer};
+use predicates::str::contains;
+use std::fs;
+use std::os::unix::net::UnixListener;
+use std::process::Command;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::prelude::*;
+
+// `kvs-server` listens on a Unix socket beside TCP, replacing a stale socket file.
+#[test]
+fn cli_unix_socket() {
+    let temp_dir = TempDir::new().unwrap();
+    let socket = temp_dir.path().join("kvs.sock");
+    // left behind by a server that is no longer running
+    drop(UnixListener::bind(&socket).unwrap());
+    let unix_addr = format!("unix:{}", socket.display());
+    let tcp_addr = "127.0.0.1:4100";
+
+    let (sender, receiver) = mpsc::sync_channel(0);
+    let mut child = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(&["--addr", tcp_addr, "--addr", &unix_addr])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    let handle = thread::spawn(move || {
+        let _ = receiver.recv(); // wait for main thread to finish
+        child.kill().expect("server exited before killed");
+    });
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["set", "key1", "value1", "--addr", &unix_addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("");
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["get", "key1", "--addr", tcp_addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("value1\n");
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["set", "key2", "value2", "--addr", tcp_addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["get", "key2", "--addr", &unix_addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("value2\n");
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["rm", "key1", "--addr", &unix_addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["get", "key1", "--addr", &unix_addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("Key not found\n");
+
+    // TLS is only available over TCP
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["get", "key2", "--addr", &unix_addr, "--ca", "ca.pem"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("TLS is not supported over Unix sockets"));
+
+    sender.send(()).unwrap();
+    handle.join().unwrap();
+}
+
+// The server refuses to replace a file that is not a socket.
+#[test]
+fn cli_unix_socket_path_taken() {
+    let temp_dir = TempDir::new().unwrap();
+    let socket = temp_dir.path().join("kvs.sock");
+    fs::write(&socket, "not a socket").unwrap();
+
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(&["--addr", &format!("unix:{}", socket.display())])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+    assert_eq!(fs::read_to_string(&socket).unwrap(), "not a socket");
+}
+
+// `KvsClient::connect_unix` talks to a server listening only on a Unix socket.
+#[test]
+fn client_unix_socket() {
+    let temp_dir = TempDir::new().unwrap();
+    let socket = temp_dir.path().join("kvs.sock");
+    let engine = KvStore::<RayonThreadPool>::open(temp_dir.path(), 2).unwrap();
+    let addr = Addr::Unix(socket.clone());
+    thread::spawn(move || {
+        KvsServer::new(engine)
+            .with_listener(addr)
+            .run_listeners()
+            .unwrap()
+    });
+    thread::sleep(Duration::from_secs(1));
+
+    let client = KvsClient::connect_unix(&socket).wait().unwrap();
+    let client = client
+        .set("key1".to_owned(), "value1".to_owned())
+        .wait()
+        .unwrap();
+    let (value, client) = client.get("key1".to_owned()).wait().unwrap();
+    assert_eq!(value, Some("value1".to_owned()));
+    let (value, _) = client.get("key2".to_owned()).wait().unwrap();
+    assert_eq!(value, None);
+}
+
+#[test]
+fn parse_addr() {
+    assert_eq!(
+        "127.0.0.1:4000".parse::<Addr>().unwrap(),
+        Addr::Tcp("127.0.0.1:4000".parse().unwrap())
+    );
+    assert_eq!(
+        "unix:/run/kvs.sock".parse::<Addr>().unwrap(),
+        Addr::Unix("/run/kvs.sock".into())
+    );
+    assert_eq!(
+        Addr::Unix("/run/kvs.sock".into()).to_string(),
+        "unix:/run/kvs.sock"
+    );
+    assert!("unix:".parse::<Addr>().is_err());
+    assert!("localhost".parse::<Addr>().is_err());
+}
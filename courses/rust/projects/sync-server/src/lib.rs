@@ -0,0 +1,15 @@
+#![deny(missing_docs)]
+//! The synchronous server of the key/value stores of projects 3 and 4.
+//!
+//! It serves every connection on a thread of a `ThreadPool`, with per-connection
+//! read timeouts and a cap on the number of open connections. The projects plug
+//! their storage engines in through the `KvsEngine` trait.
+
+#[macro_use]
+extern crate log;
+
+pub use server::{KvsEngine, KvsServer};
+
+pub mod protocol;
+mod server;
+pub mod thread_pool;
@@ -0,0 +1,52 @@
+//! The messages exchanged by `KvsServer` and its clients, serialized as JSON.
+
+use serde::{Deserialize, Serialize};
+
+/// A request of a client.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Request {
+    /// Gets the value of a key.
+    Get {
+        /// The key to get
+        key: String,
+    },
+    /// Sets the value of a key.
+    Set {
+        /// The key to set
+        key: String,
+        /// The new value
+        value: String,
+    },
+    /// Removes a key.
+    Remove {
+        /// The key to remove
+        key: String,
+    },
+}
+
+/// The response to `Request::Get`.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum GetResponse {
+    /// The value of the key, if it exists.
+    Ok(Option<String>),
+    /// The error message of the engine.
+    Err(String),
+}
+
+/// The response to `Request::Set`.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum SetResponse {
+    /// The value is set.
+    Ok(()),
+    /// The error message of the engine.
+    Err(String),
+}
+
+/// The response to `Request::Remove`.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum RemoveResponse {
+    /// The key is removed.
+    Ok(()),
+    /// The error message of the engine.
+    Err(String),
+}
@@ -0,0 +1,216 @@
+use crate::protocol::{GetResponse, RemoveResponse, Request, SetResponse};
+use crate::thread_pool::ThreadPool;
+use serde_json::Deserializer;
+use std::fmt;
+use std::io::{self, BufReader, BufWriter, Result, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// How long the accept loop waits after a failed `accept`.
+///
+/// Errors such as running out of file descriptors persist until a connection
+/// is closed, so retrying at once would only spin the accept loop.
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(100);
+
+/// The storage engine behind a `KvsServer`.
+///
+/// The server clones the engine for every connection and may use the clones
+/// from several threads at the same time.
+pub trait KvsEngine: Clone + Send + 'static {
+    /// The error returned by the engine, sent to the client as a message.
+    type Error: fmt::Display;
+
+    /// Sets the value of a string key to a string.
+    ///
+    /// If the key already exists, the previous value will be overwritten.
+    fn set(&self, key: String, value: String) -> std::result::Result<(), Self::Error>;
+
+    /// Gets the string value of a given string key.
+    ///
+    /// Returns `None` if the given key does not exist.
+    fn get(&self, key: String) -> std::result::Result<Option<String>, Self::Error>;
+
+    /// Removes a given key.
+    ///
+    /// # Errors
+    ///
+    /// It returns an error if the given key is not found.
+    fn remove(&self, key: String) -> std::result::Result<(), Self::Error>;
+}
+
+/// The server of a key value store.
+///
+/// Every accepted connection is served on a thread of the pool `P`, so a slow
+/// client only occupies its own thread.
+pub struct KvsServer<E: KvsEngine, P: ThreadPool> {
+    engine: E,
+    pool: P,
+    read_timeout: Option<Duration>,
+    max_connections: Option<usize>,
+}
+
+impl<E: KvsEngine, P: ThreadPool> KvsServer<E, P> {
+    /// Create a `KvsServer` with a given storage engine.
+    ///
+    /// By default connections never time out and their number is not limited.
+    pub fn new(engine: E, pool: P) -> Self {
+        KvsServer {
+            engine,
+            pool,
+            read_timeout: None,
+            max_connections: None,
+        }
+    }
+
+    /// Closes a connection once the client has sent nothing for `timeout`.
+    ///
+    /// `None` lets idle connections stay open forever.
+    pub fn read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    /// Limits the number of open connections.
+    ///
+    /// An accepted connection holds its place until it is closed, including while
+    /// it waits in the queue of the pool for a free thread. So with a pool of `N`
+    /// threads, at most `N` connections are served at the same time and the others
+    /// wait until one of them is closed, or times out if it is idle.
+    ///
+    /// When the limit is reached, new connections wait in the listen backlog
+    /// until an open connection is closed. `None` removes the limit.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the limit is zero.
+    pub fn max_connections(mut self, max: Option<usize>) -> Self {
+        assert!(max != Some(0), "the connection limit must not be zero");
+        self.max_connections = max;
+        self
+    }
+
+    /// Run the server listening on the given address
+    pub fn run<A: ToSocketAddrs>(self, addr: A) -> Result<()> {
+        self.run_on(TcpListener::bind(addr)?)
+    }
+
+    /// Run the server accepting connections from the given listener
+    pub fn run_on(self, listener: TcpListener) -> Result<()> {
+        let limit = self.max_connections.map(ConnectionLimit::new);
+        loop {
+            let slot = limit.as_ref().map(ConnectionLimit::acquire);
+            let stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    error!("Connection failed: {}", e);
+                    thread::sleep(ACCEPT_ERROR_DELAY);
+                    continue;
+                }
+            };
+            let engine = self.engine.clone();
+            let read_timeout = self.read_timeout;
+            self.pool.spawn(move || {
+                let _slot = slot;
+                if let Err(e) = serve(engine, stream, read_timeout) {
+                    error!("Error on serving client: {}", e);
+                }
+            })
+        }
+    }
+}
+
+fn serve<E: KvsEngine>(engine: E, tcp: TcpStream, read_timeout: Option<Duration>) -> Result<()> {
+    let peer_addr = tcp.peer_addr()?;
+    tcp.set_read_timeout(read_timeout)?;
+    let reader = BufReader::new(&tcp);
+    let mut writer = BufWriter::new(&tcp);
+    let req_reader = Deserializer::from_reader(reader).into_iter::<Request>();
+
+    macro_rules! send_resp {
+        ($resp:expr) => {{
+            let resp = $resp;
+            serde_json::to_writer(&mut writer, &resp)?;
+            writer.flush()?;
+            debug!("Response sent to {}: {:?}", peer_addr, resp);
+        };};
+    }
+
+    for req in req_reader {
+        let req = match req {
+            Ok(req) => req,
+            Err(e) if e.is_io() => {
+                let e = io::Error::from(e);
+                if is_timeout(&e) {
+                    info!("Closing idle connection from {}", peer_addr);
+                    return Ok(());
+                }
+                return Err(e);
+            }
+            Err(e) => return Err(e.into()),
+        };
+        debug!("Receive request from {}: {:?}", peer_addr, req);
+        match req {
+            Request::Get { key } => send_resp!(match engine.get(key) {
+                Ok(value) => GetResponse::Ok(value),
+                Err(e) => GetResponse::Err(format!("{}", e)),
+            }),
+            Request::Set { key, value } => send_resp!(match engine.set(key, value) {
+                Ok(_) => SetResponse::Ok(()),
+                Err(e) => SetResponse::Err(format!("{}", e)),
+            }),
+            Request::Remove { key } => send_resp!(match engine.remove(key) {
+                Ok(_) => RemoveResponse::Ok(()),
+                Err(e) => RemoveResponse::Err(format!("{}", e)),
+            }),
+        };
+    }
+    Ok(())
+}
+
+// A timed out read reports `WouldBlock` on Unix and `TimedOut` on Windows.
+fn is_timeout(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut
+}
+
+/// Counts the connections being served and blocks the accept loop while the
+/// maximum is reached.
+#[derive(Clone)]
+struct ConnectionLimit {
+    inner: Arc<(Mutex<usize>, Condvar)>,
+    max: usize,
+}
+
+impl ConnectionLimit {
+    fn new(max: usize) -> Self {
+        ConnectionLimit {
+            inner: Arc::new((Mutex::new(0), Condvar::new())),
+            max,
+        }
+    }
+
+    fn acquire(&self) -> ConnectionSlot {
+        let (ref count, ref freed) = *self.inner;
+        let mut count = count.lock().unwrap();
+        if *count >= self.max {
+            warn!("Reached the limit of {} connections", self.max);
+            while *count >= self.max {
+                count = freed.wait(count).unwrap();
+            }
+        }
+        *count += 1;
+        ConnectionSlot(self.clone())
+    }
+}
+
+/// Frees its place in the `ConnectionLimit` when dropped.
+struct ConnectionSlot(ConnectionLimit);
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let (ref count, ref freed) = *(self.0).inner;
+        *count.lock().unwrap() -= 1;
+        freed.notify_one();
+    }
+}
@@ -1,7 +1,7 @@
 //! This module provides various thread pools. All thread pools should implement
 //! the `ThreadPool` trait.
 
-use crate::Result;
+use std::io::Result;
 
 mod naive;
 mod rayon;
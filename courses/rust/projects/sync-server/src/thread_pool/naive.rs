@@ -1,7 +1,7 @@
 use std::thread;
 
 use super::ThreadPool;
-use crate::Result;
+use std::io::Result;
 
 /// It is actually not a thread pool. It spawns a new thread every time
 /// the `spawn` method is called.
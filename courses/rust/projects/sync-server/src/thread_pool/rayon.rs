@@ -1,5 +1,5 @@
 use super::ThreadPool;
-use crate::{KvsError, Result};
+use std::io::{self, Result};
 
 /// Wrapper of rayon::ThreadPool
 pub struct RayonThreadPool(rayon::ThreadPool);
@@ -9,7 +9,7 @@ impl ThreadPool for RayonThreadPool {
         let pool = rayon::ThreadPoolBuilder::new()
             .num_threads(threads as usize)
             .build()
-            .map_err(|e| KvsError::StringError(format!("{}", e)))?;
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
         Ok(RayonThreadPool(pool))
     }
 
@@ -1,7 +1,7 @@
 use std::thread;
 
 use super::ThreadPool;
-use crate::Result;
+use std::io::Result;
 
 use crossbeam::channel::{self, Receiver, Sender};
 
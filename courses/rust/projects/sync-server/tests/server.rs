@@ -0,0 +1,146 @@
+use kvs_sync_server::protocol::{GetResponse, Request, SetResponse};
+use kvs_sync_server::thread_pool::*;
+use kvs_sync_server::{KvsEngine, KvsServer};
+use serde::Deserialize;
+use serde_json::de::{Deserializer, IoRead};
+use std::collections::HashMap;
+use std::io::{self, BufReader, Read, Result, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// An engine keeping the keys in memory.
+#[derive(Clone, Default)]
+struct MemoryEngine(Arc<Mutex<HashMap<String, String>>>);
+
+impl KvsEngine for MemoryEngine {
+    type Error = String;
+
+    fn set(&self, key: String, value: String) -> std::result::Result<(), String> {
+        self.0.lock().unwrap().insert(key, value);
+        Ok(())
+    }
+
+    fn get(&self, key: String) -> std::result::Result<Option<String>, String> {
+        Ok(self.0.lock().unwrap().get(&key).cloned())
+    }
+
+    fn remove(&self, key: String) -> std::result::Result<(), String> {
+        match self.0.lock().unwrap().remove(&key) {
+            Some(_) => Ok(()),
+            None => Err("Key not found".to_owned()),
+        }
+    }
+}
+
+// A client speaking the JSON protocol of the server.
+struct Client {
+    reader: Deserializer<IoRead<BufReader<TcpStream>>>,
+    writer: TcpStream,
+}
+
+impl Client {
+    fn connect(addr: SocketAddr) -> Result<Client> {
+        let writer = TcpStream::connect(addr)?;
+        let reader = Deserializer::from_reader(BufReader::new(writer.try_clone()?));
+        Ok(Client { reader, writer })
+    }
+
+    fn get(&mut self, key: &str) -> Result<Option<String>> {
+        self.send(&Request::Get {
+            key: key.to_owned(),
+        })?;
+        match GetResponse::deserialize(&mut self.reader)? {
+            GetResponse::Ok(value) => Ok(value),
+            GetResponse::Err(msg) => Err(io::Error::new(io::ErrorKind::Other, msg)),
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        self.send(&Request::Set {
+            key: key.to_owned(),
+            value: value.to_owned(),
+        })?;
+        match SetResponse::deserialize(&mut self.reader)? {
+            SetResponse::Ok(_) => Ok(()),
+            SetResponse::Err(msg) => Err(io::Error::new(io::ErrorKind::Other, msg)),
+        }
+    }
+
+    fn send(&mut self, request: &Request) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, request)?;
+        self.writer.flush()
+    }
+}
+
+// Runs a server in the background on a free port and returns its address.
+fn start_server<F>(configure: F) -> Result<SocketAddr>
+where
+    F: FnOnce(
+            KvsServer<MemoryEngine, SharedQueueThreadPool>,
+        ) -> KvsServer<MemoryEngine, SharedQueueThreadPool>
+        + Send
+        + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    let pool = SharedQueueThreadPool::new(4)?;
+    let server = configure(KvsServer::new(MemoryEngine::default(), pool));
+    thread::spawn(move || server.run_on(listener).unwrap());
+    Ok(addr)
+}
+
+// An idle connection must not keep other clients from being served.
+#[test]
+fn idle_client_does_not_block_others() -> Result<()> {
+    let addr = start_server(|server| server)?;
+
+    let _idle = TcpStream::connect(addr)?;
+    let mut client = Client::connect(addr)?;
+    client.set("key1", "value1")?;
+    assert_eq!(client.get("key1")?, Some("value1".to_owned()));
+    Ok(())
+}
+
+// The server closes connections which stay silent longer than the read timeout.
+#[test]
+fn read_timeout_closes_idle_connection() -> Result<()> {
+    let addr = start_server(|server| server.read_timeout(Some(Duration::from_millis(200))))?;
+
+    let mut idle = TcpStream::connect(addr)?;
+    idle.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut buf = [0; 1];
+    assert_eq!(idle.read(&mut buf)?, 0);
+
+    let mut client = Client::connect(addr)?;
+    client.set("key1", "value1")?;
+    Ok(())
+}
+
+// Connections beyond the limit wait until an open connection is closed.
+#[test]
+fn max_connections_queues_new_clients() -> Result<()> {
+    let addr = start_server(|server| server.max_connections(Some(1)))?;
+
+    // being answered, the first client holds the only place
+    let mut first = Client::connect(addr)?;
+    assert_eq!(first.get("key1")?, None);
+
+    let mut second = TcpStream::connect(addr)?;
+    second.write_all(br#"{"Set":{"key":"key1","value":"value1"}}"#)?;
+    second.flush()?;
+    // the request of the second client is sent, but not served yet
+    assert_eq!(first.get("key1")?, None);
+
+    drop(first);
+    // only fails the test if the second client is never served
+    second.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut buf = [0; 1];
+    assert_eq!(second.read(&mut buf)?, 1);
+
+    drop(second);
+    let mut client = Client::connect(addr)?;
+    assert_eq!(client.get("key1")?, Some("value1".to_owned()));
+    Ok(())
+}